@@ -0,0 +1,214 @@
+//mbtiles, https://github.com/mapbox/mbtiles-spec: an sqlite file with a tiles table keyed by
+//zoom, column and row, where rows count up from the bottom of the map like tms
+use super::sqlite::{column_position, Database, Lookup, Value};
+use super::{ArchiveError, ArchiveMetadata, TileFormat};
+use crate::geo::{Bounds, LatLon};
+use std::path::Path;
+
+const TILE_KEY: [&str; 3] = ["zoom_level", "tile_column", "tile_row"];
+
+enum TileTable {
+    //tile data kept with the coordinates
+    Plain {
+        lookup: Lookup,
+        data: usize,
+    },
+    //tiles is a view, coordinates map to an id and each distinct image is stored once
+    Deduplicated {
+        map: Lookup,
+        tile_id: usize,
+        images: Lookup,
+        data: usize,
+    },
+}
+
+pub struct MbTiles {
+    database: Database,
+    tiles: TileTable,
+    pub metadata: ArchiveMetadata,
+}
+
+impl MbTiles {
+    pub fn open(path: &Path) -> Result<Self, ArchiveError> {
+        let database = Database::open(path)?;
+        let missing = |what: &str| ArchiveError::Invalid(format!("mbtiles file has no {}", what));
+        let tiles = if database.entry("table", "tiles").is_some() {
+            TileTable::Plain {
+                lookup: Lookup::new(&database, "tiles", &TILE_KEY)?,
+                data: column_position(&database, "tiles", "tile_data")
+                    .ok_or_else(|| missing("tile_data column"))?,
+            }
+        } else if database.entry("table", "map").is_some()
+            && database.entry("table", "images").is_some()
+        {
+            TileTable::Deduplicated {
+                map: Lookup::new(&database, "map", &TILE_KEY)?,
+                tile_id: column_position(&database, "map", "tile_id")
+                    .ok_or_else(|| missing("tile_id column in map"))?,
+                images: Lookup::new(&database, "images", &["tile_id"])?,
+                data: column_position(&database, "images", "tile_data")
+                    .ok_or_else(|| missing("tile_data column in images"))?,
+            }
+        } else {
+            return Err(missing("tiles table"));
+        };
+        let metadata = read_metadata(&database)?;
+        Ok(Self {
+            database,
+            tiles,
+            metadata,
+        })
+    }
+
+    pub fn tile(&self, coords: &(u32, u32, u32)) -> Result<Option<Vec<u8>>, ArchiveError> {
+        let (x, y, z) = *coords;
+        if z >= 32 {
+            return Ok(None);
+        }
+        //flipped to count from the bottom
+        let row = (1u64 << z) - 1 - y as u64;
+        let key = [
+            Value::Integer(z as i64),
+            Value::Integer(x as i64),
+            Value::Integer(row as i64),
+        ];
+        let (record, data) = match &self.tiles {
+            TileTable::Plain { lookup, data } => (lookup.find(&self.database, &key)?, *data),
+            TileTable::Deduplicated {
+                map,
+                tile_id,
+                images,
+                data,
+            } => {
+                let id = match map.find(&self.database, &key)? {
+                    Some(mut record) if *tile_id < record.len() => record.swap_remove(*tile_id),
+                    _ => return Ok(None),
+                };
+                (images.find(&self.database, &[id])?, *data)
+            }
+        };
+        Ok(record
+            .filter(|record| data < record.len())
+            .and_then(|mut record| record.swap_remove(data).into_bytes()))
+    }
+}
+
+//the name/value pairs of the metadata table, only the ones that matter to the map are kept
+fn read_metadata(database: &Database) -> Result<ArchiveMetadata, ArchiveError> {
+    let mut metadata = ArchiveMetadata::default();
+    let root = match database.entry("table", "metadata") {
+        Some(entry) => entry.root_page,
+        None => {
+            log::warn!("mbtiles file has no metadata, assuming png tiles");
+            return Ok(metadata);
+        }
+    };
+    let name_column = column_position(database, "metadata", "name").unwrap_or(0);
+    let value_column = column_position(database, "metadata", "value").unwrap_or(1);
+    let mut pairs = Vec::new();
+    database.scan(root, &mut |_, record| {
+        let text = |idx: usize| record.get(idx).and_then(Value::as_text).map(str::to_string);
+        if let (Some(name), Some(value)) = (text(name_column), text(value_column)) {
+            pairs.push((name, value));
+        }
+    })?;
+    for (name, value) in pairs {
+        match name.as_str() {
+            "name" => metadata.name = Some(value),
+            "attribution" => metadata.attribution = Some(value),
+            "format" => metadata.format = TileFormat::from_name(&value),
+            "minzoom" => metadata.min_zoom = value.trim().parse().ok(),
+            "maxzoom" => metadata.max_zoom = value.trim().parse().ok(),
+            "bounds" => metadata.bounds = parse_bounds(&value),
+            _ => {}
+        }
+    }
+    Ok(metadata)
+}
+
+//"left,bottom,right,top" in degrees
+fn parse_bounds(value: &str) -> Option<Bounds> {
+    let numbers = value
+        .split(',')
+        .map(|number| number.trim().parse::<f64>().ok())
+        .collect::<Option<Vec<f64>>>()?;
+    match numbers[..] {
+        [west, south, east, north] => {
+            let mut bounds = Bounds::new(LatLon::new(south, west));
+            bounds.extend(&LatLon::new(north, east));
+            Some(bounds)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    //made with python's sqlite3 with 512 byte pages, so the index and table both need
+    //interior pages and the big tile overflows
+    const TILES: &[u8] = include_bytes!("test_data/tiles.mbtiles");
+    const DEDUPLICATED: &[u8] = include_bytes!("test_data/deduplicated.mbtiles");
+
+    fn file(name: &str, bytes: &[u8]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("map_maker_{}_{}.mbtiles", std::process::id(), name));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn tiles_are_looked_up_through_the_index() {
+        let path = file("mbtiles_index", TILES);
+        let archive = MbTiles::open(&path).unwrap();
+        let metadata = &archive.metadata;
+        assert_eq!(metadata.name.as_deref(), Some("Test tiles"));
+        assert_eq!(metadata.attribution.as_deref(), Some("© testers"));
+        assert_eq!(metadata.format, TileFormat::Png);
+        assert_eq!((metadata.min_zoom, metadata.max_zoom), (Some(0), Some(3)));
+        let bounds = metadata.bounds.unwrap();
+        assert_eq!((bounds.min.lon, bounds.max.lat), (-180.0, 85.05));
+        for z in 0..4 {
+            for x in 0..1 << z {
+                for y in 0..1 << z {
+                    //each tile holds its own coordinates, but one big enough to spill onto
+                    //overflow pages
+                    let expected = if (x, y, z) == (5, 2, 3) {
+                        (0..=255u8).cycle().take(256 * 12).collect()
+                    } else {
+                        format!("{}/{}/{}", z, x, y).into_bytes()
+                    };
+                    assert_eq!(archive.tile(&(x, y, z)).unwrap(), Some(expected));
+                }
+            }
+        }
+        assert_eq!(archive.tile(&(0, 0, 4)).unwrap(), None);
+        assert_eq!(archive.tile(&(0, 0, 40)).unwrap(), None);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn deduplicated_tiles_are_found_without_an_index() {
+        let path = file("mbtiles_deduplicated", DEDUPLICATED);
+        let archive = MbTiles::open(&path).unwrap();
+        assert_eq!(archive.metadata.format, TileFormat::Mvt);
+        assert_eq!(archive.metadata.bounds, None);
+        let tile = |x, y| archive.tile(&(x, y, 1)).unwrap();
+        assert_eq!(tile(0, 0), Some(b"land tile".to_vec()));
+        assert_eq!(tile(0, 1), Some(b"land tile".to_vec()));
+        assert_eq!(tile(1, 1), Some(b"sea tile".to_vec()));
+        assert_eq!(tile(1, 0), None);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bounds_are_west_south_east_north() {
+        let bounds = parse_bounds(" 5.9, 45.8,10.5 ,47.8").unwrap();
+        assert_eq!(bounds.min, LatLon::new(45.8, 5.9));
+        assert_eq!(bounds.max, LatLon::new(47.8, 10.5));
+        assert!(parse_bounds("5.9,45.8,10.5").is_none());
+        assert!(parse_bounds("west,south,east,north").is_none());
+    }
+}
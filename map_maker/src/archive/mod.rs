@@ -0,0 +1,162 @@
+//single files holding a whole tileset, mbtiles and pmtiles, read in place of a tile server
+pub mod mbtiles;
+pub mod pmtiles;
+mod sqlite;
+
+use crate::geo::{lat_lon_to_world_pixel, Bounds, LatLon, TILE_SIZE};
+use crate::tile_manager::tile_manager::TileKind;
+use mbtiles::MbTiles;
+use pmtiles::PmTiles;
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("file ends early")]
+    Truncated,
+    #[error("invalid archive: {0}")]
+    Invalid(String),
+    #[error("unsupported archive: {0}")]
+    Unsupported(String),
+}
+
+/// How an archive's tiles are encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TileFormat {
+    Png,
+    Jpeg,
+    Webp,
+    //mapbox vector tiles
+    Mvt,
+    Other(String),
+}
+
+impl TileFormat {
+    //the names mbtiles metadata uses
+    fn from_name(name: &str) -> Self {
+        match name.trim().to_lowercase().as_str() {
+            "png" => TileFormat::Png,
+            "jpg" | "jpeg" => TileFormat::Jpeg,
+            "webp" => TileFormat::Webp,
+            "pbf" | "mvt" => TileFormat::Mvt,
+            other => TileFormat::Other(other.to_string()),
+        }
+    }
+
+    pub fn kind(&self) -> TileKind {
+        match self {
+            TileFormat::Mvt => TileKind::Vector,
+            _ => TileKind::Raster,
+        }
+    }
+}
+
+/// What an archive says about itself.
+#[derive(Debug, Clone)]
+pub struct ArchiveMetadata {
+    pub name: Option<String>,
+    pub attribution: Option<String>,
+    pub format: TileFormat,
+    pub bounds: Option<Bounds>,
+    pub min_zoom: Option<u8>,
+    pub max_zoom: Option<u8>,
+}
+
+impl Default for ArchiveMetadata {
+    fn default() -> Self {
+        Self {
+            name: None,
+            attribution: None,
+            format: TileFormat::Png,
+            bounds: None,
+            min_zoom: None,
+            max_zoom: None,
+        }
+    }
+}
+
+impl ArchiveMetadata {
+    /// Whether the archive could have tile `coords`, going by its zoom range and bounds.
+    /// Tiles it can't have aren't looked up.
+    pub fn covers(&self, coords: &(u32, u32, u32)) -> bool {
+        let (x, y, z) = *coords;
+        if self.min_zoom.map_or(false, |min| z < min as u32)
+            || self.max_zoom.map_or(false, |max| z > max as u32)
+        {
+            return false;
+        }
+        let bounds = match self.bounds {
+            //bounds across the antimeridian aren't worth the trouble, they cover everything
+            Some(bounds) if bounds.min.lon <= bounds.max.lon => bounds,
+            _ => return true,
+        };
+        let zoom = z.min(u8::MAX as u32) as u8;
+        let top_left = lat_lon_to_world_pixel(&LatLon::new(bounds.max.lat, bounds.min.lon), zoom);
+        let bottom_right =
+            lat_lon_to_world_pixel(&LatLon::new(bounds.min.lat, bounds.max.lon), zoom);
+        let (left, top) = (x as f64 * TILE_SIZE, y as f64 * TILE_SIZE);
+        left <= bottom_right.0
+            && left + TILE_SIZE >= top_left.0
+            && top <= bottom_right.1
+            && top + TILE_SIZE >= top_left.1
+    }
+}
+
+/// Whether a layer's url is a path to an archive rather than a tile server.
+pub fn is_archive_path(url: &str) -> bool {
+    let url = url.trim().to_lowercase();
+    !url.contains("://") && (url.ends_with(".mbtiles") || url.ends_with(".pmtiles"))
+}
+
+enum Reader {
+    MbTiles(MbTiles),
+    PmTiles(PmTiles),
+}
+
+/// An open archive. Tiles are read from the file as they're asked for.
+pub struct TileArchive {
+    reader: Reader,
+}
+
+impl TileArchive {
+    /// Opens an archive, telling the format apart by the extension.
+    pub fn open(path: &Path) -> Result<Self, ArchiveError> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        let reader = match extension.as_str() {
+            "mbtiles" => Reader::MbTiles(MbTiles::open(path)?),
+            "pmtiles" => Reader::PmTiles(PmTiles::open(path)?),
+            _ => {
+                return Err(ArchiveError::Unsupported(format!(
+                    "{} isn't an mbtiles or pmtiles file",
+                    path.display()
+                )))
+            }
+        };
+        Ok(Self { reader })
+    }
+
+    pub fn metadata(&self) -> &ArchiveMetadata {
+        match &self.reader {
+            Reader::MbTiles(archive) => &archive.metadata,
+            Reader::PmTiles(archive) => &archive.metadata,
+        }
+    }
+
+    /// The bytes of tile `coords`, x, y, z as slippy maps count them. None if the archive
+    /// doesn't have it.
+    pub fn tile(&self, coords: &(u32, u32, u32)) -> Result<Option<Vec<u8>>, ArchiveError> {
+        if !self.metadata().covers(coords) {
+            return Ok(None);
+        }
+        match &self.reader {
+            Reader::MbTiles(archive) => archive.tile(coords),
+            Reader::PmTiles(archive) => archive.tile(coords),
+        }
+    }
+}
@@ -0,0 +1,531 @@
+//pmtiles version 3, https://github.com/protomaps/PMTiles/blob/main/spec/v3/spec.md: a header,
+//directories that map tile ids along a hilbert curve to byte ranges, then the tile data
+use super::{ArchiveError, ArchiveMetadata, TileFormat};
+use crate::geo::{Bounds, LatLon};
+use flate2::read::GzDecoder;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+const HEADER_SIZE: usize = 127;
+//the spec never nests leaf directories deeper than this
+const MAX_DEPTH: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    None,
+    Gzip,
+    //brotli and zstd, neither can be read here
+    Other(u8),
+}
+
+impl Compression {
+    fn from_byte(byte: u8) -> Self {
+        match byte {
+            //0 is "unknown", treated as not compressed
+            0 | 1 => Compression::None,
+            2 => Compression::Gzip,
+            other => Compression::Other(other),
+        }
+    }
+
+    fn decompress(self, bytes: Vec<u8>) -> Result<Vec<u8>, ArchiveError> {
+        match self {
+            Compression::None => Ok(bytes),
+            Compression::Gzip => {
+                let mut out = Vec::new();
+                GzDecoder::new(&bytes[..]).read_to_end(&mut out)?;
+                Ok(out)
+            }
+            Compression::Other(kind) => Err(ArchiveError::Unsupported(format!(
+                "pmtiles compression {}",
+                kind
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    tile_id: u64,
+    offset: u64,
+    length: u64,
+    //0 for entries that point at a leaf directory
+    run_length: u64,
+}
+
+pub struct PmTiles {
+    file: Mutex<File>,
+    //offsets in a corrupt file can point anywhere, nothing past this is read
+    file_len: u64,
+    root: Vec<Entry>,
+    leaf_offset: u64,
+    data_offset: u64,
+    internal_compression: Compression,
+    tile_compression: Compression,
+    //leaf directories already read, by offset
+    leaves: Mutex<HashMap<u64, Arc<Vec<Entry>>>>,
+    pub metadata: ArchiveMetadata,
+}
+
+impl PmTiles {
+    pub fn open(path: &Path) -> Result<Self, ArchiveError> {
+        let mut file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut header = [0u8; HEADER_SIZE];
+        file.read_exact(&mut header)?;
+        if &header[..7] != b"PMTiles" {
+            return Err(ArchiveError::Invalid(String::from("not a pmtiles file")));
+        }
+        if header[7] != 3 {
+            return Err(ArchiveError::Unsupported(format!(
+                "pmtiles version {}",
+                header[7]
+            )));
+        }
+        let u64_at = |pos: usize| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&header[pos..pos + 8]);
+            u64::from_le_bytes(bytes)
+        };
+        let degrees_at = |pos: usize| {
+            let mut bytes = [0u8; 4];
+            bytes.copy_from_slice(&header[pos..pos + 4]);
+            i32::from_le_bytes(bytes) as f64 / 10_000_000.0
+        };
+        let internal_compression = Compression::from_byte(header[97]);
+        let tile_compression = Compression::from_byte(header[98]);
+        let format = match header[99] {
+            1 => TileFormat::Mvt,
+            2 => TileFormat::Png,
+            3 => TileFormat::Jpeg,
+            4 => TileFormat::Webp,
+            5 => TileFormat::Other(String::from("avif")),
+            _ => TileFormat::Other(String::new()),
+        };
+        let (west, south, east, north) = (
+            degrees_at(102),
+            degrees_at(106),
+            degrees_at(110),
+            degrees_at(114),
+        );
+        let bounds = if west < east && south < north {
+            let mut bounds = Bounds::new(LatLon::new(south, west));
+            bounds.extend(&LatLon::new(north, east));
+            Some(bounds)
+        } else {
+            None
+        };
+
+        let mut archive = Self {
+            file: Mutex::new(file),
+            file_len,
+            root: Vec::new(),
+            leaf_offset: u64_at(40),
+            data_offset: u64_at(56),
+            internal_compression,
+            tile_compression,
+            leaves: Mutex::new(HashMap::new()),
+            metadata: ArchiveMetadata {
+                name: None,
+                attribution: None,
+                format,
+                bounds,
+                min_zoom: Some(header[100]),
+                max_zoom: Some(header[101]),
+            },
+        };
+        archive.root = archive.directory(u64_at(8), u64_at(16))?;
+        let (name, attribution) = archive.read_json_metadata(u64_at(24), u64_at(32))?;
+        archive.metadata.name = name;
+        archive.metadata.attribution = attribution;
+        Ok(archive)
+    }
+
+    fn read(&self, offset: u64, length: u64) -> Result<Vec<u8>, ArchiveError> {
+        match offset.checked_add(length) {
+            Some(end) if end <= self.file_len => {}
+            _ => return Err(ArchiveError::Truncated),
+        }
+        let mut bytes = vec![0u8; length as usize];
+        let mut file = self.file.lock().expect("pmtiles file lock");
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    //the name and attribution from the json metadata, the rest is for other tools
+    fn read_json_metadata(
+        &self,
+        offset: u64,
+        length: u64,
+    ) -> Result<(Option<String>, Option<String>), ArchiveError> {
+        if length == 0 {
+            return Ok((None, None));
+        }
+        let bytes = self
+            .internal_compression
+            .decompress(self.read(offset, length)?)?;
+        let json: serde_json::Value = match serde_json::from_slice(&bytes) {
+            Ok(json) => json,
+            Err(e) => {
+                log::warn!("pmtiles metadata isn't json: {}", e);
+                return Ok((None, None));
+            }
+        };
+        let text = |key: &str| {
+            json.get(key)
+                .and_then(|value| value.as_str())
+                .map(str::to_string)
+        };
+        Ok((text("name"), text("attribution")))
+    }
+
+    fn directory(&self, offset: u64, length: u64) -> Result<Vec<Entry>, ArchiveError> {
+        let bytes = self
+            .internal_compression
+            .decompress(self.read(offset, length)?)?;
+        parse_directory(&bytes)
+    }
+
+    fn leaf(&self, offset: u64, length: u64) -> Result<Arc<Vec<Entry>>, ArchiveError> {
+        let offset = at(self.leaf_offset, offset)?;
+        if let Some(leaf) = self.leaves.lock().expect("pmtiles leaf lock").get(&offset) {
+            return Ok(leaf.clone());
+        }
+        let leaf = Arc::new(self.directory(offset, length)?);
+        self.leaves
+            .lock()
+            .expect("pmtiles leaf lock")
+            .insert(offset, leaf.clone());
+        Ok(leaf)
+    }
+
+    pub fn tile(&self, coords: &(u32, u32, u32)) -> Result<Option<Vec<u8>>, ArchiveError> {
+        let (x, y, z) = *coords;
+        if z > 26 {
+            return Ok(None);
+        }
+        let id = tile_id(x, y, z);
+        let mut leaf: Option<Arc<Vec<Entry>>> = None;
+        for _ in 0..MAX_DEPTH {
+            let entries = leaf.as_deref().unwrap_or(&self.root);
+            let entry = match find(entries, id) {
+                Some(entry) => *entry,
+                None => return Ok(None),
+            };
+            if entry.run_length > 0 {
+                let bytes = self.read(at(self.data_offset, entry.offset)?, entry.length)?;
+                return self.tile_compression.decompress(bytes).map(Some);
+            }
+            leaf = Some(self.leaf(entry.offset, entry.length)?);
+        }
+        Err(ArchiveError::Invalid(String::from(
+            "leaf directories nest too deep",
+        )))
+    }
+}
+
+//`offset` into the section starting at `start`
+fn at(start: u64, offset: u64) -> Result<u64, ArchiveError> {
+    start.checked_add(offset).ok_or(ArchiveError::Truncated)
+}
+
+//tiles are numbered zoom by zoom, and within a zoom along a hilbert curve
+fn tile_id(x: u32, y: u32, z: u32) -> u64 {
+    let mut id = 0u64;
+    for zoom in 0..z {
+        id += 1u64 << (2 * zoom);
+    }
+    let n = 1u64 << z;
+    let (mut x, mut y) = (x as u64, y as u64);
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u64;
+        let ry = (y & s > 0) as u64;
+        id += s * s * ((3 * rx) ^ ry);
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    id
+}
+
+//the entry covering `id`: an exact match, the run of tiles it falls in, or the leaf
+//directory that holds it
+fn find(entries: &[Entry], id: u64) -> Option<&Entry> {
+    let idx = match entries.binary_search_by_key(&id, |entry| entry.tile_id) {
+        Ok(idx) => return entries.get(idx),
+        Err(0) => return None,
+        Err(idx) => idx - 1,
+    };
+    let entry = &entries[idx];
+    if entry.run_length == 0 || id - entry.tile_id < entry.run_length {
+        Some(entry)
+    } else {
+        None
+    }
+}
+
+//a directory is a count of entries then each field for all of them in turn, ids as deltas
+//and offsets as 0 when the tile follows right after the one before
+fn parse_directory(bytes: &[u8]) -> Result<Vec<Entry>, ArchiveError> {
+    let mut pos = 0;
+    let count = varint(bytes, &mut pos)? as usize;
+    //every entry takes at least four bytes, so a bigger count is corrupt
+    if count > bytes.len() {
+        return Err(ArchiveError::Invalid(String::from("bad directory length")));
+    }
+    let mut entries = vec![
+        Entry {
+            tile_id: 0,
+            offset: 0,
+            length: 0,
+            run_length: 0,
+        };
+        count
+    ];
+    let mut last_id = 0u64;
+    for entry in entries.iter_mut() {
+        last_id = last_id
+            .checked_add(varint(bytes, &mut pos)?)
+            .ok_or_else(|| ArchiveError::Invalid(String::from("tile id out of range")))?;
+        entry.tile_id = last_id;
+    }
+    for entry in entries.iter_mut() {
+        entry.run_length = varint(bytes, &mut pos)?;
+    }
+    for entry in entries.iter_mut() {
+        entry.length = varint(bytes, &mut pos)?;
+    }
+    for idx in 0..count {
+        let offset = varint(bytes, &mut pos)?;
+        entries[idx].offset = if offset == 0 && idx > 0 {
+            at(entries[idx - 1].offset, entries[idx - 1].length)?
+        } else {
+            offset.saturating_sub(1)
+        };
+    }
+    Ok(entries)
+}
+
+//little endian base 128, as protobuf does it
+fn varint(bytes: &[u8], pos: &mut usize) -> Result<u64, ArchiveError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*pos).ok_or(ArchiveError::Truncated)?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(ArchiveError::Invalid(String::from("varint too long")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn push_varint(out: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            out.push((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    //entries as tile id, run length, length and offset. offsets right after the entry
+    //before are written as 0
+    fn directory(entries: &[(u64, u64, u64, u64)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        push_varint(&mut out, entries.len() as u64);
+        let mut last_id = 0;
+        for (id, _, _, _) in entries {
+            push_varint(&mut out, id - last_id);
+            last_id = *id;
+        }
+        for (_, run_length, _, _) in entries {
+            push_varint(&mut out, *run_length);
+        }
+        for (_, _, length, _) in entries {
+            push_varint(&mut out, *length);
+        }
+        let mut next = None;
+        for (_, _, length, offset) in entries {
+            push_varint(&mut out, if next == Some(*offset) { 0 } else { offset + 1 });
+            next = Some(offset + length);
+        }
+        out
+    }
+
+    //an archive of zoom 0 to 2 with gzipped directories: a tile for zoom 0, one shared by
+    //the whole of zoom 1 and two tiles of zoom 2 in a leaf directory
+    fn archive() -> Vec<u8> {
+        let data = b"zeroonefiveseven";
+        let leaf = gzip(&directory(&[(5, 1, 4, 7), (7, 1, 5, 11)]));
+        let root = gzip(&directory(&[
+            (0, 1, 4, 0),
+            (1, 4, 3, 4),
+            (5, 0, leaf.len() as u64, 0),
+        ]));
+        let metadata =
+            gzip(r#"{"name": "Test", "attribution": "© testers", "vector_layers": []}"#.as_bytes());
+
+        let mut header = vec![0u8; HEADER_SIZE];
+        header[..7].copy_from_slice(b"PMTiles");
+        header[7] = 3;
+        let mut offset = HEADER_SIZE as u64;
+        for (field, section) in [&root, &metadata, &leaf, &data.to_vec()].iter().enumerate() {
+            let pos = 8 + field * 16;
+            header[pos..pos + 8].copy_from_slice(&offset.to_le_bytes());
+            header[pos + 8..pos + 16].copy_from_slice(&(section.len() as u64).to_le_bytes());
+            offset += section.len() as u64;
+        }
+        header[97] = 2;
+        header[98] = 1;
+        header[99] = 2;
+        header[101] = 2;
+        for (idx, degrees) in [-10.0f64, -20.0, 30.0, 40.0].iter().enumerate() {
+            let pos = 102 + idx * 4;
+            let value = (degrees * 10_000_000.0) as i32;
+            header[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        [header, root, metadata, leaf, data.to_vec()].concat()
+    }
+
+    fn file(name: &str, bytes: &[u8]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("map_maker_{}_{}.pmtiles", std::process::id(), name));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn open(name: &str, bytes: &[u8]) -> Result<PmTiles, ArchiveError> {
+        let path = file(name, bytes);
+        let archive = PmTiles::open(&path);
+        std::fs::remove_file(path).unwrap();
+        archive
+    }
+
+    fn set_u64(bytes: &mut [u8], pos: usize, value: u64) {
+        bytes[pos..pos + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    #[test]
+    fn tile_ids_follow_the_hilbert_curve() {
+        assert_eq!(tile_id(0, 0, 0), 0);
+        let zoom_1: Vec<u64> = [(0, 0), (0, 1), (1, 1), (1, 0)]
+            .iter()
+            .map(|(x, y)| tile_id(*x, *y, 1))
+            .collect();
+        assert_eq!(zoom_1, vec![1, 2, 3, 4]);
+        //every tile of a zoom gets its own id, right after the zoom before
+        let mut zoom_3: Vec<u64> = (0..64).map(|idx| tile_id(idx % 8, idx / 8, 3)).collect();
+        zoom_3.sort_unstable();
+        assert_eq!(zoom_3, (21..85).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn tiles_are_found_in_runs_and_leaves() {
+        let archive = open("pmtiles_tiles", &archive()).unwrap();
+        let metadata = &archive.metadata;
+        assert_eq!(metadata.name.as_deref(), Some("Test"));
+        assert_eq!(metadata.attribution.as_deref(), Some("© testers"));
+        assert_eq!(metadata.format, TileFormat::Png);
+        assert_eq!((metadata.min_zoom, metadata.max_zoom), (Some(0), Some(2)));
+        let bounds = metadata.bounds.unwrap();
+        assert_eq!(
+            (bounds.min, bounds.max),
+            (LatLon::new(-20.0, -10.0), LatLon::new(40.0, 30.0))
+        );
+
+        assert_eq!(archive.tile(&(0, 0, 0)).unwrap(), Some(b"zero".to_vec()));
+        for (x, y) in [(0, 0), (0, 1), (1, 0), (1, 1)].iter() {
+            assert_eq!(archive.tile(&(*x, *y, 1)).unwrap(), Some(b"one".to_vec()));
+        }
+        for idx in 0..16 {
+            let coords = (idx % 4, idx / 4, 2);
+            let expected = match tile_id(coords.0, coords.1, 2) {
+                5 => Some(b"five".to_vec()),
+                7 => Some(b"seven".to_vec()),
+                _ => None,
+            };
+            assert_eq!(archive.tile(&coords).unwrap(), expected);
+        }
+        assert_eq!(archive.tile(&(0, 0, 3)).unwrap(), None);
+        assert_eq!(archive.tile(&(0, 0, 30)).unwrap(), None);
+    }
+
+    #[test]
+    fn offsets_past_the_end_of_the_file_are_errors() {
+        let mut bytes = archive();
+        set_u64(&mut bytes, 16, 1 << 40);
+        assert!(matches!(
+            open("pmtiles_root", &bytes),
+            Err(ArchiveError::Truncated)
+        ));
+        let mut bytes = archive();
+        set_u64(&mut bytes, 8, u64::MAX);
+        assert!(matches!(
+            open("pmtiles_wrap", &bytes),
+            Err(ArchiveError::Truncated)
+        ));
+        //tile data starting past the end
+        let mut bytes = archive();
+        let len = bytes.len() as u64;
+        set_u64(&mut bytes, 56, len);
+        let archive = open("pmtiles_data", &bytes).unwrap();
+        assert!(matches!(
+            archive.tile(&(0, 0, 0)),
+            Err(ArchiveError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn unreadable_archives_are_rejected() {
+        let mut bytes = archive();
+        bytes[7] = 2;
+        assert!(matches!(
+            open("pmtiles_version", &bytes),
+            Err(ArchiveError::Unsupported(_))
+        ));
+        bytes[0] = b'p';
+        assert!(matches!(
+            open("pmtiles_magic", &bytes),
+            Err(ArchiveError::Invalid(_))
+        ));
+        //brotli tiles
+        let mut bytes = archive();
+        bytes[98] = 3;
+        let archive = open("pmtiles_brotli", &bytes).unwrap();
+        assert!(matches!(
+            archive.tile(&(0, 0, 0)),
+            Err(ArchiveError::Unsupported(_))
+        ));
+        assert!(matches!(
+            parse_directory(&[200, 1]),
+            Err(ArchiveError::Invalid(_))
+        ));
+        assert!(matches!(
+            parse_directory(&[1, 1]),
+            Err(ArchiveError::Truncated)
+        ));
+    }
+}
@@ -0,0 +1,800 @@
+//just enough of the sqlite file format, https://www.sqlite.org/fileformat.html, to read the
+//tables of an mbtiles file. there's no sql here: rows are found by rowid or through an index
+//on a table's leading columns, and anything else is a full scan
+use super::ArchiveError;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Mutex;
+
+const MAGIC: &[u8] = b"SQLite format 3\0";
+const HEADER_SIZE: usize = 100;
+//b-tree page types
+const INTERIOR_INDEX: u8 = 2;
+const INTERIOR_TABLE: u8 = 5;
+const LEAF_INDEX: u8 = 10;
+const LEAF_TABLE: u8 = 13;
+//a corrupt file could send a lookup around in circles
+const MAX_DEPTH: usize = 32;
+//the spec's floor on the usable part of a page, the cell size sums below rely on it
+const MIN_USABLE_SIZE: usize = 480;
+
+/// A column value. Only what mbtiles files hold is told apart.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl Value {
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Integer(value) => Some(*value),
+            Value::Real(value) => Some(*value as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Value::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Text and blobs as bytes, tile data is stored as either.
+    pub fn into_bytes(self) -> Option<Vec<u8>> {
+        match self {
+            Value::Blob(bytes) => Some(bytes),
+            Value::Text(text) => Some(text.into_bytes()),
+            _ => None,
+        }
+    }
+
+    //sqlite's order for values of different types: null, numbers, text, blobs
+    fn compare(&self, other: &Value) -> Ordering {
+        fn rank(value: &Value) -> u8 {
+            match value {
+                Value::Null => 0,
+                Value::Integer(_) | Value::Real(_) => 1,
+                Value::Text(_) => 2,
+                Value::Blob(_) => 3,
+            }
+        }
+        match (self, other) {
+            (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+            (Value::Integer(_), Value::Real(_))
+            | (Value::Real(_), Value::Integer(_))
+            | (Value::Real(_), Value::Real(_)) => {
+                let (a, b) = (self.as_f64(), other.as_f64());
+                a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+            }
+            (Value::Text(a), Value::Text(b)) => a.as_bytes().cmp(b.as_bytes()),
+            (Value::Blob(a), Value::Blob(b)) => a.cmp(b),
+            _ => rank(self).cmp(&rank(other)),
+        }
+    }
+
+    fn as_f64(&self) -> f64 {
+        match self {
+            Value::Integer(value) => *value as f64,
+            Value::Real(value) => *value,
+            _ => 0.0,
+        }
+    }
+}
+
+/// A table or index, as listed in the schema.
+#[derive(Debug, Clone)]
+pub struct SchemaEntry {
+    pub kind: String,
+    pub name: String,
+    pub table: String,
+    pub root_page: u32,
+    pub sql: String,
+}
+
+pub struct Database {
+    file: Mutex<File>,
+    page_size: usize,
+    //how many whole pages the file holds
+    page_count: u64,
+    //the part of each page that isn't reserved for extensions
+    usable_size: usize,
+    schema: Vec<SchemaEntry>,
+}
+
+impl Database {
+    pub fn open(path: &Path) -> Result<Self, ArchiveError> {
+        let mut file = File::open(path)?;
+        let mut header = [0u8; HEADER_SIZE];
+        file.read_exact(&mut header)?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(ArchiveError::Invalid(String::from("not an sqlite file")));
+        }
+        let page_size = match u16::from_be_bytes([header[16], header[17]]) {
+            1 => 65536,
+            size => size as usize,
+        };
+        if !page_size.is_power_of_two() || !(512..=65536).contains(&page_size) {
+            return Err(ArchiveError::Invalid(format!(
+                "bad page size {}",
+                page_size
+            )));
+        }
+        let usable_size = match page_size.checked_sub(header[20] as usize) {
+            Some(usable) if usable >= MIN_USABLE_SIZE => usable,
+            _ => {
+                return Err(ArchiveError::Invalid(format!(
+                    "{} bytes of each page reserved",
+                    header[20]
+                )))
+            }
+        };
+        let page_count = file.metadata()?.len() / page_size as u64;
+        //text is assumed to be utf-8 everywhere below
+        if u32::from_be_bytes([header[56], header[57], header[58], header[59]]) > 1 {
+            return Err(ArchiveError::Invalid(String::from(
+                "only utf-8 sqlite files can be read",
+            )));
+        }
+        let mut database = Self {
+            file: Mutex::new(file),
+            page_size,
+            page_count,
+            usable_size,
+            schema: Vec::new(),
+        };
+        //the schema is a table like any other, rooted on the first page
+        let mut schema = Vec::new();
+        database.scan(1, &mut |_, record| {
+            let text = |idx: usize| {
+                record
+                    .get(idx)
+                    .and_then(Value::as_text)
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            schema.push(SchemaEntry {
+                kind: text(0),
+                name: text(1),
+                table: text(2),
+                root_page: record.get(3).and_then(Value::as_i64).unwrap_or(0) as u32,
+                sql: text(4),
+            });
+        })?;
+        database.schema = schema;
+        Ok(database)
+    }
+
+    pub fn schema(&self) -> &[SchemaEntry] {
+        &self.schema
+    }
+
+    pub fn entry(&self, kind: &str, name: &str) -> Option<&SchemaEntry> {
+        self.schema
+            .iter()
+            .find(|entry| entry.kind == kind && entry.name.eq_ignore_ascii_case(name))
+    }
+
+    fn page(&self, number: u32) -> Result<Vec<u8>, ArchiveError> {
+        if number == 0 || number as u64 > self.page_count {
+            return Err(ArchiveError::Invalid(format!(
+                "page {} doesn't exist",
+                number
+            )));
+        }
+        let mut page = vec![0u8; self.page_size];
+        let mut file = self.file.lock().expect("sqlite file lock");
+        file.seek(SeekFrom::Start((number as u64 - 1) * self.page_size as u64))?;
+        file.read_exact(&mut page)?;
+        Ok(page)
+    }
+
+    //the b-tree part of a page, the first page has the file header in front of it
+    fn btree_page(&self, number: u32) -> Result<BtreePage, ArchiveError> {
+        let data = self.page(number)?;
+        let start = if number == 1 { HEADER_SIZE } else { 0 };
+        let kind = *data.get(start).ok_or(ArchiveError::Truncated)?;
+        let interior = match kind {
+            INTERIOR_INDEX | INTERIOR_TABLE => true,
+            LEAF_INDEX | LEAF_TABLE => false,
+            _ => {
+                return Err(ArchiveError::Invalid(format!(
+                    "page {} isn't a b-tree page",
+                    number
+                )))
+            }
+        };
+        let cell_count = be_u16(&data, start + 3)? as usize;
+        let right_most = if interior {
+            be_u32(&data, start + 8)?
+        } else {
+            0
+        };
+        let pointers = start + if interior { 12 } else { 8 };
+        let mut cells = Vec::with_capacity(cell_count);
+        for idx in 0..cell_count {
+            cells.push(be_u16(&data, pointers + idx * 2)? as usize);
+        }
+        Ok(BtreePage {
+            kind,
+            data,
+            cells,
+            right_most,
+        })
+    }
+
+    //the whole payload of a cell, following it onto overflow pages when it doesn't fit
+    fn payload(
+        &self,
+        page: &[u8],
+        offset: usize,
+        size: usize,
+        table: bool,
+    ) -> Result<Vec<u8>, ArchiveError> {
+        //the file header was checked to leave at least MIN_USABLE_SIZE, none of these go
+        //below 0
+        let usable = self.usable_size;
+        //nothing bigger than the whole file can be in it
+        if size as u64 > self.page_count * usable as u64 {
+            return Err(ArchiveError::Invalid(String::from(
+                "cell is bigger than the file",
+            )));
+        }
+        let max_local = if table {
+            usable - 35
+        } else {
+            (usable - 12) * 64 / 255 - 23
+        };
+        let min_local = (usable - 12) * 32 / 255 - 23;
+        if size <= max_local {
+            return Ok(slice(page, offset, size)?.to_vec());
+        }
+        let mut local = min_local + (size - min_local) % (usable - 4);
+        if local > max_local {
+            local = min_local;
+        }
+        let mut payload = slice(page, offset, local)?.to_vec();
+        let mut next = be_u32(page, offset + local)?;
+        while payload.len() < size {
+            if next == 0 {
+                return Err(ArchiveError::Invalid(String::from(
+                    "overflow chain ends early",
+                )));
+            }
+            let overflow = self.page(next)?;
+            next = be_u32(&overflow, 0)?;
+            let take = (size - payload.len()).min(usable - 4);
+            payload.extend_from_slice(slice(&overflow, 4, take)?);
+        }
+        Ok(payload)
+    }
+
+    /// Calls `row` with the rowid and columns of every row of the table rooted at `root`.
+    pub fn scan(
+        &self,
+        root: u32,
+        row: &mut dyn FnMut(i64, Vec<Value>),
+    ) -> Result<(), ArchiveError> {
+        let mut pages = vec![(root, 0)];
+        while let Some((number, depth)) = pages.pop() {
+            if depth > MAX_DEPTH {
+                return Err(ArchiveError::Invalid(String::from("table is too deep")));
+            }
+            let page = self.btree_page(number)?;
+            match page.kind {
+                INTERIOR_TABLE => {
+                    //pushed in reverse so rows come out in rowid order
+                    pages.push((page.right_most, depth + 1));
+                    for cell in page.cells.iter().rev() {
+                        pages.push((be_u32(&page.data, *cell)?, depth + 1));
+                    }
+                }
+                LEAF_TABLE => {
+                    for cell in page.cells.iter() {
+                        let (rowid, record) = self.table_cell(&page, *cell)?;
+                        row(rowid, record);
+                    }
+                }
+                _ => return Err(ArchiveError::Invalid(String::from("expected a table page"))),
+            }
+        }
+        Ok(())
+    }
+
+    fn table_cell(&self, page: &BtreePage, cell: usize) -> Result<(i64, Vec<Value>), ArchiveError> {
+        let (size, used) = varint(&page.data, cell)?;
+        let (rowid, used_rowid) = varint(&page.data, cell + used)?;
+        let payload = self.payload(&page.data, cell + used + used_rowid, size as usize, true)?;
+        Ok((rowid as i64, record(&payload)?))
+    }
+
+    /// The columns of the row with `rowid` in the table rooted at `root`.
+    pub fn row(&self, root: u32, rowid: i64) -> Result<Option<Vec<Value>>, ArchiveError> {
+        let mut number = root;
+        for _ in 0..MAX_DEPTH {
+            let page = self.btree_page(number)?;
+            match page.kind {
+                INTERIOR_TABLE => {
+                    //each cell's child holds rowids up to its key
+                    number = page.right_most;
+                    for cell in page.cells.iter() {
+                        let (key, _) = varint(&page.data, cell + 4)?;
+                        if rowid <= key as i64 {
+                            number = be_u32(&page.data, *cell)?;
+                            break;
+                        }
+                    }
+                }
+                LEAF_TABLE => {
+                    for cell in page.cells.iter() {
+                        let (found, record) = self.table_cell(&page, *cell)?;
+                        if found == rowid {
+                            return Ok(Some(record));
+                        }
+                    }
+                    return Ok(None);
+                }
+                _ => return Err(ArchiveError::Invalid(String::from("expected a table page"))),
+            }
+        }
+        Err(ArchiveError::Invalid(String::from("table is too deep")))
+    }
+
+    /// The rowid stored with the first entry of the index rooted at `root` whose leading
+    /// columns equal `key`.
+    pub fn index_lookup(&self, root: u32, key: &[Value]) -> Result<Option<i64>, ArchiveError> {
+        let mut number = root;
+        for _ in 0..MAX_DEPTH {
+            let page = self.btree_page(number)?;
+            let interior = match page.kind {
+                INTERIOR_INDEX => true,
+                LEAF_INDEX => false,
+                _ => {
+                    return Err(ArchiveError::Invalid(String::from(
+                        "expected an index page",
+                    )))
+                }
+            };
+            let mut child = page.right_most;
+            for cell in page.cells.iter() {
+                let start = if interior { cell + 4 } else { *cell };
+                let (size, used) = varint(&page.data, start)?;
+                let payload = self.payload(&page.data, start + used, size as usize, false)?;
+                let entry = record(&payload)?;
+                match compare_prefix(&entry, key) {
+                    Ordering::Less => continue,
+                    //interior cells are entries too, not just signposts
+                    Ordering::Equal => return Ok(entry.last().and_then(Value::as_i64)),
+                    Ordering::Greater => {
+                        child = be_u32(&page.data, *cell).unwrap_or(0);
+                        break;
+                    }
+                }
+            }
+            if !interior {
+                return Ok(None);
+            }
+            number = child;
+        }
+        Err(ArchiveError::Invalid(String::from("index is too deep")))
+    }
+}
+
+struct BtreePage {
+    kind: u8,
+    data: Vec<u8>,
+    //where each cell starts
+    cells: Vec<usize>,
+    //the child after the last cell of interior pages
+    right_most: u32,
+}
+
+fn compare_prefix(entry: &[Value], key: &[Value]) -> Ordering {
+    for (value, wanted) in entry.iter().zip(key.iter()) {
+        match value.compare(wanted) {
+            Ordering::Equal => continue,
+            other => return other,
+        }
+    }
+    Ordering::Equal
+}
+
+/// The columns of a table, or of an index, from the sql that made it. `INTEGER PRIMARY KEY`
+/// columns come back flagged, sqlite keeps them as the rowid rather than in the row.
+pub fn columns(sql: &str) -> Vec<(String, bool)> {
+    let (open, close) = match (sql.find('('), sql.rfind(')')) {
+        (Some(open), Some(close)) if open < close => (open, close),
+        _ => return Vec::new(),
+    };
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = open + 1;
+    for (idx, c) in sql[..close]
+        .char_indices()
+        .skip_while(|(idx, _)| *idx <= open)
+    {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&sql[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&sql[start..close]);
+
+    let mut columns = Vec::new();
+    for part in parts {
+        let part = part.trim();
+        let name = part.split_whitespace().next().unwrap_or_default();
+        let keyword = name.to_uppercase();
+        let constraint = ["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"];
+        if name.is_empty() || constraint.contains(&keyword.as_str()) {
+            continue;
+        }
+        let name = name.trim_matches(|c| c == '"' || c == '`' || c == '[' || c == ']');
+        let upper = part.to_uppercase();
+        let rowid = upper.contains("INTEGER PRIMARY KEY");
+        columns.push((name.to_string(), rowid));
+    }
+    columns
+}
+
+/// A table's rows by the values of some of their columns. It goes through an index on
+/// those columns if there is one, and otherwise reads the table through once up front.
+pub struct Lookup {
+    root: u32,
+    //where the wanted columns are in a row, None for the rowid
+    positions: Vec<Option<usize>>,
+    method: LookupMethod,
+}
+
+enum LookupMethod {
+    Index(u32),
+    //rowids by the key columns, printed since values don't hash
+    Scanned(HashMap<String, i64>),
+}
+
+impl Lookup {
+    pub fn new(database: &Database, table: &str, key: &[&str]) -> Result<Self, ArchiveError> {
+        let entry = database
+            .entry("table", table)
+            .ok_or_else(|| ArchiveError::Invalid(format!("no {} table", table)))?;
+        let table_columns = columns(&entry.sql);
+        let position = |name: &str| -> Result<Option<usize>, ArchiveError> {
+            match table_columns
+                .iter()
+                .position(|(column, _)| column.eq_ignore_ascii_case(name))
+            {
+                Some(idx) if table_columns[idx].1 => Ok(None),
+                Some(idx) => Ok(Some(idx)),
+                None => Err(ArchiveError::Invalid(format!(
+                    "{} has no {} column",
+                    table, name
+                ))),
+            }
+        };
+        let positions = key
+            .iter()
+            .map(|name| position(name))
+            .collect::<Result<_, _>>()?;
+
+        let index = database.schema().iter().find(|index| {
+            let indexed = columns(&index.sql);
+            index.kind == "index"
+                && index.table.eq_ignore_ascii_case(table)
+                && indexed.len() >= key.len()
+                && indexed
+                    .iter()
+                    .zip(key.iter())
+                    .all(|((column, _), name)| column.eq_ignore_ascii_case(name))
+        });
+        let method = match index {
+            Some(index) => LookupMethod::Index(index.root_page),
+            None => {
+                log::info!(
+                    "{} has no index to look tiles up with, reading it all",
+                    table
+                );
+                LookupMethod::Scanned(HashMap::new())
+            }
+        };
+        let mut lookup = Self {
+            root: entry.root_page,
+            positions,
+            method,
+        };
+        if let LookupMethod::Scanned(_) = lookup.method {
+            let mut rows = HashMap::new();
+            database.scan(lookup.root, &mut |rowid, record| {
+                rows.insert(lookup.key_of(rowid, &record), rowid);
+            })?;
+            lookup.method = LookupMethod::Scanned(rows);
+        }
+        Ok(lookup)
+    }
+
+    fn key_of(&self, rowid: i64, record: &[Value]) -> String {
+        let values: Vec<Value> = self
+            .positions
+            .iter()
+            .map(|position| match position {
+                Some(idx) => record.get(*idx).cloned().unwrap_or(Value::Null),
+                None => Value::Integer(rowid),
+            })
+            .collect();
+        format!("{:?}", values)
+    }
+
+    /// The row whose key columns equal `key`.
+    pub fn find(
+        &self,
+        database: &Database,
+        key: &[Value],
+    ) -> Result<Option<Vec<Value>>, ArchiveError> {
+        let rowid = match &self.method {
+            LookupMethod::Index(root) => database.index_lookup(*root, key)?,
+            LookupMethod::Scanned(rows) => rows.get(&format!("{:?}", key)).copied(),
+        };
+        match rowid {
+            Some(rowid) => database.row(self.root, rowid),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Where `column` is in the rows of `table`, None if it's the rowid or missing.
+pub fn column_position(database: &Database, table: &str, column: &str) -> Option<usize> {
+    let entry = database.entry("table", table)?;
+    let columns = columns(&entry.sql);
+    let idx = columns
+        .iter()
+        .position(|(name, _)| name.eq_ignore_ascii_case(column))?;
+    Some(idx).filter(|idx| !columns[*idx].1)
+}
+
+//decodes a record: a header of serial types, then the values
+fn record(payload: &[u8]) -> Result<Vec<Value>, ArchiveError> {
+    let (header_size, mut pos) = varint(payload, 0)?;
+    let mut types = Vec::new();
+    while pos < header_size as usize {
+        let (serial, used) = varint(payload, pos)?;
+        types.push(serial);
+        pos += used;
+    }
+    let mut body = header_size as usize;
+    let mut values = Vec::with_capacity(types.len());
+    for serial in types {
+        let int = |len: usize| -> Result<i64, ArchiveError> {
+            let bytes = slice(payload, body, len)?;
+            //sign extended from however many bytes it's stored in
+            let mut value = if bytes[0] & 0x80 != 0 { -1i64 } else { 0 };
+            for byte in bytes {
+                value = (value << 8) | *byte as i64;
+            }
+            Ok(value)
+        };
+        let (value, len) = match serial {
+            0 => (Value::Null, 0),
+            1 => (Value::Integer(int(1)?), 1),
+            2 => (Value::Integer(int(2)?), 2),
+            3 => (Value::Integer(int(3)?), 3),
+            4 => (Value::Integer(int(4)?), 4),
+            5 => (Value::Integer(int(6)?), 6),
+            6 => (Value::Integer(int(8)?), 8),
+            7 => (Value::Real(f64::from_bits(int(8)? as u64)), 8),
+            8 => (Value::Integer(0), 0),
+            9 => (Value::Integer(1), 0),
+            serial if serial >= 12 && serial % 2 == 0 => {
+                let len = (serial as usize - 12) / 2;
+                (Value::Blob(slice(payload, body, len)?.to_vec()), len)
+            }
+            serial if serial >= 13 => {
+                let len = (serial as usize - 13) / 2;
+                let text = String::from_utf8_lossy(slice(payload, body, len)?).into_owned();
+                (Value::Text(text), len)
+            }
+            _ => return Err(ArchiveError::Invalid(format!("bad serial type {}", serial))),
+        };
+        values.push(value);
+        body += len;
+    }
+    Ok(values)
+}
+
+//sqlite's varints are big endian, 7 bits a byte, except a 9th byte uses all 8
+fn varint(bytes: &[u8], pos: usize) -> Result<(u64, usize), ArchiveError> {
+    let mut value = 0u64;
+    for idx in 0..9 {
+        let byte = *bytes.get(pos + idx).ok_or(ArchiveError::Truncated)?;
+        if idx == 8 {
+            return Ok(((value << 8) | byte as u64, 9));
+        }
+        value = (value << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            return Ok((value, idx + 1));
+        }
+    }
+    unreachable!("the ninth byte always ends a varint")
+}
+
+fn slice(bytes: &[u8], pos: usize, len: usize) -> Result<&[u8], ArchiveError> {
+    let end = pos.checked_add(len).ok_or(ArchiveError::Truncated)?;
+    bytes.get(pos..end).ok_or(ArchiveError::Truncated)
+}
+
+fn be_u16(bytes: &[u8], pos: usize) -> Result<u16, ArchiveError> {
+    let bytes = slice(bytes, pos, 2)?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn be_u32(bytes: &[u8], pos: usize) -> Result<u32, ArchiveError> {
+    let bytes = slice(bytes, pos, 4)?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const TILES: &[u8] = include_bytes!("test_data/tiles.mbtiles");
+
+    fn file(name: &str, bytes: &[u8]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("map_maker_{}_{}.sqlite", std::process::id(), name));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    //opens the test file with `change` made to it
+    fn open_changed(name: &str, change: impl FnOnce(&mut Vec<u8>)) -> Result<(), ArchiveError> {
+        let mut bytes = TILES.to_vec();
+        change(&mut bytes);
+        let path = file(name, &bytes);
+        let opened = Database::open(&path).map(|_| ());
+        std::fs::remove_file(path).unwrap();
+        opened
+    }
+
+    #[test]
+    fn rows_are_found_by_rowid_and_index() {
+        let path = file("sqlite_rows", TILES);
+        let database = Database::open(&path).unwrap();
+        let tiles = database.entry("table", "TILES").unwrap();
+        let index = database.entry("index", "tile_index").unwrap();
+        assert_eq!(index.table, "tiles");
+        let mut count = 0;
+        database
+            .scan(tiles.root_page, &mut |rowid, _| {
+                count += 1;
+                assert_eq!(rowid, count);
+            })
+            .unwrap();
+        assert_eq!(count, 85);
+        let row = database.row(tiles.root_page, 2).unwrap().unwrap();
+        assert_eq!(
+            row,
+            vec![
+                Value::Integer(1),
+                Value::Integer(0),
+                Value::Integer(1),
+                Value::Blob(b"1/0/0".to_vec())
+            ]
+        );
+        assert_eq!(database.row(tiles.root_page, 86).unwrap(), None);
+        let key = |z, x, y| [Value::Integer(z), Value::Integer(x), Value::Integer(y)];
+        assert_eq!(
+            database
+                .index_lookup(index.root_page, &key(3, 7, 0))
+                .unwrap(),
+            Some(85)
+        );
+        assert_eq!(
+            database
+                .index_lookup(index.root_page, &key(3, 8, 0))
+                .unwrap(),
+            None
+        );
+        //a number after all the zooms, and text, which sorts after numbers
+        assert_eq!(
+            database
+                .index_lookup(index.root_page, &key(4, 0, 0))
+                .unwrap(),
+            None
+        );
+        let text = [Value::Text(String::from("3"))];
+        assert_eq!(database.index_lookup(index.root_page, &text).unwrap(), None);
+        //tables aren't indexes
+        assert!(database
+            .index_lookup(tiles.root_page, &key(0, 0, 0))
+            .is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bad_page_sizes_are_rejected() {
+        for size in [0u16, 256, 1000, 1024 + 512].iter() {
+            let opened = open_changed("sqlite_page_size", |bytes| {
+                bytes[16..18].copy_from_slice(&size.to_be_bytes())
+            });
+            assert!(matches!(opened, Err(ArchiveError::Invalid(_))), "{}", size);
+        }
+        //too much of each page reserved
+        let opened = open_changed("sqlite_reserved", |bytes| bytes[20] = 64);
+        assert!(matches!(opened, Err(ArchiveError::Invalid(_))));
+        let opened = open_changed("sqlite_magic", |bytes| bytes[0] = b's');
+        assert!(matches!(opened, Err(ArchiveError::Invalid(_))));
+        let opened = open_changed("sqlite_utf16", |bytes| bytes[59] = 2);
+        assert!(matches!(opened, Err(ArchiveError::Invalid(_))));
+    }
+
+    #[test]
+    fn pages_past_the_end_of_the_file_are_errors() {
+        let path = file("sqlite_truncated", &TILES[..512 * 4]);
+        let database = Database::open(&path).unwrap();
+        let tiles = database.entry("table", "tiles").unwrap().root_page;
+        let index = database.entry("index", "tile_index").unwrap().root_page;
+        assert!(database.scan(tiles, &mut |_, _| {}).is_err());
+        assert!(database.row(tiles, 85).is_err());
+        //the first zoom is on a leaf page that was cut off
+        assert!(database.index_lookup(index, &[Value::Integer(0)]).is_err());
+        assert!(database.row(0, 1).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn records_hold_every_serial_type() {
+        let mut payload = vec![9, 1, 2, 8, 9, 7, 0, 17, 14];
+        payload.push(0xff);
+        payload.extend_from_slice(&[0x01, 0x02]);
+        payload.extend_from_slice(&1.5f64.to_bits().to_be_bytes());
+        payload.extend_from_slice(b"hi");
+        payload.push(9);
+        assert_eq!(
+            record(&payload).unwrap(),
+            vec![
+                Value::Integer(-1),
+                Value::Integer(258),
+                Value::Integer(0),
+                Value::Integer(1),
+                Value::Real(1.5),
+                Value::Null,
+                Value::Text(String::from("hi")),
+                Value::Blob(vec![9]),
+            ]
+        );
+        assert!(matches!(
+            record(&payload[..payload.len() - 1]),
+            Err(ArchiveError::Truncated)
+        ));
+        assert!(matches!(record(&[2, 10]), Err(ArchiveError::Invalid(_))));
+        //nine byte varints use all of the last byte
+        assert_eq!(varint(&[0xff; 9], 0).unwrap(), (u64::MAX, 9));
+        assert_eq!(varint(&[0x81, 0x00], 0).unwrap(), (128, 2));
+    }
+
+    #[test]
+    fn columns_are_read_from_the_sql() {
+        let sql = "CREATE TABLE t (id INTEGER PRIMARY KEY, \"name\" text, \
+                   value numeric(10, 2), PRIMARY KEY (name))";
+        assert_eq!(
+            columns(sql),
+            vec![
+                (String::from("id"), true),
+                (String::from("name"), false),
+                (String::from("value"), false),
+            ]
+        );
+        assert!(columns("CREATE VIEW tiles AS SELECT * FROM map").is_empty());
+    }
+}
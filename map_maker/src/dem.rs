@@ -0,0 +1,153 @@
+//digital elevation model used to fill in elevations for drawn points
+//
+//reads ESRI ASCII grids (.asc) in lat/lon degrees, which gdal_translate, QGIS and most
+//DEM download portals can produce
+use crate::formats::FormatError;
+use crate::geo::LatLon;
+use std::path::Path;
+
+pub struct Dem {
+    columns: usize,
+    rows: usize,
+    //lower left corner of the lower left cell, degrees
+    west: f64,
+    south: f64,
+    cell_size: f64,
+    no_data: Option<f64>,
+    //row major, first row is the northernmost
+    values: Vec<f64>,
+}
+
+impl Dem {
+    pub fn read_ascii_grid(path: &Path) -> Result<Self, FormatError> {
+        let text = std::fs::read_to_string(path)?;
+        Dem::parse_ascii_grid(&text)
+    }
+
+    pub fn parse_ascii_grid(text: &str) -> Result<Self, FormatError> {
+        let mut tokens = text.split_whitespace().peekable();
+        let mut header = std::collections::HashMap::new();
+        //header lines are "key value" until the first numeric token
+        while let Some(token) = tokens.peek() {
+            if token.parse::<f64>().is_ok() {
+                break;
+            }
+            let key = tokens.next().unwrap_or_default().to_lowercase();
+            let value: f64 = tokens
+                .next()
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| FormatError::Invalid(format!("bad header value for {}", key)))?;
+            header.insert(key, value);
+        }
+        let get = |key: &str| {
+            header
+                .get(key)
+                .copied()
+                .ok_or_else(|| FormatError::Invalid(format!("missing {}", key)))
+        };
+        let columns = get("ncols")? as usize;
+        let rows = get("nrows")? as usize;
+        if columns == 0 || rows == 0 {
+            return Err(FormatError::Invalid(String::from("dem has no cells")));
+        }
+        let cell_size = get("cellsize")?;
+        if cell_size <= 0.0 {
+            return Err(FormatError::Invalid(String::from(
+                "cellsize must be positive",
+            )));
+        }
+        //corner or center registration
+        let west = get("xllcorner").or_else(|_| get("xllcenter").map(|x| x - cell_size / 2.0))?;
+        let south = get("yllcorner").or_else(|_| get("yllcenter").map(|y| y - cell_size / 2.0))?;
+        let values = tokens
+            .map(|value| value.parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|_| FormatError::Invalid(String::from("non numeric elevation")))?;
+        if columns.checked_mul(rows) != Some(values.len()) {
+            return Err(FormatError::Invalid(format!(
+                "expected {}x{} elevations, found {}",
+                columns,
+                rows,
+                values.len()
+            )));
+        }
+        log::info!("loaded {}x{} dem", columns, rows);
+        Ok(Self {
+            columns,
+            rows,
+            west,
+            south,
+            cell_size,
+            no_data: header.get("nodata_value").copied(),
+            values,
+        })
+    }
+
+    fn value(&self, column: usize, row: usize) -> Option<f64> {
+        let value = self.values[row * self.columns + column];
+        match self.no_data {
+            Some(no_data) if value == no_data => None,
+            _ => Some(value),
+        }
+    }
+
+    /// Bilinear interpolation between the four nearest cell centers.
+    pub fn elevation(&self, point: &LatLon) -> Option<f64> {
+        let north = self.south + self.rows as f64 * self.cell_size;
+        //fractional cell coordinates measured between cell centers
+        let x = (point.lon - self.west) / self.cell_size - 0.5;
+        let y = (north - point.lat) / self.cell_size - 0.5;
+        if x < -0.5 || y < -0.5 || x > self.columns as f64 - 0.5 || y > self.rows as f64 - 0.5 {
+            return None;
+        }
+        let clamp = |v: f64, max: usize| v.max(0.0).min((max - 1) as f64);
+        let (x, y) = (clamp(x, self.columns), clamp(y, self.rows));
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(self.columns - 1), (y0 + 1).min(self.rows - 1));
+        let (tx, ty) = (x - x0 as f64, y - y0 as f64);
+        let top = self.value(x0, y0)? * (1.0 - tx) + self.value(x1, y0)? * tx;
+        let bottom = self.value(x0, y1)? * (1.0 - tx) + self.value(x1, y1)? * tx;
+        Some(top * (1.0 - ty) + bottom * ty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRID: &str = "ncols 3\nnrows 2\nxllcorner 7.0\nyllcorner 46.0\ncellsize 1.0\n\
+                        NODATA_value -9999\n100 200 -9999\n300 400 500\n";
+
+    #[test]
+    fn elevations_are_interpolated_between_cell_centers() {
+        let dem = Dem::parse_ascii_grid(GRID).unwrap();
+        //cell centers
+        assert_eq!(dem.elevation(&LatLon::new(47.5, 7.5)), Some(100.0));
+        assert_eq!(dem.elevation(&LatLon::new(46.5, 8.5)), Some(400.0));
+        //half way between the centers of the four western cells
+        assert_eq!(dem.elevation(&LatLon::new(47.0, 8.0)), Some(250.0));
+        //the edge of the grid takes the nearest cell
+        assert_eq!(dem.elevation(&LatLon::new(46.0, 7.0)), Some(300.0));
+    }
+
+    #[test]
+    fn points_off_the_grid_or_near_no_data_have_no_elevation() {
+        let dem = Dem::parse_ascii_grid(GRID).unwrap();
+        assert_eq!(dem.elevation(&LatLon::new(45.9, 7.5)), None);
+        assert_eq!(dem.elevation(&LatLon::new(46.5, 10.1)), None);
+        assert_eq!(dem.elevation(&LatLon::new(47.5, 9.5)), None);
+        assert_eq!(dem.elevation(&LatLon::new(47.0, 9.0)), None);
+    }
+
+    #[test]
+    fn empty_or_short_grids_are_rejected() {
+        let header = "xllcorner 0\nyllcorner 0\ncellsize 1\n";
+        for size in ["ncols 0\nnrows 2\n", "ncols 2\nnrows 0\n"].iter() {
+            assert!(Dem::parse_ascii_grid(&format!("{}{}", size, header)).is_err());
+        }
+        let short = format!("ncols 2\nnrows 2\n{}1 2 3", header);
+        assert!(Dem::parse_ascii_grid(&short).is_err());
+        let flat = "ncols 1\nnrows 1\nxllcorner 0\nyllcorner 0\ncellsize 0\n5";
+        assert!(Dem::parse_ascii_grid(flat).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+//the side panel section for downloading an area's tiles ahead of time, see offline.rs
+use crate::features::DrawingLayer;
+use crate::layer_panel::PANEL_WIDTH;
+use crate::offline::{Download, RegionChoice};
+use iced::{button, pick_list, Align, Button, Column, Element, Length, PickList, Row, Text};
+
+const ZOOM_LEVELS: [u8; 20] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19,
+];
+
+#[derive(Debug, Clone)]
+pub enum DownloadMessage {
+    RegionSelected(RegionChoice),
+    MinZoomSelected(u8),
+    MaxZoomSelected(u8),
+    Start,
+    Pause,
+    Resume,
+    Cancel,
+}
+
+pub struct DownloadPanel {
+    pub region: RegionChoice,
+    pub min_zoom: u8,
+    pub max_zoom: u8,
+    //how big the download would be, or why it can't be done
+    estimate: String,
+    //rebuilt from the drawing layers every time the panel is drawn
+    choices: Vec<RegionChoice>,
+    region_state: pick_list::State<RegionChoice>,
+    min_zoom_state: pick_list::State<u8>,
+    max_zoom_state: pick_list::State<u8>,
+    start: button::State,
+    pause: button::State,
+    cancel: button::State,
+}
+
+impl Default for DownloadPanel {
+    fn default() -> Self {
+        Self {
+            region: RegionChoice::default(),
+            min_zoom: 12,
+            max_zoom: 15,
+            estimate: String::new(),
+            choices: Vec::new(),
+            region_state: pick_list::State::default(),
+            min_zoom_state: pick_list::State::default(),
+            max_zoom_state: pick_list::State::default(),
+            start: button::State::new(),
+            pause: button::State::new(),
+            cancel: button::State::new(),
+        }
+    }
+}
+
+impl DownloadPanel {
+    pub fn set_estimate(&mut self, estimate: String) {
+        self.estimate = estimate;
+    }
+
+    /// Picking a zoom past the other end of the range drags that end along.
+    pub fn select_zooms(&mut self, message: &DownloadMessage) {
+        match message {
+            DownloadMessage::MinZoomSelected(zoom) => {
+                self.min_zoom = *zoom;
+                self.max_zoom = self.max_zoom.max(*zoom);
+            }
+            DownloadMessage::MaxZoomSelected(zoom) => {
+                self.max_zoom = *zoom;
+                self.min_zoom = self.min_zoom.min(*zoom);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn view<'a>(
+        &'a mut self,
+        drawing_layers: &[DrawingLayer],
+        download: Option<&Download>,
+    ) -> Element<'a, DownloadMessage> {
+        self.choices = RegionChoice::all(drawing_layers);
+        //the polygon may have been deleted since it was picked
+        if !self.choices.contains(&self.region) {
+            self.region = RegionChoice::VisibleMap;
+        }
+
+        let mut column = Column::new()
+            .spacing(4)
+            .width(Length::Units(PANEL_WIDTH))
+            .push(Text::new("offline download").size(18))
+            .push(PickList::new(
+                &mut self.region_state,
+                &self.choices[..],
+                Some(self.region.clone()),
+                DownloadMessage::RegionSelected,
+            ))
+            .push(
+                Row::new()
+                    .spacing(5)
+                    .align_items(Align::Center)
+                    .push(Text::new("zoom").size(16))
+                    .push(PickList::new(
+                        &mut self.min_zoom_state,
+                        &ZOOM_LEVELS[..],
+                        Some(self.min_zoom),
+                        DownloadMessage::MinZoomSelected,
+                    ))
+                    .push(Text::new("to").size(16))
+                    .push(PickList::new(
+                        &mut self.max_zoom_state,
+                        &ZOOM_LEVELS[..],
+                        Some(self.max_zoom),
+                        DownloadMessage::MaxZoomSelected,
+                    )),
+            )
+            .push(Text::new(&self.estimate).size(14));
+
+        let mut buttons = Row::new().spacing(5);
+        match download {
+            Some(download) if !download.is_finished() => {
+                let pause = if download.paused {
+                    Button::new(&mut self.pause, Text::new("resume").size(16))
+                        .on_press(DownloadMessage::Resume)
+                } else {
+                    Button::new(&mut self.pause, Text::new("pause").size(16))
+                        .on_press(DownloadMessage::Pause)
+                };
+                buttons = buttons.push(pause).push(
+                    Button::new(&mut self.cancel, Text::new("cancel").size(16))
+                        .on_press(DownloadMessage::Cancel),
+                );
+            }
+            _ => {
+                buttons = buttons.push(
+                    Button::new(&mut self.start, Text::new("download").size(16))
+                        .on_press(DownloadMessage::Start),
+                );
+            }
+        }
+        column = column.push(buttons);
+        if let Some(download) = download {
+            column = column.push(Text::new(download.to_string()).size(14));
+        }
+        column.into()
+    }
+}
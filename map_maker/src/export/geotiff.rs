@@ -0,0 +1,673 @@
+//writes a rendered map region as a GeoTIFF so GIS tools can line it up with other data
+//
+//the render comes out in web mercator pixels. it's written as is for EPSG:3857, or
+//resampled onto a grid in the target projection for EPSG:4326 and UTM. pixels the
+//reprojected grid has no data for are left transparent
+use crate::export::raster::RasterExport;
+use crate::export::ExportError;
+use crate::geo::{Bounds, LatLon};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use image::{Rgba, RgbaImage};
+use std::f64::consts::PI;
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+
+//WGS 84 ellipsoid
+const SEMI_MAJOR_AXIS: f64 = 6_378_137.0;
+const FLATTENING: f64 = 1.0 / 298.257_223_563;
+const UTM_SCALE: f64 = 0.9996;
+const UTM_FALSE_EASTING: f64 = 500_000.0;
+const UTM_FALSE_NORTHING_SOUTH: f64 = 10_000_000.0;
+//points along each edge of the render used to find its extent once reprojected
+const EDGE_SAMPLES: usize = 32;
+//uncompressed bytes in each strip
+const STRIP_SIZE: usize = 64 * 1024;
+
+//tiff field types
+const ASCII: u16 = 2;
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const RATIONAL: u16 = 5;
+const DOUBLE: u16 = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeoTiffProjection {
+    WebMercator,
+    Wgs84,
+    //the zone is picked from the middle of the export
+    Utm,
+}
+
+impl GeoTiffProjection {
+    pub const ALL: [GeoTiffProjection; 3] = [
+        GeoTiffProjection::WebMercator,
+        GeoTiffProjection::Wgs84,
+        GeoTiffProjection::Utm,
+    ];
+}
+
+impl fmt::Display for GeoTiffProjection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            GeoTiffProjection::WebMercator => "EPSG:3857",
+            GeoTiffProjection::Wgs84 => "EPSG:4326",
+            GeoTiffProjection::Utm => "UTM",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+//a projection with its parameters filled in
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Crs {
+    WebMercator,
+    Wgs84,
+    Utm { zone: u8, north: bool },
+}
+
+impl Crs {
+    fn new(projection: GeoTiffProjection, bounds: &Bounds) -> Self {
+        match projection {
+            GeoTiffProjection::WebMercator => Crs::WebMercator,
+            GeoTiffProjection::Wgs84 => Crs::Wgs84,
+            GeoTiffProjection::Utm => {
+                //plain 6 degree zones, without the exceptions around norway and svalbard
+                let center = bounds.center();
+                let zone = ((center.lon + 180.0) / 6.0).floor() as i64 + 1;
+                Crs::Utm {
+                    zone: zone.max(1).min(60) as u8,
+                    north: center.lat >= 0.0,
+                }
+            }
+        }
+    }
+
+    fn epsg(&self) -> u16 {
+        match self {
+            Crs::WebMercator => 3857,
+            Crs::Wgs84 => 4326,
+            Crs::Utm { zone, north: true } => 32600 + *zone as u16,
+            Crs::Utm { zone, north: false } => 32700 + *zone as u16,
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Crs::WebMercator => String::from("WGS 84 / Pseudo-Mercator"),
+            Crs::Wgs84 => String::from("WGS 84"),
+            Crs::Utm { zone, north } => {
+                format!(
+                    "WGS 84 / UTM zone {}{}",
+                    zone,
+                    if *north { 'N' } else { 'S' }
+                )
+            }
+        }
+    }
+
+    //x and y in the projection's units, meters or degrees
+    fn forward(&self, point: &LatLon) -> (f64, f64) {
+        match self {
+            Crs::WebMercator => mercator_forward(point),
+            Crs::Wgs84 => (point.lon, point.lat),
+            Crs::Utm { zone, north } => utm_forward(point, *zone, *north),
+        }
+    }
+
+    fn inverse(&self, xy: (f64, f64)) -> LatLon {
+        match self {
+            Crs::WebMercator => mercator_inverse(xy),
+            Crs::Wgs84 => LatLon::new(xy.1, xy.0),
+            Crs::Utm { zone, north } => utm_inverse(xy, *zone, *north),
+        }
+    }
+}
+
+fn mercator_forward(point: &LatLon) -> (f64, f64) {
+    let lat = point
+        .lat
+        .max(-85.051_128_78)
+        .min(85.051_128_78)
+        .to_radians();
+    (
+        SEMI_MAJOR_AXIS * point.lon.to_radians(),
+        SEMI_MAJOR_AXIS * (PI / 4.0 + lat / 2.0).tan().ln(),
+    )
+}
+
+fn mercator_inverse(xy: (f64, f64)) -> LatLon {
+    LatLon::new(
+        (xy.1 / SEMI_MAJOR_AXIS).sinh().atan().to_degrees(),
+        (xy.0 / SEMI_MAJOR_AXIS).to_degrees(),
+    )
+}
+
+//the series coefficients of the Krüger transverse mercator, good to well under a
+//millimeter inside a zone
+struct Kruger {
+    //scaled radius of the rectifying sphere
+    scale: f64,
+    n: f64,
+    alpha: [f64; 3],
+    beta: [f64; 3],
+    delta: [f64; 3],
+}
+
+impl Kruger {
+    fn wgs84() -> Self {
+        let n = FLATTENING / (2.0 - FLATTENING);
+        let (n2, n3) = (n * n, n * n * n);
+        Self {
+            scale: UTM_SCALE * SEMI_MAJOR_AXIS / (1.0 + n) * (1.0 + n2 / 4.0 + n2 * n2 / 64.0),
+            n,
+            alpha: [
+                n / 2.0 - 2.0 * n2 / 3.0 + 5.0 * n3 / 16.0,
+                13.0 * n2 / 48.0 - 3.0 * n3 / 5.0,
+                61.0 * n3 / 240.0,
+            ],
+            beta: [
+                n / 2.0 - 2.0 * n2 / 3.0 + 37.0 * n3 / 96.0,
+                n2 / 48.0 + n3 / 15.0,
+                17.0 * n3 / 480.0,
+            ],
+            delta: [
+                2.0 * n - 2.0 * n2 / 3.0 - 2.0 * n3,
+                7.0 * n2 / 3.0 - 8.0 * n3 / 5.0,
+                56.0 * n3 / 15.0,
+            ],
+        }
+    }
+}
+
+fn central_meridian(zone: u8) -> f64 {
+    (zone as f64 * 6.0 - 183.0).to_radians()
+}
+
+fn utm_forward(point: &LatLon, zone: u8, north: bool) -> (f64, f64) {
+    let k = Kruger::wgs84();
+    let lat = point.lat.to_radians();
+    let lon = point.lon.to_radians() - central_meridian(zone);
+    let e = 2.0 * k.n.sqrt() / (1.0 + k.n);
+    let t = (lat.sin().atanh() - e * (e * lat.sin()).atanh()).sinh();
+    let xi = t.atan2(lon.cos());
+    let eta = (lon.sin() / (1.0 + t * t).sqrt()).atanh();
+    let (mut x, mut y) = (eta, xi);
+    for (idx, alpha) in k.alpha.iter().enumerate() {
+        let j = 2.0 * (idx + 1) as f64;
+        x += alpha * (j * xi).cos() * (j * eta).sinh();
+        y += alpha * (j * xi).sin() * (j * eta).cosh();
+    }
+    let false_northing = if north { 0.0 } else { UTM_FALSE_NORTHING_SOUTH };
+    (
+        UTM_FALSE_EASTING + k.scale * x,
+        false_northing + k.scale * y,
+    )
+}
+
+fn utm_inverse(xy: (f64, f64), zone: u8, north: bool) -> LatLon {
+    let k = Kruger::wgs84();
+    let false_northing = if north { 0.0 } else { UTM_FALSE_NORTHING_SOUTH };
+    let xi = (xy.1 - false_northing) / k.scale;
+    let eta = (xy.0 - UTM_FALSE_EASTING) / k.scale;
+    let (mut xi_prime, mut eta_prime) = (xi, eta);
+    for (idx, beta) in k.beta.iter().enumerate() {
+        let j = 2.0 * (idx + 1) as f64;
+        xi_prime -= beta * (j * xi).sin() * (j * eta).cosh();
+        eta_prime -= beta * (j * xi).cos() * (j * eta).sinh();
+    }
+    let chi = (xi_prime.sin() / eta_prime.cosh()).asin();
+    let mut lat = chi;
+    for (idx, delta) in k.delta.iter().enumerate() {
+        lat += delta * (2.0 * (idx + 1) as f64 * chi).sin();
+    }
+    let lon = central_meridian(zone) + eta_prime.sinh().atan2(xi_prime.cos());
+    LatLon::new(lat.to_degrees(), lon.to_degrees())
+}
+
+//an image placed in a projection: the x, y of its top left corner and the size of a
+//pixel, in the projection's units
+struct Placed {
+    image: RgbaImage,
+    origin: (f64, f64),
+    pixel_size: (f64, f64),
+}
+
+/// Writes `image`, a render of `export`, as a GeoTIFF in `projection`.
+pub fn write_geotiff(
+    path: &Path,
+    export: &RasterExport,
+    image: RgbaImage,
+    projection: GeoTiffProjection,
+) -> Result<(), ExportError> {
+    let bounds = &export.bounds;
+    let top_left = mercator_forward(&LatLon::new(bounds.max.lat, bounds.min.lon));
+    let bottom_right = mercator_forward(&LatLon::new(bounds.min.lat, bounds.max.lon));
+    let pixel_size = (
+        (bottom_right.0 - top_left.0) / image.width() as f64,
+        (top_left.1 - bottom_right.1) / image.height() as f64,
+    );
+    let mercator = Placed {
+        image,
+        origin: top_left,
+        pixel_size,
+    };
+    let crs = Crs::new(projection, bounds);
+    let placed = match crs {
+        Crs::WebMercator => mercator,
+        _ => reproject(&mercator, &crs),
+    };
+    log::info!(
+        "geotiff in {} is {}x{}",
+        crs.name(),
+        placed.image.width(),
+        placed.image.height()
+    );
+    std::fs::write(path, encode(&placed, &crs, export.dpi())?)?;
+    Ok(())
+}
+
+//resamples a web mercator image onto a grid in `crs` with square pixels
+fn reproject(source: &Placed, crs: &Crs) -> Placed {
+    let (width, height) = source.image.dimensions();
+    let source_point = |u: f64, v: f64| {
+        mercator_inverse((
+            source.origin.0 + u * width as f64 * source.pixel_size.0,
+            source.origin.1 - v * height as f64 * source.pixel_size.1,
+        ))
+    };
+    //the edges of a mercator rectangle bend in other projections, so walk them
+    let mut min = (f64::INFINITY, f64::INFINITY);
+    let mut max = (f64::NEG_INFINITY, f64::NEG_INFINITY);
+    for idx in 0..=EDGE_SAMPLES {
+        let t = idx as f64 / EDGE_SAMPLES as f64;
+        for (u, v) in [(t, 0.0), (t, 1.0), (0.0, t), (1.0, t)].iter() {
+            let (x, y) = crs.forward(&source_point(*u, *v));
+            min = (min.0.min(x), min.1.min(y));
+            max = (max.0.max(x), max.1.max(y));
+        }
+    }
+    //about as many pixels as the render had
+    let area = (max.0 - min.0) * (max.1 - min.1);
+    let pixel_size = (area / (width as f64 * height as f64)).sqrt();
+    let out_width = ((max.0 - min.0) / pixel_size).ceil().max(1.0) as u32;
+    let out_height = ((max.1 - min.1) / pixel_size).ceil().max(1.0) as u32;
+
+    let mut image = RgbaImage::new(out_width, out_height);
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let target = (
+            min.0 + (x as f64 + 0.5) * pixel_size,
+            max.1 - (y as f64 + 0.5) * pixel_size,
+        );
+        let (mx, my) = mercator_forward(&crs.inverse(target));
+        let u = (mx - source.origin.0) / source.pixel_size.0 - 0.5;
+        let v = (source.origin.1 - my) / source.pixel_size.1 - 0.5;
+        if let Some(sample) = sample_bilinear(&source.image, u, v) {
+            *pixel = sample;
+        }
+    }
+    Placed {
+        image,
+        origin: (min.0, max.1),
+        pixel_size: (pixel_size, pixel_size),
+    }
+}
+
+//`u`, `v` are pixel coordinates with pixel centers on whole numbers
+fn sample_bilinear(image: &RgbaImage, u: f64, v: f64) -> Option<Rgba<u8>> {
+    let (width, height) = (image.width() as f64, image.height() as f64);
+    if u < -0.5 || v < -0.5 || u > width - 0.5 || v > height - 0.5 {
+        return None;
+    }
+    let u = u.max(0.0).min(width - 1.0);
+    let v = v.max(0.0).min(height - 1.0);
+    let (x0, y0) = (u.floor() as u32, v.floor() as u32);
+    let (x1, y1) = (
+        (x0 + 1).min(image.width() - 1),
+        (y0 + 1).min(image.height() - 1),
+    );
+    let (fx, fy) = (u - x0 as f64, v - y0 as f64);
+    let mut out = [0u8; 4];
+    for (channel, value) in out.iter_mut().enumerate() {
+        let at = |x: u32, y: u32| image.get_pixel(x, y)[channel] as f64;
+        let top = at(x0, y0) * (1.0 - fx) + at(x1, y0) * fx;
+        let bottom = at(x0, y1) * (1.0 - fx) + at(x1, y1) * fx;
+        *value = (top * (1.0 - fy) + bottom * fy).round() as u8;
+    }
+    Some(Rgba(out))
+}
+
+enum TagValue {
+    Ascii(String),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Rational(Vec<(u32, u32)>),
+    Double(Vec<f64>),
+}
+
+impl TagValue {
+    //field type, count and the value's little endian bytes
+    fn encode(&self) -> (u16, u32, Vec<u8>) {
+        let mut bytes = Vec::new();
+        match self {
+            TagValue::Ascii(text) => {
+                bytes.extend_from_slice(text.as_bytes());
+                bytes.push(0);
+                (ASCII, bytes.len() as u32, bytes)
+            }
+            TagValue::Short(values) => {
+                values
+                    .iter()
+                    .for_each(|value| bytes.extend_from_slice(&value.to_le_bytes()));
+                (SHORT, values.len() as u32, bytes)
+            }
+            TagValue::Long(values) => {
+                values
+                    .iter()
+                    .for_each(|value| bytes.extend_from_slice(&value.to_le_bytes()));
+                (LONG, values.len() as u32, bytes)
+            }
+            TagValue::Rational(values) => {
+                for (numerator, denominator) in values {
+                    bytes.extend_from_slice(&numerator.to_le_bytes());
+                    bytes.extend_from_slice(&denominator.to_le_bytes());
+                }
+                (RATIONAL, values.len() as u32, bytes)
+            }
+            TagValue::Double(values) => {
+                values
+                    .iter()
+                    .for_each(|value| bytes.extend_from_slice(&value.to_le_bytes()));
+                (DOUBLE, values.len() as u32, bytes)
+            }
+        }
+    }
+}
+
+//the GeoKeyDirectory, its ascii params and the model tags placing the image
+fn geo_tags(placed: &Placed, crs: &Crs) -> Vec<(u16, TagValue)> {
+    let citation = format!("{}|", crs.name());
+    //key id, tiff tag holding the value (0 when it's inline), count, value
+    let mut keys: Vec<[u16; 4]> = vec![
+        //GTModelTypeGeoKey, projected or geographic
+        [1024, 0, 1, if *crs == Crs::Wgs84 { 2 } else { 1 }],
+        //GTRasterTypeGeoKey, RasterPixelIsArea
+        [1025, 0, 1, 1],
+        //GTCitationGeoKey
+        [1026, 34737, citation.len() as u16, 0],
+    ];
+    if *crs == Crs::Wgs84 {
+        //GeographicTypeGeoKey and GeogAngularUnitsGeoKey in degrees
+        keys.push([2048, 0, 1, crs.epsg()]);
+        keys.push([2054, 0, 1, 9102]);
+    } else {
+        //ProjectedCSTypeGeoKey and ProjLinearUnitsGeoKey in meters
+        keys.push([3072, 0, 1, crs.epsg()]);
+        keys.push([3076, 0, 1, 9001]);
+    }
+    let mut directory = vec![1, 1, 0, keys.len() as u16];
+    for key in keys {
+        directory.extend_from_slice(&key);
+    }
+
+    vec![
+        (
+            33550,
+            TagValue::Double(vec![placed.pixel_size.0, placed.pixel_size.1, 0.0]),
+        ),
+        (
+            33922,
+            TagValue::Double(vec![0.0, 0.0, 0.0, placed.origin.0, placed.origin.1, 0.0]),
+        ),
+        (34735, TagValue::Short(directory)),
+        (34737, TagValue::Ascii(citation)),
+    ]
+}
+
+//a little endian tiff with deflated RGBA strips, then the out of line tag values, then
+//the one IFD
+fn encode(placed: &Placed, crs: &Crs, dpi: f32) -> Result<Vec<u8>, ExportError> {
+    let image = &placed.image;
+    let row_bytes = image.width() as usize * 4;
+    let rows_per_strip = (STRIP_SIZE / row_bytes).max(1);
+
+    let to_u32 = |value: usize| -> Result<u32, ExportError> {
+        if value > u32::MAX as usize {
+            Err(ExportError::TooLarge)
+        } else {
+            Ok(value as u32)
+        }
+    };
+    let mut out: Vec<u8> = vec![b'I', b'I', 42, 0, 0, 0, 0, 0];
+    let mut strip_offsets = Vec::new();
+    let mut strip_counts = Vec::new();
+    for strip in image.as_raw().chunks(rows_per_strip * row_bytes) {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(strip)?;
+        let compressed = encoder.finish()?;
+        strip_offsets.push(to_u32(out.len())?);
+        strip_counts.push(to_u32(compressed.len())?);
+        out.extend_from_slice(&compressed);
+    }
+
+    let dpi = (dpi.round() as u32).max(1);
+    let mut tags: Vec<(u16, TagValue)> = vec![
+        (256, TagValue::Long(vec![image.width()])),
+        (257, TagValue::Long(vec![image.height()])),
+        (258, TagValue::Short(vec![8, 8, 8, 8])),
+        //adobe deflate
+        (259, TagValue::Short(vec![8])),
+        //rgb
+        (262, TagValue::Short(vec![2])),
+        (273, TagValue::Long(strip_offsets)),
+        (277, TagValue::Short(vec![4])),
+        (278, TagValue::Long(vec![rows_per_strip as u32])),
+        (279, TagValue::Long(strip_counts)),
+        (282, TagValue::Rational(vec![(dpi, 1)])),
+        (283, TagValue::Rational(vec![(dpi, 1)])),
+        //chunky, rgbargba...
+        (284, TagValue::Short(vec![1])),
+        //inches
+        (296, TagValue::Short(vec![2])),
+        //unassociated alpha
+        (338, TagValue::Short(vec![2])),
+    ];
+    tags.extend(geo_tags(placed, crs));
+    tags.sort_by_key(|(tag, _)| *tag);
+
+    //values over four bytes go before the IFD, word aligned
+    let mut entries = Vec::new();
+    for (tag, value) in &tags {
+        let (field_type, count, bytes) = value.encode();
+        let field = if bytes.len() <= 4 {
+            let mut inline = bytes.clone();
+            inline.resize(4, 0);
+            inline
+        } else {
+            if out.len() % 2 == 1 {
+                out.push(0);
+            }
+            let offset = to_u32(out.len())?;
+            out.extend_from_slice(&bytes);
+            offset.to_le_bytes().to_vec()
+        };
+        entries.push((*tag, field_type, count, field));
+    }
+    if out.len() % 2 == 1 {
+        out.push(0);
+    }
+    let ifd = to_u32(out.len())?;
+    out[4..8].copy_from_slice(&ifd.to_le_bytes());
+    out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    for (tag, field_type, count, field) in entries {
+        out.extend_from_slice(&tag.to_le_bytes());
+        out.extend_from_slice(&field_type.to_le_bytes());
+        out.extend_from_slice(&count.to_le_bytes());
+        out.extend_from_slice(&field);
+    }
+    //no next IFD
+    out.extend_from_slice(&0u32.to_le_bytes());
+    to_u32(out.len())?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+    use std::collections::HashMap;
+    use std::io::Read;
+
+    //the tags of a little endian tiff's first IFD, as their type and value bytes
+    fn read_tags(file: &[u8]) -> HashMap<u16, (u16, Vec<u8>)> {
+        let u16_at = |at: usize| u16::from_le_bytes([file[at], file[at + 1]]);
+        let u32_at =
+            |at: usize| u32::from_le_bytes([file[at], file[at + 1], file[at + 2], file[at + 3]]);
+        assert_eq!(&file[..4], b"II\x2a\x00");
+        let ifd = u32_at(4) as usize;
+        (0..u16_at(ifd) as usize)
+            .map(|idx| {
+                let entry = ifd + 2 + idx * 12;
+                let field_type = u16_at(entry + 2);
+                let size = match field_type {
+                    ASCII => 1,
+                    SHORT => 2,
+                    LONG => 4,
+                    _ => 8,
+                };
+                let length = size * u32_at(entry + 4) as usize;
+                let start = if length <= 4 {
+                    entry + 8
+                } else {
+                    u32_at(entry + 8) as usize
+                };
+                (
+                    u16_at(entry),
+                    (field_type, file[start..start + length].to_vec()),
+                )
+            })
+            .collect()
+    }
+
+    fn longs(value: &(u16, Vec<u8>)) -> Vec<u32> {
+        value
+            .1
+            .chunks(4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect()
+    }
+
+    fn doubles(value: &(u16, Vec<u8>)) -> Vec<f64> {
+        value
+            .1
+            .chunks(8)
+            .map(|bytes| {
+                let mut array = [0; 8];
+                array.copy_from_slice(bytes);
+                f64::from_le_bytes(array)
+            })
+            .collect()
+    }
+
+    fn export() -> RasterExport {
+        let mut bounds = Bounds::new(LatLon::new(46.0, 7.0));
+        bounds.extend(&LatLon::new(46.5, 7.5));
+        RasterExport::new(bounds, 10, 192.0)
+    }
+
+    fn written(image: RgbaImage, projection: GeoTiffProjection, name: &str) -> Vec<u8> {
+        let path =
+            std::env::temp_dir().join(format!("map_maker_{}_{}.tif", std::process::id(), name));
+        write_geotiff(&path, &export(), image, projection).unwrap();
+        let file = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        file
+    }
+
+    #[test]
+    fn projections_round_trip() {
+        let point = LatLon::new(46.55, 7.98);
+        let crs = Crs::new(GeoTiffProjection::Utm, &export().bounds);
+        assert_eq!(crs.epsg(), 32632);
+        //about a millimeter, what the utm series are good to
+        for crs in [Crs::WebMercator, Crs::Wgs84, crs].iter() {
+            let back = crs.inverse(crs.forward(&point));
+            assert!((back.lat - point.lat).abs() < 1e-8, "{:?}", crs);
+            assert!((back.lon - point.lon).abs() < 1e-8, "{:?}", crs);
+        }
+        let mut south = Bounds::new(LatLon::new(-34.0, 18.0));
+        south.extend(&LatLon::new(-33.5, 18.5));
+        assert_eq!(Crs::new(GeoTiffProjection::Utm, &south).epsg(), 32734);
+    }
+
+    #[test]
+    fn utm_matches_known_coordinates() {
+        //the central meridian of a zone runs through 500 km east
+        let (x, y) = utm_forward(&LatLon::new(0.0, 9.0), 32, true);
+        assert!((x - 500_000.0).abs() < 1e-6 && y.abs() < 1e-6);
+        let (x, y) = utm_forward(&LatLon::new(0.0, 0.0), 31, true);
+        assert!((x - 166_021.443).abs() < 0.01, "{}", x);
+        assert!(y.abs() < 1e-6);
+        let (_, y) = utm_forward(&LatLon::new(-0.000_001, 9.0), 32, false);
+        assert!((y - UTM_FALSE_NORTHING_SOUTH).abs() < 1.0);
+        let (x, _) = mercator_forward(&LatLon::new(0.0, 180.0));
+        assert!((x - 20_037_508.343).abs() < 0.001);
+    }
+
+    #[test]
+    fn mercator_renders_are_written_as_they_are() {
+        let image = RgbaImage::from_fn(300, 200, |x, y| Rgba([x as u8, y as u8, 7, 255]));
+        let file = written(image.clone(), GeoTiffProjection::WebMercator, "mercator");
+        let tags = read_tags(&file);
+
+        assert_eq!(longs(&tags[&256]), [300]);
+        assert_eq!(longs(&tags[&257]), [200]);
+        let tiepoint = doubles(&tags[&33922]);
+        let (west, north) = mercator_forward(&LatLon::new(46.5, 7.0));
+        assert!((tiepoint[3] - west).abs() < 1e-6 && (tiepoint[4] - north).abs() < 1e-6);
+        //GeoKeyDirectory holds ProjectedCSTypeGeoKey for 3857
+        let keys: Vec<u16> = tags[&34735]
+            .1
+            .chunks(2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect();
+        assert!(keys.chunks(4).any(|key| key == [3072, 0, 1, 3857]));
+        assert_eq!(tags[&34737].1, b"WGS 84 / Pseudo-Mercator|\0");
+
+        let mut pixels = Vec::new();
+        for (offset, count) in longs(&tags[&273]).into_iter().zip(longs(&tags[&279])) {
+            let strip = &file[offset as usize..(offset + count) as usize];
+            ZlibDecoder::new(strip).read_to_end(&mut pixels).unwrap();
+        }
+        assert_eq!(pixels, image.into_raw());
+    }
+
+    #[test]
+    fn reprojected_renders_cover_the_export() {
+        let image = RgbaImage::from_pixel(300, 200, Rgba([10, 20, 30, 255]));
+        let file = written(image, GeoTiffProjection::Wgs84, "wgs84");
+        let tags = read_tags(&file);
+
+        let tiepoint = doubles(&tags[&33922]);
+        let scale = doubles(&tags[&33550]);
+        let (width, height) = (longs(&tags[&256])[0], longs(&tags[&257])[0]);
+        let east = tiepoint[3] + width as f64 * scale[0];
+        let south = tiepoint[4] - height as f64 * scale[1];
+        for (found, expected) in [
+            (tiepoint[3], 7.0),
+            (tiepoint[4], 46.5),
+            (east, 7.5),
+            (south, 46.0),
+        ]
+        .iter()
+        {
+            assert!(
+                (found - expected).abs() <= scale[0],
+                "{} {}",
+                found,
+                expected
+            );
+        }
+    }
+}
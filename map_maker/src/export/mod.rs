@@ -0,0 +1,22 @@
+//turning the map into files meant to be looked at rather than edited
+pub mod geotiff;
+pub mod page;
+pub mod pdf;
+pub mod png;
+pub mod raster;
+pub mod svg;
+pub mod text;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("image error")]
+    Image(#[from] image::ImageError),
+    #[error("png error")]
+    Png(#[from] ::png::EncodingError),
+    #[error("image too large for a tiff")]
+    TooLarge,
+}
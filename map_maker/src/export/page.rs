@@ -0,0 +1,1363 @@
+//lays a map out on a sheet of paper at a fixed scale, as a list of things to draw that
+//the pdf and svg writers turn into their own vector operations
+//
+//everything on the page is in millimeters from the top left corner of the sheet, y down
+use crate::export::raster::RasterExport;
+use crate::export::text;
+use crate::features::label::{layout_labels, Label};
+use crate::features::{shown_labels, DrawingLayer, Feature, FeatureStyle, Geometry};
+use crate::geo::{lat_lon_to_world_pixel, world_pixel_to_lat_lon, Bounds, LatLon, MapView};
+use crate::tile_manager::tile_manager::TileSnapshot;
+use ab_glyph::FontVec;
+use iced::Color;
+use image::RgbaImage;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fmt;
+
+pub const MM_PER_INCH: f64 = 25.4;
+//size of a screen pixel on paper, styles are in screen pixels
+pub const MM_PER_PIXEL: f64 = MM_PER_INCH / 96.0;
+const EARTH_CIRCUMFERENCE: f64 = 2.0 * PI * 6_378_137.0;
+//tiles are fetched deep enough to give at least this resolution on paper
+const PRINT_DPI: f64 = 200.0;
+const MAX_TILE_ZOOM: u8 = 19;
+//how far an item can be shrunk or grown on the page
+const MIN_ITEM_SCALE: f64 = 0.25;
+const MAX_ITEM_SCALE: f64 = 4.0;
+const MARGIN: f64 = 12.0;
+//room for the title above the map, when there is one
+const HEADER: f64 = 12.0;
+//room between the frame and the margin for the grid tick labels
+const TICK_SPACE_SIDE: f64 = 13.0;
+const TICK_SPACE: f64 = 5.0;
+const TICK_LENGTH: f64 = 1.5;
+//band under the map for the scale bar, north arrow, legend and attribution
+const FOOTER: f64 = 26.0;
+const CIRCLE_SEGMENTS: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaperSize {
+    A4,
+    A3,
+    Letter,
+}
+
+impl PaperSize {
+    pub const ALL: [PaperSize; 3] = [PaperSize::A4, PaperSize::A3, PaperSize::Letter];
+
+    /// Width and height in portrait, millimeters.
+    pub fn size(&self) -> (f64, f64) {
+        match self {
+            PaperSize::A4 => (210.0, 297.0),
+            PaperSize::A3 => (297.0, 420.0),
+            PaperSize::Letter => (215.9, 279.4),
+        }
+    }
+}
+
+impl fmt::Display for PaperSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PaperSize::A4 => "A4",
+            PaperSize::A3 => "A3",
+            PaperSize::Letter => "Letter",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The denominator of a map scale, 25000 is 1:25 000.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapScale(pub u32);
+
+impl MapScale {
+    pub const COMMON: [MapScale; 8] = [
+        MapScale(5_000),
+        MapScale(10_000),
+        MapScale(25_000),
+        MapScale(50_000),
+        MapScale(100_000),
+        MapScale(250_000),
+        MapScale(500_000),
+        MapScale(1_000_000),
+    ];
+}
+
+impl fmt::Display for MapScale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        //digits in groups of three, the way scales are printed on maps
+        let digits = self.0.to_string();
+        let mut grouped = String::new();
+        for (idx, digit) in digits.chars().enumerate() {
+            if idx > 0 && (digits.len() - idx) % 3 == 0 {
+                grouped.push(' ');
+            }
+            grouped.push(digit);
+        }
+        write!(f, "1:{}", grouped)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Rect {
+    pub fn contains(&self, point: (f64, f64)) -> bool {
+        point.0 >= self.x
+            && point.0 <= self.x + self.width
+            && point.1 >= self.y
+            && point.1 <= self.y + self.height
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextAnchor {
+    Start,
+    Middle,
+    End,
+}
+
+/// One thing drawn on the page.
+pub enum PageItem {
+    Image {
+        image: RgbaImage,
+        rect: Rect,
+    },
+    //rings are filled even-odd, so holes work
+    Path {
+        rings: Vec<Vec<(f64, f64)>>,
+        closed: bool,
+        fill: Option<Color>,
+        //color and width in millimeters
+        stroke: Option<(Color, f64)>,
+    },
+    //`position` is on the baseline, `size` is the em size, both in millimeters
+    Text {
+        text: String,
+        position: (f64, f64),
+        size: f64,
+        color: Color,
+        anchor: TextAnchor,
+        halo: Option<(Color, f64)>,
+    },
+}
+
+pub struct Page {
+    //width and height in millimeters
+    pub size: (f64, f64),
+    pub items: Vec<PageItem>,
+    //where each item that can be moved ended up, the map first
+    pub regions: Vec<(LayoutItem, Rect)>,
+    //how many of the items are the map, the rest are decorations
+    map_items: usize,
+}
+
+impl Page {
+    /// Swaps the title, scale bar and so on for ones laid out again, leaving the map.
+    pub fn decorate(&mut self, decorations: Vec<Decoration>) {
+        self.items.truncate(self.map_items);
+        self.regions.truncate(1);
+        for decoration in decorations {
+            self.items.extend(decoration.items);
+            self.regions.push((decoration.item, decoration.rect));
+        }
+    }
+}
+
+/// The parts of a layout that can be dragged around the page and resized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LayoutItem {
+    Map,
+    Title,
+    ScaleBar,
+    NorthArrow,
+    Legend,
+    Attribution,
+}
+
+impl LayoutItem {
+    pub const ALL: [LayoutItem; 6] = [
+        LayoutItem::Map,
+        LayoutItem::Title,
+        LayoutItem::ScaleBar,
+        LayoutItem::NorthArrow,
+        LayoutItem::Legend,
+        LayoutItem::Attribution,
+    ];
+
+    //how it's written in a saved layout
+    fn key(&self) -> &'static str {
+        match self {
+            LayoutItem::Map => "map",
+            LayoutItem::Title => "title",
+            LayoutItem::ScaleBar => "scale_bar",
+            LayoutItem::NorthArrow => "north_arrow",
+            LayoutItem::Legend => "legend",
+            LayoutItem::Attribution => "attribution",
+        }
+    }
+}
+
+/// How far an item was dragged from where the layout puts it, and how much it was grown
+/// or shrunk about its top left corner.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Placement {
+    //millimeters
+    pub offset: (f64, f64),
+    pub scale: f64,
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Self {
+            offset: (0.0, 0.0),
+            scale: 1.0,
+        }
+    }
+}
+
+impl Placement {
+    fn point(&self, origin: (f64, f64), point: (f64, f64)) -> (f64, f64) {
+        (
+            origin.0 + self.offset.0 + (point.0 - origin.0) * self.scale,
+            origin.1 + self.offset.1 + (point.1 - origin.1) * self.scale,
+        )
+    }
+
+    fn rect(&self, rect: &Rect) -> Rect {
+        Rect {
+            x: rect.x + self.offset.0,
+            y: rect.y + self.offset.1,
+            width: rect.width * self.scale,
+            height: rect.height * self.scale,
+        }
+    }
+
+    //moves and scales everything drawn for an item that was laid out in `rect`
+    fn apply(&self, items: &mut [PageItem], rect: &Rect) {
+        let origin = (rect.x, rect.y);
+        for item in items {
+            match item {
+                PageItem::Image { rect, .. } => *rect = self.rect(rect),
+                PageItem::Path { rings, stroke, .. } => {
+                    for point in rings.iter_mut().flatten() {
+                        *point = self.point(origin, *point);
+                    }
+                    if let Some((_, width)) = stroke {
+                        *width *= self.scale;
+                    }
+                }
+                PageItem::Text {
+                    position,
+                    size,
+                    halo,
+                    ..
+                } => {
+                    *position = self.point(origin, *position);
+                    *size *= self.scale;
+                    if let Some((_, width)) = halo {
+                        *width *= self.scale;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The items drawn for one of the movable parts of the page around the map.
+pub struct Decoration {
+    pub item: LayoutItem,
+    pub items: Vec<PageItem>,
+    //what the item takes up on the page
+    pub rect: Rect,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GridStyle {
+    None,
+    //short ticks on the outside of the frame
+    Ticks,
+    //ticks plus lines across the map
+    Lines,
+}
+
+impl GridStyle {
+    pub const ALL: [GridStyle; 3] = [GridStyle::None, GridStyle::Ticks, GridStyle::Lines];
+
+    //how it's written in a saved layout
+    fn key(&self) -> &'static str {
+        match self {
+            GridStyle::None => "none",
+            GridStyle::Ticks => "ticks",
+            GridStyle::Lines => "lines",
+        }
+    }
+}
+
+impl fmt::Display for GridStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            GridStyle::None => "no grid",
+            GridStyle::Ticks => "grid ticks",
+            GridStyle::Lines => "grid lines",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A printed page: a map frame at a fixed scale around `center`, and everything placed
+/// around it. Kept with the project and turned into a [`Page`] for the pdf and svg
+/// writers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrintLayout {
+    pub paper: PaperSize,
+    pub landscape: bool,
+    pub scale: MapScale,
+    //middle of the map frame
+    pub center: LatLon,
+    //left off the page when empty
+    pub title: String,
+    //typed in credits, the visible tile layers' own are used when it's empty
+    pub attribution: String,
+    pub legend: bool,
+    pub scale_bar: bool,
+    pub north_arrow: bool,
+    pub grid: GridStyle,
+    //items that were dragged or resized, the rest stay where the layout puts them
+    pub placements: HashMap<LayoutItem, Placement>,
+}
+
+impl Default for PrintLayout {
+    fn default() -> Self {
+        Self {
+            paper: PaperSize::A4,
+            landscape: false,
+            scale: MapScale(25_000),
+            center: LatLon::new(0.0, 0.0),
+            title: String::new(),
+            attribution: String::new(),
+            legend: true,
+            scale_bar: true,
+            north_arrow: true,
+            grid: GridStyle::Ticks,
+            placements: HashMap::new(),
+        }
+    }
+}
+
+impl PrintLayout {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "paper": self.paper.to_string(),
+            "landscape": self.landscape,
+            "scale": self.scale.0,
+            "center": [self.center.lon, self.center.lat],
+            "title": self.title,
+            "attribution": self.attribution,
+            "legend": self.legend,
+            "scale_bar": self.scale_bar,
+            "north_arrow": self.north_arrow,
+            "grid": self.grid.key(),
+            "placements": self
+                .placements
+                .iter()
+                .map(|(item, placement)| {
+                    let value = json!({
+                        "offset": [placement.offset.0, placement.offset.1],
+                        "scale": placement.scale,
+                    });
+                    (item.key().to_string(), value)
+                })
+                .collect::<serde_json::Map<String, Value>>(),
+        })
+    }
+
+    /// Reads a layout written by `to_json`. Anything missing or unreadable keeps its
+    /// default, so older saves still open.
+    pub fn from_json(value: &Value) -> Self {
+        let mut layout = Self::default();
+        let text = |key: &str| value.get(key).and_then(Value::as_str);
+        let flag = |key: &str| value.get(key).and_then(Value::as_bool);
+        if let Some(paper) = text("paper") {
+            match PaperSize::ALL.iter().find(|size| size.to_string() == paper) {
+                Some(size) => layout.paper = *size,
+                None => log::warn!("unknown paper size {}", paper),
+            }
+        }
+        if let Some(grid) = text("grid") {
+            match GridStyle::ALL.iter().find(|style| style.key() == grid) {
+                Some(style) => layout.grid = *style,
+                None => log::warn!("unknown grid style {}", grid),
+            }
+        }
+        if let Some(scale) = value.get("scale").and_then(Value::as_u64) {
+            if scale > 0 && scale <= u32::MAX as u64 {
+                layout.scale = MapScale(scale as u32);
+            }
+        }
+        if let Some(center) = value.get("center").and_then(Value::as_array) {
+            let lon = center.get(0).and_then(Value::as_f64);
+            let lat = center.get(1).and_then(Value::as_f64);
+            if let (Some(lon), Some(lat)) = (lon, lat) {
+                layout.center = LatLon::new(lat, lon);
+            }
+        }
+        if let Some(title) = text("title") {
+            layout.title = title.to_string();
+        }
+        if let Some(attribution) = text("attribution") {
+            layout.attribution = attribution.to_string();
+        }
+        layout.landscape = flag("landscape").unwrap_or(layout.landscape);
+        layout.legend = flag("legend").unwrap_or(layout.legend);
+        layout.scale_bar = flag("scale_bar").unwrap_or(layout.scale_bar);
+        layout.north_arrow = flag("north_arrow").unwrap_or(layout.north_arrow);
+        if let Some(placements) = value.get("placements").and_then(Value::as_object) {
+            for (key, placement) in placements {
+                let item = match LayoutItem::ALL.iter().find(|item| item.key() == key) {
+                    Some(item) => *item,
+                    None => {
+                        log::warn!("unknown layout item {}", key);
+                        continue;
+                    }
+                };
+                let number = |idx: usize| placement["offset"].get(idx).and_then(Value::as_f64);
+                let scale = placement["scale"].as_f64().unwrap_or(1.0);
+                if let (Some(x), Some(y)) = (number(0), number(1)) {
+                    let placement = Placement {
+                        offset: (x, y),
+                        scale: scale.clamp(MIN_ITEM_SCALE, MAX_ITEM_SCALE),
+                    };
+                    layout.placements.insert(item, placement);
+                }
+            }
+        }
+        layout
+    }
+
+    pub fn placement(&self, item: LayoutItem) -> Placement {
+        self.placements.get(&item).copied().unwrap_or_default()
+    }
+
+    /// Drags an item `by` millimeters.
+    pub fn move_item(&mut self, item: LayoutItem, by: (f64, f64)) {
+        let placement = self.placements.entry(item).or_default();
+        placement.offset.0 += by.0;
+        placement.offset.1 += by.1;
+    }
+
+    /// Grows or shrinks an item by `factor`, within limits.
+    pub fn resize_item(&mut self, item: LayoutItem, factor: f64) {
+        let placement = self.placements.entry(item).or_default();
+        placement.scale = (placement.scale * factor).clamp(MIN_ITEM_SCALE, MAX_ITEM_SCALE);
+    }
+
+    pub fn page_size(&self) -> (f64, f64) {
+        let (width, height) = self.paper.size();
+        if self.landscape {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    fn has_title(&self) -> bool {
+        !self.title.trim().is_empty()
+    }
+
+    /// Where the map goes on the page.
+    pub fn frame(&self) -> Rect {
+        let (width, height) = self.page_size();
+        let header = if self.has_title() { HEADER } else { 0.0 };
+        let (side, edge) = match self.grid {
+            GridStyle::None => (0.0, 0.0),
+            _ => (TICK_SPACE_SIDE, TICK_SPACE),
+        };
+        let frame = Rect {
+            x: MARGIN + side,
+            y: MARGIN + header + edge,
+            width: width - 2.0 * (MARGIN + side),
+            height: height - 2.0 * MARGIN - header - 2.0 * edge - FOOTER,
+        };
+        self.placement(LayoutItem::Map).rect(&frame)
+    }
+
+    //the band under the map, the width of the page inside the margins
+    fn footer(&self) -> Rect {
+        let (width, height) = self.page_size();
+        Rect {
+            x: MARGIN,
+            y: height - MARGIN - FOOTER + 4.0,
+            width: width - 2.0 * MARGIN,
+            height: FOOTER - 4.0,
+        }
+    }
+
+    //ground meters per world pixel at the center of the map
+    fn meters_per_pixel(&self, zoom: u8) -> f64 {
+        EARTH_CIRCUMFERENCE * self.center.lat.to_radians().cos() / crate::geo::world_size(zoom)
+    }
+
+    //frame size in world pixels at `zoom`
+    fn extent(&self, zoom: u8) -> (f64, f64) {
+        let frame = self.frame();
+        let meters_per_mm = self.scale.0 as f64 / 1000.0;
+        let per_pixel = self.meters_per_pixel(zoom);
+        (
+            frame.width * meters_per_mm / per_pixel,
+            frame.height * meters_per_mm / per_pixel,
+        )
+    }
+
+    /// The shallowest zoom whose tiles are sharp enough to print.
+    pub fn tile_zoom(&self) -> u8 {
+        let frame = self.frame();
+        let wanted = frame.width / MM_PER_INCH * PRINT_DPI;
+        (0..MAX_TILE_ZOOM)
+            .find(|zoom| self.extent(*zoom).0 >= wanted)
+            .unwrap_or(MAX_TILE_ZOOM)
+    }
+
+    //world pixel of the top left corner of the frame
+    fn top_left(&self, zoom: u8) -> (f64, f64) {
+        let center = lat_lon_to_world_pixel(&self.center, zoom);
+        let extent = self.extent(zoom);
+        (center.0 - extent.0 / 2.0, center.1 - extent.1 / 2.0)
+    }
+
+    /// The geographic extent of the map frame.
+    pub fn bounds(&self) -> Bounds {
+        let zoom = self.tile_zoom();
+        let top_left = self.top_left(zoom);
+        let extent = self.extent(zoom);
+        let mut bounds = Bounds::new(world_pixel_to_lat_lon(top_left, zoom));
+        bounds.extend(&world_pixel_to_lat_lon(
+            (top_left.0 + extent.0, top_left.1 + extent.1),
+            zoom,
+        ));
+        bounds
+    }
+
+    /// The tiles under the frame, fetch its `tile_coords` before composing.
+    pub fn raster(&self) -> RasterExport {
+        RasterExport {
+            bounds: self.bounds(),
+            zoom: self.tile_zoom(),
+            pixel_ratio: 1.0,
+        }
+    }
+
+    /// Lays out the whole page. Labels need `font` to be measured and are left out
+    /// without it.
+    pub fn compose(
+        &self,
+        snapshot: &TileSnapshot,
+        layers: &[DrawingLayer],
+        font: Option<&FontVec>,
+    ) -> Page {
+        let frame = self.frame();
+        let mut items = vec![PageItem::Image {
+            image: self.raster().stitch_tiles(snapshot),
+            rect: frame,
+        }];
+
+        let map = MapTransform::new(self);
+        for layer in layers.iter().filter(|layer| layer.is_shown()) {
+            for feature in &layer.features {
+                if layer.opacity < 1.0 {
+                    let mut feature = feature.clone();
+                    feature.style = layer.faded_style(&feature.style);
+                    feature_items(&mut items, &feature, &map, &frame);
+                } else {
+                    feature_items(&mut items, feature, &map, &frame);
+                }
+            }
+        }
+        if self.grid == GridStyle::Lines {
+            grid_line_items(&mut items, &map, &frame, &self.bounds());
+        }
+        if let Some(font) = font {
+            label_items(&mut items, layers, &map, &frame, font);
+        }
+        items.push(PageItem::Path {
+            rings: vec![rect_ring(&frame)],
+            closed: true,
+            fill: None,
+            stroke: Some((Color::BLACK, 0.3)),
+        });
+        if self.grid != GridStyle::None {
+            grid_tick_items(&mut items, &map, &frame, &self.bounds());
+        }
+
+        let mut page = Page {
+            size: self.page_size(),
+            map_items: items.len(),
+            items,
+            regions: vec![(LayoutItem::Map, frame)],
+        };
+        page.decorate(self.decorations(snapshot, layers));
+        page
+    }
+
+    /// Everything around the map, where it's been dragged to: the title, scale bar,
+    /// north arrow, legend and attribution. They're quick to lay out again on their own,
+    /// see Page::decorate.
+    pub fn decorations(&self, snapshot: &TileSnapshot, layers: &[DrawingLayer]) -> Vec<Decoration> {
+        let mut decorations = Vec::new();
+        let mut add = |item: LayoutItem, mut items: Vec<PageItem>| {
+            let rect = match items_bounds(&items) {
+                Some(rect) => rect,
+                None => return,
+            };
+            let placement = self.placement(item);
+            placement.apply(&mut items, &rect);
+            decorations.push(Decoration {
+                item,
+                items,
+                rect: placement.rect(&rect),
+            });
+        };
+
+        if self.has_title() {
+            let title = PageItem::Text {
+                text: self.title.trim().to_string(),
+                position: (self.page_size().0 / 2.0, MARGIN + 7.0),
+                size: 6.0,
+                color: Color::BLACK,
+                anchor: TextAnchor::Middle,
+                halo: None,
+            };
+            add(LayoutItem::Title, vec![title]);
+        }
+
+        let footer = self.footer();
+        if self.scale_bar {
+            let mut items = Vec::new();
+            self.scale_bar_items(&mut items, &footer);
+            add(LayoutItem::ScaleBar, items);
+        }
+        if self.north_arrow {
+            let mut items = Vec::new();
+            north_arrow_items(&mut items, &footer);
+            add(LayoutItem::NorthArrow, items);
+        }
+        if self.legend {
+            let mut items = Vec::new();
+            legend_items(&mut items, layers, &footer);
+            add(LayoutItem::Legend, items);
+        }
+        let attribution = match self.attribution.trim() {
+            "" => snapshot.attribution().to_string(),
+            typed => typed.to_string(),
+        };
+        if !attribution.is_empty() {
+            let attribution = PageItem::Text {
+                text: attribution,
+                position: (footer.x + footer.width, footer.y + footer.height),
+                size: 2.0,
+                color: Color::BLACK,
+                anchor: TextAnchor::End,
+                halo: None,
+            };
+            add(LayoutItem::Attribution, vec![attribution]);
+        }
+        decorations
+    }
+
+    fn scale_bar_items(&self, items: &mut Vec<PageItem>, footer: &Rect) {
+        //a round ground distance that takes up about a quarter of the map width
+        let meters_per_mm = self.scale.0 as f64 / 1000.0;
+        let target = footer.width / 4.0 * meters_per_mm;
+        let magnitude = 10f64.powf(target.log10().floor());
+        let meters = [5.0, 2.0, 1.0]
+            .iter()
+            .map(|step| step * magnitude)
+            .find(|meters| *meters <= target)
+            .unwrap_or(magnitude);
+        let length = meters / meters_per_mm;
+
+        let (x, y) = (footer.x, footer.y + 4.0);
+        let segments = 4;
+        for idx in 0..segments {
+            let segment = Rect {
+                x: x + length * idx as f64 / segments as f64,
+                y,
+                width: length / segments as f64,
+                height: 1.5,
+            };
+            let fill = if idx % 2 == 0 {
+                Color::BLACK
+            } else {
+                Color::WHITE
+            };
+            items.push(PageItem::Path {
+                rings: vec![rect_ring(&segment)],
+                closed: true,
+                fill: Some(fill),
+                stroke: Some((Color::BLACK, 0.2)),
+            });
+        }
+        let distance = if meters >= 1000.0 {
+            format!("{} km", meters / 1000.0)
+        } else {
+            format!("{} m", meters)
+        };
+        items.push(small_text("0", (x, y - 1.0), TextAnchor::Middle));
+        items.push(small_text(
+            &distance,
+            (x + length, y - 1.0),
+            TextAnchor::Middle,
+        ));
+        items.push(small_text(
+            &self.scale.to_string(),
+            (x, y + 6.5),
+            TextAnchor::Start,
+        ));
+    }
+}
+
+//projects lat/lon onto the page
+struct MapTransform {
+    view: MapView,
+    frame: Rect,
+    mm_per_world_pixel: f64,
+}
+
+impl MapTransform {
+    fn new(layout: &PrintLayout) -> Self {
+        let zoom = layout.tile_zoom();
+        let frame = layout.frame();
+        Self {
+            view: MapView {
+                zoom,
+                top_left: layout.top_left(zoom),
+            },
+            frame,
+            mm_per_world_pixel: frame.width / layout.extent(zoom).0,
+        }
+    }
+
+    fn to_page(&self, point: &LatLon) -> (f64, f64) {
+        let (x, y) = self.view.to_screen(point);
+        self.screen_to_page((x, y))
+    }
+
+    fn screen_to_page(&self, screen: (f32, f32)) -> (f64, f64) {
+        (
+            self.frame.x + screen.0 as f64 * self.mm_per_world_pixel,
+            self.frame.y + screen.1 as f64 * self.mm_per_world_pixel,
+        )
+    }
+}
+
+fn rect_ring(rect: &Rect) -> Vec<(f64, f64)> {
+    vec![
+        (rect.x, rect.y),
+        (rect.x + rect.width, rect.y),
+        (rect.x + rect.width, rect.y + rect.height),
+        (rect.x, rect.y + rect.height),
+    ]
+}
+
+fn circle_ring(center: (f64, f64), radius: f64) -> Vec<(f64, f64)> {
+    (0..CIRCLE_SEGMENTS)
+        .map(|idx| {
+            let angle = idx as f64 / CIRCLE_SEGMENTS as f64 * 2.0 * PI;
+            (
+                center.0 + radius * angle.cos(),
+                center.1 + radius * angle.sin(),
+            )
+        })
+        .collect()
+}
+
+//roughly what `items` cover on the page, glyphs are taken as 0.55 of the size across
+fn items_bounds(items: &[PageItem]) -> Option<Rect> {
+    let (mut min, mut max) = ((f64::MAX, f64::MAX), (f64::MIN, f64::MIN));
+    let mut extend = |point: (f64, f64)| {
+        min = (min.0.min(point.0), min.1.min(point.1));
+        max = (max.0.max(point.0), max.1.max(point.1));
+    };
+    for item in items {
+        match item {
+            PageItem::Image { rect, .. } => {
+                extend((rect.x, rect.y));
+                extend((rect.x + rect.width, rect.y + rect.height));
+            }
+            PageItem::Path { rings, .. } => rings.iter().flatten().for_each(|p| extend(*p)),
+            PageItem::Text {
+                text,
+                position,
+                size,
+                anchor,
+                ..
+            } => {
+                let width = text.chars().count() as f64 * size * 0.55;
+                let left = match anchor {
+                    TextAnchor::Start => position.0,
+                    TextAnchor::Middle => position.0 - width / 2.0,
+                    TextAnchor::End => position.0 - width,
+                };
+                extend((left, position.1 - size * 0.8));
+                extend((left + width, position.1 + size * 0.2));
+            }
+        }
+    }
+    if min.0 > max.0 {
+        return None;
+    }
+    Some(Rect {
+        x: min.0,
+        y: min.1,
+        width: max.0 - min.0,
+        height: max.1 - min.1,
+    })
+}
+
+fn small_text(content: &str, position: (f64, f64), anchor: TextAnchor) -> PageItem {
+    PageItem::Text {
+        text: content.to_string(),
+        position,
+        size: 2.5,
+        color: Color::BLACK,
+        anchor,
+        halo: None,
+    }
+}
+
+fn stroke_of(style: &FeatureStyle) -> Option<(Color, f64)> {
+    Some((style.stroke, style.stroke_width as f64 * MM_PER_PIXEL))
+}
+
+fn feature_items(items: &mut Vec<PageItem>, feature: &Feature, map: &MapTransform, frame: &Rect) {
+    let style = &feature.style;
+    let project = |line: &[LatLon]| -> Vec<(f64, f64)> {
+        line.iter().map(|point| map.to_page(point)).collect()
+    };
+    let marker = |items: &mut Vec<PageItem>, point: &LatLon| {
+        let center = map.to_page(point);
+        if frame.contains(center) {
+            items.push(PageItem::Path {
+                rings: vec![circle_ring(center, style.marker_size as f64 * MM_PER_PIXEL)],
+                closed: true,
+                fill: Some(style.marker_color),
+                stroke: Some((style.stroke, MM_PER_PIXEL)),
+            });
+        }
+    };
+    let line = |items: &mut Vec<PageItem>, line: &[LatLon]| {
+        let parts = clip_line(&project(line), frame);
+        if !parts.is_empty() {
+            items.push(PageItem::Path {
+                rings: parts,
+                closed: false,
+                fill: None,
+                stroke: stroke_of(style),
+            });
+        }
+    };
+    let polygon = |items: &mut Vec<PageItem>, rings: &[Vec<LatLon>]| {
+        let rings: Vec<Vec<(f64, f64)>> = rings
+            .iter()
+            .map(|ring| clip_ring(&project(ring), frame))
+            .filter(|ring| ring.len() > 2)
+            .collect();
+        if !rings.is_empty() {
+            items.push(PageItem::Path {
+                rings,
+                closed: true,
+                fill: Some(style.fill),
+                stroke: stroke_of(style),
+            });
+        }
+    };
+
+    match &feature.geometry {
+        Geometry::Point(point) => marker(items, point),
+        Geometry::MultiPoint(points) => points.iter().for_each(|point| marker(items, point)),
+        Geometry::LineString(points) => line(items, points),
+        Geometry::MultiLineString(lines) => lines.iter().for_each(|points| line(items, points)),
+        Geometry::Polygon(rings) => polygon(items, rings),
+        Geometry::MultiPolygon(polygons) => polygons.iter().for_each(|rings| polygon(items, rings)),
+    }
+}
+
+fn label_items(
+    items: &mut Vec<PageItem>,
+    layers: &[DrawingLayer],
+    map: &MapTransform,
+    frame: &Rect,
+    font: &FontVec,
+) {
+    //layout works in world pixels here, so measure text in them too
+    let pixels_per_screen_pixel = (MM_PER_PIXEL / map.mm_per_world_pixel) as f32;
+    let measure = |content: &str, size: u16| {
+        let (width, height) = text::measure(font, content, size);
+        (
+            width * pixels_per_screen_pixel,
+            height * pixels_per_screen_pixel,
+        )
+    };
+    let viewport = (
+        (frame.width / map.mm_per_world_pixel) as f32,
+        (frame.height / map.mm_per_world_pixel) as f32,
+    );
+    let labels = shown_labels(layers);
+    let labels: Vec<&Label> = labels.iter().collect();
+    for placed in layout_labels(&labels, &map.view, viewport, &measure) {
+        let style = placed.style;
+        let height = style.font_size as f32 * MM_PER_PIXEL as f32;
+        let size = text::em_size(font, height) as f64;
+        let baseline_offset = text::baseline_offset(font, height) as f64;
+        for (glyph, center) in placed.glyphs.iter() {
+            let center = map.screen_to_page(*center);
+            if !frame.contains(center) {
+                continue;
+            }
+            items.push(PageItem::Text {
+                text: glyph.to_string(),
+                position: (center.0, center.1 + baseline_offset),
+                size,
+                color: style.color,
+                anchor: TextAnchor::Middle,
+                halo: Some((style.halo_color, style.halo_width as f64 * MM_PER_PIXEL)),
+            });
+        }
+    }
+}
+
+//a round step in degrees giving a handful of lines across `span`
+fn grid_step(span: f64) -> f64 {
+    let steps = [
+        0.001, 0.002, 0.005, 0.01, 0.02, 0.05, 0.1, 0.2, 0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 30.0,
+    ];
+    steps
+        .iter()
+        .copied()
+        .find(|step| span / step <= 6.0)
+        .unwrap_or(30.0)
+}
+
+fn format_degrees(value: f64, step: f64, positive: char, negative: char) -> String {
+    let decimals = (-step.log10()).ceil().max(0.0) as usize;
+    let hemisphere = if value < 0.0 { negative } else { positive };
+    format!("{:.*}°{}", decimals, value.abs(), hemisphere)
+}
+
+//round degree values across the frame, with where they fall on the page
+fn grid_positions(map: &MapTransform, bounds: &Bounds) -> (Vec<(f64, String)>, Vec<(f64, String)>) {
+    let mut columns = Vec::new();
+    let step = grid_step(bounds.max.lon - bounds.min.lon);
+    let mut lon = (bounds.min.lon / step).ceil() * step;
+    while lon <= bounds.max.lon {
+        let x = map.to_page(&LatLon::new(bounds.center().lat, lon)).0;
+        columns.push((x, format_degrees(lon, step, 'E', 'W')));
+        lon += step;
+    }
+
+    let mut rows = Vec::new();
+    let step = grid_step(bounds.max.lat - bounds.min.lat);
+    let mut lat = (bounds.min.lat / step).ceil() * step;
+    while lat <= bounds.max.lat {
+        let y = map.to_page(&LatLon::new(lat, bounds.center().lon)).1;
+        rows.push((y, format_degrees(lat, step, 'N', 'S')));
+        lat += step;
+    }
+    (columns, rows)
+}
+
+fn grid_line_items(items: &mut Vec<PageItem>, map: &MapTransform, frame: &Rect, bounds: &Bounds) {
+    let (columns, rows) = grid_positions(map, bounds);
+    let bottom = frame.y + frame.height;
+    let right = frame.x + frame.width;
+    let mut lines: Vec<Vec<(f64, f64)>> = Vec::new();
+    lines.extend(
+        columns
+            .iter()
+            .map(|(x, _)| vec![(*x, frame.y), (*x, bottom)]),
+    );
+    lines.extend(rows.iter().map(|(y, _)| vec![(frame.x, *y), (right, *y)]));
+    if !lines.is_empty() {
+        items.push(PageItem::Path {
+            rings: lines,
+            closed: false,
+            fill: None,
+            stroke: Some((Color::from_rgba(0.0, 0.0, 0.0, 0.5), 0.15)),
+        });
+    }
+}
+
+//ticks outside all four edges of the frame, labelled with their coordinate
+fn grid_tick_items(items: &mut Vec<PageItem>, map: &MapTransform, frame: &Rect, bounds: &Bounds) {
+    let (columns, rows) = grid_positions(map, bounds);
+    let bottom = frame.y + frame.height;
+    let right = frame.x + frame.width;
+    let mut ticks: Vec<Vec<(f64, f64)>> = Vec::new();
+    for (x, label) in &columns {
+        ticks.push(vec![(*x, frame.y - TICK_LENGTH), (*x, frame.y)]);
+        ticks.push(vec![(*x, bottom), (*x, bottom + TICK_LENGTH)]);
+        let above = (*x, frame.y - TICK_LENGTH - 0.8);
+        let below = (*x, bottom + TICK_LENGTH + 2.8);
+        items.push(small_text(label, above, TextAnchor::Middle));
+        items.push(small_text(label, below, TextAnchor::Middle));
+    }
+    for (y, label) in &rows {
+        ticks.push(vec![(frame.x - TICK_LENGTH, *y), (frame.x, *y)]);
+        ticks.push(vec![(right, *y), (right + TICK_LENGTH, *y)]);
+        //baseline a little under the tick so the text sits centered on it
+        let left = (frame.x - TICK_LENGTH - 0.8, *y + 0.9);
+        let beside = (right + TICK_LENGTH + 0.8, *y + 0.9);
+        items.push(small_text(label, left, TextAnchor::End));
+        items.push(small_text(label, beside, TextAnchor::Start));
+    }
+    if !ticks.is_empty() {
+        items.push(PageItem::Path {
+            rings: ticks,
+            closed: false,
+            fill: None,
+            stroke: Some((Color::BLACK, 0.2)),
+        });
+    }
+}
+
+fn north_arrow_items(items: &mut Vec<PageItem>, footer: &Rect) {
+    //web mercator keeps north straight up
+    let x = footer.x + footer.width * 0.4;
+    let top = footer.y + 3.0;
+    let bottom = top + 10.0;
+    items.push(PageItem::Path {
+        rings: vec![vec![(x, top), (x + 3.0, bottom), (x, bottom - 3.0)]],
+        closed: true,
+        fill: Some(Color::BLACK),
+        stroke: Some((Color::BLACK, 0.2)),
+    });
+    items.push(PageItem::Path {
+        rings: vec![vec![(x, top), (x - 3.0, bottom), (x, bottom - 3.0)]],
+        closed: true,
+        fill: Some(Color::WHITE),
+        stroke: Some((Color::BLACK, 0.2)),
+    });
+    items.push(PageItem::Text {
+        text: String::from("N"),
+        position: (x, top - 0.5),
+        size: 3.5,
+        color: Color::BLACK,
+        anchor: TextAnchor::Middle,
+        halo: None,
+    });
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Swatch {
+    Marker,
+    Line,
+    Area,
+}
+
+impl Swatch {
+    fn of(geometry: &Geometry) -> Self {
+        match geometry {
+            Geometry::Point(_) | Geometry::MultiPoint(_) => Swatch::Marker,
+            Geometry::LineString(_) | Geometry::MultiLineString(_) => Swatch::Line,
+            Geometry::Polygon(_) | Geometry::MultiPolygon(_) => Swatch::Area,
+        }
+    }
+
+    fn noun(&self) -> &'static str {
+        match self {
+            Swatch::Marker => "points",
+            Swatch::Line => "lines",
+            Swatch::Area => "areas",
+        }
+    }
+}
+
+//one entry for every distinct kind and style of feature in each layer
+fn legend_entries(layers: &[DrawingLayer]) -> Vec<(String, Swatch, FeatureStyle)> {
+    let mut entries = Vec::new();
+    for layer in layers.iter().filter(|layer| layer.is_shown()) {
+        let mut looks: Vec<(Swatch, FeatureStyle)> = Vec::new();
+        for feature in &layer.features {
+            let look = (Swatch::of(&feature.geometry), feature.style);
+            if !looks.contains(&look) {
+                looks.push(look);
+            }
+        }
+        let several = looks.len() > 1;
+        for (swatch, style) in looks {
+            let name = if several {
+                format!("{}: {}", layer.name, swatch.noun())
+            } else {
+                layer.name.clone()
+            };
+            entries.push((name, swatch, style));
+        }
+    }
+    entries
+}
+
+fn legend_items(items: &mut Vec<PageItem>, layers: &[DrawingLayer], footer: &Rect) {
+    const ROW: f64 = 5.0;
+    const COLUMN: f64 = 40.0;
+    //the bottom line of the footer is kept for the attribution
+    let rows = ((footer.height - 4.0) / ROW).floor().max(1.0) as usize;
+    let left = footer.x + footer.width * 0.5;
+    let entries = legend_entries(layers);
+    let columns = ((footer.x + footer.width - left) / COLUMN).floor().max(1.0) as usize;
+    if entries.len() > rows * columns {
+        log::warn!(
+            "legend only has room for {} of {} entries",
+            rows * columns,
+            entries.len()
+        );
+    }
+
+    for (idx, (name, swatch, style)) in entries.iter().take(rows * columns).enumerate() {
+        let x = left + (idx / rows) as f64 * COLUMN;
+        let y = footer.y + (idx % rows) as f64 * ROW;
+        let item = match swatch {
+            Swatch::Marker => PageItem::Path {
+                rings: vec![circle_ring((x + 3.0, y + 2.0), 1.5)],
+                closed: true,
+                fill: Some(style.marker_color),
+                stroke: Some((style.stroke, MM_PER_PIXEL)),
+            },
+            Swatch::Line => PageItem::Path {
+                rings: vec![vec![(x, y + 2.0), (x + 6.0, y + 2.0)]],
+                closed: false,
+                fill: None,
+                stroke: stroke_of(style),
+            },
+            Swatch::Area => PageItem::Path {
+                rings: vec![rect_ring(&Rect {
+                    x,
+                    y: y + 0.5,
+                    width: 6.0,
+                    height: 3.0,
+                })],
+                closed: true,
+                fill: Some(style.fill),
+                stroke: stroke_of(style),
+            },
+        };
+        items.push(item);
+        items.push(small_text(name, (x + 8.0, y + 3.0), TextAnchor::Start));
+    }
+}
+
+/// Cuts a polyline down to the parts inside `rect`.
+fn clip_line(line: &[(f64, f64)], rect: &Rect) -> Vec<Vec<(f64, f64)>> {
+    let mut parts: Vec<Vec<(f64, f64)>> = Vec::new();
+    let mut current: Vec<(f64, f64)> = Vec::new();
+    for segment in line.windows(2) {
+        match clip_segment(segment[0], segment[1], rect) {
+            Some((start, end)) => {
+                if current.last() != Some(&start) {
+                    if current.len() > 1 {
+                        parts.push(std::mem::take(&mut current));
+                    }
+                    current = vec![start];
+                }
+                current.push(end);
+            }
+            None => {
+                if current.len() > 1 {
+                    parts.push(std::mem::take(&mut current));
+                }
+                current.clear();
+            }
+        }
+    }
+    if current.len() > 1 {
+        parts.push(current);
+    }
+    parts
+}
+
+//liang-barsky
+fn clip_segment(a: (f64, f64), b: (f64, f64), rect: &Rect) -> Option<((f64, f64), (f64, f64))> {
+    let delta = (b.0 - a.0, b.1 - a.1);
+    let checks = [
+        (-delta.0, a.0 - rect.x),
+        (delta.0, rect.x + rect.width - a.0),
+        (-delta.1, a.1 - rect.y),
+        (delta.1, rect.y + rect.height - a.1),
+    ];
+    let (mut enter, mut exit) = (0.0f64, 1.0f64);
+    for (p, q) in checks.iter() {
+        if *p == 0.0 {
+            if *q < 0.0 {
+                return None;
+            }
+        } else {
+            let t = q / p;
+            if *p < 0.0 {
+                enter = enter.max(t);
+            } else {
+                exit = exit.min(t);
+            }
+        }
+    }
+    if enter > exit {
+        return None;
+    }
+    //exact end points when nothing is cut, so clip_line can tell the pieces connect
+    let at = |t: f64| {
+        if t <= 0.0 {
+            a
+        } else if t >= 1.0 {
+            b
+        } else {
+            (a.0 + delta.0 * t, a.1 + delta.1 * t)
+        }
+    };
+    Some((at(enter), at(exit)))
+}
+
+/// Sutherland-Hodgman against each edge of `rect`.
+fn clip_ring(ring: &[(f64, f64)], rect: &Rect) -> Vec<(f64, f64)> {
+    let right = rect.x + rect.width;
+    let bottom = rect.y + rect.height;
+    //inside test and intersection for each edge
+    let edges: [(&dyn Fn((f64, f64)) -> bool, f64, bool); 4] = [
+        (&|p: (f64, f64)| p.0 >= rect.x, rect.x, true),
+        (&|p: (f64, f64)| p.0 <= right, right, true),
+        (&|p: (f64, f64)| p.1 >= rect.y, rect.y, false),
+        (&|p: (f64, f64)| p.1 <= bottom, bottom, false),
+    ];
+    let mut output = ring.to_vec();
+    for (inside, value, vertical) in edges.iter() {
+        let input = std::mem::take(&mut output);
+        let cross = |a: (f64, f64), b: (f64, f64)| {
+            if *vertical {
+                let t = (value - a.0) / (b.0 - a.0);
+                (*value, a.1 + (b.1 - a.1) * t)
+            } else {
+                let t = (value - a.1) / (b.1 - a.1);
+                (a.0 + (b.0 - a.0) * t, *value)
+            }
+        };
+        for (idx, current) in input.iter().enumerate() {
+            let previous = input[(idx + input.len() - 1) % input.len()];
+            match (inside(*current), inside(previous)) {
+                (true, true) => output.push(*current),
+                (true, false) => {
+                    output.push(cross(previous, *current));
+                    output.push(*current);
+                }
+                (false, true) => output.push(cross(previous, *current)),
+                (false, false) => {}
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::HttpSettings;
+    use crate::tile_manager::tile_manager::TileManager;
+
+    fn layout() -> PrintLayout {
+        PrintLayout {
+            paper: PaperSize::Letter,
+            landscape: true,
+            scale: MapScale(50_000),
+            center: LatLon::new(46.5, 7.75),
+            title: String::from("Bernese Oberland"),
+            attribution: String::from("swisstopo"),
+            legend: false,
+            grid: GridStyle::Lines,
+            ..PrintLayout::default()
+        }
+    }
+
+    fn compose(layout: &PrintLayout) -> Page {
+        let snapshot = TileManager::with_settings(HttpSettings::default(), None).snapshot(&[]);
+        layout.compose(&snapshot, &[], None)
+    }
+
+    fn region(page: &Page, item: LayoutItem) -> Rect {
+        page.regions
+            .iter()
+            .find(|(region, _)| *region == item)
+            .map(|(_, rect)| *rect)
+            .unwrap()
+    }
+
+    #[test]
+    fn layouts_survive_a_round_trip() {
+        let mut layout = layout();
+        layout.move_item(LayoutItem::Title, (10.0, -5.0));
+        layout.resize_item(LayoutItem::NorthArrow, 1.5);
+        let json = serde_json::to_string(&layout.to_json()).unwrap();
+        let read = PrintLayout::from_json(&serde_json::from_str(&json).unwrap());
+        assert_eq!(read, layout);
+    }
+
+    #[test]
+    fn unreadable_layout_values_keep_their_defaults() {
+        let read = PrintLayout::from_json(&json!({
+            "paper": "A0",
+            "scale": 0,
+            "center": [7.5],
+            "placements": {
+                "title": {"offset": [1.0, 2.0], "scale": 100.0},
+                "compass": {"offset": [1.0, 2.0]},
+            },
+        }));
+        let mut expected = PrintLayout::default();
+        expected.placements.insert(
+            LayoutItem::Title,
+            Placement {
+                offset: (1.0, 2.0),
+                scale: MAX_ITEM_SCALE,
+            },
+        );
+        assert_eq!(read, expected);
+    }
+
+    #[test]
+    fn placements_move_and_scale_their_item() {
+        let mut layout = layout();
+        let before = region(&compose(&layout), LayoutItem::NorthArrow);
+        layout.move_item(LayoutItem::NorthArrow, (-20.0, 3.0));
+        layout.resize_item(LayoutItem::NorthArrow, 2.0);
+        let after = region(&compose(&layout), LayoutItem::NorthArrow);
+
+        assert!((after.x - (before.x - 20.0)).abs() < 1e-9);
+        assert!((after.y - (before.y + 3.0)).abs() < 1e-9);
+        assert!((after.width - before.width * 2.0).abs() < 1e-9);
+        assert!((after.height - before.height * 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn decorating_again_keeps_the_map() {
+        let mut layout = layout();
+        let mut page = compose(&layout);
+        let (items, regions) = (page.items.len(), page.regions.clone());
+        assert_eq!(regions[0], (LayoutItem::Map, layout.frame()));
+
+        layout.move_item(LayoutItem::Title, (0.0, 4.0));
+        let snapshot = TileManager::with_settings(HttpSettings::default(), None).snapshot(&[]);
+        page.decorate(layout.decorations(&snapshot, &[]));
+        assert_eq!(page.items.len(), items);
+        assert_eq!(page.regions.len(), regions.len());
+        assert_eq!(page.regions[0], regions[0]);
+        let title = region(&page, LayoutItem::Title);
+        let before = regions
+            .iter()
+            .find(|(item, _)| *item == LayoutItem::Title)
+            .unwrap()
+            .1;
+        assert!((title.y - before.y - 4.0).abs() < 1e-9);
+    }
+}
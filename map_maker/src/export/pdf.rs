@@ -0,0 +1,560 @@
+//writes a composed page as a single page PDF 1.4
+//
+//written by hand like the gpx and kml files: the page is small enough that a writer
+//library would mostly be in the way. the content stream is set up in millimeters with
+//y down so page items go in unchanged, and text is set in the export font embedded as
+//a WinAnsi TrueType font, or Helvetica when there is no font
+use crate::export::page::{Page, PageItem, TextAnchor, MM_PER_INCH};
+use crate::export::text;
+use crate::export::ExportError;
+use ab_glyph::{Font, FontVec};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use iced::Color;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::Path;
+
+const POINTS_PER_MM: f64 = 72.0 / MM_PER_INCH;
+//WinAnsi codes 0x80 to 0x9f, the rest of the printable range matches latin-1
+const WIN_ANSI_HIGH: [Option<char>; 32] = [
+    Some('€'),
+    None,
+    Some('‚'),
+    Some('ƒ'),
+    Some('„'),
+    Some('…'),
+    Some('†'),
+    Some('‡'),
+    Some('ˆ'),
+    Some('‰'),
+    Some('Š'),
+    Some('‹'),
+    Some('Œ'),
+    None,
+    Some('Ž'),
+    None,
+    None,
+    Some('‘'),
+    Some('’'),
+    Some('“'),
+    Some('”'),
+    Some('•'),
+    Some('–'),
+    Some('—'),
+    Some('˜'),
+    Some('™'),
+    Some('š'),
+    Some('›'),
+    Some('œ'),
+    None,
+    Some('ž'),
+    Some('Ÿ'),
+];
+
+fn win_ansi_char(code: u8) -> Option<char> {
+    match code {
+        0x80..=0x9f => WIN_ANSI_HIGH[(code - 0x80) as usize],
+        0x20..=0x7e | 0xa0..=0xff => Some(code as char),
+        _ => None,
+    }
+}
+
+//characters WinAnsi can't encode come out as '?'
+fn win_ansi_bytes(content: &str) -> Vec<u8> {
+    content
+        .chars()
+        .map(|ch| match ch as u32 {
+            0x20..=0x7e | 0xa0..=0xff => ch as u8,
+            _ => (0x80..=0x9f)
+                .find(|code| win_ansi_char(*code) == Some(ch))
+                .unwrap_or(b'?'),
+        })
+        .collect()
+}
+
+//a pdf literal string
+fn pdf_string(bytes: &[u8]) -> String {
+    let mut out = String::from("(");
+    for byte in bytes {
+        match byte {
+            b'(' | b')' | b'\\' => {
+                out.push('\\');
+                out.push(*byte as char);
+            }
+            0x20..=0x7e => out.push(*byte as char),
+            _ => {
+                let _ = write!(out, "\\{:03o}", byte);
+            }
+        }
+    }
+    out.push(')');
+    out
+}
+
+fn deflate(data: &[u8]) -> Result<Vec<u8>, ExportError> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+fn stream(dict: &str, data: &[u8]) -> Vec<u8> {
+    let mut object = format!("<< {} /Length {} >>\nstream\n", dict, data.len()).into_bytes();
+    object.extend_from_slice(data);
+    object.extend_from_slice(b"\nendstream");
+    object
+}
+
+//builds the content stream, remembering the transparency states it needs
+struct Content<'a> {
+    ops: String,
+    font: Option<&'a FontVec>,
+    //alpha in thousandths for fill and stroke, and the resource name for it
+    alpha_states: HashMap<(u16, u16), String>,
+    images: Vec<String>,
+}
+
+impl<'a> Content<'a> {
+    fn alpha(&mut self, fill: f32, stroke: f32) {
+        let key = (
+            (fill.max(0.0).min(1.0) * 1000.0).round() as u16,
+            (stroke.max(0.0).min(1.0) * 1000.0).round() as u16,
+        );
+        let count = self.alpha_states.len();
+        let name = self
+            .alpha_states
+            .entry(key)
+            .or_insert_with(|| format!("GS{}", count))
+            .clone();
+        let _ = writeln!(self.ops, "/{} gs", name);
+    }
+
+    fn fill_color(&mut self, color: &Color) {
+        let _ = writeln!(self.ops, "{:.3} {:.3} {:.3} rg", color.r, color.g, color.b);
+    }
+
+    fn stroke_color(&mut self, color: &Color) {
+        let _ = writeln!(self.ops, "{:.3} {:.3} {:.3} RG", color.r, color.g, color.b);
+    }
+
+    fn text_width(&self, content: &str, size: f64) -> f64 {
+        match self.font {
+            Some(font) => text::em_width(font, content, size as f32) as f64,
+            //close enough to helvetica for centering short strings
+            None => content.chars().count() as f64 * size * 0.55,
+        }
+    }
+
+    fn item(&mut self, item: &PageItem, image_idx: &mut usize) {
+        match item {
+            PageItem::Image { rect, .. } => {
+                let name = format!("Im{}", *image_idx);
+                *image_idx += 1;
+                //the unit square, flipped back since the page is y down
+                let _ = writeln!(
+                    self.ops,
+                    "q {:.3} 0 0 {:.3} {:.3} {:.3} cm /{} Do Q",
+                    rect.width,
+                    -rect.height,
+                    rect.x,
+                    rect.y + rect.height,
+                    name
+                );
+                self.images.push(name);
+            }
+            PageItem::Path {
+                rings,
+                closed,
+                fill,
+                stroke,
+            } => {
+                self.ops.push_str("q\n");
+                self.alpha(
+                    fill.map_or(1.0, |fill| fill.a),
+                    stroke.map_or(1.0, |(color, _)| color.a),
+                );
+                if let Some(fill) = fill {
+                    self.fill_color(fill);
+                }
+                if let Some((color, width)) = stroke {
+                    self.stroke_color(color);
+                    let _ = writeln!(self.ops, "{:.3} w 1 j 1 J", width);
+                }
+                for ring in rings {
+                    for (idx, (x, y)) in ring.iter().enumerate() {
+                        let op = if idx == 0 { "m" } else { "l" };
+                        let _ = writeln!(self.ops, "{:.3} {:.3} {}", x, y, op);
+                    }
+                    if *closed {
+                        self.ops.push_str("h\n");
+                    }
+                }
+                let paint = match (fill.is_some(), stroke.is_some()) {
+                    (true, true) => "B*",
+                    (true, false) => "f*",
+                    (false, true) => "S",
+                    (false, false) => "n",
+                };
+                let _ = writeln!(self.ops, "{}\nQ", paint);
+            }
+            PageItem::Text {
+                text,
+                position,
+                size,
+                color,
+                anchor,
+                halo,
+            } => {
+                let width = self.text_width(text, *size);
+                let x = match anchor {
+                    TextAnchor::Start => position.0,
+                    TextAnchor::Middle => position.0 - width / 2.0,
+                    TextAnchor::End => position.0 - width,
+                };
+                let string = pdf_string(&win_ansi_bytes(text));
+                //the text matrix flips glyphs upright again
+                let place = format!(
+                    "BT /F1 {:.3} Tf 1 0 0 -1 {:.3} {:.3} Tm",
+                    size, x, position.1
+                );
+                self.ops.push_str("q\n");
+                if let Some((halo_color, halo_width)) = halo {
+                    if *halo_width > 0.0 {
+                        self.alpha(1.0, halo_color.a);
+                        self.stroke_color(halo_color);
+                        let _ = writeln!(
+                            self.ops,
+                            "{:.3} w 1 j {} 1 Tr {} Tj ET",
+                            halo_width * 2.0,
+                            place,
+                            string
+                        );
+                    }
+                }
+                self.alpha(color.a, 1.0);
+                self.fill_color(color);
+                let _ = writeln!(self.ops, "{} 0 Tr {} Tj ET\nQ", place, string);
+            }
+        }
+    }
+}
+
+/// Writes `page` to `path`. `font` should be the font the page was composed with.
+pub fn write_pdf(
+    path: &Path,
+    page: &Page,
+    font: Option<(&FontVec, &[u8])>,
+) -> Result<(), ExportError> {
+    let mut content = Content {
+        ops: String::new(),
+        font: font.map(|(font, _)| font),
+        alpha_states: HashMap::new(),
+        images: Vec::new(),
+    };
+    //millimeters, y down from the top left
+    let _ = writeln!(
+        content.ops,
+        "{:.5} 0 0 {:.5} 0 {:.3} cm",
+        POINTS_PER_MM,
+        -POINTS_PER_MM,
+        page.size.1 * POINTS_PER_MM
+    );
+    let mut image_idx = 0;
+    for item in &page.items {
+        content.item(item, &mut image_idx);
+    }
+
+    //objects are numbered from 1 in the order they're pushed
+    let mut objects: Vec<Vec<u8>> = Vec::new();
+    objects.push(b"<< /Type /Catalog /Pages 2 0 R >>".to_vec());
+    objects.push(b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_vec());
+    //the page itself is filled in once the resources have numbers
+    objects.push(Vec::new());
+    objects.push(stream(
+        "/Filter /FlateDecode",
+        &deflate(content.ops.as_bytes())?,
+    ));
+
+    let mut xobjects = String::new();
+    let images = page.items.iter().filter_map(|item| match item {
+        PageItem::Image { image, .. } => Some(image),
+        _ => None,
+    });
+    for (name, image) in content.images.iter().zip(images) {
+        //tiles are opaque, drop the alpha channel
+        let rgb: Vec<u8> = image
+            .pixels()
+            .flat_map(|pixel| vec![pixel[0], pixel[1], pixel[2]])
+            .collect();
+        let dict = format!(
+            "/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceRGB \
+             /BitsPerComponent 8 /Filter /FlateDecode",
+            image.width(),
+            image.height()
+        );
+        objects.push(stream(&dict, &deflate(&rgb)?));
+        let _ = write!(xobjects, "/{} {} 0 R ", name, objects.len());
+    }
+
+    let font_object = match font {
+        Some((font, data)) => font_objects(&mut objects, font, data)?,
+        None => {
+            objects.push(
+                b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+                    .to_vec(),
+            );
+            objects.len()
+        }
+    };
+
+    let mut alpha_states: Vec<(&(u16, u16), &String)> = content.alpha_states.iter().collect();
+    alpha_states.sort_by(|a, b| a.1.cmp(b.1));
+    let mut states = String::new();
+    for ((fill, stroke), name) in alpha_states {
+        let _ = write!(
+            states,
+            "/{} << /ca {:.3} /CA {:.3} >> ",
+            name,
+            *fill as f64 / 1000.0,
+            *stroke as f64 / 1000.0
+        );
+    }
+    objects[2] = format!(
+        "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.2} {:.2}] /Contents 4 0 R \
+         /Resources << /Font << /F1 {} 0 R >> /XObject << {}>> /ExtGState << {}>> >> >>",
+        page.size.0 * POINTS_PER_MM,
+        page.size.1 * POINTS_PER_MM,
+        font_object,
+        xobjects,
+        states
+    )
+    .into_bytes();
+
+    //opentype font files can only be embedded from 1.6 on
+    let version = match font {
+        Some((_, data)) if is_cff(data) => "1.6",
+        _ => "1.4",
+    };
+    let mut file: Vec<u8> = format!("%PDF-{}\n", version).into_bytes();
+    file.extend_from_slice(b"%\xe2\xe3\xcf\xd3\n");
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (idx, object) in objects.iter().enumerate() {
+        offsets.push(file.len());
+        file.extend_from_slice(format!("{} 0 obj\n", idx + 1).as_bytes());
+        file.extend_from_slice(object);
+        file.extend_from_slice(b"\nendobj\n");
+    }
+    let xref = file.len();
+    let mut table = format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+    for offset in offsets {
+        let _ = writeln!(table, "{:010} 00000 n ", offset);
+    }
+    let _ = write!(
+        table,
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref
+    );
+    file.extend_from_slice(table.as_bytes());
+    std::fs::write(path, file)?;
+    log::info!("wrote pdf to {}", path.display());
+    Ok(())
+}
+
+//embeds the whole font file, returning the number of the font object
+fn font_objects(
+    objects: &mut Vec<Vec<u8>>,
+    font: &FontVec,
+    data: &[u8],
+) -> Result<usize, ExportError> {
+    let units_per_em = font.units_per_em().unwrap_or(1000.0);
+    //pdf font metrics are in thousandths of an em
+    let to_pdf = |value: f32| (value * 1000.0 / units_per_em).round() as i32;
+
+    //truetype outlines go in as FontFile2, cff ones as a whole opentype file
+    let cff = is_cff(data);
+    let file_dict = if cff {
+        String::from("/Subtype /OpenType /Filter /FlateDecode")
+    } else {
+        format!("/Length1 {} /Filter /FlateDecode", data.len())
+    };
+    objects.push(stream(&file_dict, &deflate(data)?));
+    let file_object = objects.len();
+
+    let ascent = to_pdf(font.ascent_unscaled());
+    let descent = to_pdf(font.descent_unscaled());
+    //the bounds of every glyph together, guessed from the ascent and descent for a font
+    //without a readable head table
+    let bbox = match head_bbox(data) {
+        Some(bbox) => bbox
+            .iter()
+            .map(|v| to_pdf(*v as f32).to_string())
+            .collect::<Vec<_>>(),
+        None => vec![
+            String::from("-500"),
+            descent.to_string(),
+            String::from("1500"),
+            ascent.to_string(),
+        ],
+    };
+    objects.push(
+        format!(
+            "<< /Type /FontDescriptor /FontName /MapMakerSans /Flags 32 \
+             /FontBBox [{}] /ItalicAngle 0 /Ascent {} /Descent {} \
+             /CapHeight {} /StemV 80 /{} {} 0 R >>",
+            bbox.join(" "),
+            ascent,
+            descent,
+            ascent,
+            if cff { "FontFile3" } else { "FontFile2" },
+            file_object
+        )
+        .into_bytes(),
+    );
+    let descriptor = objects.len();
+
+    let widths: Vec<String> = (32u8..=255)
+        .map(|code| match win_ansi_char(code) {
+            Some(ch) => to_pdf(font.h_advance_unscaled(font.glyph_id(ch))).to_string(),
+            None => String::from("0"),
+        })
+        .collect();
+    objects.push(
+        format!(
+            "<< /Type /Font /Subtype /{} /BaseFont /MapMakerSans /FirstChar 32 \
+             /LastChar 255 /Widths [{}] /Encoding /WinAnsiEncoding /FontDescriptor {} 0 R >>",
+            if cff { "Type1" } else { "TrueType" },
+            widths.join(" "),
+            descriptor
+        )
+        .into_bytes(),
+    );
+    Ok(objects.len())
+}
+
+//opentype files with cff outlines start with OTTO where truetype ones have a version
+fn is_cff(data: &[u8]) -> bool {
+    data.starts_with(b"OTTO")
+}
+
+//xMin, yMin, xMax, yMax in font units from the font's head table
+fn head_bbox(data: &[u8]) -> Option<[i16; 4]> {
+    let u16_at = |at: usize| Some(u16::from_be_bytes([*data.get(at)?, *data.get(at + 1)?]));
+    let u32_at = |at: usize| Some(((u16_at(at)? as u32) << 16) | u16_at(at + 2)? as u32);
+    //a collection points at its fonts, ab_glyph uses the first
+    let start = if data.starts_with(b"ttcf") {
+        u32_at(12)? as usize
+    } else {
+        0
+    };
+    let tables = u16_at(start + 4)? as usize;
+    let head = (0..tables)
+        .map(|idx| start + 12 + idx * 16)
+        .find(|record| data.get(*record..*record + 4) == Some(&b"head"[..]))?;
+    let offset = u32_at(head + 8)? as usize;
+    let value = |at: usize| u16_at(offset + at).map(|v| v as i16);
+    Some([value(36)?, value(38)?, value(40)?, value(42)?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::page::{PaperSize, PrintLayout};
+    use crate::http::HttpSettings;
+    use crate::tile_manager::tile_manager::TileManager;
+
+    const DEJAVU: &str = "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf";
+
+    fn page() -> Page {
+        let layout = PrintLayout {
+            paper: PaperSize::Letter,
+            title: String::from("Trails (and huts)"),
+            ..PrintLayout::default()
+        };
+        let snapshot = TileManager::with_settings(HttpSettings::default(), None).snapshot(&[]);
+        layout.compose(&snapshot, &[], None)
+    }
+
+    //writes `page` and reads it back, checking the xref table points at every object
+    fn written(page: &Page, font: Option<(&FontVec, &[u8])>, name: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("map_maker_{}_{}.pdf", std::process::id(), name));
+        write_pdf(&path, page, font).unwrap();
+        let file = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        //the xref table and trailer are plain text after the binary streams
+        let startxref = file
+            .windows(10)
+            .rposition(|window| window == b"startxref\n")
+            .unwrap();
+        let xref: usize = String::from_utf8_lossy(&file[startxref + 10..])
+            .lines()
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let table = String::from_utf8(file[xref..].to_vec()).unwrap();
+        assert!(table.starts_with("xref\n"));
+        let size: usize = table.lines().nth(1).unwrap()[2..].parse().unwrap();
+        let offsets: Vec<_> = table
+            .lines()
+            .skip(3)
+            .take_while(|line| line.ends_with(" n "))
+            .collect();
+        //the free entry for object 0 and one for each object
+        assert_eq!(offsets.len(), size - 1);
+        for (idx, offset) in offsets.into_iter().enumerate() {
+            let offset: usize = offset[..10].parse().unwrap();
+            let object = format!("{} 0 obj\n", idx + 1);
+            assert!(
+                file[offset..].starts_with(object.as_bytes()),
+                "object {}",
+                idx + 1
+            );
+        }
+        String::from_utf8_lossy(&file).to_string()
+    }
+
+    #[test]
+    fn text_is_encoded_as_win_ansi_strings() {
+        assert_eq!(win_ansi_bytes("Café €5 漢"), b"Caf\xe9 \x805 ?".to_vec());
+        assert_eq!(pdf_string(b"a (b) \\ \xe9"), "(a \\(b\\) \\\\ \\351)");
+        assert_eq!(win_ansi_char(0x81), None);
+        assert_eq!(win_ansi_char(0x99), Some('™'));
+    }
+
+    #[test]
+    fn the_bounding_box_is_read_from_the_head_table() {
+        let data = match std::fs::read(DEJAVU) {
+            Ok(data) => data,
+            Err(_) => return,
+        };
+        assert_eq!(head_bbox(&data), Some([-2090, -948, 3673, 2524]));
+        assert_eq!(head_bbox(&data[..64]), None);
+        assert_eq!(head_bbox(b"ttcf"), None);
+    }
+
+    #[test]
+    fn pages_without_a_font_use_helvetica() {
+        let text = written(&page(), None, "helvetica");
+        assert!(text.starts_with("%PDF-1.4\n"));
+        assert!(text.contains("/BaseFont /Helvetica"));
+        assert!(text.contains("/Subtype /Image"));
+        assert!(text.ends_with("%%EOF\n"));
+    }
+
+    #[test]
+    fn fonts_are_embedded_with_their_metrics() {
+        let data = match std::fs::read(DEJAVU) {
+            Ok(data) => data,
+            Err(_) => return,
+        };
+        let font = FontVec::try_from_vec(data.clone()).unwrap();
+        let text = written(&page(), Some((&font, &data)), "embedded");
+        assert!(text.starts_with("%PDF-1.4\n"));
+        assert!(text.contains("/FontFile2"));
+        assert!(text.contains(&format!("/Length1 {}", data.len())));
+        assert!(text.contains("/FontBBox [-1021 -463 1793 1232]"));
+    }
+}
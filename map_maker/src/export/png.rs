@@ -0,0 +1,35 @@
+use crate::export::ExportError;
+use image::RgbaImage;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+const METERS_PER_INCH: f32 = 0.0254;
+
+/// Writes `image` as an 8 bit RGBA PNG with a pHYs chunk so image viewers and print
+/// dialogs pick up `dpi`.
+pub fn write_png(path: &Path, image: &RgbaImage, dpi: f32) -> Result<(), ExportError> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = ::png::Encoder::new(file, image.width(), image.height());
+    encoder.set_color(::png::ColorType::RGBA);
+    encoder.set_depth(::png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+
+    //pixels per meter on both axes, then unit 1 (meters)
+    let pixels_per_meter = (dpi / METERS_PER_INCH).round() as u32;
+    let mut phys = Vec::with_capacity(9);
+    phys.extend_from_slice(&pixels_per_meter.to_be_bytes());
+    phys.extend_from_slice(&pixels_per_meter.to_be_bytes());
+    phys.push(1);
+    writer.write_chunk(*b"pHYs", &phys)?;
+
+    writer.write_image_data(image.as_raw())?;
+    log::info!(
+        "wrote {}x{} png at {} dpi to {}",
+        image.width(),
+        image.height(),
+        dpi,
+        path.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,444 @@
+//renders an area of the map to an image: stitched tiles, then drawn features, then labels
+//
+//features and labels are sized the way they look on screen at `zoom` and then scaled by
+//the pixel ratio, so a 300 dpi export looks like the window, just sharper. the tiles are
+//fetched at a deeper zoom when the pixel ratio calls for it
+use crate::export::text;
+use crate::features::label::{layout_labels, Label};
+use crate::features::tessellate::tessellate_layers;
+use crate::features::{shown_labels, DrawingLayer};
+use crate::geo::{lat_lon_to_world_pixel, Bounds, LatLon, MapView, TILE_SIZE};
+use crate::tile_manager::tile_manager::TileSnapshot;
+use ab_glyph::FontVec;
+use iced::Color;
+use iced_graphics::triangle::Mesh2D;
+use image::{imageops, Pixel, Rgba, RgbaImage};
+
+//dpi of a pixel ratio of 1
+pub const SCREEN_DPI: f32 = 96.0;
+//deepest zoom the tile servers have
+pub const MAX_TILE_ZOOM: u8 = 19;
+//widest or tallest image an export makes, the image and its tiles have to fit in memory
+pub const MAX_EXPORT_PIXELS: u32 = 20_000;
+//shown where a tile is missing or won't decode
+const MISSING_TILE: Rgba<u8> = Rgba([0xdd, 0xdd, 0xdd, 0xff]);
+
+#[derive(Debug, Clone, Copy)]
+pub struct RasterExport {
+    pub bounds: Bounds,
+    //zoom the features and labels are sized for
+    pub zoom: u8,
+    //output pixels per screen pixel
+    pub pixel_ratio: f32,
+}
+
+/// Reads a bounding box typed as "west,south,east,north" in degrees.
+pub fn parse_bbox(text: &str) -> Option<Bounds> {
+    let values = text
+        .split(',')
+        .map(|value| value.trim().parse::<f64>().ok())
+        .collect::<Option<Vec<f64>>>()?;
+    match values.as_slice() {
+        &[west, south, east, north]
+            if west < east && south < north && south >= -90.0 && north <= 90.0 =>
+        {
+            let mut bounds = Bounds::new(LatLon::new(south, west));
+            bounds.extend(&LatLon::new(north, east));
+            Some(bounds)
+        }
+        _ => None,
+    }
+}
+
+impl RasterExport {
+    pub fn new(bounds: Bounds, zoom: u8, dpi: f32) -> Self {
+        Self {
+            bounds,
+            zoom,
+            pixel_ratio: dpi / SCREEN_DPI,
+        }
+    }
+
+    /// Scales the export so the image comes out `width` pixels across, the height follows
+    /// from the bounds.
+    pub fn with_width(self, width: u32) -> Self {
+        let (top_left, bottom_right) = self.corners(self.zoom);
+        let across = (bottom_right.0 - top_left.0).max(1.0);
+        Self {
+            pixel_ratio: (width as f64 / across) as f32,
+            ..self
+        }
+    }
+
+    pub fn dpi(&self) -> f32 {
+        self.pixel_ratio * SCREEN_DPI
+    }
+
+    //world pixels of the top left and bottom right corners
+    fn corners(&self, zoom: u8) -> ((f64, f64), (f64, f64)) {
+        let top_left =
+            lat_lon_to_world_pixel(&LatLon::new(self.bounds.max.lat, self.bounds.min.lon), zoom);
+        let bottom_right =
+            lat_lon_to_world_pixel(&LatLon::new(self.bounds.min.lat, self.bounds.max.lon), zoom);
+        (top_left, bottom_right)
+    }
+
+    /// Size of the finished image in pixels.
+    pub fn size(&self) -> (u32, u32) {
+        let (top_left, bottom_right) = self.corners(self.zoom);
+        let ratio = self.pixel_ratio as f64;
+        (
+            ((bottom_right.0 - top_left.0) * ratio).round().max(1.0) as u32,
+            ((bottom_right.1 - top_left.1) * ratio).round().max(1.0) as u32,
+        )
+    }
+
+    /// Zoom the tiles are stitched at, one level deeper for every doubling of the pixel
+    /// ratio so the tiles aren't blown up.
+    pub fn tile_zoom(&self) -> u8 {
+        let extra = self.pixel_ratio.max(1.0).log2().round() as u8;
+        (self.zoom + extra).min(MAX_TILE_ZOOM).max(self.zoom)
+    }
+
+    /// x, y, z of every tile the export needs.
+    pub fn tile_coords(&self) -> Vec<(u32, u32, u32)> {
+        let zoom = self.tile_zoom();
+        let (top_left, bottom_right) = self.corners(zoom);
+        let tiles_across = 1i64 << zoom;
+        let first = (
+            (top_left.0 / TILE_SIZE).floor() as i64,
+            (top_left.1 / TILE_SIZE).floor() as i64,
+        );
+        let last = (
+            ((bottom_right.0 / TILE_SIZE).ceil() as i64 - 1).max(first.0),
+            ((bottom_right.1 / TILE_SIZE).ceil() as i64 - 1).max(first.1),
+        );
+        let mut coords = Vec::new();
+        for y in first.1.max(0)..=last.1.min(tiles_across - 1) {
+            for x in first.0..=last.0 {
+                coords.push((x.rem_euclid(tiles_across) as u32, y as u32, zoom as u32));
+            }
+        }
+        coords
+    }
+
+    /// Renders the export. Tiles missing from `snapshot` are left blank, so fetch
+    /// `tile_coords` before taking it. Labels are skipped when there's no `font`.
+    pub fn render(
+        &self,
+        snapshot: &TileSnapshot,
+        layers: &[DrawingLayer],
+        font: Option<&FontVec>,
+    ) -> RgbaImage {
+        let size = self.size();
+        let tiles = self.stitch_tiles(snapshot);
+        let mut image = imageops::resize(&tiles, size.0, size.1, imageops::FilterType::Triangle);
+
+        let (top_left, _) = self.corners(self.zoom);
+        let view = MapView {
+            zoom: self.zoom,
+            top_left,
+        };
+        let mesh = tessellate_layers(layers.iter(), &view);
+        fill_mesh(&mut image, &mesh, self.pixel_ratio);
+
+        match font {
+            Some(font) => self.draw_labels(&mut image, layers, &view, font),
+            None => {
+                if layers.iter().any(|layer| !layer.labels.is_empty()) {
+                    log::warn!("no font found, exporting without labels");
+                }
+            }
+        }
+        image
+    }
+
+    /// The visible raster layers alone, stacked in order at `tile_zoom` and cropped to
+    /// the bounds.
+    pub fn stitch_tiles(&self, snapshot: &TileSnapshot) -> RgbaImage {
+        let zoom = self.tile_zoom();
+        let (top_left, bottom_right) = self.corners(zoom);
+        let width = (bottom_right.0 - top_left.0).ceil().max(1.0) as u32;
+        let height = (bottom_right.1 - top_left.1).ceil().max(1.0) as u32;
+        let mut stitched = RgbaImage::from_pixel(width, height, MISSING_TILE);
+
+        //tiles wrap around the antimeridian, so walk the unwrapped x to place them
+        let first_x = (top_left.0 / TILE_SIZE).floor() as i64;
+        let tiles_across = 1i64 << zoom;
+        for layer in snapshot.visible_layers() {
+            let opacity = snapshot.opacity(layer);
+            for coords in self.tile_coords() {
+                let unwrapped_x = first_x + (coords.0 as i64 - first_x).rem_euclid(tiles_across);
+                let origin = (
+                    (unwrapped_x as f64 * TILE_SIZE - top_left.0).round() as i64,
+                    (coords.1 as f64 * TILE_SIZE - top_left.1).round() as i64,
+                );
+                match snapshot.tile_image(layer, &coords) {
+                    Some(tile) => blit(&mut stitched, &tile, origin, opacity),
+                    None => log::warn!("export missing tile {:?} of layer {}", coords, layer),
+                }
+            }
+        }
+        stitched
+    }
+
+    fn draw_labels(
+        &self,
+        image: &mut RgbaImage,
+        layers: &[DrawingLayer],
+        view: &MapView,
+        font: &FontVec,
+    ) {
+        let ratio = self.pixel_ratio;
+        let labels = shown_labels(layers);
+        let labels: Vec<&Label> = labels.iter().collect();
+        //laid out in screen pixels like the widget does, then scaled up
+        let viewport = (image.width() as f32 / ratio, image.height() as f32 / ratio);
+        let measure = |content: &str, size: u16| text::measure(font, content, size);
+        for placed in layout_labels(&labels, view, viewport, &measure) {
+            let style = placed.style;
+            let size = style.font_size as f32 * ratio;
+            let halo = style.halo_width * ratio;
+            for (glyph, center) in placed.glyphs.iter() {
+                let center = (center.0 * ratio, center.1 * ratio);
+                if halo > 0.0 {
+                    for dx in [-halo, 0.0, halo].iter() {
+                        for dy in [-halo, 0.0, halo].iter() {
+                            if *dx != 0.0 || *dy != 0.0 {
+                                let offset = (center.0 + dx, center.1 + dy);
+                                text::draw_glyph(
+                                    image,
+                                    font,
+                                    *glyph,
+                                    offset,
+                                    size,
+                                    style.halo_color,
+                                );
+                            }
+                        }
+                    }
+                }
+                text::draw_glyph(image, font, *glyph, center, size, style.color);
+            }
+        }
+    }
+}
+
+//copies `tile` into `image` with its top left corner at `origin`, clipping to the image
+//draws `tile` over what's already in `image`, faded to `opacity`
+fn blit(image: &mut RgbaImage, tile: &RgbaImage, origin: (i64, i64), opacity: f32) {
+    for (x, y, pixel) in tile.enumerate_pixels() {
+        let px = origin.0 + x as i64;
+        let py = origin.1 + y as i64;
+        if px >= 0 && py >= 0 && px < image.width() as i64 && py < image.height() as i64 {
+            let mut pixel = *pixel;
+            pixel[3] = (pixel[3] as f32 * opacity).round() as u8;
+            image.get_pixel_mut(px as u32, py as u32).blend(&pixel);
+        }
+    }
+}
+
+//sample points inside a pixel, a rotated grid keeps near horizontal and vertical edges
+//from stair stepping
+const SAMPLES: [(f32, f32); 4] = [
+    (0.375, 0.125),
+    (0.875, 0.375),
+    (0.625, 0.875),
+    (0.125, 0.625),
+];
+
+/// Rasterizes a tessellated mesh onto `image`, scaling its positions by `scale`.
+///
+/// Every triangle is a single color in the meshes the tessellator builds, so the first
+/// vertex's color is used for the whole triangle.
+pub fn fill_mesh(image: &mut RgbaImage, mesh: &Mesh2D, scale: f32) {
+    for triangle in mesh.indices.chunks(3) {
+        if triangle.len() < 3 {
+            continue;
+        }
+        let vertex = |idx: u32| {
+            let position = mesh.vertices[idx as usize].position;
+            (position[0] * scale, position[1] * scale)
+        };
+        let (a, b, c) = (
+            vertex(triangle[0]),
+            vertex(triangle[1]),
+            vertex(triangle[2]),
+        );
+        let color = mesh.vertices[triangle[0] as usize].color;
+        let color = Color::from_rgba(color[0], color[1], color[2], color[3]);
+        fill_triangle(image, a, b, c, color);
+    }
+}
+
+fn fill_triangle(image: &mut RgbaImage, a: (f32, f32), b: (f32, f32), c: (f32, f32), color: Color) {
+    let area = edge(a, b, c);
+    if area == 0.0 {
+        return;
+    }
+    let min_x = a.0.min(b.0).min(c.0).floor().max(0.0) as i64;
+    let min_y = a.1.min(b.1).min(c.1).floor().max(0.0) as i64;
+    let max_x = (a.0.max(b.0).max(c.0).ceil() as i64).min(image.width() as i64 - 1);
+    let max_y = (a.1.max(b.1).max(c.1).ceil() as i64).min(image.height() as i64 - 1);
+    for y in min_y..=max_y {
+        for x in min_x..=max_x {
+            let inside = sample_mask((a, b, c), area, x, y).count_ones();
+            if inside > 0 {
+                let coverage = inside as f32 / SAMPLES.len() as f32;
+                blend_pixel(image, x, y, color, coverage);
+            }
+        }
+    }
+}
+
+//a bit for each of the pixel's samples inside the triangle
+fn sample_mask((a, b, c): ((f32, f32), (f32, f32), (f32, f32)), area: f32, x: i64, y: i64) -> u8 {
+    let mut mask = 0;
+    for (idx, (sx, sy)) in SAMPLES.iter().enumerate() {
+        let p = (x as f32 + sx, y as f32 + sy);
+        //same sign as the whole triangle on all three edges, whatever the winding
+        if edge(a, b, p) * area >= 0.0 && edge(b, c, p) * area >= 0.0 && edge(c, a, p) * area >= 0.0
+        {
+            mask |= 1 << idx;
+        }
+    }
+    mask
+}
+
+/// Rasterizes all of `mesh` as one shape in `color`, ignoring the vertex colors.
+///
+/// Pixels where triangles meet or overlap are only blended once, so fills don't show the
+/// seams between their triangles and see-through lines don't darken at their joins.
+pub fn fill_shape(image: &mut RgbaImage, mesh: &Mesh2D, color: Color) {
+    if mesh.vertices.is_empty() || color.a <= 0.0 {
+        return;
+    }
+    let (mut min, mut max) = ((f32::MAX, f32::MAX), (f32::MIN, f32::MIN));
+    for vertex in &mesh.vertices {
+        let [x, y] = vertex.position;
+        min = (min.0.min(x), min.1.min(y));
+        max = (max.0.max(x), max.1.max(y));
+    }
+    let min_x = min.0.floor().max(0.0) as i64;
+    let min_y = min.1.floor().max(0.0) as i64;
+    let max_x = (max.0.ceil() as i64).min(image.width() as i64 - 1);
+    let max_y = (max.1.ceil() as i64).min(image.height() as i64 - 1);
+    if min_x > max_x || min_y > max_y {
+        return;
+    }
+    let width = (max_x - min_x + 1) as usize;
+    let mut masks = vec![0u8; width * (max_y - min_y + 1) as usize];
+    for triangle in mesh.indices.chunks(3) {
+        if triangle.len() < 3 {
+            continue;
+        }
+        let vertex = |idx: u32| {
+            let position = mesh.vertices[idx as usize].position;
+            (position[0], position[1])
+        };
+        let (a, b, c) = (
+            vertex(triangle[0]),
+            vertex(triangle[1]),
+            vertex(triangle[2]),
+        );
+        let area = edge(a, b, c);
+        if area == 0.0 {
+            continue;
+        }
+        let top = (a.1.min(b.1).min(c.1).floor() as i64).max(min_y);
+        let bottom = (a.1.max(b.1).max(c.1).ceil() as i64).min(max_y);
+        let left = (a.0.min(b.0).min(c.0).floor() as i64).max(min_x);
+        let right = (a.0.max(b.0).max(c.0).ceil() as i64).min(max_x);
+        for y in top..=bottom {
+            for x in left..=right {
+                let idx = (y - min_y) as usize * width + (x - min_x) as usize;
+                masks[idx] |= sample_mask((a, b, c), area, x, y);
+            }
+        }
+    }
+    for (idx, mask) in masks.iter().enumerate() {
+        if *mask != 0 {
+            let coverage = mask.count_ones() as f32 / SAMPLES.len() as f32;
+            let (x, y) = (min_x + (idx % width) as i64, min_y + (idx / width) as i64);
+            blend_pixel(image, x, y, color, coverage);
+        }
+    }
+}
+
+//twice the signed area of the triangle a, b, p
+fn edge(a: (f32, f32), b: (f32, f32), p: (f32, f32)) -> f32 {
+    (b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0)
+}
+
+/// Source over blending of `color` at `coverage` onto a single pixel, ignoring pixels
+/// outside the image.
+pub fn blend_pixel(image: &mut RgbaImage, x: i64, y: i64, color: Color, coverage: f32) {
+    if x < 0 || y < 0 || x >= image.width() as i64 || y >= image.height() as i64 {
+        return;
+    }
+    let alpha = (color.a * coverage).max(0.0).min(1.0);
+    if alpha <= 0.0 {
+        return;
+    }
+    let pixel = image.get_pixel_mut(x as u32, y as u32);
+    let dst_alpha = pixel[3] as f32 / 255.0;
+    let out_alpha = alpha + dst_alpha * (1.0 - alpha);
+    let src = [color.r, color.g, color.b];
+    for channel in 0..3 {
+        let dst = pixel[channel] as f32 / 255.0;
+        let out = (src[channel] * alpha + dst * dst_alpha * (1.0 - alpha)) / out_alpha;
+        pixel[channel] = (out * 255.0).round() as u8;
+    }
+    pixel[3] = (out_alpha * 255.0).round() as u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounding_boxes_are_west_south_east_north() {
+        let bounds = parse_bbox(" 7.0, 46.0 ,7.5,46.5").unwrap();
+        assert_eq!(bounds.min, LatLon::new(46.0, 7.0));
+        assert_eq!(bounds.max, LatLon::new(46.5, 7.5));
+        for text in [
+            "7.5,46,7,46.5",
+            "7,46.5,7.5,46",
+            "7,-91,8,46",
+            "7,46,8",
+            "7,46,8,x",
+            "",
+        ]
+        .iter()
+        {
+            assert!(parse_bbox(text).is_none(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn exports_fetch_deeper_tiles_for_more_pixels() {
+        let bounds = parse_bbox("7.0,46.0,7.1,46.1").unwrap();
+        let screen = RasterExport::new(bounds, 12, SCREEN_DPI);
+        assert_eq!(screen.tile_zoom(), 12);
+        let print = RasterExport::new(bounds, 12, SCREEN_DPI * 4.0);
+        assert_eq!(print.tile_zoom(), 14);
+        let (width, height) = screen.size();
+        let (print_width, print_height) = print.size();
+        assert!((print_width as i64 - width as i64 * 4).abs() <= 4);
+        assert!((print_height as i64 - height as i64 * 4).abs() <= 4);
+        assert!(print.tile_coords().iter().all(|coords| coords.2 == 14));
+        assert_eq!(screen.with_width(width * 2).size().0, width * 2);
+    }
+
+    #[test]
+    fn tiles_are_found_across_the_antimeridian() {
+        let bounds = parse_bbox("179.9,-0.1,180.1,0.1").unwrap();
+        let wrapped = RasterExport::new(bounds, 4, SCREEN_DPI);
+        let columns: Vec<u32> = wrapped
+            .tile_coords()
+            .iter()
+            .map(|coords| coords.0)
+            .collect();
+        assert_eq!(columns, [15, 0, 15, 0]);
+    }
+}
@@ -0,0 +1,189 @@
+//writes a composed page as SVG, in millimeters so it prints at the right scale
+use crate::export::page::{Page, PageItem, TextAnchor};
+use crate::export::ExportError;
+use crate::features::color_to_hex;
+use crate::formats::xml_escape;
+use iced::Color;
+use image::codecs::png::PngEncoder;
+use image::ColorType;
+use std::fmt::Write;
+use std::path::Path;
+
+//the pdf writer falls back to helvetica, so ask for it here too
+const FONT_FAMILY: &str = "DejaVu Sans, Helvetica, Arial, sans-serif";
+
+pub fn write_svg(path: &Path, page: &Page) -> Result<(), ExportError> {
+    std::fs::write(path, to_svg(page)?)?;
+    log::info!("wrote svg to {}", path.display());
+    Ok(())
+}
+
+fn paint(attribute: &str, color: &Color) -> String {
+    let mut out = format!("{}=\"{}\"", attribute, color_to_hex(color));
+    if color.a < 1.0 {
+        let _ = write!(out, " {}-opacity=\"{:.3}\"", attribute, color.a);
+    }
+    out
+}
+
+pub fn to_svg(page: &Page) -> Result<String, ExportError> {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" \
+         width=\"{w}mm\" height=\"{h}mm\" viewBox=\"0 0 {w} {h}\">",
+        w = page.size.0,
+        h = page.size.1
+    );
+    for item in &page.items {
+        match item {
+            PageItem::Image { image, rect } => {
+                let mut png = Vec::new();
+                PngEncoder::new(&mut png).encode(
+                    image.as_raw(),
+                    image.width(),
+                    image.height(),
+                    ColorType::Rgba8,
+                )?;
+                let data = base64::encode(&png);
+                let _ = writeln!(
+                    out,
+                    "  <image x=\"{:.3}\" y=\"{:.3}\" width=\"{:.3}\" height=\"{:.3}\" \
+                     preserveAspectRatio=\"none\" href=\"data:image/png;base64,{d}\" \
+                     xlink:href=\"data:image/png;base64,{d}\"/>",
+                    rect.x,
+                    rect.y,
+                    rect.width,
+                    rect.height,
+                    d = data
+                );
+            }
+            PageItem::Path {
+                rings,
+                closed,
+                fill,
+                stroke,
+            } => {
+                let mut d = String::new();
+                for ring in rings {
+                    for (idx, (x, y)) in ring.iter().enumerate() {
+                        let op = if idx == 0 { 'M' } else { 'L' };
+                        let _ = write!(d, "{}{:.3} {:.3} ", op, x, y);
+                    }
+                    if *closed {
+                        d.push_str("Z ");
+                    }
+                }
+                let fill = match fill {
+                    Some(color) => format!("{} fill-rule=\"evenodd\"", paint("fill", color)),
+                    None => String::from("fill=\"none\""),
+                };
+                let stroke = match stroke {
+                    Some((color, width)) => format!(
+                        "{} stroke-width=\"{:.3}\" stroke-linejoin=\"round\" stroke-linecap=\"round\"",
+                        paint("stroke", color),
+                        width
+                    ),
+                    None => String::from("stroke=\"none\""),
+                };
+                let _ = writeln!(out, "  <path d=\"{}\" {} {}/>", d.trim_end(), fill, stroke);
+            }
+            PageItem::Text {
+                text,
+                position,
+                size,
+                color,
+                anchor,
+                halo,
+            } => {
+                let anchor = match anchor {
+                    TextAnchor::Start => "start",
+                    TextAnchor::Middle => "middle",
+                    TextAnchor::End => "end",
+                };
+                let open = format!(
+                    "<text x=\"{:.3}\" y=\"{:.3}\" font-size=\"{:.3}\" font-family=\"{}\" \
+                     text-anchor=\"{}\"",
+                    position.0, position.1, size, FONT_FAMILY, anchor
+                );
+                let content = xml_escape(text);
+                //the halo is a stroked copy underneath, paint-order isn't everywhere yet
+                if let Some((halo_color, halo_width)) = halo {
+                    if *halo_width > 0.0 {
+                        let _ = writeln!(
+                            out,
+                            "  {} fill=\"none\" {} stroke-width=\"{:.3}\" \
+                             stroke-linejoin=\"round\">{}</text>",
+                            open,
+                            paint("stroke", halo_color),
+                            halo_width * 2.0,
+                            content
+                        );
+                    }
+                }
+                let _ = writeln!(
+                    out,
+                    "  {} {}>{}</text>",
+                    open,
+                    paint("fill", color),
+                    content
+                );
+            }
+        }
+    }
+    out.push_str("</svg>\n");
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::page::{PaperSize, PrintLayout};
+    use crate::http::HttpSettings;
+    use crate::tile_manager::tile_manager::TileManager;
+
+    #[test]
+    fn pages_are_svg_in_millimeters() {
+        let layout = PrintLayout {
+            paper: PaperSize::Letter,
+            title: String::from("Huts & <Trails>"),
+            ..PrintLayout::default()
+        };
+        let snapshot = TileManager::with_settings(HttpSettings::default(), None).snapshot(&[]);
+        let page = layout.compose(&snapshot, &[], None);
+        let svg = to_svg(&page).unwrap();
+
+        let doc = roxmltree::Document::parse(&svg).unwrap();
+        let root = doc.root_element();
+        assert_eq!(root.tag_name().name(), "svg");
+        assert_eq!(root.attribute("width"), Some("215.9mm"));
+        assert_eq!(root.attribute("viewBox"), Some("0 0 215.9 279.4"));
+        let texts: Vec<_> = root
+            .children()
+            .filter(|node| node.tag_name().name() == "text")
+            .filter_map(|node| node.text())
+            .collect();
+        assert!(texts.contains(&"Huts & <Trails>"));
+        let image = root
+            .children()
+            .find(|node| node.tag_name().name() == "image")
+            .unwrap();
+        assert!(image
+            .attribute("href")
+            .unwrap()
+            .starts_with("data:image/png;base64,"));
+    }
+
+    #[test]
+    fn see_through_colors_get_an_opacity() {
+        assert_eq!(
+            paint("fill", &Color::from_rgb8(255, 0, 16)),
+            "fill=\"#ff0010\""
+        );
+        assert_eq!(
+            paint("stroke", &Color::from_rgba8(0, 0, 0, 0.5)),
+            "stroke=\"#000000\" stroke-opacity=\"0.500\""
+        );
+    }
+}
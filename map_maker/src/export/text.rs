@@ -0,0 +1,90 @@
+//text for the exporters, which can't borrow the font iced renders the window with
+use crate::export::raster;
+use ab_glyph::{point, Font, FontVec, PxScale, ScaleFont};
+use iced::Color;
+use image::RgbaImage;
+
+//tried in order, the first one that parses is used
+const FONT_PATHS: [&str; 7] = [
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/truetype/liberation/LiberationSans-Regular.ttf",
+    "/Library/Fonts/Arial.ttf",
+    "/System/Library/Fonts/Supplemental/Arial.ttf",
+    "C:\\Windows\\Fonts\\arial.ttf",
+];
+
+/// A sans serif system font and the file it was parsed from, for the writers that
+/// embed it. `MAP_MAKER_FONT` overrides the search.
+pub fn load_font_file() -> Option<(FontVec, Vec<u8>)> {
+    let custom = std::env::var("MAP_MAKER_FONT").ok();
+    custom
+        .iter()
+        .map(String::as_str)
+        .chain(FONT_PATHS.iter().copied())
+        .find_map(|path| {
+            let data = std::fs::read(path).ok()?;
+            let font = FontVec::try_from_vec(data.clone()).ok()?;
+            log::info!("export font {}", path);
+            Some((font, data))
+        })
+}
+
+/// Width and height of `content` at `size` pixels, in the shape label layout wants.
+pub fn measure(font: &FontVec, content: &str, size: u16) -> (f32, f32) {
+    let scaled = font.as_scaled(PxScale::from(size as f32));
+    let width = content
+        .chars()
+        .map(|ch| scaled.h_advance(scaled.glyph_id(ch)))
+        .sum();
+    (width, scaled.ascent() - scaled.descent())
+}
+
+/// Draws one glyph centered on `center`, blending it over what's already there.
+pub fn draw_glyph(
+    image: &mut RgbaImage,
+    font: &FontVec,
+    glyph: char,
+    center: (f32, f32),
+    size: f32,
+    color: Color,
+) {
+    let scaled = font.as_scaled(PxScale::from(size));
+    let id = scaled.glyph_id(glyph);
+    let advance = scaled.h_advance(id);
+    //the center of the advance box, vertically halfway between ascent and descent
+    let baseline = center.1 + (scaled.ascent() + scaled.descent()) / 2.0;
+    let positioned = id.with_scale_and_position(size, point(center.0 - advance / 2.0, baseline));
+    if let Some(outline) = font.outline_glyph(positioned) {
+        let origin = outline.px_bounds().min;
+        outline.draw(|x, y, coverage| {
+            let px = origin.x as i64 + x as i64;
+            let py = origin.y as i64 + y as i64;
+            raster::blend_pixel(image, px, py, color, coverage);
+        });
+    }
+}
+
+/// Font size in ems of text that is `height` from ascent to descent. PDF and SVG size
+/// text in ems where ab_glyph and iced use the height.
+pub fn em_size(font: &FontVec, height: f32) -> f32 {
+    let units_per_em = font.units_per_em().unwrap_or(1000.0);
+    height * units_per_em / font.height_unscaled()
+}
+
+/// How far below the vertical center of a line of text its baseline is.
+pub fn baseline_offset(font: &FontVec, height: f32) -> f32 {
+    let scaled = font.as_scaled(PxScale::from(height));
+    (scaled.ascent() + scaled.descent()) / 2.0
+}
+
+/// Width of `content` at `size` ems.
+pub fn em_width(font: &FontVec, content: &str, size: f32) -> f32 {
+    let units_per_em = font.units_per_em().unwrap_or(1000.0);
+    let advance: f32 = content
+        .chars()
+        .map(|ch| font.h_advance_unscaled(font.glyph_id(ch)))
+        .sum();
+    advance / units_per_em * size
+}
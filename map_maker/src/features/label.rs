@@ -0,0 +1,271 @@
+use crate::features::{Feature, Geometry};
+use crate::geo::{lat_lon_to_world_pixel, LatLon, MapView};
+use iced::Color;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LabelAnchor {
+    //text centered on a point
+    Point(LatLon),
+    //text follows the line, e.g. a trail name
+    Polyline(Vec<LatLon>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LabelStyle {
+    pub font_size: u16,
+    pub color: Color,
+    pub halo_color: Color,
+    //0 disables the halo
+    pub halo_width: f32,
+    //degrees clockwise, only used for point labels since line labels follow the line
+    pub rotation: f32,
+}
+
+impl Default for LabelStyle {
+    fn default() -> Self {
+        Self {
+            font_size: 16,
+            color: Color::BLACK,
+            halo_color: Color::WHITE,
+            halo_width: 1.5,
+            rotation: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub text: String,
+    pub anchor: LabelAnchor,
+    pub style: LabelStyle,
+}
+
+impl Label {
+    pub fn new(text: &str, anchor: LabelAnchor) -> Self {
+        Self {
+            text: text.to_string(),
+            anchor,
+            style: LabelStyle::default(),
+        }
+    }
+
+    pub fn points(&self) -> Vec<&LatLon> {
+        match &self.anchor {
+            LabelAnchor::Point(point) => vec![point],
+            LabelAnchor::Polyline(line) => line.iter().collect(),
+        }
+    }
+}
+
+/// How many pixels from a line or a label a right click can be and still pick it.
+pub const PICK_DISTANCE: f64 = 8.0;
+
+/// Index of the label under `at`, roughly, going by where its text would be at `zoom`.
+/// Later labels are drawn over earlier ones, so they're picked first.
+pub fn label_at(labels: &[Label], at: &LatLon, zoom: u8) -> Option<usize> {
+    let at = lat_lon_to_world_pixel(at, zoom);
+    labels.iter().rposition(|label| {
+        let size = label.style.font_size as f64;
+        match &label.anchor {
+            LabelAnchor::Point(point) => {
+                let (x, y) = lat_lon_to_world_pixel(point, zoom);
+                //without a font to hand, glyphs are taken as 0.6 of the size across
+                let half_width = label.text.chars().count() as f64 * size * 0.3;
+                (at.0 - x).abs() <= half_width + PICK_DISTANCE
+                    && (at.1 - y).abs() <= size / 2.0 + PICK_DISTANCE
+            }
+            LabelAnchor::Polyline(line) => {
+                distance_to_line(line, at, zoom) <= size / 2.0 + PICK_DISTANCE
+            }
+        }
+    })
+}
+
+/// The drawn line passing closest to `at`, if one is within PICK_DISTANCE at `zoom`, so a
+/// label can follow it. Polygon outlines count, their labels run along the edge.
+pub fn line_near(features: &[Feature], at: &LatLon, zoom: u8) -> Option<Vec<LatLon>> {
+    let at = lat_lon_to_world_pixel(at, zoom);
+    let lines = features.iter().flat_map(|feature| match &feature.geometry {
+        Geometry::LineString(line) => vec![line],
+        Geometry::MultiLineString(lines) | Geometry::Polygon(lines) => lines.iter().collect(),
+        Geometry::MultiPolygon(polygons) => polygons.iter().flatten().collect(),
+        Geometry::Point(_) | Geometry::MultiPoint(_) => Vec::new(),
+    });
+    lines
+        .filter(|line| line.len() >= 2)
+        .map(|line| (distance_to_line(line, at, zoom), line))
+        .filter(|(distance, _)| *distance <= PICK_DISTANCE)
+        .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(_, line)| line.clone())
+}
+
+//world pixels from `at` to the nearest point of `line`
+fn distance_to_line(line: &[LatLon], at: (f64, f64), zoom: u8) -> f64 {
+    let points: Vec<(f64, f64)> = line
+        .iter()
+        .map(|point| lat_lon_to_world_pixel(point, zoom))
+        .collect();
+    if points.len() == 1 {
+        return ((at.0 - points[0].0).powi(2) + (at.1 - points[0].1).powi(2)).sqrt();
+    }
+    points
+        .windows(2)
+        .map(|seg| {
+            let (dx, dy) = (seg[1].0 - seg[0].0, seg[1].1 - seg[0].1);
+            let length = dx * dx + dy * dy;
+            let t = if length > 0.0 {
+                (((at.0 - seg[0].0) * dx + (at.1 - seg[0].1) * dy) / length).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let (x, y) = (seg[0].0 + dx * t, seg[0].1 + dy * t);
+            ((at.0 - x).powi(2) + (at.1 - y).powi(2)).sqrt()
+        })
+        .fold(f64::MAX, f64::min)
+}
+
+/// A label that survived placement, as individual glyphs in screen space.
+///
+/// The renderers can't rotate text, so rotated and curved labels are drawn by putting
+/// each upright glyph on the rotated baseline or along the line.
+#[derive(Debug, Clone)]
+pub struct PlacedLabel {
+    //glyph and the screen position of its center
+    pub glyphs: Vec<(char, (f32, f32))>,
+    pub style: LabelStyle,
+    //x0, y0, x1, y1 including the halo
+    pub bounds: (f32, f32, f32, f32),
+}
+
+fn overlaps(a: &(f32, f32, f32, f32), b: &(f32, f32, f32, f32)) -> bool {
+    a.0 < b.2 && b.0 < a.2 && a.1 < b.3 && b.1 < a.3
+}
+
+/// Positions the glyphs of every visible label, dropping labels that would overlap a
+/// label that was already placed. Bigger labels are placed first so they win collisions.
+///
+/// `measure` returns the width and height of a string at a font size, and `viewport` is
+/// the width and height of the area being drawn.
+pub fn layout_labels(
+    labels: &[&Label],
+    view: &MapView,
+    viewport: (f32, f32),
+    measure: &dyn Fn(&str, u16) -> (f32, f32),
+) -> Vec<PlacedLabel> {
+    let mut order: Vec<&Label> = labels.to_vec();
+    order.sort_by(|a, b| b.style.font_size.cmp(&a.style.font_size));
+
+    let screen = (0.0, 0.0, viewport.0, viewport.1);
+    let mut placed: Vec<PlacedLabel> = Vec::new();
+    for label in order {
+        if let Some(candidate) = place_label(label, view, measure) {
+            if !overlaps(&candidate.bounds, &screen) {
+                continue;
+            }
+            if placed
+                .iter()
+                .any(|other| overlaps(&other.bounds, &candidate.bounds))
+            {
+                log::trace!("label '{}' collides, skipping", label.text);
+                continue;
+            }
+            placed.push(candidate);
+        }
+    }
+    placed
+}
+
+fn place_label(
+    label: &Label,
+    view: &MapView,
+    measure: &dyn Fn(&str, u16) -> (f32, f32),
+) -> Option<PlacedLabel> {
+    let size = label.style.font_size;
+    let widths: Vec<f32> = label
+        .text
+        .chars()
+        .map(|ch| measure(&ch.to_string(), size).0)
+        .collect();
+    let total_width: f32 = widths.iter().sum();
+    if total_width <= 0.0 {
+        return None;
+    }
+    let height = measure(&label.text, size).1.max(size as f32);
+
+    //distance along the baseline of each glyph center, relative to the start of the text
+    let mut offsets = Vec::with_capacity(widths.len());
+    let mut run = 0.0;
+    for width in &widths {
+        offsets.push(run + width / 2.0);
+        run += width;
+    }
+
+    let centers: Vec<(f32, f32)> = match &label.anchor {
+        LabelAnchor::Point(lat_lon) => {
+            let anchor = view.to_screen(lat_lon);
+            let angle = label.style.rotation.to_radians();
+            let dir = (angle.cos(), angle.sin());
+            offsets
+                .iter()
+                .map(|offset| {
+                    let along = offset - total_width / 2.0;
+                    (anchor.0 + dir.0 * along, anchor.1 + dir.1 * along)
+                })
+                .collect()
+        }
+        LabelAnchor::Polyline(points) => {
+            let mut line: Vec<(f32, f32)> = points.iter().map(|p| view.to_screen(p)).collect();
+            if line.len() < 2 {
+                return None;
+            }
+            //keep the text reading left to right
+            if line[0].0 > line[line.len() - 1].0 {
+                line.reverse();
+            }
+            let length: f32 = line
+                .windows(2)
+                .map(|seg| ((seg[1].0 - seg[0].0).powi(2) + (seg[1].1 - seg[0].1).powi(2)).sqrt())
+                .sum();
+            if length < total_width {
+                return None;
+            }
+            let start = (length - total_width) / 2.0;
+            offsets
+                .iter()
+                .map(|offset| point_along(&line, start + offset))
+                .collect()
+        }
+    };
+
+    let pad = label.style.halo_width;
+    let mut bounds = (f32::MAX, f32::MAX, f32::MIN, f32::MIN);
+    for (center, width) in centers.iter().zip(widths.iter()) {
+        bounds.0 = bounds.0.min(center.0 - width / 2.0 - pad);
+        bounds.1 = bounds.1.min(center.1 - height / 2.0 - pad);
+        bounds.2 = bounds.2.max(center.0 + width / 2.0 + pad);
+        bounds.3 = bounds.3.max(center.1 + height / 2.0 + pad);
+    }
+
+    Some(PlacedLabel {
+        glyphs: label.text.chars().zip(centers.into_iter()).collect(),
+        style: label.style,
+        bounds,
+    })
+}
+
+//walks `distance` pixels along the line, clamping to its ends
+fn point_along(line: &[(f32, f32)], distance: f32) -> (f32, f32) {
+    let mut remaining = distance;
+    for seg in line.windows(2) {
+        let seg_len = ((seg[1].0 - seg[0].0).powi(2) + (seg[1].1 - seg[0].1).powi(2)).sqrt();
+        if remaining <= seg_len && seg_len > 0.0 {
+            let t = remaining / seg_len;
+            return (
+                seg[0].0 + (seg[1].0 - seg[0].0) * t,
+                seg[0].1 + (seg[1].1 - seg[0].1) * t,
+            );
+        }
+        remaining -= seg_len;
+    }
+    line[line.len() - 1]
+}
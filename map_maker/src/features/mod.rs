@@ -0,0 +1,230 @@
+//things drawn on top of the tiles, stored in lat/lon so they survive zooming and panning
+pub mod label;
+pub mod tessellate;
+
+use crate::geo::{Bounds, LatLon};
+use iced::Color;
+use label::Label;
+use std::hash::{Hash, Hasher};
+
+pub type Properties = serde_json::Map<String, serde_json::Value>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Geometry {
+    Point(LatLon),
+    LineString(Vec<LatLon>),
+    //outer ring first, then holes
+    Polygon(Vec<Vec<LatLon>>),
+    MultiPoint(Vec<LatLon>),
+    MultiLineString(Vec<Vec<LatLon>>),
+    MultiPolygon(Vec<Vec<Vec<LatLon>>>),
+}
+
+impl Geometry {
+    /// Every coordinate in the geometry, in the order they're stored.
+    pub fn points(&self) -> Vec<&LatLon> {
+        match self {
+            Geometry::Point(point) => vec![point],
+            Geometry::LineString(line) | Geometry::MultiPoint(line) => line.iter().collect(),
+            Geometry::Polygon(rings) | Geometry::MultiLineString(rings) => {
+                rings.iter().flatten().collect()
+            }
+            Geometry::MultiPolygon(polygons) => polygons.iter().flatten().flatten().collect(),
+        }
+    }
+}
+
+/// Values recorded alongside a coordinate, e.g. by a GPS unit.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PointData {
+    //meters
+    pub elevation: Option<f64>,
+    //ISO 8601, kept as written
+    pub time: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeatureStyle {
+    pub stroke: Color,
+    pub stroke_width: f32,
+    pub fill: Color,
+    pub marker_color: Color,
+    //radius in pixels
+    pub marker_size: f32,
+}
+
+impl Default for FeatureStyle {
+    fn default() -> Self {
+        Self {
+            stroke: Color::from_rgb8(0x55, 0x55, 0x55),
+            stroke_width: 2.0,
+            fill: Color::from_rgba8(0x55, 0x55, 0x55, 0.6),
+            marker_color: Color::from_rgb8(0x7e, 0x7e, 0x7e),
+            marker_size: 6.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Feature {
+    pub geometry: Geometry,
+    //whatever came with the feature on import, written back out on export
+    pub properties: Properties,
+    pub style: FeatureStyle,
+    //one entry per coordinate in `geometry.points()` order, empty if nothing was recorded
+    pub point_data: Vec<PointData>,
+}
+
+impl Feature {
+    pub fn new(geometry: Geometry) -> Self {
+        Self {
+            geometry,
+            properties: Properties::new(),
+            style: FeatureStyle::default(),
+            point_data: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DrawingLayer {
+    pub name: String,
+    pub features: Vec<Feature>,
+    pub labels: Vec<Label>,
+    pub visible: bool,
+    //scales the alpha of everything in the layer
+    pub opacity: f32,
+    //locked layers can't be renamed, deleted or added to
+    pub locked: bool,
+}
+
+impl DrawingLayer {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            features: Vec::new(),
+            labels: Vec::new(),
+            visible: true,
+            opacity: 1.0,
+            locked: false,
+        }
+    }
+
+    /// Whether anything of the layer ends up on the map.
+    pub fn is_shown(&self) -> bool {
+        self.visible && self.opacity > 0.0
+    }
+
+    /// `color` faded by the layer's opacity.
+    pub fn fade(&self, color: Color) -> Color {
+        Color {
+            a: color.a * self.opacity,
+            ..color
+        }
+    }
+
+    pub fn faded_style(&self, style: &FeatureStyle) -> FeatureStyle {
+        FeatureStyle {
+            stroke: self.fade(style.stroke),
+            fill: self.fade(style.fill),
+            marker_color: self.fade(style.marker_color),
+            ..*style
+        }
+    }
+
+    /// Feeds everything that changes how the layer is drawn to `state`, so widgets can tell
+    /// when it has to be drawn again. Floats go in by their bits.
+    pub fn hash_drawn<H: Hasher>(&self, state: &mut H) {
+        fn color<H: Hasher>(color: &Color, state: &mut H) {
+            for channel in [color.r, color.g, color.b, color.a].iter() {
+                channel.to_bits().hash(state);
+            }
+        }
+        fn point<H: Hasher>(point: &LatLon, state: &mut H) {
+            point.lat.to_bits().hash(state);
+            point.lon.to_bits().hash(state);
+        }
+        self.visible.hash(state);
+        self.opacity.to_bits().hash(state);
+        self.features.len().hash(state);
+        for feature in self.features.iter() {
+            std::mem::discriminant(&feature.geometry).hash(state);
+            let points = feature.geometry.points();
+            points.len().hash(state);
+            points.into_iter().for_each(|p| point(p, state));
+            let style = &feature.style;
+            color(&style.stroke, state);
+            color(&style.fill, state);
+            color(&style.marker_color, state);
+            style.stroke_width.to_bits().hash(state);
+            style.marker_size.to_bits().hash(state);
+        }
+        self.labels.len().hash(state);
+        for label in self.labels.iter() {
+            label.text.hash(state);
+            let points = label.points();
+            points.len().hash(state);
+            points.into_iter().for_each(|p| point(p, state));
+            let style = &label.style;
+            style.font_size.hash(state);
+            color(&style.color, state);
+            color(&style.halo_color, state);
+            style.halo_width.to_bits().hash(state);
+            style.rotation.to_bits().hash(state);
+        }
+    }
+
+    /// The area covered by the layer's features and labels, if it has any.
+    pub fn bounds(&self) -> Option<Bounds> {
+        let mut bounds: Option<Bounds> = None;
+        let features = self.features.iter().flat_map(|f| f.geometry.points());
+        let labels = self.labels.iter().flat_map(|label| label.points());
+        for point in features.chain(labels) {
+            match bounds.as_mut() {
+                Some(bounds) => bounds.extend(point),
+                None => bounds = Some(Bounds::new(*point)),
+            }
+        }
+        bounds
+    }
+}
+
+/// The labels of the layers that are shown, faded with their layer.
+pub fn shown_labels(layers: &[DrawingLayer]) -> Vec<Label> {
+    let mut labels = Vec::new();
+    for layer in layers.iter().filter(|layer| layer.is_shown()) {
+        for label in &layer.labels {
+            let mut label = label.clone();
+            label.style.color = layer.fade(label.style.color);
+            label.style.halo_color = layer.fade(label.style.halo_color);
+            labels.push(label);
+        }
+    }
+    labels
+}
+
+/// Parses `#rgb` or `#rrggbb` as used by the simplestyle properties.
+pub fn color_from_hex(hex: &str) -> Option<Color> {
+    let hex = hex.trim().trim_start_matches('#');
+    let digits: Vec<u8> = match hex.len() {
+        3 => hex
+            .chars()
+            .map(|c| c.to_digit(16).map(|d| (d * 17) as u8))
+            .collect::<Option<Vec<u8>>>()?,
+        6 => (0..3)
+            .map(|i| u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?,
+        _ => return None,
+    };
+    Some(Color::from_rgb8(digits[0], digits[1], digits[2]))
+}
+
+/// Formats the rgb part of a colour as `#rrggbb`, alpha is written separately as an opacity.
+pub fn color_to_hex(color: &Color) -> String {
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        (color.r * 255.0).round() as u8,
+        (color.g * 255.0).round() as u8,
+        (color.b * 255.0).round() as u8
+    )
+}
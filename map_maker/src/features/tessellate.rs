@@ -0,0 +1,195 @@
+//turns drawn features into triangles in screen space so they can be drawn as a single
+//Mesh2D primitive, or rasterized by the exporters
+use crate::features::{DrawingLayer, Feature, Geometry};
+use crate::geo::{LatLon, MapView};
+use iced::Color;
+use iced_graphics::triangle::{Mesh2D, Vertex2D};
+
+//segments used to approximate markers and line joins
+const CIRCLE_SEGMENTS: usize = 12;
+
+pub fn tessellate_layers<'a>(
+    layers: impl Iterator<Item = &'a DrawingLayer>,
+    view: &MapView,
+) -> Mesh2D {
+    let mut mesh = Mesh2D {
+        vertices: Vec::new(),
+        indices: Vec::new(),
+    };
+    for layer in layers.filter(|layer| layer.is_shown()) {
+        let first_vertex = mesh.vertices.len();
+        for feature in &layer.features {
+            tessellate_feature(&mut mesh, feature, view);
+        }
+        if layer.opacity < 1.0 {
+            for vertex in &mut mesh.vertices[first_vertex..] {
+                vertex.color[3] *= layer.opacity;
+            }
+        }
+    }
+    mesh
+}
+
+pub fn tessellate_feature(mesh: &mut Mesh2D, feature: &Feature, view: &MapView) {
+    let style = &feature.style;
+    let to_screen = |points: &[LatLon]| -> Vec<(f32, f32)> {
+        points.iter().map(|p| view.to_screen(p)).collect()
+    };
+    let marker = |mesh: &mut Mesh2D, point: &LatLon| {
+        let center = view.to_screen(point);
+        disc(mesh, center, style.marker_size + 1.0, style.stroke);
+        disc(mesh, center, style.marker_size, style.marker_color);
+    };
+    let polygon = |mesh: &mut Mesh2D, rings: &[Vec<LatLon>]| {
+        let rings: Vec<Vec<(f32, f32)>> = rings.iter().map(|ring| to_screen(ring)).collect();
+        fill(mesh, &rings, style.fill);
+        for ring in &rings {
+            let mut closed = ring.clone();
+            if let Some(first) = ring.first() {
+                closed.push(*first);
+            }
+            stroke(mesh, &closed, style.stroke_width, style.stroke);
+        }
+    };
+
+    match &feature.geometry {
+        Geometry::Point(point) => marker(mesh, point),
+        Geometry::MultiPoint(points) => points.iter().for_each(|point| marker(mesh, point)),
+        Geometry::LineString(line) => {
+            stroke(mesh, &to_screen(line), style.stroke_width, style.stroke)
+        }
+        Geometry::MultiLineString(lines) => {
+            for line in lines {
+                stroke(mesh, &to_screen(line), style.stroke_width, style.stroke);
+            }
+        }
+        Geometry::Polygon(rings) => polygon(mesh, rings),
+        Geometry::MultiPolygon(polygons) => {
+            for rings in polygons {
+                polygon(mesh, rings);
+            }
+        }
+    }
+}
+
+fn push_vertex(mesh: &mut Mesh2D, position: (f32, f32), color: Color) -> u32 {
+    mesh.vertices.push(Vertex2D {
+        position: [position.0, position.1],
+        color: [color.r, color.g, color.b, color.a],
+    });
+    (mesh.vertices.len() - 1) as u32
+}
+
+fn quad(mesh: &mut Mesh2D, corners: [(f32, f32); 4], color: Color) {
+    let base = mesh.vertices.len() as u32;
+    for corner in corners.iter() {
+        push_vertex(mesh, *corner, color);
+    }
+    mesh.indices
+        .extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+}
+
+pub fn disc(mesh: &mut Mesh2D, center: (f32, f32), radius: f32, color: Color) {
+    let middle = push_vertex(mesh, center, color);
+    for i in 0..CIRCLE_SEGMENTS {
+        let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::PI * 2.0;
+        push_vertex(
+            mesh,
+            (
+                center.0 + radius * angle.cos(),
+                center.1 + radius * angle.sin(),
+            ),
+            color,
+        );
+    }
+    for i in 0..CIRCLE_SEGMENTS as u32 {
+        let next = (i + 1) % CIRCLE_SEGMENTS as u32;
+        mesh.indices
+            .extend_from_slice(&[middle, middle + 1 + i, middle + 1 + next]);
+    }
+}
+
+/// A quad per segment with a disc at every interior vertex to round off the joins.
+pub fn stroke(mesh: &mut Mesh2D, line: &[(f32, f32)], width: f32, color: Color) {
+    if width <= 0.0 || color.a <= 0.0 {
+        return;
+    }
+    let half = width / 2.0;
+    for seg in line.windows(2) {
+        let (dx, dy) = (seg[1].0 - seg[0].0, seg[1].1 - seg[0].1);
+        let len = (dx * dx + dy * dy).sqrt();
+        if len == 0.0 {
+            continue;
+        }
+        let normal = (-dy / len * half, dx / len * half);
+        quad(
+            mesh,
+            [
+                (seg[0].0 + normal.0, seg[0].1 + normal.1),
+                (seg[1].0 + normal.0, seg[1].1 + normal.1),
+                (seg[1].0 - normal.0, seg[1].1 - normal.1),
+                (seg[0].0 - normal.0, seg[0].1 - normal.1),
+            ],
+            color,
+        );
+    }
+    if line.len() > 2 && half > 1.0 {
+        for joint in &line[1..line.len() - 1] {
+            disc(mesh, *joint, half, color);
+        }
+    }
+}
+
+/// Even-odd fill of a set of rings (outer ring and holes) by slicing it into horizontal
+/// trapezoids between consecutive vertex heights.
+pub fn fill(mesh: &mut Mesh2D, rings: &[Vec<(f32, f32)>], color: Color) {
+    if color.a <= 0.0 {
+        return;
+    }
+    let mut edges: Vec<((f32, f32), (f32, f32))> = Vec::new();
+    for ring in rings {
+        for i in 0..ring.len() {
+            let a = ring[i];
+            let b = ring[(i + 1) % ring.len()];
+            if a.1 != b.1 {
+                //store edges top to bottom
+                edges.push(if a.1 < b.1 { (a, b) } else { (b, a) });
+            }
+        }
+    }
+    let mut heights: Vec<f32> = edges.iter().flat_map(|(a, b)| vec![a.1, b.1]).collect();
+    heights.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    heights.dedup();
+
+    let x_at = |edge: &((f32, f32), (f32, f32)), y: f32| {
+        let (a, b) = edge;
+        a.0 + (b.0 - a.0) * (y - a.1) / (b.1 - a.1)
+    };
+    for band in heights.windows(2) {
+        let (top, bottom) = (band[0], band[1]);
+        let middle = (top + bottom) / 2.0;
+        let mut crossing: Vec<&((f32, f32), (f32, f32))> = edges
+            .iter()
+            .filter(|(a, b)| a.1 <= top && b.1 >= bottom)
+            .collect();
+        crossing.sort_by(|a, b| {
+            x_at(a, middle)
+                .partial_cmp(&x_at(b, middle))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        for pair in crossing.chunks(2) {
+            if let [left, right] = pair {
+                quad(
+                    mesh,
+                    [
+                        (x_at(left, top), top),
+                        (x_at(right, top), top),
+                        (x_at(right, bottom), bottom),
+                        (x_at(left, bottom), bottom),
+                    ],
+                    color,
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,407 @@
+//GeoJSON (RFC 7946) FeatureCollections <-> drawing layers
+//
+//styles use the simplestyle property names (stroke, stroke-width, fill, marker-color...)
+//that geojson.io and most web tools understand, and the layer a feature belongs to is
+//kept in a "layer" property so a round trip keeps the layers apart.
+//features with a "map_maker:label" property, as written on export, are read as text labels rather than drawn geometry
+use crate::features::label::LabelAnchor;
+use crate::features::{
+    color_from_hex, color_to_hex, DrawingLayer, Feature, FeatureStyle, Geometry, Properties,
+};
+use crate::formats::{as_label, label_properties, layer_name, FormatError};
+use crate::geo::LatLon;
+use iced::Color;
+use serde_json::{json, Value};
+use std::path::Path;
+
+const LAYER_KEY: &str = "layer";
+
+pub fn read_geojson(path: &Path) -> Result<Vec<DrawingLayer>, FormatError> {
+    let text = std::fs::read_to_string(path)?;
+    parse_geojson(&text, &layer_name(path))
+}
+
+pub fn write_geojson(path: &Path, layers: &[DrawingLayer]) -> Result<(), FormatError> {
+    let text = serde_json::to_string_pretty(&to_geojson(layers))?;
+    std::fs::write(path, text)?;
+    Ok(())
+}
+
+/// Features without a "layer" property end up in a layer called `default_layer`.
+pub fn parse_geojson(text: &str, default_layer: &str) -> Result<Vec<DrawingLayer>, FormatError> {
+    let root: Value = serde_json::from_str(text)?;
+    from_geojson(&root, default_layer)
+}
+
+/// Like `parse_geojson`, for GeoJSON that's already been parsed, e.g. inside a project.
+pub fn from_geojson(root: &Value, default_layer: &str) -> Result<Vec<DrawingLayer>, FormatError> {
+    let features: Vec<&Value> = match root["type"].as_str() {
+        Some("FeatureCollection") => root["features"]
+            .as_array()
+            .ok_or_else(|| FormatError::Invalid(String::from("features is not an array")))?
+            .iter()
+            .collect(),
+        Some("Feature") => vec![root],
+        //a bare geometry, treat it as a feature without properties
+        Some(_) => vec![root],
+        None => return Err(FormatError::Invalid(String::from("missing type"))),
+    };
+
+    let mut layers: Vec<DrawingLayer> = Vec::new();
+    for (feature_idx, feature) in features.into_iter().enumerate() {
+        let (geometry_value, mut properties) = if feature["type"] == "Feature" {
+            let properties = feature["properties"]
+                .as_object()
+                .cloned()
+                .unwrap_or_default();
+            (&feature["geometry"], properties)
+        } else {
+            (feature, Properties::new())
+        };
+        if geometry_value.is_null() {
+            log::warn!("skipping feature without geometry");
+            continue;
+        }
+
+        //one broken feature shouldn't lose the rest of the file
+        let geometries = match parse_geometry(geometry_value) {
+            Ok(geometries) => geometries,
+            Err(e) => {
+                log::warn!("skipping {}: {}", feature_name(feature_idx, &properties), e);
+                continue;
+            }
+        };
+
+        let name = match properties.remove(LAYER_KEY) {
+            Some(Value::String(name)) => name,
+            _ => default_layer.to_string(),
+        };
+        let layer_idx = match layers.iter().position(|layer| layer.name == name) {
+            Some(idx) => idx,
+            None => {
+                layers.push(DrawingLayer::new(&name));
+                layers.len() - 1
+            }
+        };
+        let layer = &mut layers[layer_idx];
+
+        let style = take_style(&mut properties);
+        for geometry in geometries {
+            if let Some(label) = as_label(&geometry, &properties) {
+                layer.labels.push(label);
+                continue;
+            }
+            layer.features.push(Feature {
+                geometry,
+                properties: properties.clone(),
+                style,
+                point_data: Vec::new(),
+            });
+        }
+    }
+    log::info!("read {} geojson layers", layers.len());
+    Ok(layers)
+}
+
+//how a warning refers to a feature, by its name if it has one
+fn feature_name(idx: usize, properties: &Properties) -> String {
+    match properties.get("name").and_then(Value::as_str) {
+        Some(name) => format!("feature {} ({:?})", idx, name),
+        None => format!("feature {}", idx),
+    }
+}
+
+pub fn to_geojson(layers: &[DrawingLayer]) -> Value {
+    let mut features: Vec<Value> = Vec::new();
+    for layer in layers {
+        for feature in &layer.features {
+            let mut properties = feature.properties.clone();
+            properties.insert(LAYER_KEY.to_string(), json!(layer.name));
+            put_style(&mut properties, &feature.style);
+            features.push(json!({
+                "type": "Feature",
+                "geometry": geometry_to_value(&feature.geometry),
+                "properties": properties,
+            }));
+        }
+        for label in &layer.labels {
+            let geometry = match &label.anchor {
+                LabelAnchor::Point(point) => Geometry::Point(*point),
+                LabelAnchor::Polyline(line) => Geometry::LineString(line.clone()),
+            };
+            let mut properties = label_properties(label);
+            properties.insert(LAYER_KEY.to_string(), json!(layer.name));
+            features.push(json!({
+                "type": "Feature",
+                "geometry": geometry_to_value(&geometry),
+                "properties": properties,
+            }));
+        }
+    }
+    json!({
+        "type": "FeatureCollection",
+        "features": features,
+    })
+}
+
+//pulls the simplestyle keys out of the properties, they live in the style from now on
+fn take_style(properties: &mut Properties) -> FeatureStyle {
+    let mut style = FeatureStyle::default();
+    let mut color = |key: &str| {
+        properties
+            .remove(key)
+            .and_then(|value| value.as_str().and_then(color_from_hex))
+    };
+    if let Some(stroke) = color("stroke") {
+        style.stroke = stroke;
+    }
+    if let Some(fill) = color("fill") {
+        //simplestyle fills are translucent unless fill-opacity says otherwise
+        style.fill = Color {
+            a: style.fill.a,
+            ..fill
+        };
+    }
+    if let Some(marker) = color("marker-color") {
+        style.marker_color = marker;
+    }
+    let mut number = |key: &str| properties.remove(key).and_then(|value| value.as_f64());
+    if let Some(width) = number("stroke-width") {
+        style.stroke_width = width as f32;
+    }
+    if let Some(opacity) = number("stroke-opacity") {
+        style.stroke.a = opacity as f32;
+    }
+    if let Some(opacity) = number("fill-opacity") {
+        style.fill.a = opacity as f32;
+    }
+    match properties.remove("marker-size") {
+        Some(Value::String(size)) => {
+            style.marker_size = match size.as_str() {
+                "small" => 4.0,
+                "large" => 9.0,
+                _ => 6.0,
+            }
+        }
+        Some(Value::Number(size)) => style.marker_size = size.as_f64().unwrap_or(6.0) as f32,
+        _ => {}
+    }
+    style
+}
+
+//keeps f32 noise like 0.6000000238418579 out of the file
+fn rounded(value: f32) -> f64 {
+    (value as f64 * 1000.0).round() / 1000.0
+}
+
+fn put_style(properties: &mut Properties, style: &FeatureStyle) {
+    properties.insert("stroke".to_string(), json!(color_to_hex(&style.stroke)));
+    properties.insert(
+        "stroke-width".to_string(),
+        json!(rounded(style.stroke_width)),
+    );
+    properties.insert("stroke-opacity".to_string(), json!(rounded(style.stroke.a)));
+    properties.insert("fill".to_string(), json!(color_to_hex(&style.fill)));
+    properties.insert("fill-opacity".to_string(), json!(rounded(style.fill.a)));
+    properties.insert(
+        "marker-color".to_string(),
+        json!(color_to_hex(&style.marker_color)),
+    );
+    properties.insert("marker-size".to_string(), json!(rounded(style.marker_size)));
+}
+
+fn position(value: &Value) -> Result<LatLon, FormatError> {
+    //geojson positions are [lon, lat, optional elevation]
+    match value.as_array().map(|pos| pos.as_slice()) {
+        Some([lon, lat, ..]) => match (lat.as_f64(), lon.as_f64()) {
+            (Some(lat), Some(lon)) => Ok(LatLon::new(lat, lon)),
+            _ => Err(FormatError::Invalid(String::from("non numeric position"))),
+        },
+        _ => Err(FormatError::Invalid(format!("bad position {}", value))),
+    }
+}
+
+fn positions(value: &Value) -> Result<Vec<LatLon>, FormatError> {
+    value
+        .as_array()
+        .ok_or_else(|| FormatError::Invalid(String::from("expected an array of positions")))?
+        .iter()
+        .map(position)
+        .collect()
+}
+
+fn rings(value: &Value) -> Result<Vec<Vec<LatLon>>, FormatError> {
+    value
+        .as_array()
+        .ok_or_else(|| FormatError::Invalid(String::from("expected an array of rings")))?
+        .iter()
+        .map(|ring| {
+            //the closing position repeats the first, the renderer closes rings itself
+            let mut ring = positions(ring)?;
+            if ring.len() > 1 && ring.first() == ring.last() {
+                ring.pop();
+            }
+            Ok(ring)
+        })
+        .collect()
+}
+
+//a GeometryCollection gives back one geometry per member
+fn parse_geometry(value: &Value) -> Result<Vec<Geometry>, FormatError> {
+    let coordinates = &value["coordinates"];
+    let geometry = match value["type"].as_str() {
+        Some("Point") => Geometry::Point(position(coordinates)?),
+        Some("MultiPoint") => Geometry::MultiPoint(positions(coordinates)?),
+        Some("LineString") => Geometry::LineString(positions(coordinates)?),
+        Some("MultiLineString") => Geometry::MultiLineString(
+            coordinates
+                .as_array()
+                .ok_or_else(|| FormatError::Invalid(String::from("expected lines")))?
+                .iter()
+                .map(positions)
+                .collect::<Result<_, _>>()?,
+        ),
+        Some("Polygon") => Geometry::Polygon(rings(coordinates)?),
+        Some("MultiPolygon") => Geometry::MultiPolygon(
+            coordinates
+                .as_array()
+                .ok_or_else(|| FormatError::Invalid(String::from("expected polygons")))?
+                .iter()
+                .map(rings)
+                .collect::<Result<_, _>>()?,
+        ),
+        Some("GeometryCollection") => {
+            let mut geometries = Vec::new();
+            for member in value["geometries"].as_array().into_iter().flatten() {
+                geometries.extend(parse_geometry(member)?);
+            }
+            return Ok(geometries);
+        }
+        other => {
+            return Err(FormatError::Invalid(format!(
+                "unknown geometry type {:?}",
+                other
+            )))
+        }
+    };
+    Ok(vec![geometry])
+}
+
+fn position_value(point: &LatLon) -> Value {
+    json!([point.lon, point.lat])
+}
+
+fn line_value(line: &[LatLon]) -> Value {
+    Value::Array(line.iter().map(position_value).collect())
+}
+
+fn rings_value(rings: &[Vec<LatLon>]) -> Value {
+    Value::Array(
+        rings
+            .iter()
+            .map(|ring| {
+                let mut closed = ring.clone();
+                if let Some(first) = ring.first() {
+                    closed.push(*first);
+                }
+                line_value(&closed)
+            })
+            .collect(),
+    )
+}
+
+pub fn geometry_to_value(geometry: &Geometry) -> Value {
+    match geometry {
+        Geometry::Point(point) => json!({"type": "Point", "coordinates": position_value(point)}),
+        Geometry::MultiPoint(points) => {
+            json!({"type": "MultiPoint", "coordinates": line_value(points)})
+        }
+        Geometry::LineString(line) => {
+            json!({"type": "LineString", "coordinates": line_value(line)})
+        }
+        Geometry::MultiLineString(lines) => json!({
+            "type": "MultiLineString",
+            "coordinates": lines.iter().map(|line| line_value(line)).collect::<Vec<Value>>(),
+        }),
+        Geometry::Polygon(rings) => json!({"type": "Polygon", "coordinates": rings_value(rings)}),
+        Geometry::MultiPolygon(polygons) => json!({
+            "type": "MultiPolygon",
+            "coordinates": polygons.iter().map(|rings| rings_value(rings)).collect::<Vec<Value>>(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::label::Label;
+
+    #[test]
+    fn layers_styles_and_labels_survive_a_round_trip() {
+        let mut trails = DrawingLayer::new("trails");
+        let mut feature = Feature::new(Geometry::LineString(vec![
+            LatLon::new(46.5, 7.25),
+            LatLon::new(46.75, 7.5),
+        ]));
+        feature.style.stroke = Color::from_rgb8(0x12, 0x34, 0x56);
+        feature.style.stroke_width = 4.0;
+        feature
+            .properties
+            .insert("name".to_string(), json!("ridge"));
+        trails.features.push(feature);
+        let mut label = Label::new("summit", LabelAnchor::Point(LatLon::new(46.6, 7.3)));
+        label.style.font_size = 20;
+        trails.labels.push(label);
+        let mut lakes = DrawingLayer::new("lakes");
+        lakes
+            .features
+            .push(Feature::new(Geometry::Polygon(vec![vec![
+                LatLon::new(0.0, 0.0),
+                LatLon::new(0.0, 1.0),
+                LatLon::new(1.0, 1.0),
+                LatLon::new(0.0, 0.0),
+            ]])));
+        let layers = vec![trails, lakes];
+
+        let text = to_geojson(&layers).to_string();
+        let read = parse_geojson(&text, "imported").unwrap();
+        assert_eq!(read, layers);
+    }
+
+    #[test]
+    fn broken_features_are_skipped() {
+        let text = r##"{"type": "FeatureCollection", "features": [
+            {"type": "Feature", "geometry": {"type": "Point", "coordinates": [1]}},
+            {"type": "Feature", "geometry": null},
+            {"type": "Feature", "geometry": {"type": "Point", "coordinates": [7.5, 46.5]},
+             "properties": {"marker-color": "#f00"}}
+        ]}"##;
+        let layers = parse_geojson(text, "imported").unwrap();
+        assert_eq!(layers.len(), 1);
+        assert_eq!(layers[0].name, "imported");
+        assert_eq!(layers[0].features.len(), 1);
+        let feature = &layers[0].features[0];
+        assert_eq!(feature.geometry, Geometry::Point(LatLon::new(46.5, 7.5)));
+        assert_eq!(feature.style.marker_color, Color::from_rgb8(0xff, 0, 0));
+        assert!(feature.properties.is_empty());
+    }
+
+    #[test]
+    fn features_with_a_plain_label_property_stay_features() {
+        let text = r##"{"type": "FeatureCollection", "features": [
+            {"type": "Feature", "geometry": {"type": "Point", "coordinates": [7.5, 46.5]},
+             "properties": {"label": "x"}}
+        ]}"##;
+        let layers = parse_geojson(text, "imported").unwrap();
+        assert!(layers[0].labels.is_empty());
+        assert_eq!(layers[0].features.len(), 1);
+        let feature = &layers[0].features[0];
+        assert_eq!(feature.geometry, Geometry::Point(LatLon::new(46.5, 7.5)));
+        assert_eq!(feature.properties.get("label"), Some(&json!("x")));
+    }
+
+    #[test]
+    fn files_without_a_type_are_rejected() {
+        assert!(parse_geojson(r#"{"features": []}"#, "imported").is_err());
+    }
+}
@@ -0,0 +1,317 @@
+//GPX 1.1 (and the 1.0 files older units still write) <-> drawing layers
+//
+//every track segment and route becomes its own line, waypoints become markers, and
+//the elevation and time of every point is kept in the feature's point_data.
+//the "gpx_type" property records which of the three a feature came from
+use crate::dem::Dem;
+use crate::features::{DrawingLayer, Feature, Geometry, PointData, Properties};
+use crate::formats::{layer_name, xml_escape, FormatError};
+use crate::geo::LatLon;
+use iced::Color;
+use roxmltree::Node;
+use serde_json::json;
+use std::fmt::Write;
+use std::path::Path;
+
+pub const GPX_TYPE_KEY: &str = "gpx_type";
+
+pub fn read_gpx(path: &Path) -> Result<Vec<DrawingLayer>, FormatError> {
+    let text = std::fs::read_to_string(path)?;
+    Ok(vec![parse_gpx(&text, &layer_name(path))?])
+}
+
+/// What drawn lines without a "gpx_type" of their own are written as.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineKind {
+    Route,
+    Track,
+}
+
+/// Writes lines as routes or tracks and points as waypoints. Polygons have no GPX
+/// equivalent and are skipped. Points without a recorded elevation get one from `dem`
+/// when there is one.
+pub fn write_gpx(
+    path: &Path,
+    layers: &[DrawingLayer],
+    lines_as: LineKind,
+    dem: Option<&Dem>,
+) -> Result<(), FormatError> {
+    std::fs::write(path, to_gpx(layers, lines_as, dem))?;
+    Ok(())
+}
+
+pub fn to_gpx(layers: &[DrawingLayer], lines_as: LineKind, dem: Option<&Dem>) -> String {
+    //gpx 1.1 wants all waypoints, then routes, then tracks
+    let mut waypoints = String::new();
+    let mut routes = String::new();
+    let mut tracks = String::new();
+    for feature in layers.iter().flat_map(|layer| layer.features.iter()) {
+        let points = feature.geometry.points();
+        let data = |idx: usize| feature.point_data.get(idx).cloned().unwrap_or_default();
+        let kind = match feature
+            .properties
+            .get(GPX_TYPE_KEY)
+            .and_then(|v| v.as_str())
+        {
+            Some("route") => LineKind::Route,
+            Some("track") => LineKind::Track,
+            _ => lines_as,
+        };
+        match &feature.geometry {
+            Geometry::Point(_) | Geometry::MultiPoint(_) => {
+                for (idx, point) in points.iter().enumerate() {
+                    write_point(&mut waypoints, "wpt", point, &data(idx), dem, "  ");
+                    write_metadata(&mut waypoints, &feature.properties, WAYPOINT_KEYS, "    ");
+                    waypoints.push_str("  </wpt>\n");
+                }
+            }
+            Geometry::LineString(_) | Geometry::MultiLineString(_) => {
+                let lines: Vec<&Vec<LatLon>> = match &feature.geometry {
+                    Geometry::LineString(line) => vec![line],
+                    Geometry::MultiLineString(lines) => lines.iter().collect(),
+                    _ => unreachable!(),
+                };
+                //point_data runs across every line of a multi line
+                let mut idx = 0;
+                if kind == LineKind::Track {
+                    tracks.push_str("  <trk>\n");
+                    write_metadata(&mut tracks, &feature.properties, LINE_KEYS, "    ");
+                }
+                for line in lines {
+                    if kind == LineKind::Track {
+                        tracks.push_str("    <trkseg>\n");
+                    } else {
+                        routes.push_str("  <rte>\n");
+                        write_metadata(&mut routes, &feature.properties, LINE_KEYS, "    ");
+                    }
+                    for point in line {
+                        match kind {
+                            LineKind::Track => {
+                                write_point(&mut tracks, "trkpt", point, &data(idx), dem, "      ");
+                                tracks.push_str("      </trkpt>\n");
+                            }
+                            LineKind::Route => {
+                                write_point(&mut routes, "rtept", point, &data(idx), dem, "    ");
+                                routes.push_str("    </rtept>\n");
+                            }
+                        }
+                        idx += 1;
+                    }
+                    if kind == LineKind::Track {
+                        tracks.push_str("    </trkseg>\n");
+                    } else {
+                        routes.push_str("  </rte>\n");
+                    }
+                }
+                if kind == LineKind::Track {
+                    tracks.push_str("  </trk>\n");
+                }
+            }
+            Geometry::Polygon(_) | Geometry::MultiPolygon(_) => {
+                log::warn!("gpx has no polygons, skipping one");
+            }
+        }
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <gpx version=\"1.1\" creator=\"map_maker\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n\
+         {}{}{}</gpx>\n",
+        waypoints, routes, tracks
+    )
+}
+
+//opens the point element and writes its ele and time, the caller closes it
+fn write_point(
+    out: &mut String,
+    tag: &str,
+    point: &LatLon,
+    data: &PointData,
+    dem: Option<&Dem>,
+    indent: &str,
+) {
+    let _ = writeln!(
+        out,
+        "{}<{} lat=\"{:.7}\" lon=\"{:.7}\">",
+        indent, tag, point.lat, point.lon
+    );
+    let elevation = data
+        .elevation
+        .or_else(|| dem.and_then(|dem| dem.elevation(point)));
+    if let Some(elevation) = elevation {
+        let _ = writeln!(out, "{}  <ele>{:.1}</ele>", indent, elevation);
+    }
+    if let Some(time) = &data.time {
+        let _ = writeln!(out, "{}  <time>{}</time>", indent, xml_escape(time));
+    }
+}
+
+//in gpx schema order, only waypoints can have a symbol
+const WAYPOINT_KEYS: &[&str] = &["name", "cmt", "desc", "sym", "type"];
+const LINE_KEYS: &[&str] = &["name", "cmt", "desc", "type"];
+
+fn write_metadata(out: &mut String, properties: &Properties, keys: &[&str], indent: &str) {
+    for key in keys {
+        if let Some(value) = properties.get(*key).and_then(|v| v.as_str()) {
+            let _ = writeln!(out, "{}<{}>{}</{}>", indent, key, xml_escape(value), key);
+        }
+    }
+}
+
+pub fn parse_gpx(text: &str, name: &str) -> Result<DrawingLayer, FormatError> {
+    let doc = roxmltree::Document::parse(text)?;
+    let root = doc.root_element();
+    if root.tag_name().name() != "gpx" {
+        return Err(FormatError::Invalid(String::from("not a gpx file")));
+    }
+
+    let mut layer = DrawingLayer::new(name);
+    for node in root.children().filter(Node::is_element) {
+        match node.tag_name().name() {
+            "wpt" => {
+                let (point, data) = read_point(&node)?;
+                let mut feature = Feature::new(Geometry::Point(point));
+                feature.properties = read_metadata(&node, "waypoint");
+                feature.point_data = vec![data];
+                layer.features.push(feature);
+            }
+            "rte" => {
+                let points = children(&node, "rtept")
+                    .map(|rtept| read_point(&rtept))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut feature = line_feature(points, read_metadata(&node, "route"));
+                feature.style.stroke = Color::from_rgb8(0x1f, 0x77, 0xb4);
+                layer.features.push(feature);
+            }
+            "trk" => {
+                let properties = read_metadata(&node, "track");
+                for segment in children(&node, "trkseg") {
+                    let points = children(&segment, "trkpt")
+                        .map(|trkpt| read_point(&trkpt))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let mut feature = line_feature(points, properties.clone());
+                    feature.style.stroke = Color::from_rgb8(0xd6, 0x27, 0x28);
+                    layer.features.push(feature);
+                }
+            }
+            _ => {}
+        }
+    }
+    log::info!("read {} gpx features", layer.features.len());
+    Ok(layer)
+}
+
+fn children<'a, 'input: 'a>(
+    node: &Node<'a, 'input>,
+    tag: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |child| child.is_element() && child.tag_name().name() == tag)
+}
+
+fn child_text<'a>(node: &Node<'a, '_>, tag: &str) -> Option<&'a str> {
+    node.children()
+        .find(|child| child.is_element() && child.tag_name().name() == tag)
+        .and_then(|child| child.text())
+        .map(str::trim)
+}
+
+fn line_feature(points: Vec<(LatLon, PointData)>, properties: Properties) -> Feature {
+    let (line, data): (Vec<LatLon>, Vec<PointData>) = points.into_iter().unzip();
+    let mut feature = Feature::new(Geometry::LineString(line));
+    feature.properties = properties;
+    feature.point_data = data;
+    feature.style.stroke_width = 3.0;
+    feature
+}
+
+fn read_point(node: &Node<'_, '_>) -> Result<(LatLon, PointData), FormatError> {
+    let coord = |name: &str| -> Result<f64, FormatError> {
+        node.attribute(name)
+            .and_then(|value| value.trim().parse().ok())
+            .ok_or_else(|| FormatError::Invalid(format!("point without a valid {}", name)))
+    };
+    let data = PointData {
+        elevation: child_text(node, "ele").and_then(|ele| ele.parse().ok()),
+        time: child_text(node, "time").map(str::to_string),
+    };
+    Ok((LatLon::new(coord("lat")?, coord("lon")?), data))
+}
+
+fn read_metadata(node: &Node<'_, '_>, gpx_type: &str) -> Properties {
+    let mut properties = Properties::new();
+    properties.insert(GPX_TYPE_KEY.to_string(), json!(gpx_type));
+    for key in ["name", "desc", "sym", "cmt", "type"].iter() {
+        if let Some(text) = child_text(node, key) {
+            properties.insert(key.to_string(), json!(text));
+        }
+    }
+    properties
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACK: &str = r#"<?xml version="1.0"?>
+<gpx version="1.1" creator="test" xmlns="http://www.topografix.com/GPX/1/1">
+  <wpt lat="46.5" lon="7.5"><ele>2100.5</ele><name>hut</name><sym>Lodge</sym></wpt>
+  <trk>
+    <name>day one</name>
+    <trkseg>
+      <trkpt lat="46.1" lon="7.1"><ele>1500</ele><time>2021-07-01T08:00:00Z</time></trkpt>
+      <trkpt lat="46.2" lon="7.2"><ele>1600</ele><time>2021-07-01T09:00:00Z</time></trkpt>
+    </trkseg>
+    <trkseg>
+      <trkpt lat="46.3" lon="7.3"/>
+      <trkpt lat="46.4" lon="7.4"/>
+    </trkseg>
+  </trk>
+</gpx>"#;
+
+    #[test]
+    fn segments_and_waypoints_become_features() {
+        let layer = parse_gpx(TRACK, "tour").unwrap();
+        assert_eq!(layer.name, "tour");
+        assert_eq!(layer.features.len(), 3);
+
+        let hut = &layer.features[0];
+        assert_eq!(hut.geometry, Geometry::Point(LatLon::new(46.5, 7.5)));
+        assert_eq!(hut.properties[GPX_TYPE_KEY], "waypoint");
+        assert_eq!(hut.properties["name"], "hut");
+        assert_eq!(hut.point_data[0].elevation, Some(2100.5));
+
+        let day_one = &layer.features[1];
+        assert_eq!(day_one.properties[GPX_TYPE_KEY], "track");
+        assert_eq!(day_one.properties["name"], "day one");
+        assert_eq!(day_one.point_data[1].elevation, Some(1600.0));
+        assert_eq!(
+            day_one.point_data[0].time.as_deref(),
+            Some("2021-07-01T08:00:00Z")
+        );
+        assert_eq!(layer.features[2].point_data, vec![PointData::default(); 2]);
+    }
+
+    #[test]
+    fn tracks_survive_a_round_trip() {
+        let layer = parse_gpx(TRACK, "tour").unwrap();
+        let text = to_gpx(std::slice::from_ref(&layer), LineKind::Route, None);
+        let read = parse_gpx(&text, "tour").unwrap();
+        let geometries = |layer: &DrawingLayer| {
+            layer
+                .features
+                .iter()
+                .map(|feature| (feature.geometry.clone(), feature.point_data.clone()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(geometries(&read), geometries(&layer));
+        //the lines keep being tracks rather than becoming routes
+        assert_eq!(read.features[1].properties[GPX_TYPE_KEY], "track");
+    }
+
+    #[test]
+    fn points_without_coordinates_are_rejected() {
+        let text = r#"<gpx><wpt lat="46.5"><name>nowhere</name></wpt></gpx>"#;
+        assert!(parse_gpx(text, "broken").is_err());
+        assert!(parse_gpx("<kml/>", "wrong").is_err());
+    }
+}
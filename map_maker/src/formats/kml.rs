@@ -0,0 +1,650 @@
+//Google Earth KML and zipped KMZ <-> drawing layers
+//
+//each Folder becomes a layer (nested folders are named "parent/child"), placemarks
+//outside any folder go in a layer named after the Document or the file. shared
+//Style/StyleMap definitions are resolved through styleUrl, StyleMaps use their "normal" style
+use crate::features::label::{Label, LabelAnchor};
+use crate::features::{DrawingLayer, Feature, FeatureStyle, Geometry, PointData, Properties};
+use crate::formats::{as_label, label_properties, layer_name, xml_escape, FormatError};
+use crate::geo::LatLon;
+use iced::Color;
+use roxmltree::Node;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::path::Path;
+
+pub fn read_kml(path: &Path) -> Result<Vec<DrawingLayer>, FormatError> {
+    let text = std::fs::read_to_string(path)?;
+    parse_kml(&text, &layer_name(path))
+}
+
+/// Reads the first .kml document in the archive, which is doc.kml by convention.
+pub fn read_kmz(path: &Path) -> Result<Vec<DrawingLayer>, FormatError> {
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(path)?)?;
+    for idx in 0..archive.len() {
+        let mut entry = archive.by_index(idx)?;
+        if entry.name().to_lowercase().ends_with(".kml") {
+            let mut text = String::new();
+            entry.read_to_string(&mut text)?;
+            return parse_kml(&text, &layer_name(path));
+        }
+    }
+    Err(FormatError::Invalid(String::from("no kml document in kmz")))
+}
+
+pub fn write_kml(path: &Path, layers: &[DrawingLayer]) -> Result<(), FormatError> {
+    std::fs::write(path, to_kml(layers, &layer_name(path)))?;
+    Ok(())
+}
+
+pub fn write_kmz(path: &Path, layers: &[DrawingLayer]) -> Result<(), FormatError> {
+    let mut archive = zip::ZipWriter::new(std::fs::File::create(path)?);
+    archive.start_file("doc.kml", zip::write::FileOptions::default())?;
+    archive.write_all(to_kml(layers, &layer_name(path)).as_bytes())?;
+    archive.finish()?;
+    Ok(())
+}
+
+//styles as far as we understand them, None where the kml didn't say
+#[derive(Debug, Clone, Default)]
+struct KmlStyle {
+    line_color: Option<Color>,
+    line_width: Option<f32>,
+    poly_color: Option<Color>,
+    fill: Option<bool>,
+    outline: Option<bool>,
+    icon_color: Option<Color>,
+    icon_scale: Option<f32>,
+    label_color: Option<Color>,
+    label_scale: Option<f32>,
+}
+
+impl KmlStyle {
+    //inline styles on a placemark override the shared one field by field
+    fn merge(&self, over: &KmlStyle) -> KmlStyle {
+        KmlStyle {
+            line_color: over.line_color.or(self.line_color),
+            line_width: over.line_width.or(self.line_width),
+            poly_color: over.poly_color.or(self.poly_color),
+            fill: over.fill.or(self.fill),
+            outline: over.outline.or(self.outline),
+            icon_color: over.icon_color.or(self.icon_color),
+            icon_scale: over.icon_scale.or(self.icon_scale),
+            label_color: over.label_color.or(self.label_color),
+            label_scale: over.label_scale.or(self.label_scale),
+        }
+    }
+
+    fn to_feature_style(&self) -> FeatureStyle {
+        let mut style = FeatureStyle::default();
+        if let Some(color) = self.line_color {
+            style.stroke = color;
+        }
+        if let Some(width) = self.line_width {
+            style.stroke_width = width;
+        }
+        if let Some(color) = self.poly_color {
+            style.fill = color;
+        }
+        if self.fill == Some(false) {
+            style.fill.a = 0.0;
+        }
+        if self.outline == Some(false) {
+            style.stroke.a = 0.0;
+        }
+        if let Some(color) = self.icon_color {
+            style.marker_color = color;
+        }
+        if let Some(scale) = self.icon_scale {
+            style.marker_size *= scale;
+        }
+        style
+    }
+}
+
+/// KML colours are aabbggrr.
+fn color_from_kml(text: &str) -> Option<Color> {
+    let text = text.trim();
+    if text.len() != 8 {
+        return None;
+    }
+    let byte = |idx: usize| u8::from_str_radix(text.get(idx..idx + 2)?, 16).ok();
+    Some(Color::from_rgba8(
+        byte(6)?,
+        byte(4)?,
+        byte(2)?,
+        byte(0)? as f32 / 255.0,
+    ))
+}
+
+fn color_to_kml(color: &Color) -> String {
+    let byte = |v: f32| (v * 255.0).round() as u8;
+    format!(
+        "{:02x}{:02x}{:02x}{:02x}",
+        byte(color.a),
+        byte(color.b),
+        byte(color.g),
+        byte(color.r)
+    )
+}
+
+fn child<'a, 'input>(node: &Node<'a, 'input>, tag: &str) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|c| c.is_element() && c.tag_name().name() == tag)
+}
+
+fn child_text<'a>(node: &Node<'a, '_>, tag: &str) -> Option<&'a str> {
+    child(node, tag).and_then(|c| c.text()).map(str::trim)
+}
+
+fn read_style(node: &Node<'_, '_>) -> KmlStyle {
+    let mut style = KmlStyle::default();
+    if let Some(line) = child(node, "LineStyle") {
+        style.line_color = child_text(&line, "color").and_then(color_from_kml);
+        style.line_width = child_text(&line, "width").and_then(|w| w.parse().ok());
+    }
+    if let Some(poly) = child(node, "PolyStyle") {
+        style.poly_color = child_text(&poly, "color").and_then(color_from_kml);
+        style.fill = child_text(&poly, "fill").map(|f| f != "0");
+        style.outline = child_text(&poly, "outline").map(|o| o != "0");
+    }
+    if let Some(icon) = child(node, "IconStyle") {
+        style.icon_color = child_text(&icon, "color").and_then(color_from_kml);
+        style.icon_scale = child_text(&icon, "scale").and_then(|s| s.parse().ok());
+    }
+    if let Some(label) = child(node, "LabelStyle") {
+        style.label_color = child_text(&label, "color").and_then(color_from_kml);
+        style.label_scale = child_text(&label, "scale").and_then(|s| s.parse().ok());
+    }
+    style
+}
+
+//collects every shared Style by id, then resolves StyleMaps to their normal style
+fn read_styles(doc: &roxmltree::Document<'_>) -> HashMap<String, KmlStyle> {
+    let mut styles = HashMap::new();
+    for node in doc.descendants().filter(|n| n.has_tag_name("Style")) {
+        if let Some(id) = node.attribute("id") {
+            styles.insert(id.to_string(), read_style(&node));
+        }
+    }
+    for node in doc.descendants().filter(|n| n.has_tag_name("StyleMap")) {
+        let id = match node.attribute("id") {
+            Some(id) => id,
+            None => continue,
+        };
+        let normal = node
+            .children()
+            .filter(|pair| pair.has_tag_name("Pair"))
+            .find(|pair| child_text(pair, "key") == Some("normal"))
+            .and_then(|pair| child_text(&pair, "styleUrl"))
+            .and_then(|url| styles.get(url.trim_start_matches('#')).cloned());
+        if let Some(normal) = normal {
+            styles.insert(id.to_string(), normal);
+        }
+    }
+    styles
+}
+
+pub fn parse_kml(text: &str, name: &str) -> Result<Vec<DrawingLayer>, FormatError> {
+    let doc = roxmltree::Document::parse(text)?;
+    let root = doc.root_element();
+    if root.tag_name().name() != "kml" {
+        return Err(FormatError::Invalid(String::from("not a kml file")));
+    }
+    let styles = read_styles(&doc);
+    let top = child(&root, "Document").unwrap_or(root);
+    let top_name = child_text(&top, "name").unwrap_or(name).to_string();
+
+    let mut layers: Vec<DrawingLayer> = Vec::new();
+    read_container(&top, &top_name, "", &styles, &mut layers)?;
+    layers.retain(|layer| !layer.features.is_empty() || !layer.labels.is_empty());
+    log::info!("read {} kml layers", layers.len());
+    Ok(layers)
+}
+
+//placemarks go in `layer`, folders become layers named with `prefix` in front
+fn read_container(
+    node: &Node<'_, '_>,
+    layer: &str,
+    prefix: &str,
+    styles: &HashMap<String, KmlStyle>,
+    layers: &mut Vec<DrawingLayer>,
+) -> Result<(), FormatError> {
+    for item in node.children().filter(Node::is_element) {
+        match item.tag_name().name() {
+            "Folder" | "Document" => {
+                let folder = child_text(&item, "name").unwrap_or("folder");
+                let folder_layer = if prefix.is_empty() {
+                    folder.to_string()
+                } else {
+                    format!("{}/{}", prefix, folder)
+                };
+                read_container(&item, &folder_layer, &folder_layer, styles, layers)?;
+            }
+            "Placemark" => {
+                let idx = match layers.iter().position(|l| l.name == layer) {
+                    Some(idx) => idx,
+                    None => {
+                        layers.push(DrawingLayer::new(layer));
+                        layers.len() - 1
+                    }
+                };
+                //one broken placemark shouldn't lose the rest of the file
+                if let Err(e) = read_placemark(&item, styles, &mut layers[idx]) {
+                    let name = child_text(&item, "name").unwrap_or("unnamed");
+                    log::warn!("skipping placemark {:?}: {}", name, e);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn read_placemark(
+    node: &Node<'_, '_>,
+    styles: &HashMap<String, KmlStyle>,
+    layer: &mut DrawingLayer,
+) -> Result<(), FormatError> {
+    let mut properties = Properties::new();
+    for key in ["name", "description"].iter() {
+        if let Some(text) = child_text(node, key) {
+            properties.insert(key.to_string(), json!(text));
+        }
+    }
+    if let Some(extended) = child(node, "ExtendedData") {
+        for data in extended.children().filter(|n| n.has_tag_name("Data")) {
+            if let (Some(key), Some(value)) = (data.attribute("name"), child_text(&data, "value")) {
+                properties.insert(key.to_string(), json!(value));
+            }
+        }
+    }
+
+    let shared = child_text(node, "styleUrl")
+        .and_then(|url| styles.get(url.trim_start_matches('#')))
+        .cloned()
+        .unwrap_or_default();
+    let kml_style = match child(node, "Style") {
+        Some(inline) => shared.merge(&read_style(&inline)),
+        None => shared,
+    };
+
+    let mut geometries: Vec<(Geometry, Vec<PointData>)> = Vec::new();
+    for item in node.children().filter(Node::is_element) {
+        read_geometry(&item, &mut geometries)?;
+    }
+    //labels keep their style in ExtendedData
+    if let [(geometry, _)] = geometries.as_slice() {
+        if let Some(label) = as_label(geometry, &properties) {
+            layer.labels.push(label);
+            return Ok(());
+        }
+    }
+    //otherwise a placemark with a name and an invisible icon is a point label, as Google
+    //Earth shows it
+    let hidden_icon = kml_style.icon_scale.map_or(false, |scale| scale == 0.0);
+    if let (Some(name), true, [(Geometry::Point(point), _)]) = (
+        properties.get("name").and_then(|n| n.as_str()),
+        hidden_icon,
+        geometries.as_slice(),
+    ) {
+        let mut label = Label::new(name, LabelAnchor::Point(*point));
+        if let Some(color) = kml_style.label_color {
+            label.style.color = color;
+        }
+        if let Some(scale) = kml_style.label_scale {
+            label.style.font_size = (label.style.font_size as f32 * scale) as u16;
+        }
+        layer.labels.push(label);
+        return Ok(());
+    }
+
+    let style = kml_style.to_feature_style();
+    for (geometry, point_data) in merge_multi(geometries) {
+        layer.features.push(Feature {
+            geometry,
+            properties: properties.clone(),
+            style,
+            point_data,
+        });
+    }
+    Ok(())
+}
+
+//a MultiGeometry of one kind becomes one Multi* feature, mixed ones stay separate
+fn merge_multi(geometries: Vec<(Geometry, Vec<PointData>)>) -> Vec<(Geometry, Vec<PointData>)> {
+    if geometries.len() < 2 {
+        return geometries;
+    }
+    let all = |pred: fn(&Geometry) -> bool| geometries.iter().all(|(g, _)| pred(g));
+    let point_data: Vec<PointData> = geometries
+        .iter()
+        .flat_map(|(_, data)| data.iter().cloned())
+        .collect();
+    let merged = if all(|g| matches!(g, Geometry::Point(_))) {
+        Geometry::MultiPoint(
+            geometries
+                .iter()
+                .filter_map(|(g, _)| match g {
+                    Geometry::Point(p) => Some(*p),
+                    _ => None,
+                })
+                .collect(),
+        )
+    } else if all(|g| matches!(g, Geometry::LineString(_))) {
+        Geometry::MultiLineString(
+            geometries
+                .iter()
+                .filter_map(|(g, _)| match g {
+                    Geometry::LineString(line) => Some(line.clone()),
+                    _ => None,
+                })
+                .collect(),
+        )
+    } else if all(|g| matches!(g, Geometry::Polygon(_))) {
+        Geometry::MultiPolygon(
+            geometries
+                .iter()
+                .filter_map(|(g, _)| match g {
+                    Geometry::Polygon(rings) => Some(rings.clone()),
+                    _ => None,
+                })
+                .collect(),
+        )
+    } else {
+        return geometries;
+    };
+    vec![(merged, point_data)]
+}
+
+//"lon,lat[,alt]" tuples separated by whitespace
+fn read_coordinates(node: &Node<'_, '_>) -> Result<(Vec<LatLon>, Vec<PointData>), FormatError> {
+    let text = child_text(node, "coordinates")
+        .ok_or_else(|| FormatError::Invalid(String::from("geometry without coordinates")))?;
+    let mut points = Vec::new();
+    let mut data = Vec::new();
+    for tuple in text.split_whitespace() {
+        let values: Vec<f64> = tuple
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| FormatError::Invalid(format!("bad coordinate {}", tuple)))?;
+        if values.len() < 2 {
+            return Err(FormatError::Invalid(format!("bad coordinate {}", tuple)));
+        }
+        points.push(LatLon::new(values[1], values[0]));
+        data.push(PointData {
+            elevation: values.get(2).copied(),
+            time: None,
+        });
+    }
+    Ok((points, data))
+}
+
+fn read_ring(node: &Node<'_, '_>) -> Result<(Vec<LatLon>, Vec<PointData>), FormatError> {
+    let ring = child(node, "LinearRing")
+        .ok_or_else(|| FormatError::Invalid(String::from("boundary without a LinearRing")))?;
+    let (mut points, mut data) = read_coordinates(&ring)?;
+    if points.len() > 1 && points.first() == points.last() {
+        points.pop();
+        data.pop();
+    }
+    Ok((points, data))
+}
+
+fn read_geometry(
+    node: &Node<'_, '_>,
+    out: &mut Vec<(Geometry, Vec<PointData>)>,
+) -> Result<(), FormatError> {
+    match node.tag_name().name() {
+        "Point" => {
+            let (points, data) = read_coordinates(node)?;
+            if let Some(point) = points.first() {
+                out.push((Geometry::Point(*point), data));
+            }
+        }
+        "LineString" => {
+            let (points, data) = read_coordinates(node)?;
+            out.push((Geometry::LineString(points), data));
+        }
+        "Polygon" => {
+            let mut rings = Vec::new();
+            let mut data = Vec::new();
+            for boundary in ["outerBoundaryIs", "innerBoundaryIs"].iter() {
+                for edge in node.children().filter(|n| n.has_tag_name(*boundary)) {
+                    let (ring, ring_data) = read_ring(&edge)?;
+                    rings.push(ring);
+                    data.extend(ring_data);
+                }
+            }
+            out.push((Geometry::Polygon(rings), data));
+        }
+        "MultiGeometry" => {
+            for item in node.children().filter(Node::is_element) {
+                read_geometry(&item, out)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+pub fn to_kml(layers: &[DrawingLayer], name: &str) -> String {
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<kml xmlns=\"http://www.opengis.net/kml/2.2\">\n<Document>\n");
+    let _ = writeln!(out, "<name>{}</name>", xml_escape(name));
+    for layer in layers {
+        let _ = writeln!(out, "<Folder>\n<name>{}</name>", xml_escape(&layer.name));
+        for feature in &layer.features {
+            write_placemark(&mut out, feature);
+        }
+        for label in &layer.labels {
+            write_label(&mut out, label);
+        }
+        out.push_str("</Folder>\n");
+    }
+    out.push_str("</Document>\n</kml>\n");
+    out
+}
+
+fn write_placemark(out: &mut String, feature: &Feature) {
+    out.push_str("<Placemark>\n");
+    for key in ["name", "description"].iter() {
+        if let Some(value) = feature.properties.get(*key).and_then(|v| v.as_str()) {
+            let _ = writeln!(out, "<{}>{}</{}>", key, xml_escape(value), key);
+        }
+    }
+    let style = &feature.style;
+    let _ = writeln!(
+        out,
+        "<Style><IconStyle><color>{}</color><scale>{:.2}</scale></IconStyle>\
+         <LineStyle><color>{}</color><width>{:.1}</width></LineStyle>\
+         <PolyStyle><color>{}</color></PolyStyle></Style>",
+        color_to_kml(&style.marker_color),
+        style.marker_size / FeatureStyle::default().marker_size,
+        color_to_kml(&style.stroke),
+        style.stroke_width,
+        color_to_kml(&style.fill)
+    );
+    write_extended_data(
+        out,
+        feature
+            .properties
+            .iter()
+            .filter(|(key, _)| key.as_str() != "name" && key.as_str() != "description"),
+    );
+    write_geometry(out, &feature.geometry);
+    out.push_str("</Placemark>\n");
+}
+
+fn write_label(out: &mut String, label: &Label) {
+    let style = &label.style;
+    let _ = writeln!(
+        out,
+        "<Placemark>\n<name>{}</name>\n\
+         <Style><IconStyle><scale>0</scale></IconStyle>\
+         <LabelStyle><color>{}</color><scale>{:.2}</scale></LabelStyle></Style>",
+        xml_escape(&label.text),
+        color_to_kml(&style.color),
+        style.font_size as f32 / crate::features::label::LabelStyle::default().font_size as f32
+    );
+    write_extended_data(out, label_properties(label).iter());
+    match &label.anchor {
+        LabelAnchor::Point(point) => write_geometry(out, &Geometry::Point(*point)),
+        LabelAnchor::Polyline(line) => write_geometry(out, &Geometry::LineString(line.clone())),
+    }
+    out.push_str("</Placemark>\n");
+}
+
+fn write_extended_data<'a>(
+    out: &mut String,
+    properties: impl Iterator<Item = (&'a String, &'a serde_json::Value)>,
+) {
+    let mut properties = properties.peekable();
+    if properties.peek().is_none() {
+        return;
+    }
+    out.push_str("<ExtendedData>\n");
+    for (key, value) in properties {
+        let value = match value {
+            serde_json::Value::String(text) => text.clone(),
+            other => other.to_string(),
+        };
+        let _ = writeln!(
+            out,
+            "<Data name=\"{}\"><value>{}</value></Data>",
+            xml_escape(key),
+            xml_escape(&value)
+        );
+    }
+    out.push_str("</ExtendedData>\n");
+}
+
+fn coordinates(points: &[LatLon]) -> String {
+    points
+        .iter()
+        .map(|p| format!("{:.7},{:.7}", p.lon, p.lat))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn write_polygon(out: &mut String, rings: &[Vec<LatLon>]) {
+    out.push_str("<Polygon>\n");
+    for (idx, ring) in rings.iter().enumerate() {
+        let boundary = if idx == 0 {
+            "outerBoundaryIs"
+        } else {
+            "innerBoundaryIs"
+        };
+        let mut closed = ring.clone();
+        if let Some(first) = ring.first() {
+            closed.push(*first);
+        }
+        let _ = writeln!(
+            out,
+            "<{}><LinearRing><coordinates>{}</coordinates></LinearRing></{}>",
+            boundary,
+            coordinates(&closed),
+            boundary
+        );
+    }
+    out.push_str("</Polygon>\n");
+}
+
+fn write_geometry(out: &mut String, geometry: &Geometry) {
+    match geometry {
+        Geometry::Point(point) => {
+            let _ = writeln!(
+                out,
+                "<Point><coordinates>{}</coordinates></Point>",
+                coordinates(&[*point])
+            );
+        }
+        Geometry::LineString(line) => {
+            let _ = writeln!(
+                out,
+                "<LineString><coordinates>{}</coordinates></LineString>",
+                coordinates(line)
+            );
+        }
+        Geometry::Polygon(rings) => write_polygon(out, rings),
+        Geometry::MultiPoint(points) => {
+            out.push_str("<MultiGeometry>\n");
+            for point in points {
+                write_geometry(out, &Geometry::Point(*point));
+            }
+            out.push_str("</MultiGeometry>\n");
+        }
+        Geometry::MultiLineString(lines) => {
+            out.push_str("<MultiGeometry>\n");
+            for line in lines {
+                write_geometry(out, &Geometry::LineString(line.clone()));
+            }
+            out.push_str("</MultiGeometry>\n");
+        }
+        Geometry::MultiPolygon(polygons) => {
+            out.push_str("<MultiGeometry>\n");
+            for rings in polygons {
+                write_polygon(out, rings);
+            }
+            out.push_str("</MultiGeometry>\n");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_survive_a_round_trip() {
+        let mut layer = DrawingLayer::new("names");
+        let mut peak = Label::new("Piz & Co", LabelAnchor::Point(LatLon::new(46.5, 9.8)));
+        peak.style.font_size = 24;
+        peak.style.color = Color::from_rgb8(0x80, 0x20, 0x10);
+        peak.style.halo_width = 2.5;
+        peak.style.rotation = -30.0;
+        layer.labels.push(peak);
+        let trail = vec![LatLon::new(46.0, 9.0), LatLon::new(46.1, 9.2)];
+        layer
+            .labels
+            .push(Label::new("Via Engiadina", LabelAnchor::Polyline(trail)));
+
+        let text = to_kml(&[layer.clone()], "document");
+        let read = parse_kml(&text, "document").unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].name, "names");
+        assert!(read[0].features.is_empty());
+        assert_eq!(read[0].labels, layer.labels);
+    }
+
+    #[test]
+    fn folders_become_layers_and_shared_styles_apply() {
+        let text = r#"<?xml version="1.0" encoding="UTF-8"?>
+<kml xmlns="http://www.opengis.net/kml/2.2"><Document>
+  <name>trip</name>
+  <Style id="red"><LineStyle><color>ff0000ff</color><width>5</width></LineStyle></Style>
+  <Placemark><name>loose</name><Point><coordinates>7.5,46.5,1200</coordinates></Point></Placemark>
+  <Folder><name>routes</name>
+    <Folder><name>day one</name>
+      <Placemark><styleUrl>#red</styleUrl>
+        <LineString><coordinates>7.0,46.0 7.1,46.1</coordinates></LineString>
+      </Placemark>
+    </Folder>
+  </Folder>
+</Document></kml>"#;
+        let layers = parse_kml(text, "file").unwrap();
+        let names: Vec<_> = layers.iter().map(|layer| layer.name.as_str()).collect();
+        assert_eq!(names, ["trip", "routes/day one"]);
+
+        let loose = &layers[0].features[0];
+        assert_eq!(loose.geometry, Geometry::Point(LatLon::new(46.5, 7.5)));
+        assert_eq!(loose.point_data[0].elevation, Some(1200.0));
+        let line = &layers[1].features[0];
+        assert_eq!(line.style.stroke, Color::from_rgb8(0xff, 0, 0));
+        assert_eq!(line.style.stroke_width, 5.0);
+    }
+}
@@ -0,0 +1,108 @@
+//reading and writing drawing layers in the formats other tools use
+pub mod geojson;
+pub mod gpx;
+pub mod kml;
+
+use crate::features::label::{Label, LabelAnchor};
+use crate::features::{color_from_hex, color_to_hex, Geometry, Properties};
+use serde_json::{json, Value};
+use thiserror::Error;
+
+//features with this property are text labels rather than drawn geometry. it's namespaced so
+//a plain "label" in someone else's data stays a property of its feature
+const LABEL_KEY: &str = "map_maker:label";
+
+#[derive(Debug, Error)]
+pub enum FormatError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("xml error: {0}")]
+    Xml(#[from] roxmltree::Error),
+    #[error("zip error: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("invalid file: {0}")]
+    Invalid(String),
+}
+
+/// Escapes text for use in XML content or attribute values.
+pub fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Layer name to use for a file that doesn't name its own layers.
+pub fn layer_name(path: &std::path::Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| String::from("imported"))
+}
+
+/// The properties a label is written out with, anchored to a point or a line.
+pub fn label_properties(label: &Label) -> Properties {
+    let rounded = |value: f32| (value as f64 * 1000.0).round() / 1000.0;
+    let style = &label.style;
+    let mut properties = Properties::new();
+    properties.insert(LABEL_KEY.to_string(), json!(label.text));
+    properties.insert("label-size".to_string(), json!(style.font_size));
+    properties.insert("label-color".to_string(), json!(color_to_hex(&style.color)));
+    properties.insert(
+        "label-halo-color".to_string(),
+        json!(color_to_hex(&style.halo_color)),
+    );
+    properties.insert(
+        "label-halo-width".to_string(),
+        json!(rounded(style.halo_width)),
+    );
+    properties.insert("label-rotation".to_string(), json!(rounded(style.rotation)));
+    properties
+}
+
+/// Reads back a label written with `label_properties`, if the properties are one.
+/// Numbers may come as text, formats like KML keep every value as a string.
+pub fn as_label(geometry: &Geometry, properties: &Properties) -> Option<Label> {
+    let text = properties.get(LABEL_KEY)?.as_str()?;
+    let anchor = match geometry {
+        Geometry::Point(point) => LabelAnchor::Point(*point),
+        Geometry::LineString(line) => LabelAnchor::Polyline(line.clone()),
+        _ => return None,
+    };
+    let mut label = Label::new(text, anchor);
+    let number = |key: &str| match properties.get(key)? {
+        Value::String(text) => text.parse::<f64>().ok(),
+        value => value.as_f64(),
+    };
+    let color = |key: &str| {
+        properties
+            .get(key)
+            .and_then(Value::as_str)
+            .and_then(color_from_hex)
+    };
+    if let Some(size) = number("label-size") {
+        label.style.font_size = size as u16;
+    }
+    if let Some(color) = color("label-color") {
+        label.style.color = color;
+    }
+    if let Some(color) = color("label-halo-color") {
+        label.style.halo_color = color;
+    }
+    if let Some(width) = number("label-halo-width") {
+        label.style.halo_width = width as f32;
+    }
+    if let Some(rotation) = number("label-rotation") {
+        label.style.rotation = rotation as f32;
+    }
+    Some(label)
+}
@@ -0,0 +1,163 @@
+//web mercator (EPSG:3857) helpers shared by the map widget, importers and exporters
+//
+//"world pixels" are the pixel coordinates of the whole slippy map at a zoom level,
+//so tile (x, y) covers world pixels (x * 256, y * 256) to ((x + 1) * 256, (y + 1) * 256)
+use std::f64::consts::PI;
+
+pub const TILE_SIZE: f64 = 256.0;
+/// Half the width of the web mercator world in meters, EPSG:3857 runs from minus this to this.
+pub const MERCATOR_EDGE: f64 = 20_037_508.342_789_244;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LatLon {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl LatLon {
+    pub fn new(lat: f64, lon: f64) -> Self {
+        Self { lat, lon }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub min: LatLon,
+    pub max: LatLon,
+}
+
+impl Bounds {
+    pub fn new(point: LatLon) -> Self {
+        Self {
+            min: point,
+            max: point,
+        }
+    }
+
+    pub fn extend(&mut self, point: &LatLon) {
+        self.min.lat = self.min.lat.min(point.lat);
+        self.min.lon = self.min.lon.min(point.lon);
+        self.max.lat = self.max.lat.max(point.lat);
+        self.max.lon = self.max.lon.max(point.lon);
+    }
+
+    pub fn union(&mut self, other: &Bounds) {
+        self.extend(&other.min);
+        self.extend(&other.max);
+    }
+
+    pub fn center(&self) -> LatLon {
+        LatLon::new(
+            (self.min.lat + self.max.lat) / 2.0,
+            (self.min.lon + self.max.lon) / 2.0,
+        )
+    }
+
+    /// The highest zoom level, up to `max_zoom`, at which the bounds fit in a
+    /// `viewport` sized area.
+    pub fn fit_zoom(&self, viewport: (f64, f64), max_zoom: u8) -> u8 {
+        let mut zoom = max_zoom;
+        while zoom > 0 {
+            let top_left = lat_lon_to_world_pixel(&LatLon::new(self.max.lat, self.min.lon), zoom);
+            let bottom_right =
+                lat_lon_to_world_pixel(&LatLon::new(self.min.lat, self.max.lon), zoom);
+            if bottom_right.0 - top_left.0 <= viewport.0
+                && bottom_right.1 - top_left.1 <= viewport.1
+            {
+                break;
+            }
+            zoom -= 1;
+        }
+        zoom
+    }
+}
+
+/// Size of the whole map in world pixels at `zoom`.
+pub fn world_size(zoom: u8) -> f64 {
+    TILE_SIZE * (1u64 << zoom) as f64
+}
+
+pub fn lat_lon_to_world_pixel(lat_lon: &LatLon, zoom: u8) -> (f64, f64) {
+    let size = world_size(zoom);
+    //mercator blows up at the poles, clamp to the usual slippy map limit
+    let lat = lat_lon
+        .lat
+        .max(-85.051_128_78)
+        .min(85.051_128_78)
+        .to_radians();
+    let x = (lat_lon.lon + 180.0) / 360.0 * size;
+    let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * size;
+    (x, y)
+}
+
+pub fn world_pixel_to_lat_lon(pixel: (f64, f64), zoom: u8) -> LatLon {
+    let size = world_size(zoom);
+    let lon = pixel.0 / size * 360.0 - 180.0;
+    let n = PI - 2.0 * PI * pixel.1 / size;
+    let lat = n.sinh().atan().to_degrees();
+    LatLon { lat, lon }
+}
+
+/// The EPSG:3857 extent of tile `coords` as min x, min y, max x, max y in meters.
+pub fn tile_meters(coords: &(u32, u32, u32)) -> (f64, f64, f64, f64) {
+    let (x, y, z) = *coords;
+    let tiles = (1u64 << z) as f64;
+    //as fractions of the edge, so tiles meeting at the middle of the world meet at exactly 0.
+    //rows count down from the top, y is flipped
+    let edge = |tile: u32| MERCATOR_EDGE * (2.0 * tile as f64 / tiles - 1.0);
+    (edge(x), 0.0 - edge(y + 1), edge(x + 1), 0.0 - edge(y))
+}
+
+/// What part of the world the map widget is showing.
+///
+/// `top_left` is the world pixel drawn at the top left corner of the widget before the
+/// widget's own drag offset is applied.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MapView {
+    pub zoom: u8,
+    pub top_left: (f64, f64),
+}
+
+impl MapView {
+    /// Builds the view for the tile grid centered on `load_pixel`, matching where the
+    /// map widget draws its tiles (the center tile sits one tile in from the top left).
+    pub fn from_load_pixel(load_pixel: (f32, f32), zoom: u8) -> Self {
+        let center_tile = (
+            (load_pixel.0 as f64 / TILE_SIZE).floor(),
+            (load_pixel.1 as f64 / TILE_SIZE).floor(),
+        );
+        Self {
+            zoom,
+            top_left: (
+                (center_tile.0 - 1.0) * TILE_SIZE,
+                (center_tile.1 - 1.0) * TILE_SIZE,
+            ),
+        }
+    }
+
+    /// Applies the drag offset the widget keeps in its state.
+    pub fn offset(&self, drag: (f32, f32)) -> Self {
+        Self {
+            zoom: self.zoom,
+            top_left: (
+                self.top_left.0 + drag.0 as f64,
+                self.top_left.1 + drag.1 as f64,
+            ),
+        }
+    }
+
+    pub fn to_screen(&self, lat_lon: &LatLon) -> (f32, f32) {
+        let (x, y) = lat_lon_to_world_pixel(lat_lon, self.zoom);
+        ((x - self.top_left.0) as f32, (y - self.top_left.1) as f32)
+    }
+
+    pub fn to_lat_lon(&self, screen: (f32, f32)) -> LatLon {
+        world_pixel_to_lat_lon(
+            (
+                screen.0 as f64 + self.top_left.0,
+                screen.1 as f64 + self.top_left.1,
+            ),
+            self.zoom,
+        )
+    }
+}
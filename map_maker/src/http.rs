@@ -0,0 +1,504 @@
+//how tiles and capabilities are fetched. tile servers want to know who's asking and not to
+//be flooded, and commercial ones want an api key. all of it is set in http.json in the
+//settings directory, for every request, for the sources on a host or for one source, e.g.
+//
+//  {
+//    "user_agent": "map_maker/0.1.0 (me@example.org)",
+//    "max_requests_per_second": 8,
+//    "hosts": {
+//      "tile.openstreetmap.org": { "max_requests_per_second": 2 }
+//    },
+//    "sources": {
+//      "MapTiler Streets": { "api_key": { "param": "key", "env": "MAPTILER_KEY" } }
+//    }
+//  }
+//
+//sources and hosts get the top level settings for anything they don't set themselves, a
+//source listed under its name doesn't use the settings of its host. keys can be read from
+//the environment, so they stay out of files that get shared, and never go in project
+//files: they're added to a url only as it's requested
+use crate::project::config_dir;
+use crate::tile_manager::tile_manager::TileSource;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::time::Instant;
+
+/// Who requests come from unless the settings say otherwise.
+pub const USER_AGENT: &str = concat!("map_maker/", env!("CARGO_PKG_VERSION"));
+const DEFAULT_TIMEOUT_SECONDS: u64 = 30;
+const DEFAULT_MAX_REQUESTS_PER_SECOND: u32 = 8;
+
+#[derive(Debug, Error)]
+pub enum HttpError {
+    #[error("http error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("invalid http settings: {0}")]
+    Invalid(String),
+}
+
+/// How requests to a source or host are made.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestSettings {
+    //sent with every request, e.g. a Referer some servers insist on
+    pub headers: Vec<(String, String)>,
+    //the query parameter a key goes in and the key. urls with `{key}` get it there instead
+    pub api_key: Option<(String, String)>,
+    //0 for no limit
+    pub max_requests_per_second: u32,
+    pub timeout: Duration,
+    //without one the usual HTTP_PROXY and HTTPS_PROXY variables are used
+    pub proxy: Option<String>,
+}
+
+impl Default for RequestSettings {
+    fn default() -> Self {
+        Self {
+            headers: Vec::new(),
+            api_key: None,
+            max_requests_per_second: DEFAULT_MAX_REQUESTS_PER_SECOND,
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECONDS),
+            proxy: None,
+        }
+    }
+}
+
+impl RequestSettings {
+    //`value`'s settings on top of `self`'s
+    fn with_json(&self, value: &Value) -> Self {
+        let mut settings = self.clone();
+        if let Some(headers) = value["headers"].as_object() {
+            for (name, header) in headers {
+                let header = header.as_str().unwrap_or_default().to_string();
+                settings
+                    .headers
+                    .retain(|(existing, _)| !existing.eq_ignore_ascii_case(name));
+                settings.headers.push((name.clone(), header));
+            }
+        }
+        if let Some(param) = value["api_key"]["param"].as_str() {
+            let key = match value["api_key"]["env"].as_str() {
+                Some(variable) => std::env::var(variable).ok(),
+                None => value["api_key"]["value"].as_str().map(str::to_string),
+            };
+            match key {
+                Some(key) => settings.api_key = Some((param.to_string(), key)),
+                None => log::warn!("no api key for {} in http settings or environment", param),
+            }
+        }
+        if let Some(limit) = value["max_requests_per_second"].as_u64() {
+            settings.max_requests_per_second = limit as u32;
+        }
+        if let Some(timeout) = value["timeout_seconds"].as_f64() {
+            settings.timeout = Duration::from_secs_f64(timeout.max(0.1));
+        }
+        if let Some(proxy) = value["proxy"].as_str() {
+            settings.proxy = Some(proxy.to_string());
+        }
+        settings
+    }
+
+    fn client(&self, user_agent: &str) -> Result<reqwest::Client, HttpError> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in self.headers.iter() {
+            let invalid = |what: &str| HttpError::Invalid(format!("{} {}", what, name));
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid("header name"))?,
+                HeaderValue::from_str(value).map_err(|_| invalid("value of header"))?,
+            );
+        }
+        let mut builder = reqwest::Client::builder()
+            .user_agent(user_agent)
+            .default_headers(headers)
+            .timeout(self.timeout);
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy.as_str())?);
+        }
+        Ok(builder.build()?)
+    }
+
+    //`url` with the api key in it, encoded so keys with reserved characters survive
+    fn with_key(&self, url: &str) -> String {
+        let (param, key) = match &self.api_key {
+            Some(api_key) => api_key,
+            None => return url.to_string(),
+        };
+        let key: String = form_urlencoded::byte_serialize(key.as_bytes()).collect();
+        if url.contains("{key}") {
+            return url.replace("{key}", &key);
+        }
+        let param: String = form_urlencoded::byte_serialize(param.as_bytes()).collect();
+        let separator = if url.contains('?') { '&' } else { '?' };
+        format!("{}{}{}={}", url, separator, param, key)
+    }
+}
+
+/// The settings of every source and host, along with the ones the rest get.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpSettings {
+    pub user_agent: String,
+    pub default: RequestSettings,
+    //by lowercased host name
+    pub hosts: HashMap<String, RequestSettings>,
+    //by source name
+    pub sources: HashMap<String, RequestSettings>,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        Self {
+            user_agent: USER_AGENT.to_string(),
+            default: RequestSettings::default(),
+            hosts: HashMap::new(),
+            sources: HashMap::new(),
+        }
+    }
+}
+
+impl HttpSettings {
+    pub fn from_json(value: &Value) -> Self {
+        let default = RequestSettings::default().with_json(value);
+        let listed = |key: &str, lowercase: bool| {
+            value[key]
+                .as_object()
+                .into_iter()
+                .flatten()
+                .map(|(name, settings)| {
+                    let name = if lowercase {
+                        name.to_lowercase()
+                    } else {
+                        name.clone()
+                    };
+                    (name, default.with_json(settings))
+                })
+                .collect()
+        };
+        Self {
+            user_agent: value["user_agent"]
+                .as_str()
+                .unwrap_or(USER_AGENT)
+                .to_string(),
+            hosts: listed("hosts", true),
+            sources: listed("sources", false),
+            default,
+        }
+    }
+
+    /// The settings in http.json, or the defaults if there isn't one.
+    pub fn load_default() -> Self {
+        let text = match config_dir().map(|dir| std::fs::read_to_string(dir.join("http.json"))) {
+            Some(Ok(text)) => text,
+            _ => return Self::default(),
+        };
+        match serde_json::from_str::<Value>(&text) {
+            Ok(value) => Self::from_json(&value),
+            Err(e) => {
+                log::error!("couldn't read http.json, using the defaults: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    fn settings(&self, key: &EndpointKey) -> &RequestSettings {
+        match key {
+            EndpointKey::Source(name) => self.sources.get(name),
+            EndpointKey::Host(host) => self.hosts.get(host),
+        }
+        .unwrap_or(&self.default)
+    }
+}
+
+//what requests share a client and rate limit: a source with settings of its own, or else
+//the host the request goes to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum EndpointKey {
+    Source(String),
+    Host(String),
+}
+
+//a client, and when the next request through it may go out
+struct Endpoint {
+    client: reqwest::Client,
+    settings: RequestSettings,
+    next_slot: Mutex<Instant>,
+}
+
+impl Endpoint {
+    //waits until the rate limit allows another request. requests are spaced evenly, each
+    //takes the next free slot. `limit` is a lower one for this request, it still takes a
+    //slot of the endpoint's so everything going to it counts against one schedule
+    async fn wait_turn(&self, limit: Option<u32>) {
+        let per_second = match (self.settings.max_requests_per_second, limit) {
+            (0, None) => return,
+            (0, Some(limit)) => limit,
+            (max, Some(limit)) => max.min(limit),
+            (max, None) => max,
+        };
+        let interval = Duration::from_secs_f64(1.0 / per_second.max(1) as f64);
+        let slot = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + interval;
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+}
+
+/// Makes requests the way the settings say. A source with settings of its own gets a
+/// client and rate limit to itself, other requests share one with the rest going to the
+/// same host.
+pub struct HttpClient {
+    settings: HttpSettings,
+    endpoints: Mutex<HashMap<EndpointKey, Arc<Endpoint>>>,
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        HttpClient::new(HttpSettings::load_default())
+    }
+}
+
+impl HttpClient {
+    pub fn new(settings: HttpSettings) -> Self {
+        Self {
+            settings,
+            endpoints: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn endpoint(&self, source: Option<&TileSource>, url: &str) -> Result<Arc<Endpoint>, HttpError> {
+        let key = match source {
+            Some(source) if self.settings.sources.contains_key(&source.name) => {
+                EndpointKey::Source(source.name.clone())
+            }
+            _ => EndpointKey::Host(host_name(url)),
+        };
+        let mut endpoints = self.endpoints.lock().unwrap();
+        if let Some(endpoint) = endpoints.get(&key) {
+            return Ok(endpoint.clone());
+        }
+        let settings = self.settings.settings(&key).clone();
+        let endpoint = Arc::new(Endpoint {
+            client: settings.client(&self.settings.user_agent)?,
+            settings,
+            next_slot: Mutex::new(Instant::now()),
+        });
+        endpoints.insert(key, endpoint.clone());
+        Ok(endpoint)
+    }
+
+    /// A GET of `url` with its host's api key added, once the host's rate limit allows it.
+    pub async fn get(&self, url: &str) -> Result<reqwest::RequestBuilder, HttpError> {
+        let endpoint = self.endpoint(None, url)?;
+        endpoint.wait_turn(None).await;
+        Ok(endpoint.client.get(endpoint.settings.with_key(url)))
+    }
+
+    /// A GET of one of `source`'s tiles, made with the source's settings. Offline downloads
+    /// go no faster than the source's own rate limit as well.
+    pub async fn get_tile(
+        &self,
+        source: &TileSource,
+        url: &str,
+        download: bool,
+    ) -> Result<reqwest::RequestBuilder, HttpError> {
+        let endpoint = self.endpoint(Some(source), url)?;
+        let limit = if download {
+            Some(source.rate_limit)
+        } else {
+            None
+        };
+        endpoint.wait_turn(limit).await;
+        Ok(endpoint.client.get(endpoint.settings.with_key(url)))
+    }
+}
+
+//the lowercased host of a url, empty if it hasn't one
+fn host_name(url: &str) -> String {
+    url.split("://")
+        .nth(1)
+        .and_then(|rest| rest.split(['/', '?']).next())
+        .map(|authority| authority.rsplit('@').next().unwrap_or(authority))
+        .map(|host| host.split(':').next().unwrap_or(host))
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn settings(per_second: u32) -> RequestSettings {
+        RequestSettings {
+            max_requests_per_second: per_second,
+            ..RequestSettings::default()
+        }
+    }
+
+    fn endpoint(per_second: u32) -> Endpoint {
+        Endpoint {
+            client: reqwest::Client::new(),
+            settings: settings(per_second),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    //how long after the first each of `count` turns came
+    async fn turns(endpoint: &Endpoint, limit: Option<u32>, count: usize) -> Vec<u128> {
+        let start = Instant::now();
+        let mut turns = Vec::new();
+        for _ in 0..count {
+            endpoint.wait_turn(limit).await;
+            turns.push((Instant::now() - start).as_millis());
+        }
+        turns
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn requests_are_spaced_by_the_rate_limit() {
+        assert_eq!(turns(&endpoint(4), None, 3).await, vec![0, 250, 500]);
+        //a lower limit for the request wins, a higher one doesn't
+        assert_eq!(turns(&endpoint(4), Some(2), 3).await, vec![0, 500, 1000]);
+        assert_eq!(turns(&endpoint(4), Some(10), 3).await, vec![0, 250, 500]);
+        assert_eq!(turns(&endpoint(0), None, 3).await, vec![0, 0, 0]);
+        assert_eq!(turns(&endpoint(0), Some(5), 3).await, vec![0, 200, 400]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn requests_waiting_together_take_turns() {
+        let endpoint = endpoint(10);
+        let start = Instant::now();
+        let waits = (0..4).map(|_| async {
+            endpoint.wait_turn(None).await;
+            (Instant::now() - start).as_millis()
+        });
+        let mut turns = futures::future::join_all(waits).await;
+        turns.sort_unstable();
+        assert_eq!(turns, vec![0, 100, 200, 300]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn downloads_go_no_faster_than_their_source_allows() {
+        let client = HttpClient::new(HttpSettings {
+            default: settings(0),
+            ..HttpSettings::default()
+        });
+        let source = TileSource {
+            rate_limit: 2,
+            ..TileSource::default()
+        };
+        //only the waiting matters, the requests are never sent
+        let start = Instant::now();
+        for _ in 0..2 {
+            let _ = client.get_tile(&source, &source.url, false).await.unwrap();
+        }
+        assert_eq!(Instant::now(), start);
+        for _ in 0..2 {
+            let _ = client.get_tile(&source, &source.url, true).await.unwrap();
+        }
+        assert_eq!((Instant::now() - start).as_millis(), 500);
+    }
+
+    #[tokio::test]
+    async fn sources_with_settings_get_an_endpoint_of_their_own() {
+        let http = HttpSettings::from_json(&json!({
+            "max_requests_per_second": 3,
+            "hosts": { "Tiles.Example.org": { "max_requests_per_second": 1 } },
+            "sources": { "Streets": { "timeout_seconds": 5 } }
+        }));
+        let client = HttpClient::new(http);
+        let streets = TileSource {
+            name: String::from("Streets"),
+            ..TileSource::default()
+        };
+        let url = "https://tiles.example.org/1/2/3.png";
+        let by_host = client.endpoint(None, url).unwrap();
+        let other_source = client.endpoint(Some(&TileSource::default()), url).unwrap();
+        let by_source = client.endpoint(Some(&streets), url).unwrap();
+        assert!(Arc::ptr_eq(&by_host, &other_source));
+        assert!(!Arc::ptr_eq(&by_host, &by_source));
+        assert_eq!(by_host.settings.max_requests_per_second, 1);
+        //sources fall back on the top level settings, not their host's
+        assert_eq!(by_source.settings.max_requests_per_second, 3);
+        assert_eq!(by_source.settings.timeout, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn settings_are_layered_on_the_top_level_ones() {
+        let http = HttpSettings::from_json(&json!({
+            "user_agent": "tester",
+            "headers": { "Referer": "https://example.org" },
+            "hosts": {
+                "a.example.org": {
+                    "headers": { "referer": "https://a.example.org", "X-Extra": "1" },
+                    "api_key": { "param": "key", "value": "secret" },
+                    "proxy": "http://localhost:3128"
+                }
+            }
+        }));
+        assert_eq!(http.user_agent, "tester");
+        assert_eq!(
+            http.default.headers,
+            vec![(String::from("Referer"), String::from("https://example.org"))]
+        );
+        let host = &http.hosts["a.example.org"];
+        assert_eq!(
+            host.headers,
+            vec![
+                (String::from("X-Extra"), String::from("1")),
+                (
+                    String::from("referer"),
+                    String::from("https://a.example.org")
+                ),
+            ]
+        );
+        assert_eq!(
+            host.api_key,
+            Some((String::from("key"), String::from("secret")))
+        );
+        assert_eq!(host.proxy.as_deref(), Some("http://localhost:3128"));
+        assert_eq!(
+            host.max_requests_per_second,
+            DEFAULT_MAX_REQUESTS_PER_SECOND
+        );
+        assert_eq!(HttpSettings::from_json(&json!({})), HttpSettings::default());
+    }
+
+    #[test]
+    fn keys_are_encoded_into_the_url() {
+        let keyed = RequestSettings {
+            api_key: Some((String::from("api key"), String::from("a b&c=d/é"))),
+            ..RequestSettings::default()
+        };
+        assert_eq!(
+            keyed.with_key("https://example.org/1/2/3.png"),
+            "https://example.org/1/2/3.png?api+key=a+b%26c%3Dd%2F%C3%A9"
+        );
+        assert_eq!(
+            keyed.with_key("https://example.org/tile?x=1"),
+            "https://example.org/tile?x=1&api+key=a+b%26c%3Dd%2F%C3%A9"
+        );
+        assert_eq!(
+            keyed.with_key("https://example.org/{key}/tile"),
+            "https://example.org/a+b%26c%3Dd%2F%C3%A9/tile"
+        );
+        let url = "https://example.org/tile";
+        assert_eq!(RequestSettings::default().with_key(url), url);
+    }
+
+    #[test]
+    fn host_names_are_lowercased_without_port_or_user() {
+        assert_eq!(
+            host_name("https://me:pw@Tiles.Example.org:8080/a?b=c"),
+            "tiles.example.org"
+        );
+        assert_eq!(host_name("http://example.org?x=1"), "example.org");
+        assert_eq!(host_name("file:///tiles/1/2/3.png"), "");
+        assert_eq!(host_name("no scheme"), "");
+    }
+}
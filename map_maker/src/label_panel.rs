@@ -0,0 +1,236 @@
+//the side panel section for writing and styling labels. right clicking the map places a
+//label with the text and style set here, or picks a label that's already there to edit it
+use crate::features::label::{Label, LabelStyle};
+use crate::features::{color_from_hex, color_to_hex, DrawingLayer};
+use crate::layer_panel::PANEL_WIDTH;
+use iced::{
+    button, pick_list, slider, text_input, Align, Button, Column, Element, Length, PickList, Row,
+    Slider, Text, TextInput,
+};
+
+const FONT_SIZES: [u16; 8] = [10, 12, 14, 16, 20, 24, 32, 48];
+
+#[derive(Debug, Clone)]
+pub enum LabelMessage {
+    TextChanged(String),
+    FontSizeSelected(u16),
+    ColorChanged(String),
+    HaloColorChanged(String),
+    HaloWidthChanged(f32),
+    RotationChanged(f32),
+    Delete,
+    //stop editing the picked label, the next right click places a new one
+    Deselect,
+}
+
+pub struct LabelPanel {
+    pub text: String,
+    pub style: LabelStyle,
+    //the label being edited, by drawing layer and index into its labels
+    pub selected: Option<(usize, usize)>,
+    //colours as typed, they're only applied once they parse
+    color: String,
+    halo_color: String,
+    text_state: text_input::State,
+    font_size_state: pick_list::State<u16>,
+    color_state: text_input::State,
+    halo_color_state: text_input::State,
+    halo_width_state: slider::State,
+    rotation_state: slider::State,
+    delete: button::State,
+    deselect: button::State,
+}
+
+impl Default for LabelPanel {
+    fn default() -> Self {
+        let style = LabelStyle::default();
+        Self {
+            text: String::new(),
+            style,
+            selected: None,
+            color: color_to_hex(&style.color),
+            halo_color: color_to_hex(&style.halo_color),
+            text_state: text_input::State::new(),
+            font_size_state: pick_list::State::default(),
+            color_state: text_input::State::new(),
+            halo_color_state: text_input::State::new(),
+            halo_width_state: slider::State::new(),
+            rotation_state: slider::State::new(),
+            delete: button::State::new(),
+            deselect: button::State::new(),
+        }
+    }
+}
+
+impl LabelPanel {
+    /// What a new label says, labels can't be empty.
+    pub fn label_text(&self) -> String {
+        let text = self.text.trim();
+        if text.is_empty() {
+            String::from("label")
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// Starts editing `label`, the panel shows its text and style.
+    pub fn select(&mut self, selected: (usize, usize), label: &Label) {
+        self.selected = Some(selected);
+        self.text = label.text.clone();
+        self.style = label.style;
+        self.color = color_to_hex(&label.style.color);
+        self.halo_color = color_to_hex(&label.style.halo_color);
+    }
+
+    /// Applies a change to the panel. The picked label, if there is one, is changed along
+    /// with it, returns whether it was.
+    pub fn update(&mut self, message: LabelMessage, layers: &mut [DrawingLayer]) -> bool {
+        match message {
+            LabelMessage::TextChanged(text) => self.text = text,
+            LabelMessage::FontSizeSelected(size) => self.style.font_size = size,
+            LabelMessage::ColorChanged(hex) => {
+                if let Some(color) = color_from_hex(&hex) {
+                    self.style.color = color;
+                }
+                self.color = hex;
+            }
+            LabelMessage::HaloColorChanged(hex) => {
+                if let Some(color) = color_from_hex(&hex) {
+                    self.style.halo_color = color;
+                }
+                self.halo_color = hex;
+            }
+            LabelMessage::HaloWidthChanged(width) => self.style.halo_width = width,
+            LabelMessage::RotationChanged(rotation) => self.style.rotation = rotation,
+            LabelMessage::Delete => {
+                let removed = match self.selected.take() {
+                    Some((layer, idx)) => layers
+                        .get_mut(layer)
+                        .filter(|layer| !layer.locked && idx < layer.labels.len())
+                        .map(|layer| layer.labels.remove(idx)),
+                    None => None,
+                };
+                return removed.is_some();
+            }
+            LabelMessage::Deselect => {
+                self.selected = None;
+                return false;
+            }
+        }
+        let label = match self.selected {
+            Some((layer, idx)) => layers
+                .get_mut(layer)
+                .filter(|layer| !layer.locked)
+                .and_then(|layer| layer.labels.get_mut(idx)),
+            None => None,
+        };
+        match label {
+            Some(label) => {
+                label.text = self.label_text();
+                label.style = self.style;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn view(&mut self) -> Element<'_, LabelMessage> {
+        let title = if self.selected.is_some() {
+            "edit label"
+        } else {
+            "new label, right click to place"
+        };
+        let mut column = Column::new()
+            .spacing(4)
+            .width(Length::Units(PANEL_WIDTH))
+            .push(Text::new("labels").size(18))
+            .push(Text::new(title).size(14))
+            .push(
+                TextInput::new(
+                    &mut self.text_state,
+                    "label text",
+                    &self.text,
+                    LabelMessage::TextChanged,
+                )
+                .padding(5),
+            )
+            .push(
+                Row::new()
+                    .spacing(5)
+                    .align_items(Align::Center)
+                    .push(Text::new("size").size(16))
+                    .push(PickList::new(
+                        &mut self.font_size_state,
+                        &FONT_SIZES[..],
+                        Some(self.style.font_size),
+                        LabelMessage::FontSizeSelected,
+                    ))
+                    .push(Text::new("colour").size(16))
+                    .push(
+                        TextInput::new(
+                            &mut self.color_state,
+                            "#000000",
+                            &self.color,
+                            LabelMessage::ColorChanged,
+                        )
+                        .padding(3)
+                        .width(Length::Units(80)),
+                    ),
+            )
+            .push(
+                Row::new()
+                    .spacing(5)
+                    .align_items(Align::Center)
+                    .push(Text::new("halo").size(16))
+                    .push(
+                        TextInput::new(
+                            &mut self.halo_color_state,
+                            "#ffffff",
+                            &self.halo_color,
+                            LabelMessage::HaloColorChanged,
+                        )
+                        .padding(3)
+                        .width(Length::Units(80)),
+                    )
+                    .push(
+                        Slider::new(
+                            &mut self.halo_width_state,
+                            0.0..=4.0,
+                            self.style.halo_width,
+                            LabelMessage::HaloWidthChanged,
+                        )
+                        .step(0.5),
+                    ),
+            )
+            .push(
+                Row::new()
+                    .spacing(5)
+                    .align_items(Align::Center)
+                    .push(Text::new(format!("rotation {:.0}°", self.style.rotation)).size(16))
+                    .push(
+                        Slider::new(
+                            &mut self.rotation_state,
+                            -180.0..=180.0,
+                            self.style.rotation,
+                            LabelMessage::RotationChanged,
+                        )
+                        .step(5.0),
+                    ),
+            );
+        if self.selected.is_some() {
+            column = column.push(
+                Row::new()
+                    .spacing(5)
+                    .push(
+                        Button::new(&mut self.delete, Text::new("delete").size(16))
+                            .on_press(LabelMessage::Delete),
+                    )
+                    .push(
+                        Button::new(&mut self.deselect, Text::new("done").size(16))
+                            .on_press(LabelMessage::Deselect),
+                    ),
+            );
+        }
+        column.into()
+    }
+}
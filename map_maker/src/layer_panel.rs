@@ -0,0 +1,332 @@
+//the side panel for managing layers. drawing layers are listed above the tile layers since
+//they're always drawn over them, and each group is listed topmost first
+use crate::archive::is_archive_path;
+use crate::features::DrawingLayer;
+use crate::ogc::{is_capabilities_url, ServiceLayer};
+use crate::tile_manager::tile_manager::{RasterLayer, TileSource};
+use crate::widgets::drag_handle::{self, DragHandle};
+use iced::{
+    button, pick_list, scrollable, slider, text_input, Align, Button, Checkbox, Column, Container,
+    Element, Length, PickList, Row, Scrollable, Slider, Text, TextInput,
+};
+
+pub const PANEL_WIDTH: u16 = 300;
+//every row is this tall so how far a row was dragged says how many rows it moved
+const ROW_HEIGHT: u16 = 64;
+const ROW_SPACING: u16 = 4;
+
+/// A layer as the panel refers to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerRef {
+    //by id, see TileManager::layers
+    Raster(usize),
+    //by index into the drawing layers
+    Drawing(usize),
+}
+
+#[derive(Debug, Clone)]
+pub enum PanelMessage {
+    VisibleToggled(LayerRef, bool),
+    OpacityChanged(LayerRef, f32),
+    Renamed(LayerRef, String),
+    Deleted(LayerRef),
+    LockToggled(LayerRef, bool),
+    ZoomToLayer(LayerRef),
+    //how many rows down the list the layer was dragged, negative for up
+    Moved(LayerRef, i32),
+    RasterAdded(TileSource),
+    //the url typed in for a new tile layer
+    UrlChanged(String),
+    //the url typed in is a service's capabilities, the layers in it should be listed
+    CapabilitiesRequested(String),
+    StylePathChanged(String),
+    //the style file vector tiles should be drawn with, empty for the built in style
+    StyleChosen(String),
+}
+
+#[derive(Debug, Default)]
+struct RowState {
+    handle: drag_handle::State,
+    name: text_input::State,
+    opacity: slider::State,
+    zoom: button::State,
+    delete: button::State,
+}
+
+pub struct LayerPanel {
+    scroll: scrollable::State,
+    drawing_rows: Vec<RowState>,
+    raster_rows: Vec<RowState>,
+    presets: Vec<TileSource>,
+    add_raster: pick_list::State<TileSource>,
+    url: String,
+    url_input: text_input::State,
+    add_url: button::State,
+    //layers listed in the last capabilities document fetched
+    service_layers: Vec<ServiceLayer>,
+    service_layer_state: pick_list::State<ServiceLayer>,
+    //how fetching them went
+    service_status: String,
+    style_path: String,
+    //how reading the style went
+    style_status: String,
+    style_path_input: text_input::State,
+    load_style: button::State,
+}
+
+impl Default for LayerPanel {
+    fn default() -> Self {
+        Self {
+            scroll: scrollable::State::new(),
+            drawing_rows: Vec::new(),
+            raster_rows: Vec::new(),
+            presets: TileSource::presets(),
+            add_raster: pick_list::State::default(),
+            url: String::new(),
+            url_input: text_input::State::new(),
+            add_url: button::State::new(),
+            service_layers: Vec::new(),
+            service_layer_state: pick_list::State::default(),
+            service_status: String::new(),
+            style_path: String::new(),
+            style_status: String::from("built in style"),
+            style_path_input: text_input::State::new(),
+            load_style: button::State::new(),
+        }
+    }
+}
+
+//what a row shows, whichever kind of layer it's for
+struct RowContent<'a> {
+    layer: LayerRef,
+    name: &'a str,
+    visible: bool,
+    opacity: f32,
+    locked: bool,
+    can_zoom: bool,
+}
+
+impl LayerPanel {
+    pub fn set_url(&mut self, url: String) {
+        self.url = url;
+    }
+
+    pub fn set_service_layers(&mut self, layers: Vec<ServiceLayer>) {
+        self.service_status = format!("{} layers", layers.len());
+        self.service_layers = layers;
+    }
+
+    pub fn set_service_status(&mut self, status: String) {
+        self.service_status = status;
+    }
+
+    pub fn set_style_path(&mut self, path: String) {
+        self.style_path = path;
+    }
+
+    pub fn set_style_status(&mut self, status: String) {
+        self.style_status = status;
+    }
+
+    /// `raster_layers` bottom to top, as `TileManager::layers` gives them, each with whether
+    /// it has bounds to zoom to.
+    pub fn view<'a>(
+        &'a mut self,
+        raster_layers: Vec<(usize, &'a RasterLayer, bool)>,
+        drawing_layers: &'a [DrawingLayer],
+    ) -> Element<'a, PanelMessage> {
+        //rows are matched to layers by position, a reorder just moves the focus along
+        self.drawing_rows
+            .resize_with(drawing_layers.len(), RowState::default);
+        self.raster_rows
+            .resize_with(raster_layers.len(), RowState::default);
+
+        let mut drawing = Column::new()
+            .spacing(ROW_SPACING)
+            .push(Text::new("drawing layers").size(18));
+        for ((idx, layer), state) in drawing_layers
+            .iter()
+            .enumerate()
+            .rev()
+            .zip(self.drawing_rows.iter_mut())
+        {
+            let content = RowContent {
+                layer: LayerRef::Drawing(idx),
+                name: &layer.name,
+                visible: layer.visible,
+                opacity: layer.opacity,
+                locked: layer.locked,
+                can_zoom: !layer.features.is_empty() || !layer.labels.is_empty(),
+            };
+            drawing = drawing.push(layer_row(state, content));
+        }
+
+        let mut raster = Column::new().spacing(ROW_SPACING).push(
+            Row::new()
+                .spacing(10)
+                .align_items(Align::Center)
+                .push(Text::new("tile layers").size(18))
+                .push(PickList::new(
+                    &mut self.add_raster,
+                    &self.presets[..],
+                    None,
+                    PanelMessage::RasterAdded,
+                )),
+        );
+        //any tile url or file:// folder, vector tiles if it ends in .pbf or .mvt, the path of
+        //an archive, or a service's capabilities to pick a layer from
+        let new_source = TileSource::from_url(&self.url);
+        let mut add_url = Button::new(&mut self.add_url, Text::new("add").size(16));
+        let mut url_input = TextInput::new(
+            &mut self.url_input,
+            "https://.../{z}/{x}/{y}.png or .mbtiles file",
+            &self.url,
+            PanelMessage::UrlChanged,
+        )
+        .padding(3)
+        .size(16);
+        if is_capabilities_url(&self.url) {
+            let request = PanelMessage::CapabilitiesRequested(self.url.clone());
+            add_url = add_url.on_press(request.clone());
+            url_input = url_input.on_submit(request);
+        } else if new_source.has_placeholders() || is_archive_path(&new_source.url) {
+            add_url = add_url.on_press(PanelMessage::RasterAdded(new_source.clone()));
+            url_input = url_input.on_submit(PanelMessage::RasterAdded(new_source));
+        }
+        raster = raster.push(
+            Row::new()
+                .spacing(5)
+                .align_items(Align::Center)
+                .push(url_input)
+                .push(add_url),
+        );
+        if !self.service_layers.is_empty() {
+            raster = raster.push(PickList::new(
+                &mut self.service_layer_state,
+                &self.service_layers[..],
+                None,
+                |layer| PanelMessage::RasterAdded(layer.source),
+            ));
+        }
+        if !self.service_status.is_empty() {
+            raster = raster.push(Text::new(&self.service_status).size(14));
+        }
+        for ((id, layer, has_bounds), state) in raster_layers
+            .into_iter()
+            .rev()
+            .zip(self.raster_rows.iter_mut())
+        {
+            let content = RowContent {
+                layer: LayerRef::Raster(id),
+                name: &layer.source.name,
+                visible: layer.visible,
+                opacity: layer.opacity,
+                locked: layer.locked,
+                can_zoom: has_bounds,
+            };
+            raster = raster.push(layer_row(state, content));
+        }
+
+        //edits to the file show up on the map as soon as it's saved
+        let style = Column::new()
+            .spacing(ROW_SPACING)
+            .push(Text::new("vector style").size(18))
+            .push(
+                Row::new()
+                    .spacing(5)
+                    .align_items(Align::Center)
+                    .push(
+                        TextInput::new(
+                            &mut self.style_path_input,
+                            "style.json",
+                            &self.style_path,
+                            PanelMessage::StylePathChanged,
+                        )
+                        .padding(3)
+                        .size(16)
+                        .on_submit(PanelMessage::StyleChosen(self.style_path.clone())),
+                    )
+                    .push(
+                        Button::new(&mut self.load_style, Text::new("load").size(16))
+                            .on_press(PanelMessage::StyleChosen(self.style_path.clone())),
+                    ),
+            )
+            .push(Text::new(&self.style_status).size(14));
+
+        Scrollable::new(&mut self.scroll)
+            .width(Length::Units(PANEL_WIDTH))
+            .height(Length::Fill)
+            .spacing(15)
+            .padding(5)
+            .push(drawing)
+            .push(raster)
+            .push(style)
+            .into()
+    }
+}
+
+fn layer_row<'a>(state: &'a mut RowState, content: RowContent<'a>) -> Element<'a, PanelMessage> {
+    let layer = content.layer;
+    let row_pitch = (ROW_HEIGHT + ROW_SPACING) as f32;
+    let handle = DragHandle::new(&mut state.handle, move |distance| {
+        PanelMessage::Moved(layer, (distance / row_pitch).round() as i32)
+    })
+    .height(ROW_HEIGHT - 8);
+
+    let name: Element<'a, PanelMessage> = if content.locked {
+        Text::new(content.name).width(Length::Fill).into()
+    } else {
+        TextInput::new(&mut state.name, "name", content.name, move |name| {
+            PanelMessage::Renamed(layer, name)
+        })
+        .padding(3)
+        .width(Length::Fill)
+        .into()
+    };
+    let top = Row::new()
+        .spacing(5)
+        .align_items(Align::Center)
+        .push(Checkbox::new(content.visible, "", move |visible| {
+            PanelMessage::VisibleToggled(layer, visible)
+        }))
+        .push(name)
+        .push(Checkbox::new(content.locked, "lock", move |locked| {
+            PanelMessage::LockToggled(layer, locked)
+        }));
+
+    let mut bottom = Row::new()
+        .spacing(5)
+        .align_items(Align::Center)
+        .push(Text::new("opacity").size(16))
+        .push(
+            Slider::new(
+                &mut state.opacity,
+                0.0..=1.0,
+                content.opacity,
+                move |opacity| PanelMessage::OpacityChanged(layer, opacity),
+            )
+            .step(0.05),
+        );
+    //tile servers cover the whole world, only archives say what area they have
+    let mut zoom = Button::new(&mut state.zoom, Text::new("zoom").size(16));
+    if content.can_zoom {
+        zoom = zoom.on_press(PanelMessage::ZoomToLayer(layer));
+    }
+    bottom = bottom.push(zoom);
+    let mut delete = Button::new(&mut state.delete, Text::new("delete").size(16));
+    if !content.locked {
+        delete = delete.on_press(PanelMessage::Deleted(layer));
+    }
+    bottom = bottom.push(delete);
+
+    let rows = Column::new().spacing(4).push(top).push(bottom);
+    Container::new(
+        Row::new()
+            .spacing(5)
+            .align_items(Align::Center)
+            .push(handle)
+            .push(rows),
+    )
+    .height(Length::Units(ROW_HEIGHT))
+    .into()
+}
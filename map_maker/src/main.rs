@@ -4,25 +4,69 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
 // When compiling natively:
+mod archive;
+mod dem;
+mod download_panel;
+mod export;
+mod features;
+mod formats;
+mod geo;
+mod http;
+mod label_panel;
+mod layer_panel;
+#[cfg(test)]
+mod mock_tile_server;
+mod offline;
+mod ogc;
+mod project;
+mod tile_cache;
 mod tile_manager;
+mod vector_tile;
 mod widgets;
 use futures::future::join_all;
 use log;
 use std::sync::Arc;
 use thiserror::Error;
-use widgets::map_tile;
+use widgets::map_tile::{self, DisplayScale, TileGrid};
+use widgets::page_preview::{self, LayoutPreview, PagePreview};
 use Result;
 
+use ab_glyph::FontVec;
+use dem::Dem;
+use download_panel::{DownloadMessage, DownloadPanel};
 use env_logger::{Builder, Target};
-use tile_manager::tile_manager::Tile;
-use tile_manager::tile_manager::TileManager;
-use tile_manager::tile_manager::TileState;
+use export::geotiff::GeoTiffProjection;
+use export::page::{GridStyle, LayoutItem, MapScale, PaperSize, PrintLayout};
+use export::raster::{parse_bbox, RasterExport, MAX_EXPORT_PIXELS, MAX_TILE_ZOOM};
+use export::ExportError;
+use features::label::{label_at, line_near, Label, LabelAnchor};
+use features::DrawingLayer;
+use formats::{geojson, gpx, kml};
+use geo::{lat_lon_to_world_pixel, Bounds, LatLon, MapView, TILE_SIZE};
+use label_panel::{LabelMessage, LabelPanel};
+use layer_panel::{LayerPanel, LayerRef, PanelMessage};
+use offline::{BatchResult, Download, Estimate, Region, RegionChoice};
+use ogc::ServiceLayer;
+use project::{ExportPresets, Project, ProjectView, RecentProject, Recovery, PROJECT_EXTENSION};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use tile_manager::tile_manager::{
+    RasterLayer, Tile, TileManager, TileSnapshot, TileSource, TileState,
+};
+use vector_tile::style::{StyleWatcher, VectorStyle};
 
 pub const LOAD_TILE_DIMENSION: usize = 5;
+const PNG_DPI_OPTIONS: [u32; 4] = [96, 150, 300, 600];
+const AUTOSAVE_SECONDS: u64 = 60;
+//how often the vector style file is checked for edits
+const STYLE_POLL_SECONDS: u64 = 1;
+//offline downloads fetch a batch of tiles at most this often, see TileSource::rate_limit
+const DOWNLOAD_BATCH_SECONDS: u32 = 1;
 use crate::widgets::map_tile::TILE_DIMENSION;
 
 use iced::{
-    button, executor, Application, Button, Command, Container, Element, Length, Settings, Text,
+    button, executor, pick_list, text_input, Application, Button, Checkbox, Column, Command,
+    Container, Element, Length, PickList, Row, Settings, Text, TextInput,
 };
 
 use slippy_map_tiles;
@@ -52,7 +96,12 @@ pub fn main() -> iced::Result {
     builder.target(Target::Stdout);
     builder.filter(Some("map_maker"), log::LevelFilter::Info);
     builder.init();
-    let result = MapMaker::run(Settings::default());
+    //a file given on the command line is imported on startup
+    let open_path = std::env::args().nth(1).map(PathBuf::from);
+    let mut settings = Settings::with_flags(open_path);
+    //room for the layer panel next to the map
+    settings.window.size = (1200, 960);
+    let result = MapMaker::run(settings);
     //tokio_thread_handle.join().unwrap();
     result
 }
@@ -63,17 +112,143 @@ struct MapMaker {
     //each vector is an image tile
     //the widget handles loading the image tiles
     //
-    tiles: [[Tile; LOAD_TILE_DIMENSION]; LOAD_TILE_DIMENSION],
+    //one grid per visible raster layer, bottom to top
+    tiles: Vec<TileGrid>,
     zoom_in_state: button::State,
     zoom_out_state: button::State,
     cur_coords: (f32, f32),
     load_pixel: (f32, f32),
     zoom_level: u8,
-    client: std::sync::Arc<reqwest::Client>,
     tile_state: map_tile::State,
     tile_manager: TileManager,
+    drawing_layers: Vec<DrawingLayer>,
+    file_path: String,
+    file_path_state: text_input::State,
+    import_state: button::State,
+    export_state: button::State,
+    //lines without a gpx_type of their own go out as routes rather than tracks
+    gpx_routes: bool,
+    dem: Option<Dem>,
+    png_dpi: u32,
+    png_dpi_state: pick_list::State<u32>,
+    geotiff_projection: GeoTiffProjection,
+    geotiff_projection_state: pick_list::State<GeoTiffProjection>,
+    //what png and geotiff exports cover as typed in, empty for the map as it's shown
+    export_bbox: String,
+    export_bbox_state: text_input::State,
+    export_bbox_from_map_state: button::State,
+    export_zoom: String,
+    export_zoom_state: text_input::State,
+    //pixels across, empty to go by the dpi
+    export_width: String,
+    export_width_state: text_input::State,
+    //the page pdf and svg exports are laid out on
+    print_layout: PrintLayout,
+    //showing the page instead of the map
+    layout_mode: bool,
+    layout_preview: Option<LayoutPreview>,
+    layout_state: button::State,
+    paper_state: pick_list::State<PaperSize>,
+    map_scale_state: pick_list::State<MapScale>,
+    grid_state: pick_list::State<GridStyle>,
+    layout_title_state: text_input::State,
+    layout_attribution_state: text_input::State,
+    layout_center_state: button::State,
+    layout_reset_state: button::State,
+    page_preview_state: page_preview::State,
+    pending_export: Option<PendingExport>,
+    //where save writes to, once the map has been opened from or saved to a project
+    project_path: Option<PathBuf>,
+    recent_projects: Vec<RecentProject>,
+    recent_projects_state: pick_list::State<RecentProject>,
+    open_state: button::State,
+    save_state: button::State,
+    save_as_state: button::State,
+    //loaded once, measuring and embedding it is all the exports need
+    export_font: Option<ExportFont>,
+    //the map as it was last saved or autosaved, see autosave_state
+    autosaved_state: Value,
+    //work the last session left unsaved, until it's restored or discarded
+    recovery: Option<Recovery>,
+    restore_state: button::State,
+    discard_recovery_state: button::State,
+    layer_panel: LayerPanel,
+    //the style file vector tiles are drawn with, the built in style without one
+    style_watcher: Option<StyleWatcher>,
+    download_panel: DownloadPanel,
+    label_panel: LabelPanel,
+    //the area being downloaded for offline use, kept once it's done to show how it went
+    download: Option<Download>,
+    //counts downloads started, what comes back for one that's been replaced or stopped
+    //is dropped
+    download_generation: u64,
+    //tiles on screen that aren't cached, while offline
+    unavailable_tiles: usize,
+    display_scale: DisplayScale,
+    display_scale_state: pick_list::State<DisplayScale>,
+}
+
+//exports that are waiting on their tiles before they can be rendered
+enum PendingExport {
+    Png(PathBuf, RasterExport),
+    GeoTiff(PathBuf, RasterExport, GeoTiffProjection),
+    Page(PathBuf, PrintLayout),
+}
+
+impl PendingExport {
+    fn raster(&self) -> RasterExport {
+        match self {
+            PendingExport::Png(_, export) | PendingExport::GeoTiff(_, export, _) => *export,
+            PendingExport::Page(_, layout) => layout.raster(),
+        }
+    }
+
+    fn path(&self) -> &Path {
+        match self {
+            PendingExport::Png(path, _)
+            | PendingExport::GeoTiff(path, _, _)
+            | PendingExport::Page(path, _) => path,
+        }
+    }
+
+    //draws the export from the tiles in `snapshot` and writes it out. it takes a while for
+    //big ones, so it's done on a blocking thread
+    fn write(
+        &self,
+        snapshot: &TileSnapshot,
+        layers: &[DrawingLayer],
+        font: Option<&ExportFont>,
+    ) -> Result<(), ExportError> {
+        match self {
+            PendingExport::Png(path, export) => {
+                let font = font.map(|(font, _)| &**font);
+                let image = export.render(snapshot, layers, font);
+                export::png::write_png(path, &image, export.dpi())
+            }
+            PendingExport::GeoTiff(path, export, projection) => {
+                let font = font.map(|(font, _)| &**font);
+                let image = export.render(snapshot, layers, font);
+                export::geotiff::write_geotiff(path, export, image, *projection)
+            }
+            PendingExport::Page(path, layout) => {
+                let page = layout.compose(snapshot, layers, font.map(|(font, _)| &**font));
+                let is_pdf = path
+                    .extension()
+                    .map_or(false, |ext| ext.eq_ignore_ascii_case("pdf"));
+                if is_pdf {
+                    let font = font.map(|(font, data)| (&**font, data.as_slice()));
+                    export::pdf::write_pdf(path, &page, font)
+                } else {
+                    export::svg::write_svg(path, &page)
+                }
+            }
+        }
+    }
 }
 
+//the font exports measure labels with, along with the file's bytes to embed in pdfs
+type ExportFont = (Arc<FontVec>, Arc<Vec<u8>>);
+
 //slippy_map_tiles::lat_lon_to_tile
 #[derive(Clone, Debug)]
 pub enum MyMessage {
@@ -83,6 +258,50 @@ pub enum MyMessage {
     ImageLoadFailed,
     CenterPosition,
     VelocityEvent,
+    PlaceLabel(LatLon),
+    Label(LabelMessage),
+    FilePathChanged(String),
+    Import,
+    Export,
+    GpxRoutesToggled(bool),
+    PngDpiSelected(u32),
+    GeoTiffProjectionSelected(GeoTiffProjection),
+    ExportBboxChanged(String),
+    ExportBboxFromMap,
+    ExportZoomChanged(String),
+    ExportWidthChanged(String),
+    PaperSelected(PaperSize),
+    LandscapeToggled(bool),
+    MapScaleSelected(MapScale),
+    ExportTilesLoaded(Option<Vec<Tile>>),
+    ExportFinished(PathBuf, Result<(), String>),
+    ToggleLayoutMode,
+    LayoutTitleChanged(String),
+    LayoutAttributionChanged(String),
+    GridStyleSelected(GridStyle),
+    LegendToggled(bool),
+    ScaleBarToggled(bool),
+    NorthArrowToggled(bool),
+    LayoutCenterOnMap,
+    LayoutItemMoved(LayoutItem, (f64, f64)),
+    LayoutItemResized(LayoutItem, f64),
+    LayoutPlacementsReset,
+    LayoutTilesLoaded(Option<Vec<Tile>>),
+    OpenProject,
+    SaveProject,
+    SaveProjectAs,
+    RecentProjectSelected(RecentProject),
+    Autosave,
+    RestoreRecovery,
+    DiscardRecovery,
+    LayerPanel(PanelMessage),
+    StylePoll,
+    Download(DownloadMessage),
+    DownloadPrepared(u64, Result<Download, String>),
+    DownloadBatch(u64, BatchResult),
+    OfflineToggled(bool),
+    DisplayScaleSelected(DisplayScale),
+    CapabilitiesLoaded(Result<Vec<ServiceLayer>, String>),
 }
 
 #[derive(Debug, Error)]
@@ -94,44 +313,49 @@ enum MyError {
 }
 
 impl MapMaker {
-    //tile_handles: [[Option<image::Handle>; TILE_DIMENSION]; TILE_DIMENSION],
-    //
-    fn get_tile_imgs(&self) -> [[Vec<u8>; LOAD_TILE_DIMENSION]; LOAD_TILE_DIMENSION] {
-        let mut imgs: [[Vec<u8>; LOAD_TILE_DIMENSION]; LOAD_TILE_DIMENSION] = Default::default();
-        for x in 0..LOAD_TILE_DIMENSION {
-            for y in 0..LOAD_TILE_DIMENSION {
-                imgs[x][y] = self.tiles[x][y].image.clone();
-            }
-        }
-        imgs
-    }
-
     fn populate_tiles(&mut self) {
         let center_x = self.load_pixel.0;
         let center_y = self.load_pixel.1;
-        let center_tile: (isize, isize) = (center_x as isize / 256, center_y as isize / 256);
+        let tile = TILE_SIZE as isize;
+        let center_tile: (isize, isize) = (center_x as isize / tile, center_y as isize / tile);
         let zoom_level = self.zoom_level;
 
-        for x in 0..LOAD_TILE_DIMENSION {
-            for y in 0..LOAD_TILE_DIMENSION {
-                let tile_x = center_tile.0 + (x as isize - 2);
-                let tile_y = center_tile.1 + (y as isize - 2);
-                if tile_x > 0 && tile_y > 0 {
-                    let target_url = (tile_x as u32, tile_y as u32, zoom_level as u32);
-                    let target_tile = self.tile_manager.get_tile(&(&target_url));
+        let mut tiles = Vec::new();
+        let mut unavailable = 0;
+        for layer in self.tile_manager.visible_layers() {
+            let mut grid = TileGrid::default();
+            for x in 0..LOAD_TILE_DIMENSION {
+                for y in 0..LOAD_TILE_DIMENSION {
+                    let tile_x = center_tile.0 + (x as isize - 2);
+                    let tile_y = center_tile.1 + (y as isize - 2);
+                    if tile_x > 0 && tile_y > 0 {
+                        let target_url = (tile_x as u32, tile_y as u32, zoom_level as u32);
+                        let target_tile = self.tile_manager.get_tile(layer, &target_url);
 
-                    if let TileState::NotLoaded = target_tile.state {
-                        self.tile_manager.queue_tile_load(target_url);
-                        log::info!(
-                            "me no have tile, queueing ({},{},{})",
-                            target_url.0,
-                            target_url.1,
-                            target_url.2
-                        );
+                        if let TileState::NotLoaded = target_tile.state {
+                            self.tile_manager.queue_tile_load(layer, target_url);
+                            log::info!(
+                                "me no have tile, queueing ({},{},{}) of layer {}",
+                                target_url.0,
+                                target_url.1,
+                                target_url.2,
+                                layer
+                            );
+                        }
+                        if self.tile_manager.is_unavailable(layer, &target_url) {
+                            unavailable += 1;
+                        }
+                        grid[x][y] = self.tile_manager.tile_handle(layer, &target_url);
                     }
-                    self.tiles[x][y] = target_tile;
                 }
             }
+            tiles.push(grid);
+        }
+        self.tiles = tiles;
+        self.unavailable_tiles = unavailable;
+        //the visible map is the area the download would be of
+        if self.download_panel.region == RegionChoice::VisibleMap {
+            self.refresh_download_estimate();
         }
     }
 
@@ -140,6 +364,21 @@ impl MapMaker {
         //(Duration::from_secs(3)).await;
     }
 
+    async fn autosave_wait() {
+        tokio::time::sleep(std::time::Duration::from_secs(AUTOSAVE_SECONDS)).await;
+    }
+
+    async fn style_poll_wait() {
+        tokio::time::sleep(std::time::Duration::from_secs(STYLE_POLL_SECONDS)).await;
+    }
+
+    async fn download_batch_wait() {
+        tokio::time::sleep(std::time::Duration::from_secs(
+            DOWNLOAD_BATCH_SECONDS as u64,
+        ))
+        .await;
+    }
+
     fn process_load(resp: Option<Vec<Tile>>) -> MyMessage {
         match resp {
             Some(tiles) => MyMessage::LoadedImage(tiles),
@@ -147,26 +386,733 @@ impl MapMaker {
         }
     }
 
-    fn print_tiles(&self) {
-        for x in 0..LOAD_TILE_DIMENSION {
-            for y in 0..LOAD_TILE_DIMENSION {
-                print!(
-                    "({},{},{}) ",
-                    self.tiles[x][y].target_url.0,
-                    self.tiles[x][y].target_url.1,
-                    self.tiles[x][y].target_url.2,
+    /// Imports the drawing layers in `path`, returning the area they cover.
+    fn import_file(&mut self, path: &Path) -> Option<Bounds> {
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let result = match extension.as_str() {
+            "geojson" | "json" => geojson::read_geojson(path),
+            "gpx" => gpx::read_gpx(path),
+            "kml" => kml::read_kml(path),
+            "kmz" => kml::read_kmz(path),
+            "asc" => {
+                match Dem::read_ascii_grid(path) {
+                    Ok(dem) => self.dem = Some(dem),
+                    Err(e) => log::error!("dem load of {} failed: {}", path.display(), e),
+                }
+                return None;
+            }
+            _ => {
+                log::error!("don't know how to import {}", path.display());
+                return None;
+            }
+        };
+        match result {
+            Ok(layers) => {
+                let mut bounds: Option<Bounds> = None;
+                for layer in layers {
+                    if let Some(layer_bounds) = layer.bounds() {
+                        match bounds.as_mut() {
+                            Some(bounds) => bounds.union(&layer_bounds),
+                            None => bounds = Some(layer_bounds),
+                        }
+                    }
+                    match self
+                        .drawing_layers
+                        .iter_mut()
+                        .find(|existing| existing.name == layer.name && !existing.locked)
+                    {
+                        Some(existing) => {
+                            existing.features.extend(layer.features);
+                            existing.labels.extend(layer.labels);
+                        }
+                        None => self.drawing_layers.push(layer),
+                    }
+                }
+                bounds
+            }
+            Err(e) => {
+                log::error!("import of {} failed: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Moves the view so all of `bounds` is on screen, as close as it fits.
+    fn zoom_to_bounds(&mut self, bounds: &Bounds) -> Command<MyMessage> {
+        let viewport = (TILE_SIZE * 3.0, TILE_SIZE * 3.0);
+        self.center_on(&bounds.center(), bounds.fit_zoom(viewport, 18).max(1))
+    }
+
+    /// Moves the view so `lat_lon` is in the middle of the widget at `zoom`.
+    fn center_on(&mut self, lat_lon: &LatLon, zoom: u8) -> Command<MyMessage> {
+        self.zoom_level = zoom;
+        let center = lat_lon_to_world_pixel(lat_lon, self.zoom_level);
+        let center_tile = (
+            (center.0 / TILE_SIZE).floor(),
+            (center.1 / TILE_SIZE).floor(),
+        );
+        self.load_pixel = (
+            (center_tile.0 * TILE_SIZE) as f32,
+            (center_tile.1 * TILE_SIZE) as f32,
+        );
+        //the tile grid is centered on a tile corner, drag it so `lat_lon` is in the middle
+        //of the widget
+        self.tile_state.load_pixel = (
+            (center.0 - center_tile.0 * TILE_SIZE - TILE_SIZE / 2.0) as f32,
+            (center.1 - center_tile.1 * TILE_SIZE - TILE_SIZE / 2.0) as f32,
+        );
+        self.tile_state.velocity = (0.0, 0.0);
+        self.populate_tiles();
+        Command::perform(
+            self.tile_manager.generate_async_load(),
+            MapMaker::process_load,
+        )
+    }
+
+    fn export_file(&self, path: &Path) {
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let result = match extension.as_str() {
+            "geojson" | "json" => geojson::write_geojson(path, &self.drawing_layers),
+            "gpx" => {
+                let lines_as = if self.gpx_routes {
+                    gpx::LineKind::Route
+                } else {
+                    gpx::LineKind::Track
+                };
+                gpx::write_gpx(path, &self.drawing_layers, lines_as, self.dem.as_ref())
+            }
+            "kml" => kml::write_kml(path, &self.drawing_layers),
+            "kmz" => kml::write_kmz(path, &self.drawing_layers),
+            _ => {
+                log::error!("don't know how to export {}", path.display());
+                return;
+            }
+        };
+        match result {
+            Ok(()) => log::info!("exported {}", path.display()),
+            Err(e) => log::error!("export of {} failed: {}", path.display(), e),
+        }
+    }
+
+    //the part of the world the map widget is showing
+    fn visible_view(&self) -> MapView {
+        MapView::from_load_pixel(self.load_pixel, self.zoom_level)
+            .offset(self.tile_state.load_pixel)
+    }
+
+    fn visible_bounds(&self) -> Bounds {
+        let view = self.visible_view();
+        let widget_size = (TILE_SIZE * 3.0) as f32;
+        let mut bounds = Bounds::new(view.to_lat_lon((0.0, 0.0)));
+        bounds.extend(&view.to_lat_lon((widget_size, widget_size)));
+        bounds
+    }
+
+    //a png or geotiff of the typed in bbox, zoom and width, or of the map as it's shown
+    //for any that are left empty
+    fn raster_export(&self) -> Result<RasterExport, String> {
+        let bounds = match self.export_bbox.trim() {
+            "" => self.visible_bounds(),
+            text => parse_bbox(text)
+                .ok_or_else(|| format!("bad bbox {:?}, expected west,south,east,north", text))?,
+        };
+        let zoom = match self.export_zoom.trim() {
+            "" => self.zoom_level,
+            text => text
+                .parse::<u8>()
+                .ok()
+                .filter(|zoom| *zoom <= MAX_TILE_ZOOM)
+                .ok_or_else(|| format!("bad zoom {:?}", text))?,
+        };
+        let mut export = RasterExport::new(bounds, zoom, self.png_dpi as f32);
+        match self.export_width.trim() {
+            "" => {}
+            text => match text.parse::<u32>() {
+                Ok(width) if width > 0 => export = export.with_width(width),
+                _ => return Err(format!("bad width {:?}", text)),
+            },
+        }
+        let (width, height) = export.size();
+        if width > MAX_EXPORT_PIXELS || height > MAX_EXPORT_PIXELS {
+            return Err(format!("{}x{} pixels is too big to export", width, height));
+        }
+        Ok(export)
+    }
+
+    /// Starts a png or geotiff export of the export area, or a pdf or svg export of the
+    /// print layout. It's written once every tile it needs has been fetched.
+    fn start_export(&mut self, path: PathBuf, extension: &str) -> Command<MyMessage> {
+        let pending = match extension {
+            "pdf" | "svg" => PendingExport::Page(path, self.print_layout.clone()),
+            _ => {
+                let raster = match self.raster_export() {
+                    Ok(raster) => raster,
+                    Err(e) => {
+                        log::error!("can't export {}: {}", path.display(), e);
+                        return Command::none();
+                    }
+                };
+                match extension {
+                    "png" => PendingExport::Png(path, raster),
+                    _ => PendingExport::GeoTiff(path, raster, self.geotiff_projection),
+                }
+            }
+        };
+
+        let missing = self
+            .tile_manager
+            .missing_tiles(&pending.raster().tile_coords());
+        log::info!("export needs {} more tiles", missing.len());
+        self.pending_export = Some(pending);
+        Command::perform(
+            self.tile_manager.fetch_tiles(missing),
+            MyMessage::ExportTilesLoaded,
+        )
+    }
+
+    //writes the pending export once none of its tiles are still loading, the map may have
+    //been loading some of them when the export started
+    fn finish_export_when_loaded(&mut self) -> Command<MyMessage> {
+        let waiting = match &self.pending_export {
+            Some(pending) => self
+                .tile_manager
+                .tiles_loading(&pending.raster().tile_coords()),
+            None => return Command::none(),
+        };
+        if waiting {
+            log::info!("export is waiting on tiles still loading");
+            return Command::none();
+        }
+        match self.pending_export.take() {
+            Some(pending) => self.finish_export(pending),
+            None => Command::none(),
+        }
+    }
+
+    //the export is drawn and written off the ui thread from a copy of its tiles, and
+    //ExportFinished says how it went
+    fn finish_export(&self, pending: PendingExport) -> Command<MyMessage> {
+        let snapshot = self.tile_manager.snapshot(&pending.raster().tile_coords());
+        let layers = self.drawing_layers.clone();
+        let font = self.export_font.clone();
+        let path = pending.path().to_path_buf();
+        let write = tokio::task::spawn_blocking(move || {
+            pending
+                .write(&snapshot, &layers, font.as_ref())
+                .map_err(|e| e.to_string())
+        });
+        Command::perform(write, move |written| {
+            let written = written
+                .map_err(|e| e.to_string())
+                .and_then(|written| written);
+            MyMessage::ExportFinished(path.clone(), written)
+        })
+    }
+
+    fn to_project(&self) -> Project {
+        let widget_size = (TILE_SIZE * 3.0) as f32;
+        Project {
+            view: ProjectView {
+                center: self
+                    .visible_view()
+                    .to_lat_lon((widget_size / 2.0, widget_size / 2.0)),
+                zoom: self.zoom_level,
+            },
+            raster_layers: self
+                .tile_manager
+                .layers()
+                .map(|(_, layer)| layer.clone())
+                .collect(),
+            layers: self.drawing_layers.clone(),
+            vector_style: self
+                .style_watcher
+                .as_ref()
+                .map(|watcher| watcher.path().to_path_buf()),
+            export: ExportPresets {
+                png_dpi: self.png_dpi,
+                geotiff_projection: self.geotiff_projection,
+                gpx_routes: self.gpx_routes,
+                print_layout: self.print_layout.clone(),
+            },
+        }
+    }
+
+    /// Replaces the map with the project at `path`.
+    fn open_project(&mut self, path: &Path) -> Command<MyMessage> {
+        let project = match Project::read(path) {
+            Ok(project) => project,
+            Err(e) => {
+                log::error!("couldn't open project {}: {}", path.display(), e);
+                return Command::none();
+            }
+        };
+        let command = self.apply_project(project);
+        self.project_path = Some(path.to_path_buf());
+        self.recent_projects = project::remember_recent(path);
+        self.mark_saved();
+        log::info!("opened project {}", path.display());
+        command
+    }
+
+    fn apply_project(&mut self, project: Project) -> Command<MyMessage> {
+        if !project.raster_layers.is_empty() {
+            self.tile_manager.set_layers(project.raster_layers);
+        }
+        self.drawing_layers = project.layers;
+        self.label_panel.selected = None;
+        //labels placed on the map go in the first layer, so there has to be one
+        if self.drawing_layers.is_empty() {
+            self.drawing_layers.push(DrawingLayer::new("drawing"));
+        }
+        self.png_dpi = project.export.png_dpi;
+        self.geotiff_projection = project.export.geotiff_projection;
+        self.gpx_routes = project.export.gpx_routes;
+        self.print_layout = project.export.print_layout;
+        let style = self.watch_style(project.vector_style);
+        let view = self.center_on(&project.view.center, project.view.zoom);
+        Command::batch(vec![view, style, self.refresh_layout()])
+    }
+
+    /// Draws vector tiles with the style file at `path` from now on, reading it again
+    /// whenever it's edited. None goes back to the built in style.
+    fn watch_style(&mut self, path: Option<PathBuf>) -> Command<MyMessage> {
+        let path_text = path.as_ref().map(|path| path.display().to_string());
+        self.layer_panel
+            .set_style_path(path_text.unwrap_or_default());
+        match path {
+            Some(path) => {
+                self.style_watcher = Some(StyleWatcher::new(path));
+                self.poll_style()
+            }
+            None => {
+                let was_watching = self.style_watcher.take().is_some();
+                self.layer_panel
+                    .set_style_status(String::from("built in style"));
+                if !was_watching {
+                    return Command::none();
+                }
+                self.tile_manager.set_vector_style(VectorStyle::default());
+                self.reload_tiles()
+            }
+        }
+    }
+
+    //picks up edits to the style file
+    fn poll_style(&mut self) -> Command<MyMessage> {
+        let watcher = match self.style_watcher.as_mut() {
+            Some(watcher) => watcher,
+            None => return Command::none(),
+        };
+        let path = watcher.path().display().to_string();
+        match watcher.poll() {
+            Some(Ok(style)) => {
+                log::info!("read style {}", path);
+                self.layer_panel
+                    .set_style_status(format!("{} layers", style.layers.len()));
+                self.tile_manager.set_vector_style(style);
+                self.reload_tiles()
+            }
+            //the last good style stays until the file is fixed
+            Some(Err(e)) => {
+                log::warn!("couldn't read style {}: {}", path, e);
+                self.layer_panel.set_style_status(format!("error: {}", e));
+                Command::none()
+            }
+            None => Command::none(),
+        }
+    }
+
+    //a right click on the map. it picks the label under it to edit, otherwise a new label
+    //goes there, along the line under it if there is one
+    fn place_label(&mut self, lat_lon: LatLon) -> Command<MyMessage> {
+        let zoom = self.zoom_level;
+        //topmost layer first, the way they're drawn
+        for (idx, layer) in self.drawing_layers.iter().enumerate().rev() {
+            if !layer.is_shown() || layer.locked {
+                continue;
+            }
+            if let Some(label) = label_at(&layer.labels, &lat_lon, zoom) {
+                self.label_panel.select((idx, label), &layer.labels[label]);
+                return Command::none();
+            }
+        }
+        let anchor = self
+            .drawing_layers
+            .iter()
+            .rev()
+            .filter(|layer| layer.is_shown())
+            .find_map(|layer| line_near(&layer.features, &lat_lon, zoom))
+            .map_or(LabelAnchor::Point(lat_lon), LabelAnchor::Polyline);
+        let mut label = Label::new(&self.label_panel.label_text(), anchor);
+        label.style = self.label_panel.style;
+        //the first layer that isn't locked takes it
+        let idx = match self.drawing_layers.iter().position(|layer| !layer.locked) {
+            Some(idx) => idx,
+            None => {
+                self.drawing_layers.push(DrawingLayer::new("drawing"));
+                self.drawing_layers.len() - 1
+            }
+        };
+        let layer = &mut self.drawing_layers[idx];
+        layer.labels.push(label);
+        let selected = (idx, layer.labels.len() - 1);
+        self.label_panel.select(selected, &layer.labels[selected.1]);
+        self.refresh_layout()
+    }
+
+    fn save_project(&mut self, path: &Path) {
+        match self.to_project().write(path) {
+            Ok(()) => {
+                self.project_path = Some(path.to_path_buf());
+                self.recent_projects = project::remember_recent(path);
+                self.mark_saved();
+            }
+            Err(e) => log::error!("couldn't save project {}: {}", path.display(), e),
+        }
+    }
+
+    //what autosave compares to tell whether there's new work to keep. moving around the
+    //map on its own doesn't count
+    fn autosave_state(&self) -> Value {
+        let mut state = self.to_project().to_json();
+        state["view"] = Value::Null;
+        state
+    }
+
+    //the map now matches a project file, an autosave of it would only be stale
+    fn mark_saved(&mut self) {
+        self.autosaved_state = self.autosave_state();
+        project::discard_recovery();
+    }
+
+    /// Writes the map to this session's recovery file if it changed since it was last
+    /// saved.
+    fn autosave(&mut self) {
+        let state = self.autosave_state();
+        if state == self.autosaved_state {
+            return;
+        }
+        match project::write_recovery(&self.to_project(), self.project_path.as_deref()) {
+            Ok(()) => self.autosaved_state = state,
+            Err(e) => log::error!("autosave failed: {}", e),
+        }
+    }
+
+    //the path typed into the toolbar, with the project extension added if it has none
+    fn typed_project_path(&self) -> PathBuf {
+        let mut path = PathBuf::from(self.file_path.trim());
+        if path.extension().is_none() {
+            path.set_extension(PROJECT_EXTENSION);
+        }
+        path
+    }
+
+    //lays the page out again from whatever tiles are already loaded
+    fn compose_layout_preview(&mut self) {
+        let font = self.export_font.as_ref().map(|(font, _)| &**font);
+        let snapshot = self
+            .tile_manager
+            .snapshot(&self.print_layout.raster().tile_coords());
+        let page = self
+            .print_layout
+            .compose(&snapshot, &self.drawing_layers, font);
+        self.layout_preview = Some(LayoutPreview::new(page));
+    }
+
+    //after the raster stack changed: the tile grids are rebuilt and anything newly shown
+    //is fetched
+    fn reload_tiles(&mut self) -> Command<MyMessage> {
+        self.populate_tiles();
+        Command::batch(vec![
+            Command::perform(
+                self.tile_manager.generate_async_load(),
+                MapMaker::process_load,
+            ),
+            self.refresh_layout(),
+        ])
+    }
+
+    fn update_layers(&mut self, message: PanelMessage) -> Command<MyMessage> {
+        match message {
+            PanelMessage::VisibleToggled(LayerRef::Raster(id), visible) => {
+                self.tile_manager.set_visible(id, visible);
+                return self.reload_tiles();
+            }
+            PanelMessage::OpacityChanged(LayerRef::Raster(id), opacity) => {
+                self.tile_manager.set_opacity(id, opacity);
+                return self.reload_tiles();
+            }
+            PanelMessage::Renamed(LayerRef::Raster(id), name) => {
+                self.tile_manager.rename_layer(id, name);
+            }
+            PanelMessage::Deleted(LayerRef::Raster(id)) => {
+                self.tile_manager.remove_layer(id);
+                return self.reload_tiles();
+            }
+            PanelMessage::LockToggled(LayerRef::Raster(id), locked) => {
+                self.tile_manager.set_locked(id, locked);
+            }
+            PanelMessage::Moved(LayerRef::Raster(id), rows) => {
+                //the panel lists the top of the stack first
+                let ids: Vec<usize> = self.tile_manager.layers().map(|(id, _)| id).collect();
+                if let Some(position) = ids.iter().position(|layer| *layer == id) {
+                    let position = (position as i32 - rows).max(0) as usize;
+                    self.tile_manager.move_layer(id, position);
+                    return self.reload_tiles();
+                }
+            }
+            PanelMessage::RasterAdded(source) => {
+                log::info!("adding tile layer {}", source.name);
+                self.tile_manager.add_layer(RasterLayer::new(source));
+                self.layer_panel.set_url(String::new());
+                return self.reload_tiles();
+            }
+            PanelMessage::UrlChanged(url) => self.layer_panel.set_url(url),
+            PanelMessage::CapabilitiesRequested(url) => {
+                self.layer_panel
+                    .set_service_status(String::from("fetching capabilities"));
+                return Command::perform(
+                    ogc::fetch_layers(self.tile_manager.http.clone(), url),
+                    |result| MyMessage::CapabilitiesLoaded(result.map_err(|e| e.to_string())),
                 );
             }
-            println!("");
+            PanelMessage::StylePathChanged(path) => self.layer_panel.set_style_path(path),
+            PanelMessage::StyleChosen(path) => {
+                let path = Some(path.trim()).filter(|path| !path.is_empty());
+                return self.watch_style(path.map(PathBuf::from));
+            }
+
+            PanelMessage::VisibleToggled(LayerRef::Drawing(idx), visible) => {
+                if let Some(layer) = self.drawing_layers.get_mut(idx) {
+                    layer.visible = visible;
+                }
+                return self.refresh_layout();
+            }
+            PanelMessage::OpacityChanged(LayerRef::Drawing(idx), opacity) => {
+                if let Some(layer) = self.drawing_layers.get_mut(idx) {
+                    layer.opacity = opacity;
+                }
+                return self.refresh_layout();
+            }
+            PanelMessage::Renamed(LayerRef::Drawing(idx), name) => {
+                match self.drawing_layers.get_mut(idx) {
+                    Some(layer) if !layer.locked => layer.name = name,
+                    _ => {}
+                }
+            }
+            PanelMessage::Deleted(LayerRef::Drawing(idx)) => {
+                if self
+                    .drawing_layers
+                    .get(idx)
+                    .map_or(false, |layer| !layer.locked)
+                {
+                    let layer = self.drawing_layers.remove(idx);
+                    self.label_panel.selected = None;
+                    log::info!("deleted layer {}", layer.name);
+                    return self.refresh_layout();
+                }
+            }
+            PanelMessage::LockToggled(LayerRef::Drawing(idx), locked) => {
+                if let Some(layer) = self.drawing_layers.get_mut(idx) {
+                    layer.locked = locked;
+                }
+            }
+            PanelMessage::Moved(LayerRef::Drawing(idx), rows) => {
+                if idx < self.drawing_layers.len() {
+                    let last = self.drawing_layers.len() as i32 - 1;
+                    let position = (idx as i32 - rows).max(0).min(last) as usize;
+                    let layer = self.drawing_layers.remove(idx);
+                    self.drawing_layers.insert(position, layer);
+                    self.label_panel.selected = None;
+                    return self.refresh_layout();
+                }
+            }
+            PanelMessage::ZoomToLayer(LayerRef::Drawing(idx)) => {
+                if let Some(bounds) = self.drawing_layers.get(idx).and_then(DrawingLayer::bounds) {
+                    return self.zoom_to_bounds(&bounds);
+                }
+            }
+            PanelMessage::ZoomToLayer(LayerRef::Raster(id)) => {
+                if let Some(bounds) = self.tile_manager.layer_bounds(id) {
+                    return self.zoom_to_bounds(&bounds);
+                }
+            }
+        }
+        Command::none()
+    }
+
+    //the area picked in the download panel
+    fn download_region(&self) -> Option<Region> {
+        match &self.download_panel.region {
+            RegionChoice::VisibleMap => Some(Region::Bounds(self.visible_bounds())),
+            RegionChoice::Feature { layer, feature, .. } => {
+                let feature = self.drawing_layers.get(*layer)?.features.get(*feature)?;
+                Region::from_geometry(&feature.geometry)
+            }
+        }
+    }
+
+    //offline downloads are of the tile layers that are shown, archives are already local
+    fn download_sources(&self) -> Vec<Arc<TileSource>> {
+        self.tile_manager
+            .visible_layers()
+            .into_iter()
+            .filter_map(|id| self.tile_manager.layer_source(id))
+            .filter(|source| !source.is_local())
+            .collect()
+    }
+
+    fn refresh_download_estimate(&mut self) {
+        let estimate = match self.download_region() {
+            Some(region) => Estimate::new(
+                &region,
+                self.download_panel.min_zoom..=self.download_panel.max_zoom,
+                &self.download_sources(),
+                self.tile_manager.cache(),
+            )
+            .to_string(),
+            None => String::from("the area is gone"),
+        };
+        self.download_panel.set_estimate(estimate);
+    }
+
+    fn update_download(&mut self, message: DownloadMessage) -> Command<MyMessage> {
+        match message {
+            DownloadMessage::RegionSelected(region) => self.download_panel.region = region,
+            DownloadMessage::MinZoomSelected(_) | DownloadMessage::MaxZoomSelected(_) => {
+                self.download_panel.select_zooms(&message)
+            }
+            DownloadMessage::Start if self.tile_manager.is_offline() => {
+                self.download_panel
+                    .set_estimate(String::from("can't download while offline"));
+                return Command::none();
+            }
+            DownloadMessage::Start => {
+                let region = match self.download_region() {
+                    Some(region) => region,
+                    None => return Command::none(),
+                };
+                let zooms = self.download_panel.min_zoom..=self.download_panel.max_zoom;
+                let sources = self.download_sources();
+                self.download = None;
+                self.download_generation += 1;
+                let generation = self.download_generation;
+                self.download_panel
+                    .set_estimate(String::from("finding the tiles to download"));
+                //a big polygon has a lot of tiles to go through
+                let prepare = tokio::task::spawn_blocking(move || {
+                    Download::new(&region, zooms, sources).map_err(|e| e.to_string())
+                });
+                return Command::perform(prepare, move |prepared| {
+                    let prepared = prepared
+                        .map_err(|e| e.to_string())
+                        .and_then(|download| download);
+                    MyMessage::DownloadPrepared(generation, prepared)
+                });
+            }
+            DownloadMessage::Pause => {
+                if let Some(download) = self.download.as_mut() {
+                    download.paused = true;
+                }
+            }
+            DownloadMessage::Resume => {
+                if self.tile_manager.is_offline() {
+                    return Command::none();
+                }
+                if let Some(download) = self.download.as_mut() {
+                    download.paused = false;
+                }
+                return self.next_download_batch();
+            }
+            DownloadMessage::Cancel => {
+                //a batch still out finds nothing to report to
+                self.download_generation += 1;
+                if let Some(download) = self.download.take() {
+                    log::info!("offline download stopped at {}", download);
+                }
+            }
+        }
+        self.refresh_download_estimate();
+        Command::none()
+    }
+
+    //sends off the next batch of the download unless it's paused, done or already
+    //waiting on one
+    fn next_download_batch(&mut self) -> Command<MyMessage> {
+        let download = match self.download.as_mut() {
+            Some(download) if !download.paused && !download.in_flight => download,
+            _ => return Command::none(),
+        };
+        let batch = download.next_batch(self.tile_manager.cache(), DOWNLOAD_BATCH_SECONDS);
+        if batch.is_empty() {
+            log::info!("offline download finished, {}", download);
+            return Command::none();
+        }
+        download.in_flight = true;
+        let fetch = self.tile_manager.download_tiles(batch);
+        let generation = self.download_generation;
+        //a batch takes at least its share of time, however fast the server answers
+        Command::perform(
+            async move {
+                let (result, _) = futures::join!(fetch, MapMaker::download_batch_wait());
+                result
+            },
+            move |result| MyMessage::DownloadBatch(generation, result),
+        )
+    }
+
+    /// Redraws the layout preview after a change and fetches any tiles the page now
+    /// needs, which redraw it again once they're in.
+    fn refresh_layout(&mut self) -> Command<MyMessage> {
+        if !self.layout_mode {
+            return Command::none();
+        }
+        self.compose_layout_preview();
+        let coords = self.print_layout.raster().tile_coords();
+        let missing = self.tile_manager.missing_tiles(&coords);
+        if missing.is_empty() {
+            return Command::none();
+        }
+        log::info!("layout needs {} more tiles", missing.len());
+        Command::perform(
+            self.tile_manager.fetch_tiles(missing),
+            MyMessage::LayoutTilesLoaded,
+        )
+    }
+
+    //redraws what's around the map without composing the map again, typing a title or
+    //dragging the legend about doesn't need it. the whole page is redone if the change
+    //moved the map frame
+    fn refresh_decorations(&mut self) -> Command<MyMessage> {
+        let frame = self.print_layout.frame();
+        let map_unchanged = match &self.layout_preview {
+            Some(preview) => preview.page.regions.first() == Some(&(LayoutItem::Map, frame)),
+            None => false,
+        };
+        if !self.layout_mode || !map_unchanged {
+            return self.refresh_layout();
+        }
+        let decorations = self
+            .print_layout
+            .decorations(&self.tile_manager.snapshot(&[]), &self.drawing_layers);
+        if let Some(preview) = &mut self.layout_preview {
+            preview.decorate(decorations);
         }
+        Command::none()
     }
 }
 impl Application for MapMaker {
     type Executor = executor::Default;
     type Message = MyMessage;
-    type Flags = ();
+    type Flags = Option<PathBuf>;
 
-    fn new(_flags: ()) -> (Self, Command<MyMessage>) {
+    fn new(open_path: Option<PathBuf>) -> (Self, Command<MyMessage>) {
         // strange syntax
         //let tiles: [[Vec<u8>; 4]; 4] = [[Vec::new(); 4]; 4];
         let zoom_level: u8 = 4;
@@ -175,27 +1121,112 @@ impl Application for MapMaker {
         //    "my position tile is {}",
         //    slippy_map_tiles::lat_lon_to_tile(42.473882, -83.473203, 3)
         //);
-        let client = std::sync::Arc::new(reqwest::Client::new());
+        let mut map_maker = MapMaker {
+            //TODO: add a new function that handles initializing the array
+            tiles: Default::default(),
+            zoom_in_state: button::State::new(),
+            zoom_out_state: button::State::new(),
+            cur_coords: (42.473882, -83.473203),
+            zoom_level,
+            load_pixel: (TILE_SIZE as f32 * 4.0, TILE_SIZE as f32 * 5.0),
+            tile_state: map_tile::State::default(),
+            tile_manager: TileManager::new(),
+            drawing_layers: vec![DrawingLayer::new("drawing")],
+            file_path: open_path
+                .as_ref()
+                .map(|path| path.display().to_string())
+                .unwrap_or_default(),
+            file_path_state: text_input::State::new(),
+            import_state: button::State::new(),
+            export_state: button::State::new(),
+            gpx_routes: false,
+            dem: None,
+            png_dpi: PNG_DPI_OPTIONS[0],
+            png_dpi_state: pick_list::State::default(),
+            geotiff_projection: GeoTiffProjection::WebMercator,
+            geotiff_projection_state: pick_list::State::default(),
+            export_bbox: String::new(),
+            export_bbox_state: text_input::State::new(),
+            export_bbox_from_map_state: button::State::new(),
+            export_zoom: String::new(),
+            export_zoom_state: text_input::State::new(),
+            export_width: String::new(),
+            export_width_state: text_input::State::new(),
+            print_layout: PrintLayout::default(),
+            layout_mode: false,
+            layout_preview: None,
+            layout_state: button::State::new(),
+            paper_state: pick_list::State::default(),
+            map_scale_state: pick_list::State::default(),
+            grid_state: pick_list::State::default(),
+            layout_title_state: text_input::State::new(),
+            layout_attribution_state: text_input::State::new(),
+            layout_center_state: button::State::new(),
+            layout_reset_state: button::State::new(),
+            page_preview_state: page_preview::State::default(),
+            pending_export: None,
+            project_path: None,
+            recent_projects: project::recent_projects(),
+            recent_projects_state: pick_list::State::default(),
+            open_state: button::State::new(),
+            save_state: button::State::new(),
+            save_as_state: button::State::new(),
+            export_font: export::text::load_font_file()
+                .map(|(font, data)| (Arc::new(font), Arc::new(data))),
+            autosaved_state: Value::Null,
+            recovery: project::read_recovery(),
+            restore_state: button::State::new(),
+            discard_recovery_state: button::State::new(),
+            layer_panel: LayerPanel::default(),
+            style_watcher: None,
+            download_panel: DownloadPanel::default(),
+            label_panel: LabelPanel::default(),
+            download: None,
+            download_generation: 0,
+            unavailable_tiles: 0,
+            display_scale: DisplayScale::detect(),
+            display_scale_state: pick_list::State::default(),
+        };
+        map_maker
+            .tile_manager
+            .set_label_font(map_maker.export_font.as_ref().map(|(font, _)| font.clone()));
+        map_maker
+            .tile_manager
+            .set_display_scale(map_maker.display_scale.0);
+        if let Some(recovery) = &map_maker.recovery {
+            log::info!("found {}", recovery);
+        }
+        map_maker.print_layout.center = map_maker
+            .visible_view()
+            .to_lat_lon(((TILE_SIZE * 1.5) as f32, (TILE_SIZE * 1.5) as f32));
+        //TODO: get the tile manager to spit out the async function to run
+        let mut command = Command::none();
+        if let Some(path) = open_path {
+            let is_project = path
+                .extension()
+                .map_or(false, |ext| ext.eq_ignore_ascii_case(PROJECT_EXTENSION));
+            if is_project {
+                command = map_maker.open_project(&path);
+            } else if let Some(bounds) = map_maker.import_file(&path) {
+                command = map_maker.zoom_to_bounds(&bounds);
+            }
+        }
+        if map_maker.autosaved_state.is_null() {
+            map_maker.autosaved_state = map_maker.autosave_state();
+        }
+        let autosave = Command::perform(MapMaker::autosave_wait(), |_| MyMessage::Autosave);
+        let style_poll = Command::perform(MapMaker::style_poll_wait(), |_| MyMessage::StylePoll);
         (
-            MapMaker {
-                //TODO: add a new function that handles initializing the array
-                tiles: Default::default(),
-                zoom_in_state: button::State::new(),
-                zoom_out_state: button::State::new(),
-                cur_coords: (42.473882, -83.473203),
-                zoom_level,
-                load_pixel: (256.0 * 4.0, 256.0 * 5.0),
-                client: client.clone(),
-                tile_state: map_tile::State::default(),
-                tile_manager: TileManager::new(),
-            },
-            //TODO: get the tile manager to spit out the async function to run
-            Command::none(),
+            map_maker,
+            Command::batch(vec![command, autosave, style_poll]),
         )
     }
 
     fn title(&self) -> String {
-        String::from("MapMaker")
+        match self.project_path.as_ref().and_then(|path| path.file_stem()) {
+            Some(name) => format!("MapMaker - {}", name.to_string_lossy()),
+            None => String::from("MapMaker"),
+        }
     }
 
     fn update(&mut self, message: MyMessage) -> Command<MyMessage> {
@@ -205,11 +1236,15 @@ impl Application for MapMaker {
                 //belong in the current view
                 if tiles.len() > 0 {
                     self.tile_manager.ingest_loaded_tiles(tiles);
+                    let export = self.finish_export_when_loaded();
                     self.populate_tiles();
-                    return Command::perform(
-                        self.tile_manager.generate_async_load(),
-                        MapMaker::process_load,
-                    );
+                    return Command::batch(vec![
+                        export,
+                        Command::perform(
+                            self.tile_manager.generate_async_load(),
+                            MapMaker::process_load,
+                        ),
+                    ]);
                 }
                 return Command::none();
             }
@@ -227,6 +1262,9 @@ impl Application for MapMaker {
             }
             MyMessage::ZoomOut => {
                 println!("me zoom out");
+                if self.zoom_level == 0 {
+                    return Command::none();
+                }
                 self.zoom_level -= 1;
                 self.load_pixel.0 = self.load_pixel.0 * 0.5;
                 self.load_pixel.1 = self.load_pixel.1 * 0.5;
@@ -286,8 +1324,8 @@ impl Application for MapMaker {
                     self.tile_state.load_pixel.1 += -self.tile_state.velocity.1;
 
                     if self.tile_state.center_requested == false
-                        && ((self.tile_state.load_pixel.0.abs() > 256.0)
-                            || (self.tile_state.load_pixel.1.abs() > 256.0))
+                        && ((self.tile_state.load_pixel.0.abs() > TILE_SIZE as f32)
+                            || (self.tile_state.load_pixel.1.abs() > TILE_SIZE as f32))
                     {
                         log::trace!("requesting centering");
                         self.tile_state.center_requested = true;
@@ -312,30 +1350,314 @@ impl Application for MapMaker {
                 return Command::perform(MapMaker::velocity_wait(), |_| MyMessage::VelocityEvent);
             }
 
+            MyMessage::PlaceLabel(lat_lon) => return self.place_label(lat_lon),
+
+            MyMessage::Label(message) => {
+                if self.label_panel.update(message, &mut self.drawing_layers) {
+                    return self.refresh_layout();
+                }
+            }
+
+            MyMessage::FilePathChanged(path) => {
+                self.file_path = path;
+            }
+
+            MyMessage::Import => {
+                let path = PathBuf::from(self.file_path.trim());
+                if let Some(bounds) = self.import_file(&path) {
+                    return self.zoom_to_bounds(&bounds);
+                }
+            }
+
+            MyMessage::Export => {
+                let path = PathBuf::from(self.file_path.trim());
+                let extension = path
+                    .extension()
+                    .map(|ext| ext.to_string_lossy().to_lowercase())
+                    .unwrap_or_default();
+                match extension.as_str() {
+                    "png" | "tif" | "tiff" | "pdf" | "svg" => {
+                        return self.start_export(path, &extension)
+                    }
+                    _ => self.export_file(&path),
+                }
+            }
+
+            MyMessage::PngDpiSelected(dpi) => {
+                self.png_dpi = dpi;
+            }
+
+            MyMessage::GeoTiffProjectionSelected(projection) => {
+                self.geotiff_projection = projection;
+            }
+
+            MyMessage::ExportBboxChanged(bbox) => {
+                self.export_bbox = bbox;
+            }
+
+            MyMessage::ExportBboxFromMap => {
+                let bounds = self.visible_bounds();
+                self.export_bbox = format!(
+                    "{:.5},{:.5},{:.5},{:.5}",
+                    bounds.min.lon, bounds.min.lat, bounds.max.lon, bounds.max.lat
+                );
+            }
+
+            MyMessage::ExportZoomChanged(zoom) => {
+                self.export_zoom = zoom;
+            }
+
+            MyMessage::ExportWidthChanged(width) => {
+                self.export_width = width;
+            }
+
+            MyMessage::ExportTilesLoaded(tiles) => {
+                if let Some(tiles) = tiles {
+                    self.tile_manager.ingest_loaded_tiles(tiles);
+                }
+                return self.finish_export_when_loaded();
+            }
+
+            MyMessage::ExportFinished(path, written) => match written {
+                Ok(()) => log::info!("exported {}", path.display()),
+                Err(e) => log::error!("export of {} failed: {}", path.display(), e),
+            },
+
+            MyMessage::LayoutTilesLoaded(tiles) => {
+                if let Some(tiles) = tiles {
+                    self.tile_manager.ingest_loaded_tiles(tiles);
+                }
+                //an export may be waiting on the same tiles
+                let export = self.finish_export_when_loaded();
+                if self.layout_mode {
+                    self.compose_layout_preview();
+                }
+                return export;
+            }
+
+            MyMessage::ToggleLayoutMode => {
+                self.layout_mode = !self.layout_mode;
+                return self.refresh_layout();
+            }
+
+            MyMessage::PaperSelected(paper) => {
+                self.print_layout.paper = paper;
+                return self.refresh_layout();
+            }
+
+            MyMessage::LandscapeToggled(landscape) => {
+                self.print_layout.landscape = landscape;
+                return self.refresh_layout();
+            }
+
+            MyMessage::MapScaleSelected(scale) => {
+                self.print_layout.scale = scale;
+                return self.refresh_layout();
+            }
+
+            MyMessage::GridStyleSelected(grid) => {
+                self.print_layout.grid = grid;
+                return self.refresh_layout();
+            }
+
+            MyMessage::LayoutTitleChanged(title) => {
+                self.print_layout.title = title;
+                return self.refresh_decorations();
+            }
+
+            MyMessage::LayoutAttributionChanged(attribution) => {
+                self.print_layout.attribution = attribution;
+                return self.refresh_decorations();
+            }
+
+            MyMessage::LegendToggled(legend) => {
+                self.print_layout.legend = legend;
+                return self.refresh_decorations();
+            }
+
+            MyMessage::ScaleBarToggled(scale_bar) => {
+                self.print_layout.scale_bar = scale_bar;
+                return self.refresh_decorations();
+            }
+
+            MyMessage::NorthArrowToggled(north_arrow) => {
+                self.print_layout.north_arrow = north_arrow;
+                return self.refresh_decorations();
+            }
+
+            MyMessage::OpenProject => {
+                let path = self.typed_project_path();
+                return self.open_project(&path);
+            }
+
+            MyMessage::SaveProject => {
+                let path = match &self.project_path {
+                    Some(path) => path.clone(),
+                    None => self.typed_project_path(),
+                };
+                self.save_project(&path);
+            }
+
+            MyMessage::SaveProjectAs => {
+                let path = self.typed_project_path();
+                self.save_project(&path);
+            }
+
+            MyMessage::RecentProjectSelected(recent) => {
+                self.file_path = recent.0.display().to_string();
+                return self.open_project(&recent.0);
+            }
+
+            MyMessage::Autosave => {
+                self.autosave();
+                return Command::perform(MapMaker::autosave_wait(), |_| MyMessage::Autosave);
+            }
+
+            MyMessage::RestoreRecovery => {
+                if let Some(recovery) = self.recovery.take() {
+                    log::info!("restoring {}", recovery);
+                    let command = self.apply_project(recovery.project.clone());
+                    if let Some(path) = &recovery.project_path {
+                        self.file_path = path.display().to_string();
+                    }
+                    self.project_path = recovery.project_path.clone();
+                    //still unsaved, it's this session's to autosave now. the old file goes
+                    //once there's a copy
+                    self.autosaved_state = Value::Null;
+                    self.autosave();
+                    if self.autosaved_state != Value::Null {
+                        recovery.remove();
+                    }
+                    return command;
+                }
+            }
+
+            MyMessage::DiscardRecovery => {
+                if let Some(recovery) = self.recovery.take() {
+                    recovery.remove();
+                }
+                //another crashed session may have left work too
+                self.recovery = project::read_recovery();
+            }
+
+            MyMessage::LayerPanel(message) => return self.update_layers(message),
+
+            MyMessage::Download(message) => return self.update_download(message),
+
+            MyMessage::OfflineToggled(offline) => {
+                log::info!("going {}", if offline { "offline" } else { "online" });
+                self.tile_manager.set_offline(offline);
+                //a download can't go on without the network
+                if let Some(download) = self.download.as_mut().filter(|_| offline) {
+                    download.paused = true;
+                }
+                return self.reload_tiles();
+            }
+            MyMessage::DisplayScaleSelected(scale) => {
+                log::info!("showing tiles at {} detail", scale);
+                self.display_scale = scale;
+                self.tile_manager.set_display_scale(scale.0);
+                return self.reload_tiles();
+            }
+            MyMessage::CapabilitiesLoaded(Ok(layers)) => {
+                log::info!("service has {} layers the map can show", layers.len());
+                self.layer_panel.set_service_layers(layers);
+            }
+            MyMessage::CapabilitiesLoaded(Err(e)) => {
+                log::error!("couldn't read capabilities: {}", e);
+                self.layer_panel.set_service_status(format!("error: {}", e));
+            }
+
+            MyMessage::DownloadPrepared(generation, _)
+            | MyMessage::DownloadBatch(generation, _)
+                if generation != self.download_generation =>
+            {
+                log::debug!("dropping what came back for an old download");
+            }
+
+            MyMessage::DownloadPrepared(_, Ok(download)) => {
+                log::info!("downloading {} tiles for offline use", download.total);
+                self.download = Some(download);
+                self.refresh_download_estimate();
+                return self.next_download_batch();
+            }
+
+            MyMessage::DownloadPrepared(_, Err(e)) => {
+                log::warn!("can't download {}: {}", self.download_panel.region, e);
+                self.download_panel.set_estimate(e);
+            }
+
+            MyMessage::DownloadBatch(_, result) => {
+                match self.download.as_mut() {
+                    Some(download) => download.record(&result),
+                    None => return Command::none(),
+                }
+                return self.next_download_batch();
+            }
+
+            MyMessage::StylePoll => {
+                let next = Command::perform(MapMaker::style_poll_wait(), |_| MyMessage::StylePoll);
+                return Command::batch(vec![self.poll_style(), next]);
+            }
+
+            MyMessage::LayoutCenterOnMap => {
+                let widget_size = (TILE_SIZE * 3.0) as f32;
+                self.print_layout.center = self
+                    .visible_view()
+                    .to_lat_lon((widget_size / 2.0, widget_size / 2.0));
+                return self.refresh_layout();
+            }
+
+            MyMessage::LayoutItemMoved(item, by) => {
+                self.print_layout.move_item(item, by);
+                if item == LayoutItem::Map {
+                    return self.refresh_layout();
+                }
+                return self.refresh_decorations();
+            }
+
+            MyMessage::LayoutItemResized(item, factor) => {
+                self.print_layout.resize_item(item, factor);
+                if item == LayoutItem::Map {
+                    return self.refresh_layout();
+                }
+                return self.refresh_decorations();
+            }
+
+            MyMessage::LayoutPlacementsReset => {
+                self.print_layout.placements.clear();
+                return self.refresh_layout();
+            }
+
+            MyMessage::GpxRoutesToggled(gpx_routes) => {
+                self.gpx_routes = gpx_routes;
+            }
+
             MyMessage::CenterPosition => {
                 log::info!("centering event");
                 //change the load pixel back to something centered
                 //and start loading tiles to adjust for the change
                 //TODO: start the load
+                let tile = TILE_SIZE as f32;
                 let mut x_delta = 0.0;
                 let mut y_delta = 0.0;
-                while self.tile_state.load_pixel.0.abs() > 256.0 {
-                    if self.tile_state.load_pixel.0 < -256.0 {
-                        self.tile_state.load_pixel.0 += 256.0;
-                        x_delta = -256.0;
-                    } else if self.tile_state.load_pixel.0 > 256.0 {
-                        self.tile_state.load_pixel.0 -= 256.0;
-                        x_delta = 256.0;
+                while self.tile_state.load_pixel.0.abs() > tile {
+                    if self.tile_state.load_pixel.0 < -tile {
+                        self.tile_state.load_pixel.0 += tile;
+                        x_delta = -tile;
+                    } else if self.tile_state.load_pixel.0 > tile {
+                        self.tile_state.load_pixel.0 -= tile;
+                        x_delta = tile;
                     }
                 }
 
-                while self.tile_state.load_pixel.1.abs() > 256.0 {
-                    if self.tile_state.load_pixel.1 < -256.0 {
-                        self.tile_state.load_pixel.1 += 256.0;
-                        y_delta = -256.0;
-                    } else if self.tile_state.load_pixel.1 > 256.0 {
-                        self.tile_state.load_pixel.1 -= 256.0;
-                        y_delta = 256.0;
+                while self.tile_state.load_pixel.1.abs() > tile {
+                    if self.tile_state.load_pixel.1 < -tile {
+                        self.tile_state.load_pixel.1 += tile;
+                        y_delta = -tile;
+                    } else if self.tile_state.load_pixel.1 > tile {
+                        self.tile_state.load_pixel.1 -= tile;
+                        y_delta = tile;
                     }
                 }
 
@@ -345,7 +1667,6 @@ impl Application for MapMaker {
                 self.load_pixel.0 += x_delta;
                 self.load_pixel.1 += y_delta;
                 self.populate_tiles();
-                self.print_tiles();
                 return Command::perform(
                     self.tile_manager.generate_async_load(),
                     MapMaker::process_load,
@@ -369,22 +1690,267 @@ impl Application for MapMaker {
         //    Button::new(state, Text::new("Press Me!")).on_press(Message::ButtonPressed)
         //});
         //cannot call this function in the container declaration because of borrowing rules
-        let imgs = self.get_tile_imgs();
-        Container::new(map_tile::MapTile::new(
-            &mut self.tile_state,
-            imgs,
-            &mut self.zoom_in_state,
-            &mut self.zoom_out_state,
-            //https://stackoverflow.com/questions/27895946/expected-fn-item-found-a-different-fn-item-when-working-with-function-pointer
-            zoom_in_spawner as ButtonSpawner,
-            zoom_out_spawner as ButtonSpawner,
-            MyMessage::CenterPosition,
-            MyMessage::VelocityEvent,
-        ))
-        .width(Length::Fill)
-        .height(Length::Fill)
-        .center_x()
-        .center_y()
-        .into()
+        let view = MapView::from_load_pixel(self.load_pixel, self.zoom_level);
+        let offline = Some(self.unavailable_tiles).filter(|_| self.tile_manager.is_offline());
+        let toolbar = Row::new()
+            .spacing(10)
+            .push(
+                TextInput::new(
+                    &mut self.file_path_state,
+                    "file path",
+                    &self.file_path,
+                    MyMessage::FilePathChanged,
+                )
+                .padding(5),
+            )
+            .push(
+                Button::new(&mut self.open_state, Text::new("open"))
+                    .on_press(MyMessage::OpenProject),
+            )
+            .push(
+                Button::new(&mut self.save_state, Text::new("save"))
+                    .on_press(MyMessage::SaveProject),
+            )
+            .push(
+                Button::new(&mut self.save_as_state, Text::new("save as"))
+                    .on_press(MyMessage::SaveProjectAs),
+            )
+            .push(PickList::new(
+                &mut self.recent_projects_state,
+                &self.recent_projects[..],
+                None,
+                MyMessage::RecentProjectSelected,
+            ))
+            .push(
+                Button::new(&mut self.import_state, Text::new("import"))
+                    .on_press(MyMessage::Import),
+            )
+            .push(
+                Button::new(&mut self.export_state, Text::new("export"))
+                    .on_press(MyMessage::Export),
+            )
+            .push(
+                Button::new(
+                    &mut self.layout_state,
+                    Text::new(if self.layout_mode { "map" } else { "layout" }),
+                )
+                .on_press(MyMessage::ToggleLayoutMode),
+            )
+            .push(Checkbox::new(
+                self.tile_manager.is_offline(),
+                "offline",
+                MyMessage::OfflineToggled,
+            ))
+            .push(Text::new("detail"))
+            .push(PickList::new(
+                &mut self.display_scale_state,
+                &DisplayScale::ALL[..],
+                Some(self.display_scale),
+                MyMessage::DisplayScaleSelected,
+            ));
+        let export_options = Row::new()
+            .spacing(10)
+            .push(Checkbox::new(
+                self.gpx_routes,
+                "gpx routes",
+                MyMessage::GpxRoutesToggled,
+            ))
+            .push(Text::new("png/tiff dpi"))
+            .push(PickList::new(
+                &mut self.png_dpi_state,
+                &PNG_DPI_OPTIONS[..],
+                Some(self.png_dpi),
+                MyMessage::PngDpiSelected,
+            ))
+            .push(Text::new("tiff crs"))
+            .push(PickList::new(
+                &mut self.geotiff_projection_state,
+                &GeoTiffProjection::ALL[..],
+                Some(self.geotiff_projection),
+                MyMessage::GeoTiffProjectionSelected,
+            ));
+        //leaving these empty exports the map as it's shown
+        let export_area = Row::new()
+            .spacing(10)
+            .push(Text::new("area"))
+            .push(
+                TextInput::new(
+                    &mut self.export_bbox_state,
+                    "west,south,east,north",
+                    &self.export_bbox,
+                    MyMessage::ExportBboxChanged,
+                )
+                .padding(3)
+                .width(Length::Units(320)),
+            )
+            .push(
+                Button::new(&mut self.export_bbox_from_map_state, Text::new("from map"))
+                    .on_press(MyMessage::ExportBboxFromMap),
+            )
+            .push(Text::new("zoom"))
+            .push(
+                TextInput::new(
+                    &mut self.export_zoom_state,
+                    &self.zoom_level.to_string(),
+                    &self.export_zoom,
+                    MyMessage::ExportZoomChanged,
+                )
+                .padding(3)
+                .width(Length::Units(50)),
+            )
+            .push(Text::new("width px"))
+            .push(
+                TextInput::new(
+                    &mut self.export_width_state,
+                    "by dpi",
+                    &self.export_width,
+                    MyMessage::ExportWidthChanged,
+                )
+                .padding(3)
+                .width(Length::Units(80)),
+            );
+        let mut content = Column::new().spacing(5);
+        if let Some(recovery) = &self.recovery {
+            content = content.push(
+                Row::new()
+                    .spacing(10)
+                    .push(Text::new(format!("{} was recovered", recovery)))
+                    .push(
+                        Button::new(&mut self.restore_state, Text::new("restore"))
+                            .on_press(MyMessage::RestoreRecovery),
+                    )
+                    .push(
+                        Button::new(&mut self.discard_recovery_state, Text::new("discard"))
+                            .on_press(MyMessage::DiscardRecovery),
+                    ),
+            );
+        }
+        content = content.push(toolbar).push(export_options).push(export_area);
+        //the map or the page, next to the layer panel
+        let mut workspace = Column::new().spacing(5);
+        if self.layout_mode {
+            let layout = &self.print_layout;
+            let page_options = Row::new()
+                .spacing(10)
+                .push(PickList::new(
+                    &mut self.paper_state,
+                    &PaperSize::ALL[..],
+                    Some(layout.paper),
+                    MyMessage::PaperSelected,
+                ))
+                .push(Checkbox::new(
+                    layout.landscape,
+                    "landscape",
+                    MyMessage::LandscapeToggled,
+                ))
+                .push(PickList::new(
+                    &mut self.map_scale_state,
+                    &MapScale::COMMON[..],
+                    Some(layout.scale),
+                    MyMessage::MapScaleSelected,
+                ))
+                .push(PickList::new(
+                    &mut self.grid_state,
+                    &GridStyle::ALL[..],
+                    Some(layout.grid),
+                    MyMessage::GridStyleSelected,
+                ))
+                .push(Checkbox::new(
+                    layout.legend,
+                    "legend",
+                    MyMessage::LegendToggled,
+                ))
+                .push(Checkbox::new(
+                    layout.scale_bar,
+                    "scale bar",
+                    MyMessage::ScaleBarToggled,
+                ))
+                .push(Checkbox::new(
+                    layout.north_arrow,
+                    "north arrow",
+                    MyMessage::NorthArrowToggled,
+                ))
+                .push(
+                    Button::new(&mut self.layout_center_state, Text::new("center on map"))
+                        .on_press(MyMessage::LayoutCenterOnMap),
+                )
+                .push(
+                    Button::new(&mut self.layout_reset_state, Text::new("reset positions"))
+                        .on_press(MyMessage::LayoutPlacementsReset),
+                );
+            let page_text = Row::new()
+                .spacing(10)
+                .push(
+                    TextInput::new(
+                        &mut self.layout_title_state,
+                        "title",
+                        &layout.title,
+                        MyMessage::LayoutTitleChanged,
+                    )
+                    .padding(5),
+                )
+                .push(
+                    TextInput::new(
+                        &mut self.layout_attribution_state,
+                        "attribution, the tile layers' own when empty",
+                        &layout.attribution,
+                        MyMessage::LayoutAttributionChanged,
+                    )
+                    .padding(5),
+                );
+            workspace = workspace.push(page_options).push(page_text);
+            if let Some(preview) = &self.layout_preview {
+                workspace = workspace.push(PagePreview::new(
+                    &mut self.page_preview_state,
+                    preview,
+                    MyMessage::LayoutItemMoved,
+                    MyMessage::LayoutItemResized,
+                ));
+            }
+        } else {
+            let map = map_tile::MapTile::new(
+                &mut self.tile_state,
+                self.tiles.clone(),
+                &mut self.zoom_in_state,
+                &mut self.zoom_out_state,
+                //https://stackoverflow.com/questions/27895946/expected-fn-item-found-a-different-fn-item-when-working-with-function-pointer
+                zoom_in_spawner as ButtonSpawner,
+                zoom_out_spawner as ButtonSpawner,
+                MyMessage::CenterPosition,
+                MyMessage::VelocityEvent,
+                &self.drawing_layers,
+                view,
+                MyMessage::PlaceLabel,
+            )
+            .offline(offline)
+            .scale(self.display_scale);
+            workspace = workspace.push(map);
+        }
+        let tile_manager = &self.tile_manager;
+        let raster_layers = tile_manager
+            .layers()
+            .map(|(id, layer)| (id, layer, tile_manager.layer_bounds(id).is_some()))
+            .collect();
+        let panel = self
+            .layer_panel
+            .view(raster_layers, &self.drawing_layers)
+            .map(MyMessage::LayerPanel);
+        let download = self
+            .download_panel
+            .view(&self.drawing_layers, self.download.as_ref())
+            .map(MyMessage::Download);
+        let labels = self.label_panel.view().map(MyMessage::Label);
+        let side = Column::new()
+            .spacing(10)
+            .push(panel)
+            .push(labels)
+            .push(download);
+        content = content.push(Row::new().spacing(10).push(side).push(workspace));
+        Container::new(content)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .center_x()
+            .center_y()
+            .into()
     }
 }
@@ -0,0 +1,167 @@
+//a tile server on localhost for tests, so tile loading can be checked without a network.
+//the first part of a request's path picks how it's answered:
+//
+//  /tiles/{z}/{x}/{y}.png      a png of the tile's own colour, cacheable for an hour
+//  /slow/{z}/{x}/{y}.png       the same after a delay
+//  /missing/{z}/{x}/{y}.png    404
+//  /error/{z}/{x}/{y}.png      500
+//  /truncated/{z}/{x}/{y}.png  a png cut off half way, the connection closing early
+use image::{DynamicImage, ImageOutputFormat, Rgba, RgbaImage};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// How long slow tiles take.
+pub const LATENCY: Duration = Duration::from_millis(250);
+
+/// A request the server got, with header names lowercased.
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl MockRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub struct MockTileServer {
+    port: u16,
+    log: Arc<Log>,
+}
+
+//what the server has been asked, shared by the tasks answering
+#[derive(Default)]
+struct Log {
+    requests: Mutex<Vec<MockRequest>>,
+    answering: AtomicUsize,
+    most_answering: AtomicUsize,
+}
+
+impl MockTileServer {
+    /// Starts serving on a free port. It runs on the current tokio runtime until that stops.
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let log = Arc::new(Log::default());
+        let answering = log.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(answer(stream, answering.clone()));
+            }
+        });
+        Self { port, log }
+    }
+
+    /// The url template of one of the routes.
+    pub fn url(&self, route: &str) -> String {
+        format!(
+            "http://127.0.0.1:{}/{}/{{z}}/{{x}}/{{y}}.png",
+            self.port, route
+        )
+    }
+
+    /// Every request so far, oldest first.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.log.requests.lock().unwrap().clone()
+    }
+
+    /// The most requests the server has been answering at the same time.
+    pub fn most_at_once(&self) -> usize {
+        self.log.most_answering.load(Ordering::SeqCst)
+    }
+}
+
+/// The colour a tile is filled with, different for every tile a test is likely to ask for.
+pub fn tile_color(coords: &(u32, u32, u32)) -> Rgba<u8> {
+    let (x, y, z) = *coords;
+    Rgba([
+        (x * 40 % 256) as u8,
+        (y * 40 % 256) as u8,
+        (z * 20 % 256) as u8,
+        255,
+    ])
+}
+
+/// The png the server answers a tile with.
+pub fn tile_png(coords: &(u32, u32, u32)) -> Vec<u8> {
+    let image = RgbaImage::from_pixel(256, 256, tile_color(coords));
+    let mut bytes = Vec::new();
+    DynamicImage::ImageRgba8(image)
+        .write_to(&mut bytes, ImageOutputFormat::Png)
+        .unwrap();
+    bytes
+}
+
+//route, z, x and y of a tile path
+fn parse_path(path: &str) -> Option<(&str, (u32, u32, u32))> {
+    let mut parts = path.trim_start_matches('/').split('/');
+    let route = parts.next()?;
+    let z = parts.next()?.parse().ok()?;
+    let x = parts.next()?.parse().ok()?;
+    let y = parts.next()?.trim_end_matches(".png").parse().ok()?;
+    Some((route, (x, y, z)))
+}
+
+async fn answer(mut stream: TcpStream, log: Arc<Log>) {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => head.extend_from_slice(&buf[..n]),
+        }
+    }
+    let head = String::from_utf8_lossy(&head).to_string();
+    let mut lines = head.lines();
+    let path = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .unwrap_or_default()
+        .to_string();
+    let headers = lines
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            Some((name.trim().to_lowercase(), value.trim().to_string()))
+        })
+        .collect();
+    log.requests.lock().unwrap().push(MockRequest {
+        path: path.clone(),
+        headers,
+    });
+    let answering = log.answering.fetch_add(1, Ordering::SeqCst) + 1;
+    log.most_answering.fetch_max(answering, Ordering::SeqCst);
+
+    let (status, body, length) = match parse_path(&path) {
+        Some(("tiles", coords)) => ("200 OK", tile_png(&coords), None),
+        Some(("slow", coords)) => {
+            tokio::time::sleep(LATENCY).await;
+            ("200 OK", tile_png(&coords), None)
+        }
+        Some(("missing", _)) => ("404 Not Found", b"no such tile".to_vec(), None),
+        Some(("error", _)) => ("500 Internal Server Error", b"oops".to_vec(), None),
+        Some(("truncated", coords)) => {
+            let png = tile_png(&coords);
+            let length = png.len();
+            ("200 OK", png[..length / 2].to_vec(), Some(length))
+        }
+        _ => ("400 Bad Request", Vec::new(), None),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: image/png\r\nContent-Length: {}\r\n\
+         Cache-Control: max-age=3600\r\nConnection: close\r\n\r\n",
+        status,
+        length.unwrap_or(body.len())
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.write_all(&body).await;
+    let _ = stream.shutdown().await;
+    log.answering.fetch_sub(1, Ordering::SeqCst);
+}
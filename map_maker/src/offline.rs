@@ -0,0 +1,528 @@
+//downloading the tiles of an area ahead of time, for using the map where there's no network
+//
+//a download goes out in batches, each source getting as many tiles a batch as its rate
+//limit allows, and everything fetched goes straight to the disk cache
+use crate::features::{DrawingLayer, Geometry};
+use crate::geo::{lat_lon_to_world_pixel, Bounds, LatLon, TILE_SIZE};
+use crate::tile_cache::TileCache;
+use crate::tile_manager::tile_manager::{TileKind, TileSource};
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Most tiles a download fetches. Tile servers are mostly run on donations, they're not
+/// there to be copied wholesale.
+pub const MAX_DOWNLOAD_TILES: u64 = 50_000;
+//sizes to go by before anything of a source is cached
+const RASTER_TILE_BYTES: u64 = 20_000;
+const VECTOR_TILE_BYTES: u64 = 40_000;
+//polygons covering more tiles than this are counted by area instead of tile by tile
+const EXACT_COUNT_LIMIT: u64 = 4096;
+//how many tiles finding a polygon's tiles looks at for each it's allowed to find, so a
+//shape with a lot of empty space between its edges can't keep it going for ages
+const SCANNED_PER_TILE: u64 = 8;
+//how many already cached tiles a batch skips over at most, so a mostly cached area
+//doesn't hold up the ui checking them all at once
+const MAX_SKIPPED: usize = 2000;
+
+//a polygon's rings in world pixels
+type WorldPolygon = Vec<Vec<(f64, f64)>>;
+
+#[derive(Debug, Error)]
+pub enum OfflineError {
+    #[error("{0} tiles are too many to download, pick a smaller area or fewer zoom levels")]
+    TooManyTiles(u64),
+    #[error("more than {0} tiles to download, pick a smaller area or fewer zoom levels")]
+    OverLimit(u64),
+    #[error("there's no area to download")]
+    EmptyRegion,
+    #[error("no tile layers are shown")]
+    NoSources,
+}
+
+/// An area to download.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Region {
+    Bounds(Bounds),
+    //outer ring then holes, like polygons in drawing layers
+    Polygons(Vec<Vec<Vec<LatLon>>>),
+}
+
+impl Region {
+    pub fn from_geometry(geometry: &Geometry) -> Option<Self> {
+        match geometry {
+            Geometry::Polygon(rings) => Some(Region::Polygons(vec![rings.clone()])),
+            Geometry::MultiPolygon(polygons) => Some(Region::Polygons(polygons.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn bounds(&self) -> Option<Bounds> {
+        match self {
+            Region::Bounds(bounds) => Some(*bounds),
+            Region::Polygons(polygons) => {
+                let mut points = polygons.iter().flatten().flatten();
+                let mut bounds = Bounds::new(*points.next()?);
+                points.for_each(|point| bounds.extend(point));
+                Some(bounds)
+            }
+        }
+    }
+
+    /// Every tile at `zoom` with some of the region on it, None if there are more than
+    /// `limit`. Polygons are gone through a row at a time across just the columns their
+    /// edges reach, a long thin one isn't checked over all of its bounds.
+    pub fn tiles(&self, zoom: u8, limit: u64) -> Option<Vec<(u32, u32, u32)>> {
+        let zoom_tiles = |rows_and_columns: BTreeSet<(u32, u32)>| {
+            let tiles = rows_and_columns.into_iter();
+            tiles.map(|(y, x)| (x, y, zoom as u32)).collect()
+        };
+        if let Region::Bounds(bounds) = self {
+            let (first, last) = tile_range(bounds, zoom);
+            let count = (last.0 - first.0 + 1) as u64 * (last.1 - first.1 + 1) as u64;
+            if count > limit {
+                return None;
+            }
+            let rows = first.1..=last.1;
+            let tiles = rows.flat_map(|y| (first.0..=last.0).map(move |x| (y, x)));
+            return Some(zoom_tiles(tiles.collect()));
+        }
+        let last_tile = (1u64 << zoom) as f64 - 1.0;
+        let tile = |pixel: f64| (pixel / TILE_SIZE).floor().max(0.0).min(last_tile) as u32;
+        let mut scan_budget = limit.saturating_mul(SCANNED_PER_TILE);
+        //row then column, polygons of a multipolygon can share tiles
+        let mut tiles = BTreeSet::new();
+        for rings in self.world_polygons(zoom).unwrap_or_default() {
+            let points = || rings.iter().flatten();
+            let top = points().map(|point| point.1).fold(f64::INFINITY, f64::min);
+            let bottom = points()
+                .map(|point| point.1)
+                .fold(f64::NEG_INFINITY, f64::max);
+            if !top.is_finite() || !bottom.is_finite() {
+                continue;
+            }
+            for y in tile(top)..=tile(bottom) {
+                let (left, right) = match row_span(&rings, y) {
+                    Some(span) => span,
+                    None => continue,
+                };
+                for x in tile(left)..=tile(right) {
+                    scan_budget = scan_budget.checked_sub(1)?;
+                    if covers_tile(&rings, x, y) {
+                        tiles.insert((y, x));
+                    }
+                }
+                if tiles.len() as u64 > limit {
+                    return None;
+                }
+            }
+        }
+        Some(zoom_tiles(tiles))
+    }
+
+    /// How many tiles `tiles` gives back, estimated from their area for big polygons.
+    pub fn tile_count(&self, zoom: u8) -> u64 {
+        let (first, last) = match self.bounds() {
+            Some(bounds) => tile_range(&bounds, zoom),
+            None => return 0,
+        };
+        let in_bounds = (last.0 - first.0 + 1) as u64 * (last.1 - first.1 + 1) as u64;
+        let polygons = match self.world_polygons(zoom) {
+            Some(polygons) if in_bounds > EXACT_COUNT_LIMIT => polygons,
+            Some(_) => {
+                let tiles = self.tiles(zoom, in_bounds);
+                return tiles.map_or(in_bounds, |tiles| tiles.len() as u64);
+            }
+            None => return in_bounds,
+        };
+        //the tiles inside, plus a row of them along the edges
+        let mut area = 0.0;
+        let mut perimeter = 0.0;
+        for rings in &polygons {
+            for (idx, ring) in rings.iter().enumerate() {
+                let ring_area = signed_area(ring).abs();
+                area += if idx == 0 { ring_area } else { -ring_area };
+                perimeter += ring_length(ring);
+            }
+        }
+        let tiles = area.max(0.0) / (TILE_SIZE * TILE_SIZE) + perimeter / TILE_SIZE;
+        (tiles.ceil() as u64).max(1).min(in_bounds)
+    }
+
+    //the polygons in world pixels at `zoom`, None for plain bounds
+    fn world_polygons(&self, zoom: u8) -> Option<Vec<WorldPolygon>> {
+        match self {
+            Region::Bounds(_) => None,
+            Region::Polygons(polygons) => Some(
+                polygons
+                    .iter()
+                    .map(|rings| {
+                        rings
+                            .iter()
+                            .map(|ring| {
+                                ring.iter()
+                                    .map(|point| lat_lon_to_world_pixel(point, zoom))
+                                    .collect()
+                            })
+                            .collect()
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+//first and last tile x and y under `bounds`
+fn tile_range(bounds: &Bounds, zoom: u8) -> ((u32, u32), (u32, u32)) {
+    let last_tile = (1u64 << zoom) as f64 - 1.0;
+    let top_left = lat_lon_to_world_pixel(&LatLon::new(bounds.max.lat, bounds.min.lon), zoom);
+    let bottom_right = lat_lon_to_world_pixel(&LatLon::new(bounds.min.lat, bounds.max.lon), zoom);
+    let tile = |pixel: f64| (pixel / TILE_SIZE).floor().max(0.0).min(last_tile) as u32;
+    (
+        (tile(top_left.0), tile(top_left.1)),
+        (tile(bottom_right.0), tile(bottom_right.1)),
+    )
+}
+
+//how far left and right a polygon's edges reach across row `y` of tiles, in world pixels.
+//None if none of them cross it
+fn row_span(rings: &[Vec<(f64, f64)>], y: u32) -> Option<(f64, f64)> {
+    let top = y as f64 * TILE_SIZE;
+    let bottom = top + TILE_SIZE;
+    let mut span: Option<(f64, f64)> = None;
+    for ring in rings {
+        for (idx, a) in ring.iter().enumerate() {
+            let b = ring[(idx + 1) % ring.len()];
+            let (low, high) = (a.1.min(b.1), a.1.max(b.1));
+            if high < top || low > bottom {
+                continue;
+            }
+            //where the edge goes in and out of the row
+            let x_at = |edge_y: f64| a.0 + (edge_y - a.1) / (b.1 - a.1) * (b.0 - a.0);
+            let (x1, x2) = if a.1 == b.1 {
+                (a.0, b.0)
+            } else {
+                (x_at(low.max(top)), x_at(high.min(bottom)))
+            };
+            let (left, right) = span.unwrap_or((f64::INFINITY, f64::NEG_INFINITY));
+            span = Some((left.min(x1).min(x2), right.max(x1).max(x2)));
+        }
+    }
+    span
+}
+
+//whether a polygon, in world pixels, has any of tile (x, y) in it
+fn covers_tile(rings: &[Vec<(f64, f64)>], x: u32, y: u32) -> bool {
+    let left = x as f64 * TILE_SIZE;
+    let top = y as f64 * TILE_SIZE;
+    let (right, bottom) = (left + TILE_SIZE, top + TILE_SIZE);
+    let center = (left + TILE_SIZE / 2.0, top + TILE_SIZE / 2.0);
+    if contains(rings, center) {
+        return true;
+    }
+    let corners = [(left, top), (right, top), (right, bottom), (left, bottom)];
+    for ring in rings {
+        for (idx, a) in ring.iter().enumerate() {
+            if a.0 >= left && a.0 <= right && a.1 >= top && a.1 <= bottom {
+                return true;
+            }
+            let b = ring[(idx + 1) % ring.len()];
+            for side in 0..4 {
+                if segments_cross(*a, b, corners[side], corners[(side + 1) % 4]) {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+//even-odd, so holes leave their inside out
+fn contains(rings: &[Vec<(f64, f64)>], point: (f64, f64)) -> bool {
+    let mut inside = false;
+    for ring in rings {
+        for (idx, a) in ring.iter().enumerate() {
+            let b = ring[(idx + 1) % ring.len()];
+            if (a.1 > point.1) != (b.1 > point.1)
+                && point.0 < a.0 + (point.1 - a.1) / (b.1 - a.1) * (b.0 - a.0)
+            {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+fn segments_cross(a: (f64, f64), b: (f64, f64), c: (f64, f64), d: (f64, f64)) -> bool {
+    let side = |p: (f64, f64), q: (f64, f64), r: (f64, f64)| {
+        (q.0 - p.0) * (r.1 - p.1) - (q.1 - p.1) * (r.0 - p.0)
+    };
+    let (d1, d2) = (side(c, d, a), side(c, d, b));
+    let (d3, d4) = (side(a, b, c), side(a, b, d));
+    d1 * d2 < 0.0 && d3 * d4 < 0.0
+}
+
+fn signed_area(ring: &[(f64, f64)]) -> f64 {
+    let mut sum = 0.0;
+    for (idx, a) in ring.iter().enumerate() {
+        let b = ring[(idx + 1) % ring.len()];
+        sum += a.0 * b.1 - b.0 * a.1;
+    }
+    sum / 2.0
+}
+
+fn ring_length(ring: &[(f64, f64)]) -> f64 {
+    let mut length = 0.0;
+    for (idx, a) in ring.iter().enumerate() {
+        let b = ring[(idx + 1) % ring.len()];
+        length += ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt();
+    }
+    length
+}
+
+/// How big a download would be.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Estimate {
+    pub tiles: u64,
+    pub bytes: u64,
+}
+
+impl Estimate {
+    /// The tiles of every source in `sources` covering `region` at `zooms`. Sizes go by
+    /// what's already cached of each source.
+    pub fn new(
+        region: &Region,
+        zooms: RangeInclusive<u8>,
+        sources: &[Arc<TileSource>],
+        cache: Option<&TileCache>,
+    ) -> Self {
+        let per_source: u64 = zooms.map(|zoom| region.tile_count(zoom)).sum();
+        let mut estimate = Estimate::default();
+        for source in sources {
+            let tile_bytes = cache
+                .and_then(|cache| cache.average_tile_size(source))
+                .unwrap_or(match source.kind {
+                    TileKind::Raster => RASTER_TILE_BYTES,
+                    TileKind::Vector => VECTOR_TILE_BYTES,
+                });
+            estimate.tiles += per_source;
+            estimate.bytes += per_source * tile_bytes;
+        }
+        estimate
+    }
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "about {} tiles, {}",
+            self.tiles,
+            format_bytes(self.bytes)
+        )
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        0..=999_999 => format!("{:.0} kB", bytes as f64 / 1e3),
+        1_000_000..=999_999_999 => format!("{:.1} MB", bytes as f64 / 1e6),
+        _ => format!("{:.2} GB", bytes as f64 / 1e9),
+    }
+}
+
+/// How a batch of a download went.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchResult {
+    pub fetched: u64,
+    pub failed: u64,
+    pub bytes: u64,
+}
+
+//the tiles of one source still to fetch
+#[derive(Debug, Clone)]
+struct SourceQueue {
+    source: Arc<TileSource>,
+    pending: VecDeque<(u32, u32, u32)>,
+}
+
+/// A download in progress.
+#[derive(Debug, Clone)]
+pub struct Download {
+    queues: Vec<SourceQueue>,
+    pub total: u64,
+    //fetched or already cached
+    pub done: u64,
+    pub failed: u64,
+    pub bytes: u64,
+    pub paused: bool,
+    //a batch was handed out and hasn't come back yet
+    pub in_flight: bool,
+}
+
+impl Download {
+    pub fn new(
+        region: &Region,
+        zooms: RangeInclusive<u8>,
+        sources: Vec<Arc<TileSource>>,
+    ) -> Result<Self, OfflineError> {
+        if sources.is_empty() {
+            return Err(OfflineError::NoSources);
+        }
+        let per_source: u64 = zooms.clone().map(|zoom| region.tile_count(zoom)).sum();
+        let total = per_source * sources.len() as u64;
+        if total > MAX_DOWNLOAD_TILES {
+            return Err(OfflineError::TooManyTiles(total));
+        }
+        //the estimate above can be well under for odd shapes
+        let limit = MAX_DOWNLOAD_TILES / sources.len() as u64;
+        let mut tiles = VecDeque::new();
+        for zoom in zooms {
+            let remaining = limit - tiles.len() as u64;
+            match region.tiles(zoom, remaining) {
+                Some(zoom_tiles) => tiles.extend(zoom_tiles),
+                None => return Err(OfflineError::OverLimit(MAX_DOWNLOAD_TILES)),
+            }
+        }
+        if tiles.is_empty() {
+            return Err(OfflineError::EmptyRegion);
+        }
+        Ok(Self {
+            total: tiles.len() as u64 * sources.len() as u64,
+            queues: sources
+                .into_iter()
+                .map(|source| SourceQueue {
+                    source,
+                    pending: tiles.clone(),
+                })
+                .collect(),
+            done: 0,
+            failed: 0,
+            bytes: 0,
+            paused: false,
+            in_flight: false,
+        })
+    }
+
+    /// The tiles to fetch over the next `seconds`, as many of each source's as its rate
+    /// limit allows. Tiles already in `cache` count as done without being fetched.
+    pub fn next_batch(
+        &mut self,
+        cache: Option<&TileCache>,
+        seconds: u32,
+    ) -> Vec<(Arc<TileSource>, (u32, u32, u32))> {
+        let mut batch = Vec::new();
+        for queue in self.queues.iter_mut() {
+            let allowed = (queue.source.rate_limit * seconds).max(1) as usize;
+            let mut taken = 0;
+            let mut skipped = 0;
+            while taken < allowed && skipped < MAX_SKIPPED {
+                let coords = match queue.pending.pop_front() {
+                    Some(coords) => coords,
+                    None => break,
+                };
+                if cache.map_or(false, |cache| cache.contains(&queue.source, &coords)) {
+                    self.done += 1;
+                    skipped += 1;
+                    continue;
+                }
+                batch.push((queue.source.clone(), coords));
+                taken += 1;
+            }
+        }
+        batch
+    }
+
+    pub fn record(&mut self, batch: &BatchResult) {
+        self.done += batch.fetched;
+        self.failed += batch.failed;
+        self.bytes += batch.bytes;
+        self.in_flight = false;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        !self.in_flight && self.queues.iter().all(|queue| queue.pending.is_empty())
+    }
+}
+
+impl fmt::Display for Download {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} tiles, {}",
+            self.done + self.failed,
+            self.total,
+            format_bytes(self.bytes)
+        )?;
+        if self.failed > 0 {
+            write!(f, ", {} failed", self.failed)?;
+        }
+        if self.is_finished() {
+            write!(f, ", done")
+        } else if self.paused {
+            write!(f, ", paused")
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// What the download panel offers to download.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegionChoice {
+    VisibleMap,
+    //a polygon of a drawing layer, by index into the layers and the layer's features
+    Feature {
+        layer: usize,
+        feature: usize,
+        name: String,
+    },
+}
+
+impl RegionChoice {
+    /// The map, then every polygon in `layers`.
+    pub fn all(layers: &[DrawingLayer]) -> Vec<RegionChoice> {
+        let mut choices = vec![RegionChoice::VisibleMap];
+        for (layer_idx, layer) in layers.iter().enumerate() {
+            let polygons = layer.features.iter().enumerate().filter(|(_, feature)| {
+                matches!(
+                    feature.geometry,
+                    Geometry::Polygon(_) | Geometry::MultiPolygon(_)
+                )
+            });
+            for (count, (feature_idx, feature)) in polygons.enumerate() {
+                let name = match feature
+                    .properties
+                    .get("name")
+                    .and_then(|name| name.as_str())
+                {
+                    Some(name) => name.to_string(),
+                    None => format!("{} polygon {}", layer.name, count + 1),
+                };
+                choices.push(RegionChoice::Feature {
+                    layer: layer_idx,
+                    feature: feature_idx,
+                    name,
+                });
+            }
+        }
+        choices
+    }
+}
+
+impl Default for RegionChoice {
+    fn default() -> Self {
+        RegionChoice::VisibleMap
+    }
+}
+
+impl fmt::Display for RegionChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegionChoice::VisibleMap => write!(f, "visible map"),
+            RegionChoice::Feature { name, .. } => write!(f, "{}", name),
+        }
+    }
+}
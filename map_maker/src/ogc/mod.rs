@@ -0,0 +1,155 @@
+//tiles from ogc web services, the kind national mapping agencies run. their capabilities
+//documents list the layers on offer, each becomes a tile source the map can add
+pub mod wms;
+pub mod wmts;
+
+use crate::http::{HttpClient, HttpError};
+use crate::tile_manager::tile_manager::TileSource;
+use roxmltree::{Document, Node};
+use std::fmt;
+use std::sync::Arc;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum OgcError {
+    #[error("http error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("request error")]
+    Request(#[from] HttpError),
+    #[error("xml error")]
+    Xml(#[from] roxmltree::Error),
+    #[error("service error: {0}")]
+    Service(String),
+    #[error("invalid capabilities: {0}")]
+    Invalid(String),
+}
+
+/// A layer a service offers, as a source ready to add to the map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceLayer {
+    //what the layer is listed as, its title and for wmts the tile matrix set
+    pub label: String,
+    pub source: TileSource,
+}
+
+impl fmt::Display for ServiceLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.label)
+    }
+}
+
+/// Whether a url asks a service for its capabilities rather than for tiles.
+pub fn is_capabilities_url(url: &str) -> bool {
+    let url = url.trim().to_lowercase();
+    url.starts_with("http")
+        && (url.contains("getcapabilities") || url.ends_with("capabilities.xml"))
+}
+
+/// Fetches a capabilities document and lists the layers in it the map can show.
+pub async fn fetch_layers(
+    http: Arc<HttpClient>,
+    url: String,
+) -> Result<Vec<ServiceLayer>, OgcError> {
+    let text = http
+        .get(url.trim())
+        .await?
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    parse_layers(&text)
+}
+
+/// The layers in a wmts or wms capabilities document the map can show.
+pub fn parse_layers(text: &str) -> Result<Vec<ServiceLayer>, OgcError> {
+    let doc = Document::parse(text)?;
+    let root = doc.root_element();
+    if let Some(exception) = exception_text(&root) {
+        return Err(OgcError::Service(exception));
+    }
+    let layers = match root.tag_name().name() {
+        "Capabilities" => wmts::read_capabilities(&root)?.layers(),
+        //1.3.0 and 1.1.1
+        "WMS_Capabilities" | "WMT_MS_Capabilities" => wms::read_capabilities(&root)?.layers(),
+        other => {
+            return Err(OgcError::Invalid(format!(
+                "expected wmts or wms capabilities, found {}",
+                other
+            )))
+        }
+    };
+    if layers.is_empty() {
+        return Err(OgcError::Invalid(String::from(
+            "no layers the map can show in web mercator",
+        )));
+    }
+    Ok(layers)
+}
+
+//a base url ready for key-value parameters to be added on the end
+fn kvp_base(url: &str) -> String {
+    if !url.contains('?') {
+        format!("{}?", url)
+    } else if url.ends_with('?') || url.ends_with('&') {
+        url.to_string()
+    } else {
+        format!("{}&", url)
+    }
+}
+
+//percent encodes a parameter value, layer names can have spaces and worse in them
+fn encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'_'
+            | b'.'
+            | b'~'
+            | b':'
+            | b'/'
+            | b',' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+//the text of an ows:ExceptionReport, servers answer with one instead of what was asked
+fn exception_text(root: &Node<'_, '_>) -> Option<String> {
+    if root.tag_name().name() != "ExceptionReport"
+        && root.tag_name().name() != "ServiceExceptionReport"
+    {
+        return None;
+    }
+    let text = root
+        .descendants()
+        .filter(|node| node.is_text())
+        .filter_map(|node| node.text())
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    Some(text)
+}
+
+fn child<'a, 'input>(node: &Node<'a, 'input>, tag: &str) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|c| c.is_element() && c.tag_name().name() == tag)
+}
+
+fn children<'a, 'input: 'a>(
+    node: &Node<'a, 'input>,
+    tag: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |child| child.is_element() && child.tag_name().name() == tag)
+}
+
+fn child_text<'a>(node: &Node<'a, '_>, tag: &str) -> Option<&'a str> {
+    child(node, tag).and_then(|c| c.text()).map(str::trim)
+}
@@ -0,0 +1,332 @@
+//wms 1.1.1 and 1.3.0 capabilities, https://www.ogc.org/standard/wms/. wms servers draw any
+//box that's asked for, so each map tile is a GetMap request for the tile's own extent
+use super::{child, child_text, children, encode, kvp_base, OgcError, ServiceLayer};
+use crate::geo::TILE_SIZE;
+use crate::tile_manager::tile_manager::{TileKind, TileScheme, TileSource, DEFAULT_RATE_LIMIT};
+use roxmltree::Node;
+
+//crs codes of web mercator, the first one a layer offers is asked for
+const MERCATOR_CRS: [&str; 2] = ["EPSG:3857", "EPSG:900913"];
+//formats in the order they're picked, only ones the image crate is built to decode
+const PREFERRED_FORMATS: [&str; 3] = ["image/png", "image/webp", "image/jpeg"];
+
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    pub version: String,
+    //title of the service, the attribution for layers without their own
+    pub title: Option<String>,
+    //formats GetMap can answer in
+    pub formats: Vec<String>,
+    //where GetMap requests go
+    pub get_map_url: Option<String>,
+    //every layer with a name, nested ones included
+    pub layers: Vec<Layer>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Layer {
+    pub name: String,
+    pub title: String,
+    //crs codes the layer can be drawn in, along with the ones of the layers it's in
+    pub crs: Vec<String>,
+    //first style listed, empty for the server's default
+    pub style: String,
+    pub attribution: Option<String>,
+    //opaque layers cover everything under them, there's no point asking for transparency
+    pub opaque: bool,
+}
+
+pub fn read_capabilities(root: &Node<'_, '_>) -> Result<Capabilities, OgcError> {
+    let capability = child(root, "Capability")
+        .ok_or_else(|| OgcError::Invalid(String::from("capabilities have no capability")))?;
+    let get_map = child(&capability, "Request").and_then(|request| child(&request, "GetMap"));
+    let title = child(root, "Service")
+        .and_then(|service| child_text(&service, "Title"))
+        .filter(|title| !title.is_empty())
+        .map(str::to_string);
+
+    let mut layers = Vec::new();
+    let inherited = Layer {
+        name: String::new(),
+        title: String::new(),
+        crs: Vec::new(),
+        style: String::new(),
+        attribution: None,
+        opaque: false,
+    };
+    for node in children(&capability, "Layer") {
+        read_layer(&node, &inherited, &mut layers);
+    }
+    Ok(Capabilities {
+        version: root.attribute("version").unwrap_or("1.3.0").to_string(),
+        title,
+        formats: get_map
+            .iter()
+            .flat_map(|get_map| children(get_map, "Format"))
+            .filter_map(|format| format.text())
+            .map(|format| format.trim().to_string())
+            .collect(),
+        get_map_url: get_map.as_ref().and_then(get_map_url),
+        layers,
+    })
+}
+
+//reads a layer and the layers nested in it. crs codes and attribution carry down to the
+//nested layers, only layers with a name can be asked for
+fn read_layer(node: &Node<'_, '_>, parent: &Layer, layers: &mut Vec<Layer>) {
+    let mut crs = parent.crs.clone();
+    //1.1.1 calls it srs, and sometimes lists several in one element
+    for code in children(node, "CRS").chain(children(node, "SRS")) {
+        for code in code.text().unwrap_or_default().split_whitespace() {
+            if !crs.iter().any(|known| known.eq_ignore_ascii_case(code)) {
+                crs.push(code.to_string());
+            }
+        }
+    }
+    let name = child_text(node, "Name").unwrap_or_default().to_string();
+    let layer = Layer {
+        title: child_text(node, "Title")
+            .filter(|title| !title.is_empty())
+            .unwrap_or(&name)
+            .to_string(),
+        crs,
+        style: children(node, "Style")
+            .find_map(|style| child_text(&style, "Name"))
+            .unwrap_or_default()
+            .to_string(),
+        attribution: child(node, "Attribution")
+            .and_then(|attribution| child_text(&attribution, "Title"))
+            .filter(|title| !title.is_empty())
+            .map(str::to_string)
+            .or_else(|| parent.attribution.clone()),
+        opaque: matches!(node.attribute("opaque"), Some("1") | Some("true")),
+        name,
+    };
+    if !layer.name.is_empty() {
+        layers.push(layer.clone());
+    }
+    for nested in children(node, "Layer") {
+        read_layer(&nested, &layer, layers);
+    }
+}
+
+//the address from GetMap's DCPType/HTTP/Get/OnlineResource
+fn get_map_url(get_map: &Node<'_, '_>) -> Option<String> {
+    get_map
+        .descendants()
+        .filter(|node| node.is_element() && node.tag_name().name() == "Get")
+        .filter_map(|get| child(&get, "OnlineResource"))
+        .find_map(|resource| {
+            resource
+                .attributes()
+                .iter()
+                .find(|attribute| attribute.name() == "href")
+                .map(|attribute| attribute.value().to_string())
+        })
+}
+
+impl Capabilities {
+    //the format tiles are asked for in, the preferred one the server offers
+    fn format(&self) -> &str {
+        PREFERRED_FORMATS
+            .iter()
+            .find(|format| self.formats.iter().any(|offered| offered == *format))
+            .copied()
+            .or_else(|| self.formats.first().map(String::as_str))
+            .unwrap_or("image/png")
+    }
+
+    /// The layers the map can show, the named ones that can be drawn in web mercator.
+    pub fn layers(&self) -> Vec<ServiceLayer> {
+        let base = match &self.get_map_url {
+            Some(url) => kvp_base(url),
+            None => {
+                log::warn!("wms capabilities have no GetMap url");
+                return Vec::new();
+            }
+        };
+        //1.3.0 renamed srs to crs
+        let crs_key = if self.version.starts_with("1.3") {
+            "CRS"
+        } else {
+            "SRS"
+        };
+        let format = self.format();
+        let mut layers = Vec::new();
+        for layer in self.layers.iter() {
+            let crs = match MERCATOR_CRS.iter().find(|code| {
+                layer
+                    .crs
+                    .iter()
+                    .any(|offered| offered.eq_ignore_ascii_case(code))
+            }) {
+                Some(crs) => crs,
+                None => {
+                    log::info!("skipping {}, it isn't offered in web mercator", layer.name);
+                    continue;
+                }
+            };
+            let transparent = !layer.opaque && !format.contains("jpeg");
+            let url = format!(
+                "{}SERVICE=WMS&VERSION={}&REQUEST=GetMap&LAYERS={}&STYLES={}&{}={}\
+                 &BBOX={{bbox}}&WIDTH={{width}}&HEIGHT={{height}}&FORMAT={}&TRANSPARENT={}",
+                base,
+                encode(&self.version),
+                encode(&layer.name),
+                encode(&layer.style),
+                crs_key,
+                crs,
+                encode(format),
+                if transparent { "TRUE" } else { "FALSE" }
+            );
+            let label = if layer.title == layer.name {
+                layer.title.clone()
+            } else {
+                format!("{} ({})", layer.title, layer.name)
+            };
+            layers.push(ServiceLayer {
+                label,
+                source: TileSource {
+                    name: layer.title.clone(),
+                    url,
+                    attribution: layer
+                        .attribution
+                        .clone()
+                        .or_else(|| self.title.clone())
+                        .unwrap_or_default(),
+                    kind: TileKind::Raster,
+                    scheme: TileScheme::Wms,
+                    tile_size: TILE_SIZE as u32,
+                    rate_limit: DEFAULT_RATE_LIMIT,
+                },
+            });
+        }
+        layers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse_layers;
+    use super::*;
+
+    const CAPABILITIES_1_3: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<WMS_Capabilities version="1.3.0" xmlns="http://www.opengis.net/wms"
+    xmlns:xlink="http://www.w3.org/1999/xlink">
+  <Service><Name>WMS</Name><Title>National Maps</Title></Service>
+  <Capability>
+    <Request>
+      <GetMap>
+        <Format>image/gif</Format>
+        <Format>image/jpeg</Format>
+        <Format>image/png</Format>
+        <DCPType><HTTP><Get>
+          <OnlineResource xlink:type="simple" xlink:href="https://example.org/wms?map=national"/>
+        </Get></HTTP></DCPType>
+      </GetMap>
+    </Request>
+    <Layer>
+      <Title>All layers</Title>
+      <CRS>EPSG:4326</CRS>
+      <CRS>EPSG:3857</CRS>
+      <Attribution><Title>Mapping Agency</Title></Attribution>
+      <Layer opaque="1">
+        <Name>relief</Name>
+        <Title>Shaded relief</Title>
+        <Style><Name>grey</Name></Style>
+      </Layer>
+      <Layer>
+        <Name>roads</Name>
+        <Layer>
+          <Name>local grid</Name>
+          <Title>Local grid</Title>
+          <Attribution><Title>Surveyors</Title></Attribution>
+        </Layer>
+      </Layer>
+    </Layer>
+  </Capability>
+</WMS_Capabilities>"#;
+
+    const CAPABILITIES_1_1: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<WMT_MS_Capabilities version="1.1.1">
+  <Service><Title>Old Server</Title></Service>
+  <Capability>
+    <Request>
+      <GetMap>
+        <Format>image/gif</Format>
+        <Format>image/jpeg</Format>
+        <DCPType><HTTP><Get>
+          <OnlineResource xmlns:xlink="http://www.w3.org/1999/xlink" xlink:href="http://old.example.org/cgi"/>
+        </Get></HTTP></DCPType>
+      </GetMap>
+    </Request>
+    <Layer>
+      <SRS>EPSG:4326 EPSG:900913</SRS>
+      <Layer><Name>base</Name></Layer>
+    </Layer>
+    <Layer>
+      <Name>lonlat</Name>
+      <SRS>EPSG:4326</SRS>
+    </Layer>
+  </Capability>
+</WMT_MS_Capabilities>"#;
+
+    fn url(layer: &ServiceLayer) -> &str {
+        &layer.source.url
+    }
+
+    #[test]
+    fn named_layers_inherit_crs_and_attribution() {
+        let layers = parse_layers(CAPABILITIES_1_3).unwrap();
+        let labels: Vec<&str> = layers.iter().map(|layer| layer.label.as_str()).collect();
+        assert_eq!(
+            labels,
+            vec!["Shaded relief (relief)", "roads", "Local grid (local grid)"]
+        );
+        assert!(layers
+            .iter()
+            .all(|layer| layer.source.scheme == TileScheme::Wms));
+        assert_eq!(layers[0].source.attribution, "Mapping Agency");
+        assert_eq!(layers[2].source.attribution, "Surveyors");
+        //png is picked, opaque layers aren't asked for transparency
+        assert_eq!(
+            url(&layers[0]),
+            "https://example.org/wms?map=national&SERVICE=WMS&VERSION=1.3.0&REQUEST=GetMap\
+             &LAYERS=relief&STYLES=grey&CRS=EPSG:3857&BBOX={bbox}&WIDTH={width}\
+             &HEIGHT={height}&FORMAT=image/png&TRANSPARENT=FALSE"
+        );
+        assert!(url(&layers[1]).ends_with("&FORMAT=image/png&TRANSPARENT=TRUE"));
+        assert!(url(&layers[2]).contains("&LAYERS=local%20grid&STYLES=&"));
+    }
+
+    #[test]
+    fn old_servers_are_asked_in_their_own_terms() {
+        let layers = parse_layers(CAPABILITIES_1_1).unwrap();
+        //the layer only offered in lon/lat can't be shown
+        assert_eq!(layers.len(), 1);
+        let base = &layers[0];
+        assert_eq!(base.source.name, "base");
+        assert_eq!(base.source.attribution, "Old Server");
+        //gif can't be decoded, so jpeg it is, and jpeg can't be see-through
+        assert_eq!(
+            url(base),
+            "http://old.example.org/cgi?SERVICE=WMS&VERSION=1.1.1&REQUEST=GetMap&LAYERS=base\
+             &STYLES=&SRS=EPSG:900913&BBOX={bbox}&WIDTH={width}&HEIGHT={height}\
+             &FORMAT=image/jpeg&TRANSPARENT=FALSE"
+        );
+    }
+
+    #[test]
+    fn capabilities_without_get_map_have_no_layers() {
+        let text = CAPABILITIES_1_1.replace("xlink:href=\"http://old.example.org/cgi\"", "");
+        let doc = roxmltree::Document::parse(&text).unwrap();
+        let capabilities = read_capabilities(&doc.root_element()).unwrap();
+        assert_eq!(capabilities.get_map_url, None);
+        assert!(capabilities.layers().is_empty());
+        let doc = roxmltree::Document::parse("<WMS_Capabilities/>").unwrap();
+        assert!(matches!(
+            read_capabilities(&doc.root_element()),
+            Err(OgcError::Invalid(_))
+        ));
+    }
+}
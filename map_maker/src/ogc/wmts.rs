@@ -0,0 +1,467 @@
+//wmts 1.0 capabilities, https://www.ogc.org/standard/wmts/. only tile matrix sets that line up
+//with the map's own web mercator tiles can be shown, the rest are listed in the log and left
+use super::{child, child_text, children, encode, kvp_base, OgcError, ServiceLayer};
+use crate::geo::{MERCATOR_EDGE, TILE_SIZE};
+use crate::tile_manager::tile_manager::{TileKind, TileScheme, TileSource, DEFAULT_RATE_LIMIT};
+use roxmltree::Node;
+
+//scale denominator of zoom 0 of a 256 pixel web mercator tile, at wmts' 0.28mm pixels
+const ZOOM_0_SCALE: f64 = 559_082_264.028_717_8;
+//formats in the order they're picked when a layer offers several
+const PREFERRED_FORMATS: [&str; 3] = ["image/png", "image/jpeg", "image/webp"];
+
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    //who runs the service, used as the attribution
+    pub provider: Option<String>,
+    pub layers: Vec<Layer>,
+    pub matrix_sets: Vec<MatrixSet>,
+    //where key-value GetTile requests go, for layers without a url template
+    pub kvp_url: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Layer {
+    pub identifier: String,
+    pub title: String,
+    pub formats: Vec<String>,
+    //the default style, or the first one listed
+    pub style: String,
+    pub matrix_sets: Vec<String>,
+    //restful url templates by format
+    pub templates: Vec<(String, String)>,
+    //extra dimensions, e.g. time, by identifier with their default value
+    pub dimensions: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MatrixSet {
+    pub identifier: String,
+    pub crs: String,
+    pub matrices: Vec<TileMatrix>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TileMatrix {
+    pub identifier: String,
+    pub scale: f64,
+    pub top_left: (f64, f64),
+    pub tile_size: (u32, u32),
+}
+
+pub fn read_capabilities(root: &Node<'_, '_>) -> Result<Capabilities, OgcError> {
+    let contents = child(root, "Contents")
+        .ok_or_else(|| OgcError::Invalid(String::from("capabilities have no contents")))?;
+
+    let provider = child(root, "ServiceProvider")
+        .and_then(|provider| child_text(&provider, "ProviderName"))
+        .filter(|name| !name.is_empty())
+        .map(str::to_string);
+    let layers = children(&contents, "Layer")
+        .map(|node| read_layer(&node))
+        .collect();
+    let matrix_sets = children(&contents, "TileMatrixSet")
+        .map(|node| read_matrix_set(&node))
+        .collect();
+    Ok(Capabilities {
+        provider,
+        layers,
+        matrix_sets,
+        kvp_url: get_tile_url(root),
+    })
+}
+
+fn read_layer(node: &Node<'_, '_>) -> Layer {
+    let identifier = child_text(node, "Identifier")
+        .unwrap_or_default()
+        .to_string();
+    let title = child_text(node, "Title")
+        .filter(|title| !title.is_empty())
+        .unwrap_or(&identifier)
+        .to_string();
+    let styles: Vec<Node<'_, '_>> = children(node, "Style").collect();
+    let style = styles
+        .iter()
+        .find(|style| style.attribute("isDefault") == Some("true"))
+        .or_else(|| styles.first())
+        .and_then(|style| child_text(style, "Identifier"))
+        .unwrap_or("default")
+        .to_string();
+    Layer {
+        formats: children(node, "Format")
+            .filter_map(|format| format.text())
+            .map(|format| format.trim().to_string())
+            .collect(),
+        style,
+        matrix_sets: children(node, "TileMatrixSetLink")
+            .filter_map(|link| child_text(&link, "TileMatrixSet"))
+            .map(str::to_string)
+            .collect(),
+        templates: children(node, "ResourceURL")
+            .filter(|resource| resource.attribute("resourceType") == Some("tile"))
+            .filter_map(|resource| {
+                Some((
+                    resource.attribute("format").unwrap_or_default().to_string(),
+                    resource.attribute("template")?.to_string(),
+                ))
+            })
+            .collect(),
+        dimensions: children(node, "Dimension")
+            .filter_map(|dimension| {
+                let value = child_text(&dimension, "Default")
+                    .or_else(|| child_text(&dimension, "Value"))?;
+                Some((
+                    child_text(&dimension, "Identifier")?.to_string(),
+                    value.to_string(),
+                ))
+            })
+            .collect(),
+        identifier,
+        title,
+    }
+}
+
+fn read_matrix_set(node: &Node<'_, '_>) -> MatrixSet {
+    let number =
+        |node: &Node<'_, '_>, tag: &str| child_text(node, tag).and_then(|text| text.parse().ok());
+    MatrixSet {
+        identifier: child_text(node, "Identifier")
+            .unwrap_or_default()
+            .to_string(),
+        crs: child_text(node, "SupportedCRS")
+            .unwrap_or_default()
+            .to_string(),
+        matrices: children(node, "TileMatrix")
+            .filter_map(|matrix| {
+                let mut corner = child_text(&matrix, "TopLeftCorner")?
+                    .split_whitespace()
+                    .filter_map(|number| number.parse::<f64>().ok());
+                Some(TileMatrix {
+                    identifier: child_text(&matrix, "Identifier")?.to_string(),
+                    scale: number(&matrix, "ScaleDenominator")?,
+                    top_left: (corner.next()?, corner.next()?),
+                    tile_size: (
+                        number(&matrix, "TileWidth").unwrap_or(256.0) as u32,
+                        number(&matrix, "TileHeight").unwrap_or(256.0) as u32,
+                    ),
+                })
+            })
+            .collect(),
+    }
+}
+
+//the GetTile address from the operations metadata, if the server takes key-value requests
+fn get_tile_url(root: &Node<'_, '_>) -> Option<String> {
+    let operations = child(root, "OperationsMetadata")?;
+    let get_tile = children(&operations, "Operation")
+        .find(|operation| operation.attribute("name") == Some("GetTile"))?;
+    get_tile
+        .descendants()
+        .filter(|node| node.is_element() && node.tag_name().name() == "Get")
+        .find_map(|get| {
+            get.attributes()
+                .iter()
+                .find(|attribute| attribute.name() == "href")
+                .map(|attribute| attribute.value().to_string())
+        })
+}
+
+impl MatrixSet {
+    /// The identifier of the matrix for each zoom, if the set is web mercator with 256 pixel
+    /// tiles. Zooms the set skips get an empty identifier.
+    pub fn zoom_matrices(&self) -> Option<Vec<String>> {
+        let crs = self.crs.to_lowercase();
+        if !["3857", "900913", "3785", "102100"]
+            .iter()
+            .any(|code| crs.ends_with(code))
+        {
+            return None;
+        }
+        let mut zooms: Vec<String> = Vec::new();
+        for matrix in self.matrices.iter() {
+            let zoom = (ZOOM_0_SCALE / matrix.scale).log2();
+            let corner_off = (matrix.top_left.0 + MERCATOR_EDGE).abs() > 1.0
+                || (matrix.top_left.1 - MERCATOR_EDGE).abs() > 1.0;
+            if (zoom - zoom.round()).abs() > 0.01
+                || zoom.round() < 0.0
+                || matrix.tile_size != (256, 256)
+                || corner_off
+            {
+                return None;
+            }
+            let zoom = zoom.round() as usize;
+            if zooms.len() <= zoom {
+                zooms.resize(zoom + 1, String::new());
+            }
+            zooms[zoom] = matrix.identifier.clone();
+        }
+        Some(zooms).filter(|zooms| !zooms.is_empty())
+    }
+}
+
+impl Layer {
+    //the format tiles are asked for in, the preferred one the layer offers
+    fn format(&self) -> Option<&str> {
+        let offered: Vec<&str> = self
+            .formats
+            .iter()
+            .map(String::as_str)
+            .chain(self.templates.iter().map(|(format, _)| format.as_str()))
+            .collect();
+        PREFERRED_FORMATS
+            .iter()
+            .find(|format| offered.contains(format))
+            .copied()
+            .or_else(|| offered.first().copied())
+    }
+
+    //the url template tiles come from, placeholders other than the tile's own filled in
+    fn url(&self, matrix_set: &str, kvp_url: Option<&str>) -> Option<String> {
+        let format = self.format().unwrap_or("image/png");
+        let template = self
+            .templates
+            .iter()
+            .find(|(template_format, _)| template_format == format)
+            .or_else(|| self.templates.first());
+        let mut url = match (template, kvp_url) {
+            (Some((_, template)), _) => template.clone(),
+            (None, Some(base)) => format!(
+                "{}SERVICE=WMTS&REQUEST=GetTile&VERSION=1.0.0&LAYER={}&STYLE={}\
+                 &TILEMATRIXSET={}&TILEMATRIX={{TileMatrix}}&TILEROW={{TileRow}}\
+                 &TILECOL={{TileCol}}&FORMAT={}",
+                kvp_base(base),
+                encode(&self.identifier),
+                encode(&self.style),
+                encode(matrix_set),
+                encode(format)
+            ),
+            (None, None) => return None,
+        };
+        url = url
+            .replace("{Style}", &self.style)
+            .replace("{style}", &self.style)
+            .replace("{TileMatrixSet}", matrix_set);
+        for (dimension, value) in self.dimensions.iter() {
+            url = url.replace(&format!("{{{}}}", dimension), value);
+        }
+        Some(url)
+    }
+}
+
+impl Capabilities {
+    /// The layers the map can show, one per layer and web mercator matrix set.
+    pub fn layers(&self) -> Vec<ServiceLayer> {
+        let mut layers = Vec::new();
+        for layer in self.layers.iter() {
+            for set_id in layer.matrix_sets.iter() {
+                let matrices = match self
+                    .matrix_sets
+                    .iter()
+                    .find(|set| &set.identifier == set_id)
+                    .and_then(MatrixSet::zoom_matrices)
+                {
+                    Some(matrices) => matrices,
+                    None => {
+                        log::info!(
+                            "skipping {} in {}, it isn't web mercator",
+                            layer.identifier,
+                            set_id
+                        );
+                        continue;
+                    }
+                };
+                let url = match layer.url(set_id, self.kvp_url.as_deref()) {
+                    Some(url) => url,
+                    None => {
+                        log::warn!("{} has no url to fetch tiles from", layer.identifier);
+                        continue;
+                    }
+                };
+                let format = layer.format().unwrap_or_default();
+                let kind = if format.contains("mapbox-vector-tile") || format.contains("pbf") {
+                    TileKind::Vector
+                } else {
+                    TileKind::Raster
+                };
+                layers.push(ServiceLayer {
+                    label: format!("{} ({})", layer.title, set_id),
+                    source: TileSource {
+                        name: layer.title.clone(),
+                        url,
+                        attribution: self.provider.clone().unwrap_or_default(),
+                        kind,
+                        scheme: TileScheme::Wmts(matrices),
+                        tile_size: TILE_SIZE as u32,
+                        rate_limit: DEFAULT_RATE_LIMIT,
+                    },
+                });
+            }
+        }
+        layers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse_layers;
+    use super::*;
+
+    const CAPABILITIES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Capabilities xmlns="http://www.opengis.net/wmts/1.0" xmlns:ows="http://www.opengis.net/ows/1.1"
+    xmlns:xlink="http://www.w3.org/1999/xlink" version="1.0.0">
+  <ows:ServiceProvider><ows:ProviderName>Survey Office</ows:ProviderName></ows:ServiceProvider>
+  <ows:OperationsMetadata>
+    <ows:Operation name="GetCapabilities">
+      <ows:DCP><ows:HTTP><ows:Get xlink:href="https://example.org/caps?"/></ows:HTTP></ows:DCP>
+    </ows:Operation>
+    <ows:Operation name="GetTile">
+      <ows:DCP><ows:HTTP><ows:Get xlink:href="https://example.org/wmts?map=a"/></ows:HTTP></ows:DCP>
+    </ows:Operation>
+  </ows:OperationsMetadata>
+  <Contents>
+    <Layer>
+      <ows:Title>Orthophotos</ows:Title>
+      <ows:Identifier>ortho</ows:Identifier>
+      <Style><ows:Identifier>old</ows:Identifier></Style>
+      <Style isDefault="true"><ows:Identifier>new</ows:Identifier></Style>
+      <Format>image/webp</Format>
+      <Format>image/jpeg</Format>
+      <Dimension><ows:Identifier>Time</ows:Identifier><Default>2020</Default></Dimension>
+      <TileMatrixSetLink><TileMatrixSet>mercator</TileMatrixSet></TileMatrixSetLink>
+      <TileMatrixSetLink><TileMatrixSet>lonlat</TileMatrixSet></TileMatrixSetLink>
+      <ResourceURL format="image/webp" resourceType="tile"
+          template="https://example.org/{Style}/{Time}/{TileMatrixSet}/{TileMatrix}/{TileRow}/{TileCol}.webp"/>
+      <ResourceURL format="image/jpeg" resourceType="tile"
+          template="https://example.org/{Style}/{Time}/{TileMatrixSet}/{TileMatrix}/{TileRow}/{TileCol}.jpg"/>
+    </Layer>
+    <Layer>
+      <ows:Identifier>roads &amp; rails</ows:Identifier>
+      <Format>application/vnd.mapbox-vector-tile</Format>
+      <TileMatrixSetLink><TileMatrixSet>mercator</TileMatrixSet></TileMatrixSetLink>
+    </Layer>
+    <TileMatrixSet>
+      <ows:Identifier>mercator</ows:Identifier>
+      <ows:SupportedCRS>urn:ogc:def:crs:EPSG::3857</ows:SupportedCRS>
+      <TileMatrix>
+        <ows:Identifier>m0</ows:Identifier>
+        <ScaleDenominator>559082264.0287178</ScaleDenominator>
+        <TopLeftCorner>-20037508.3427892 20037508.3427892</TopLeftCorner>
+        <TileWidth>256</TileWidth><TileHeight>256</TileHeight>
+      </TileMatrix>
+      <TileMatrix>
+        <ows:Identifier>m2</ows:Identifier>
+        <ScaleDenominator>139770566.0071794</ScaleDenominator>
+        <TopLeftCorner>-20037508.3427892 20037508.3427892</TopLeftCorner>
+        <TileWidth>256</TileWidth><TileHeight>256</TileHeight>
+      </TileMatrix>
+    </TileMatrixSet>
+    <TileMatrixSet>
+      <ows:Identifier>lonlat</ows:Identifier>
+      <ows:SupportedCRS>urn:ogc:def:crs:EPSG::4326</ows:SupportedCRS>
+      <TileMatrix>
+        <ows:Identifier>0</ows:Identifier>
+        <ScaleDenominator>279541132.0143589</ScaleDenominator>
+        <TopLeftCorner>90 -180</TopLeftCorner>
+      </TileMatrix>
+    </TileMatrixSet>
+  </Contents>
+</Capabilities>"#;
+
+    fn matrix(scale: f64, top_left: (f64, f64), tile_size: (u32, u32)) -> TileMatrix {
+        TileMatrix {
+            identifier: String::from("m"),
+            scale,
+            top_left,
+            tile_size,
+        }
+    }
+
+    #[test]
+    fn web_mercator_sets_become_sources() {
+        let layers = parse_layers(CAPABILITIES).unwrap();
+        assert_eq!(layers.len(), 2);
+
+        let ortho = &layers[0];
+        assert_eq!(ortho.label, "Orthophotos (mercator)");
+        assert_eq!(ortho.source.attribution, "Survey Office");
+        assert_eq!(ortho.source.kind, TileKind::Raster);
+        assert_eq!(
+            ortho.source.scheme,
+            TileScheme::Wmts(vec![String::from("m0"), String::new(), String::from("m2")])
+        );
+        //jpeg is picked over webp, the default style and dimension values are filled in
+        assert_eq!(
+            ortho.source.url,
+            "https://example.org/new/2020/mercator/{TileMatrix}/{TileRow}/{TileCol}.jpg"
+        );
+
+        //without a template the key-value address is used
+        let roads = &layers[1];
+        assert_eq!(roads.label, "roads & rails (mercator)");
+        assert_eq!(roads.source.kind, TileKind::Vector);
+        assert_eq!(
+            roads.source.url,
+            "https://example.org/wmts?map=a&SERVICE=WMTS&REQUEST=GetTile&VERSION=1.0.0\
+             &LAYER=roads%20%26%20rails&STYLE=default&TILEMATRIXSET=mercator\
+             &TILEMATRIX={TileMatrix}&TILEROW={TileRow}&TILECOL={TileCol}\
+             &FORMAT=application/vnd.mapbox-vector-tile"
+        );
+    }
+
+    #[test]
+    fn sets_that_dont_line_up_with_the_map_are_left_out() {
+        let corner = (-MERCATOR_EDGE, MERCATOR_EDGE);
+        let set = |crs: &str, matrices| MatrixSet {
+            identifier: String::from("set"),
+            crs: crs.to_string(),
+            matrices,
+        };
+        let zoom_3 = ZOOM_0_SCALE / 8.0;
+        assert_eq!(
+            set("EPSG:3857", vec![matrix(zoom_3, corner, (256, 256))]).zoom_matrices(),
+            Some(vec![
+                String::new(),
+                String::new(),
+                String::new(),
+                String::from("m")
+            ])
+        );
+        let off = [
+            matrix(zoom_3 * 1.5, corner, (256, 256)),
+            matrix(zoom_3, corner, (512, 512)),
+            matrix(zoom_3, (0.0, 0.0), (256, 256)),
+        ];
+        for matrix in off.iter() {
+            let matrices = vec![matrix.clone()];
+            assert_eq!(set("EPSG:3857", matrices).zoom_matrices(), None);
+        }
+        assert_eq!(set("EPSG:3857", Vec::new()).zoom_matrices(), None);
+        let matrices = vec![matrix(zoom_3, corner, (256, 256))];
+        assert_eq!(set("EPSG:2056", matrices).zoom_matrices(), None);
+    }
+
+    #[test]
+    fn service_exceptions_are_errors() {
+        let report = r#"<ows:ExceptionReport xmlns:ows="http://www.opengis.net/ows/1.1">
+  <ows:Exception exceptionCode="InvalidParameterValue">
+    <ows:ExceptionText>unknown layer</ows:ExceptionText>
+  </ows:Exception>
+</ows:ExceptionReport>"#;
+        match parse_layers(report) {
+            Err(OgcError::Service(text)) => assert_eq!(text, "unknown layer"),
+            other => panic!("expected a service error, got {:?}", other),
+        }
+        assert!(matches!(
+            parse_layers("<Capabilities><Contents/></Capabilities>"),
+            Err(OgcError::Invalid(_))
+        ));
+        assert!(matches!(
+            parse_layers("<html></html>"),
+            Err(OgcError::Invalid(_))
+        ));
+        assert!(matches!(
+            parse_layers("<Capabilities><Contents></Capabilities>"),
+            Err(OgcError::Xml(_))
+        ));
+    }
+}
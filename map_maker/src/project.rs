@@ -0,0 +1,649 @@
+//saving and reopening a whole map as one json file: where the map was looking, the raster
+//layers, the drawing layers with their styles and the export settings
+//
+//every file carries a version. older files are brought up to date one version at a time
+//by MIGRATIONS before they're read, files from a newer map_maker are refused rather than
+//half read
+use crate::export::geotiff::GeoTiffProjection;
+use crate::export::page::PrintLayout;
+use crate::features::{DrawingLayer, PointData};
+use crate::formats::{geojson, FormatError};
+use crate::geo::LatLon;
+use crate::tile_manager::tile_manager::{
+    tile_size_from_url, RasterLayer, TileKind, TileScheme, TileSource, DEFAULT_RATE_LIMIT,
+};
+use fs2::FileExt;
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use thiserror::Error;
+
+pub const PROJECT_EXTENSION: &str = "mapmaker";
+const FORMAT: &str = "map_maker project";
+pub const VERSION: u64 = 2;
+//MIGRATIONS[n] turns a version n + 1 file into a version n + 2 one, in place
+const MIGRATIONS: &[fn(&mut Value)] = &[tile_sources_to_raster_layers];
+const MAX_RECENT: usize = 10;
+
+#[derive(Debug, Error)]
+pub enum ProjectError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("layer error: {0}")]
+    Format(#[from] FormatError),
+    #[error("not a map_maker project")]
+    NotAProject,
+    #[error("project version {0} is newer than this map_maker understands")]
+    TooNew(u64),
+    #[error("invalid project: {0}")]
+    Invalid(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProjectView {
+    //middle of the map widget
+    pub center: LatLon,
+    pub zoom: u8,
+}
+
+/// The export settings that aren't picked per export.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportPresets {
+    pub png_dpi: u32,
+    pub geotiff_projection: GeoTiffProjection,
+    pub gpx_routes: bool,
+    pub print_layout: PrintLayout,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Project {
+    pub view: ProjectView,
+    //bottom to top
+    pub raster_layers: Vec<RasterLayer>,
+    //in drawing order, bottom to top
+    pub layers: Vec<DrawingLayer>,
+    //style file for vector tiles, None for the built in style
+    pub vector_style: Option<PathBuf>,
+    pub export: ExportPresets,
+}
+
+impl Project {
+    pub fn read(path: &Path) -> Result<Self, ProjectError> {
+        let text = std::fs::read_to_string(path)?;
+        Project::from_json(serde_json::from_str(&text)?)
+    }
+
+    /// Writes next to `path` first and moves it into place, so a failed save doesn't
+    /// take the last good one with it.
+    pub fn write(&self, path: &Path) -> Result<(), ProjectError> {
+        write_json(path, &self.to_json())?;
+        log::info!("saved project to {}", path.display());
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "format": FORMAT,
+            "version": VERSION,
+            "view": {
+                "center": [self.view.center.lon, self.view.center.lat],
+                "zoom": self.view.zoom,
+            },
+            "raster_layers": self.raster_layers.iter().map(|layer| json!({
+                "name": layer.source.name,
+                "url": layer.source.url,
+                "attribution": layer.source.attribution,
+                "kind": match layer.source.kind {
+                    TileKind::Raster => "raster",
+                    TileKind::Vector => "vector",
+                },
+                "scheme": match layer.source.scheme {
+                    TileScheme::Xyz => "xyz",
+                    TileScheme::Tms => "tms",
+                    TileScheme::Quadkey => "quadkey",
+                    TileScheme::Wmts(_) => "wmts",
+                    TileScheme::Wms => "wms",
+                },
+                "tile_matrices": match &layer.source.scheme {
+                    TileScheme::Wmts(matrices) => Some(matrices),
+                    _ => None,
+                },
+                "tile_size": layer.source.tile_size,
+                "rate_limit": layer.source.rate_limit,
+                "opacity": layer.opacity,
+                "visible": layer.visible,
+                "locked": layer.locked,
+            })).collect::<Vec<Value>>(),
+            "layers": self.layers.iter().map(layer_to_json).collect::<Vec<Value>>(),
+            "vector_style": self.vector_style.as_ref().map(|path| path.display().to_string()),
+            "export": {
+                "png_dpi": self.export.png_dpi,
+                "geotiff_projection": self.export.geotiff_projection.to_string(),
+                "gpx_routes": self.export.gpx_routes,
+                "print_layout": self.export.print_layout.to_json(),
+            },
+        })
+    }
+
+    pub fn from_json(mut root: Value) -> Result<Self, ProjectError> {
+        migrate(&mut root)?;
+
+        let view = &root["view"];
+        let center = view["center"]
+            .as_array()
+            .and_then(|center| match center.as_slice() {
+                [lon, lat] => Some(LatLon::new(lat.as_f64()?, lon.as_f64()?)),
+                _ => None,
+            })
+            .ok_or_else(|| ProjectError::Invalid(String::from("view has no center")))?;
+        let zoom = view["zoom"]
+            .as_u64()
+            .filter(|zoom| *zoom <= 19)
+            .ok_or_else(|| ProjectError::Invalid(String::from("view has no zoom")))?
+            as u8;
+
+        let mut raster_layers = Vec::new();
+        for layer in root["raster_layers"].as_array().into_iter().flatten() {
+            let text = |key: &str| layer[key].as_str().unwrap_or_default().to_string();
+            let url = text("url");
+            if url.is_empty() {
+                log::warn!("skipping raster layer without a url");
+                continue;
+            }
+            raster_layers.push(RasterLayer {
+                source: TileSource {
+                    name: text("name"),
+                    attribution: text("attribution"),
+                    //older files don't say, the url is the best guess then
+                    kind: match layer["kind"].as_str() {
+                        Some("vector") => TileKind::Vector,
+                        Some("raster") => TileKind::Raster,
+                        _ => TileKind::from_url(&url),
+                    },
+                    scheme: match layer["scheme"].as_str() {
+                        Some("xyz") => TileScheme::Xyz,
+                        Some("tms") => TileScheme::Tms,
+                        Some("quadkey") => TileScheme::Quadkey,
+                        Some("wms") => TileScheme::Wms,
+                        Some("wmts") => TileScheme::Wmts(
+                            layer["tile_matrices"]
+                                .as_array()
+                                .into_iter()
+                                .flatten()
+                                .map(|matrix| matrix.as_str().unwrap_or_default().to_string())
+                                .collect(),
+                        ),
+                        _ => TileScheme::from_url(&url),
+                    },
+                    tile_size: layer["tile_size"]
+                        .as_u64()
+                        .map_or_else(|| tile_size_from_url(&url), |size| size.max(1) as u32),
+                    rate_limit: layer["rate_limit"]
+                        .as_u64()
+                        .map_or(DEFAULT_RATE_LIMIT, |rate| rate.max(1) as u32),
+                    url,
+                },
+                opacity: layer["opacity"]
+                    .as_f64()
+                    .map_or(1.0, |opacity| opacity.max(0.0).min(1.0) as f32),
+                visible: layer["visible"].as_bool().unwrap_or(true),
+                locked: layer["locked"].as_bool().unwrap_or(false),
+            });
+        }
+
+        let mut layers = Vec::new();
+        for layer in root["layers"].as_array().into_iter().flatten() {
+            layers.push(layer_from_json(layer)?);
+        }
+
+        //presets are nice to have, a project missing them still opens
+        let export = &root["export"];
+        let defaults = ExportPresets::default();
+        let projection = export["geotiff_projection"].as_str().and_then(|name| {
+            GeoTiffProjection::ALL
+                .iter()
+                .find(|projection| projection.to_string() == name)
+                .copied()
+        });
+        let export = ExportPresets {
+            png_dpi: export["png_dpi"]
+                .as_u64()
+                .map_or(defaults.png_dpi, |dpi| dpi as u32),
+            geotiff_projection: projection.unwrap_or(defaults.geotiff_projection),
+            gpx_routes: export["gpx_routes"]
+                .as_bool()
+                .unwrap_or(defaults.gpx_routes),
+            print_layout: PrintLayout::from_json(&export["print_layout"]),
+        };
+
+        Ok(Project {
+            view: ProjectView { center, zoom },
+            raster_layers,
+            layers,
+            vector_style: root["vector_style"].as_str().map(PathBuf::from),
+            export,
+        })
+    }
+}
+
+impl Default for ExportPresets {
+    fn default() -> Self {
+        Self {
+            png_dpi: 96,
+            geotiff_projection: GeoTiffProjection::WebMercator,
+            gpx_routes: false,
+            print_layout: PrintLayout::default(),
+        }
+    }
+}
+
+fn write_json(path: &Path, root: &Value) -> Result<(), ProjectError> {
+    let text = serde_json::to_string_pretty(root)?;
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    std::fs::write(&partial, text)?;
+    std::fs::rename(&partial, path)?;
+    Ok(())
+}
+
+fn migrate(root: &mut Value) -> Result<(), ProjectError> {
+    if root["format"].as_str() != Some(FORMAT) {
+        return Err(ProjectError::NotAProject);
+    }
+    let mut version = root["version"]
+        .as_u64()
+        .ok_or_else(|| ProjectError::Invalid(String::from("missing version")))?;
+    if version > VERSION {
+        return Err(ProjectError::TooNew(version));
+    }
+    while version < VERSION {
+        let step = (version as usize)
+            .checked_sub(1)
+            .and_then(|idx| MIGRATIONS.get(idx))
+            .ok_or_else(|| ProjectError::Invalid(format!("unknown version {}", version)))?;
+        step(root);
+        version += 1;
+        root["version"] = json!(version);
+        log::info!("migrated project to version {}", version);
+    }
+    Ok(())
+}
+
+//version 1 had a list of plain tile sources, they're now raster layers that start out
+//fully opaque and shown
+fn tile_sources_to_raster_layers(root: &mut Value) {
+    let mut layers = match root["tile_sources"].take() {
+        Value::Array(sources) => sources,
+        _ => Vec::new(),
+    };
+    for layer in layers.iter_mut() {
+        layer["opacity"] = json!(1.0);
+        layer["visible"] = json!(true);
+    }
+    if let Value::Object(map) = root {
+        map.remove("tile_sources");
+    }
+    root["raster_layers"] = Value::Array(layers);
+}
+
+//the layer's features as GeoJSON, so the styles and properties round trip the same way
+//they do through an import and export
+fn layer_to_json(layer: &DrawingLayer) -> Value {
+    let mut value = json!({
+        "name": layer.name,
+        "visible": layer.visible,
+        "opacity": layer.opacity,
+        "locked": layer.locked,
+        "features": geojson::to_geojson(std::slice::from_ref(layer)),
+    });
+    //GeoJSON has nowhere to put gps times, so recorded point data sits alongside it, one
+    //entry per feature
+    if layer
+        .features
+        .iter()
+        .any(|feature| !feature.point_data.is_empty())
+    {
+        let point_data: Vec<Value> = layer
+            .features
+            .iter()
+            .map(|feature| {
+                Value::Array(
+                    feature
+                        .point_data
+                        .iter()
+                        .map(|data| json!([data.elevation, data.time]))
+                        .collect(),
+                )
+            })
+            .collect();
+        value["point_data"] = Value::Array(point_data);
+    }
+    value
+}
+
+fn layer_from_json(value: &Value) -> Result<DrawingLayer, ProjectError> {
+    let name = value["name"]
+        .as_str()
+        .ok_or_else(|| ProjectError::Invalid(String::from("layer without a name")))?;
+    let mut layer = geojson::from_geojson(&value["features"], name)?
+        .into_iter()
+        .find(|layer| layer.name == name)
+        .unwrap_or_else(|| DrawingLayer::new(name));
+    layer.visible = value["visible"].as_bool().unwrap_or(true);
+    layer.opacity = value["opacity"]
+        .as_f64()
+        .map_or(1.0, |opacity| opacity.max(0.0).min(1.0) as f32);
+    layer.locked = value["locked"].as_bool().unwrap_or(false);
+
+    let point_data = value["point_data"].as_array();
+    for (feature, data) in layer
+        .features
+        .iter_mut()
+        .zip(point_data.into_iter().flatten())
+    {
+        let data: Vec<PointData> = data
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|entry| PointData {
+                elevation: entry[0].as_f64(),
+                time: entry[1].as_str().map(String::from),
+            })
+            .collect();
+        if data.len() == feature.geometry.points().len() {
+            feature.point_data = data;
+        } else if !data.is_empty() {
+            log::warn!("point data doesn't match its feature in layer {}", name);
+        }
+    }
+    Ok(layer)
+}
+
+/// A project in the recently opened list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecentProject(pub PathBuf);
+
+impl fmt::Display for RecentProject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.display())
+    }
+}
+
+//per user settings directory, e.g. ~/.config/map_maker
+pub fn config_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("map_maker"))
+}
+
+fn recent_file() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("recent_projects.json"))
+}
+
+/// Most recently opened or saved first.
+pub fn recent_projects() -> Vec<RecentProject> {
+    let text = match recent_file().map(std::fs::read_to_string) {
+        Some(Ok(text)) => text,
+        _ => return Vec::new(),
+    };
+    match serde_json::from_str::<Value>(&text) {
+        Ok(Value::Array(paths)) => paths
+            .iter()
+            .filter_map(Value::as_str)
+            .map(|path| RecentProject(PathBuf::from(path)))
+            .collect(),
+        _ => {
+            log::warn!("couldn't read the recent projects list");
+            Vec::new()
+        }
+    }
+}
+
+/// Moves `path` to the top of the recent projects list and gives back the new list.
+pub fn remember_recent(path: &Path) -> Vec<RecentProject> {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let mut recent = recent_projects();
+    recent.retain(|existing| existing.0 != path);
+    recent.insert(0, RecentProject(path));
+    recent.truncate(MAX_RECENT);
+
+    if let Some(file) = recent_file() {
+        let paths: Vec<Value> = recent
+            .iter()
+            .map(|project| json!(project.0.display().to_string()))
+            .collect();
+        let result = file
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&file, Value::Array(paths).to_string()));
+        if let Err(e) = result {
+            log::warn!("couldn't save the recent projects list: {}", e);
+        }
+    }
+    recent
+}
+
+//autosaves of work that hasn't been saved yet. it's a project file like any other, plus
+//where the project was saved to last, if anywhere. every session writes its own, so one
+//that's offering the last session's work doesn't write over it
+const RECOVERY_PREFIX: &str = "recovery";
+
+fn session_recovery_file() -> Option<PathBuf> {
+    let name = format!(
+        "{}-{}.{}",
+        RECOVERY_PREFIX,
+        std::process::id(),
+        PROJECT_EXTENSION
+    );
+    config_dir().map(|dir| dir.join(name))
+}
+
+lazy_static! {
+    //held once this session has autosaved, until its autosave is discarded
+    static ref SESSION_LOCK: Mutex<Option<File>> = Mutex::new(None);
+}
+
+//the lock a session holds on its recovery file for as long as it's running
+fn lock_file(recovery: &Path) -> PathBuf {
+    recovery.with_extension("lock")
+}
+
+//recovery files sessions that have ended left behind
+fn leftover_recovery_files() -> Vec<PathBuf> {
+    let entries = match config_dir().map(std::fs::read_dir) {
+        Some(Ok(entries)) => entries,
+        _ => return Vec::new(),
+    };
+    entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| match recovery_pid(path) {
+            Some(pid) => pid != std::process::id() && !session_running(path),
+            None => false,
+        })
+        .collect()
+}
+
+//the process a recovery file was written by, for files named like session_recovery_file's
+fn recovery_pid(path: &Path) -> Option<u32> {
+    if path.extension() != Some(PROJECT_EXTENSION.as_ref()) {
+        return None;
+    }
+    let stem = path.file_stem()?.to_str()?;
+    let pid = stem.strip_prefix(RECOVERY_PREFIX)?.strip_prefix('-')?;
+    if !pid.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    pid.parse().ok()
+}
+
+//whether the session that wrote a recovery file still holds its lock, so the autosave of
+//one that's still open isn't offered and then deleted from under it
+fn session_running(recovery: &Path) -> bool {
+    match File::open(lock_file(recovery)) {
+        Ok(lock) => lock.try_lock_exclusive().is_err(),
+        Err(_) => false,
+    }
+}
+
+//locked before the first autosave, a session that hasn't autosaved has nothing to guard
+fn lock_session(recovery: &Path) -> std::io::Result<()> {
+    let mut held = SESSION_LOCK.lock().unwrap();
+    if held.is_none() {
+        let lock = File::create(lock_file(recovery))?;
+        lock.try_lock_exclusive()?;
+        *held = Some(lock);
+    }
+    Ok(())
+}
+
+/// Unsaved work left behind by a session that didn't get to save it.
+#[derive(Debug, Clone)]
+pub struct Recovery {
+    pub project: Project,
+    pub project_path: Option<PathBuf>,
+    pub saved_at: Option<SystemTime>,
+    //the recovery file it was read from
+    file: PathBuf,
+}
+
+impl fmt::Display for Recovery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unsaved work")?;
+        if let Some(name) = self.project_path.as_ref().and_then(|path| path.file_stem()) {
+            write!(f, " on {}", name.to_string_lossy())?;
+        }
+        let age = self
+            .saved_at
+            .and_then(|saved_at| SystemTime::now().duration_since(saved_at).ok());
+        match age.map(|age| age.as_secs() / 60) {
+            Some(0) => write!(f, " from just now"),
+            Some(1) => write!(f, " from a minute ago"),
+            Some(minutes) if minutes < 120 => write!(f, " from {} minutes ago", minutes),
+            Some(minutes) => write!(f, " from {} hours ago", minutes / 60),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Recovery {
+    /// Deletes the recovery file, once the work has been restored or thrown away.
+    pub fn remove(&self) {
+        remove_recovery_file(&self.file);
+    }
+}
+
+/// Autosaves to this session's recovery file.
+pub fn write_recovery(project: &Project, project_path: Option<&Path>) -> Result<(), ProjectError> {
+    let file = match session_recovery_file() {
+        Some(file) => file,
+        None => return Ok(()),
+    };
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    lock_session(&file)?;
+    let mut root = project.to_json();
+    root["recovered_from"] = json!(project_path.map(|path| path.display().to_string()));
+    write_json(&file, &root)?;
+    log::info!("autosaved to {}", file.display());
+    Ok(())
+}
+
+/// The latest work another session autosaved, if it never got saved or thrown away.
+/// Older work is offered once that's been dealt with.
+pub fn read_recovery() -> Option<Recovery> {
+    let modified = |file: &PathBuf| {
+        std::fs::metadata(file)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+    let mut files = leftover_recovery_files();
+    files.sort_by_key(|file| std::cmp::Reverse(modified(file)));
+    files.into_iter().find_map(|file| {
+        let root: Value = match std::fs::read_to_string(&file)
+            .map_err(ProjectError::from)
+            .and_then(|text| Ok(serde_json::from_str(&text)?))
+        {
+            Ok(root) => root,
+            Err(e) => {
+                log::warn!("couldn't read recovery file {}: {}", file.display(), e);
+                return None;
+            }
+        };
+        let project_path = root["recovered_from"].as_str().map(PathBuf::from);
+        match Project::from_json(root) {
+            Ok(project) => Some(Recovery {
+                project,
+                project_path,
+                saved_at: modified(&file),
+                file,
+            }),
+            Err(e) => {
+                log::warn!("couldn't read recovery file {}: {}", file.display(), e);
+                None
+            }
+        }
+    })
+}
+
+/// Deletes this session's autosave, the work in it has been saved properly.
+pub fn discard_recovery() {
+    if let Some(file) = session_recovery_file() {
+        //let go of the lock before its file is removed, windows won't remove an open file
+        SESSION_LOCK.lock().unwrap().take();
+        remove_recovery_file(&file);
+    }
+}
+
+fn remove_recovery_file(file: &Path) {
+    match std::fs::remove_file(file) {
+        Ok(()) => log::info!("removed recovery file {}", file.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => log::warn!("couldn't remove recovery file {}: {}", file.display(), e),
+    }
+    let _ = std::fs::remove_file(lock_file(file));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_session_recovery_files_are_recognized() {
+        let dir = Path::new("/config");
+        assert_eq!(
+            recovery_pid(&dir.join("recovery-1234.mapmaker")),
+            Some(1234)
+        );
+        assert_eq!(recovery_pid(&dir.join("recovery.mapmaker")), None);
+        assert_eq!(recovery_pid(&dir.join("recovery-old.mapmaker")), None);
+        assert_eq!(recovery_pid(&dir.join("recovery-+12.mapmaker")), None);
+        assert_eq!(recovery_pid(&dir.join("recovery-1234.json")), None);
+        assert_eq!(recovery_pid(&dir.join("recovery_notes-1.mapmaker")), None);
+    }
+
+    #[test]
+    fn sessions_holding_their_lock_are_running() {
+        let dir = std::env::temp_dir().join(format!("map_maker_{}_locks", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let recovery = dir.join("recovery-1.mapmaker");
+        assert!(!session_running(&recovery));
+
+        let lock = File::create(lock_file(&recovery)).unwrap();
+        assert!(!session_running(&recovery));
+        lock.try_lock_exclusive().unwrap();
+        assert!(session_running(&recovery));
+        drop(lock);
+        assert!(!session_running(&recovery));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,332 @@
+//tiles kept on disk between sessions, so areas looked at before or downloaded for offline
+//use show up without the network
+//
+//each source gets a directory named after a hash of its url, holding tiles the way servers
+//lay them out, z/x/y. sources serving tiles at several sizes get one per size. what the
+//server said about how long a tile stays good is kept next to it in z/x/y.meta
+use crate::project::config_dir;
+use crate::tile_manager::tile_manager::TileSource;
+use reqwest::header::{HeaderMap, AGE, CACHE_CONTROL, ETAG, EXPIRES, LAST_MODIFIED};
+use serde_json::{json, Value};
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+//how many cached tiles are looked at to guess the size of ones that aren't cached yet
+const SIZE_SAMPLE: usize = 200;
+const META_EXTENSION: &str = "meta";
+
+/// What a server said about a tile it sent, for telling whether the cached copy is still
+/// fresh and asking whether it changed once it isn't.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CachePolicy {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    //when the tile goes stale. tiles from servers that don't say never do
+    pub expires: Option<SystemTime>,
+}
+
+impl CachePolicy {
+    /// Reads `Cache-Control`, `Expires`, `ETag` and `Last-Modified` off a response. None if
+    /// the server asked for the tile not to be kept.
+    pub fn from_headers(headers: &HeaderMap, now: SystemTime) -> Option<Self> {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.trim().to_string())
+        };
+        let mut max_age = None;
+        for directive in header(CACHE_CONTROL).unwrap_or_default().split(',') {
+            let directive = directive.trim().to_lowercase();
+            match directive.split_once('=') {
+                _ if directive == "no-store" => return None,
+                //kept, but checked with the server every time it's used
+                _ if directive == "no-cache" => max_age = Some(0),
+                Some(("max-age", seconds)) => {
+                    max_age = seconds.trim_matches('"').parse::<u64>().ok().or(Some(0))
+                }
+                _ => {}
+            }
+        }
+        let expires = match max_age {
+            //the age is how long it's already been sitting in caches on the way
+            Some(max_age) => {
+                let age = header(AGE)
+                    .and_then(|age| age.parse::<u64>().ok())
+                    .unwrap_or(0);
+                Some(now + Duration::from_secs(max_age.saturating_sub(age)))
+            }
+            //an expiry that isn't a date, "0" usually, means it's already stale
+            None => {
+                header(EXPIRES).map(|expires| httpdate::parse_http_date(&expires).unwrap_or(now))
+            }
+        };
+        Some(Self {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            expires,
+        })
+    }
+
+    pub fn is_fresh(&self, now: SystemTime) -> bool {
+        self.expires.map_or(true, |expires| now < expires)
+    }
+
+    //whether there's anything worth writing down
+    fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none() && self.expires.is_none()
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "etag": self.etag,
+            "last_modified": self.last_modified,
+            "expires": self.expires
+                .and_then(|expires| expires.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|since| since.as_secs()),
+        })
+    }
+
+    fn from_json(value: &Value) -> Self {
+        let text = |key: &str| value[key].as_str().map(str::to_string);
+        Self {
+            etag: text("etag"),
+            last_modified: text("last_modified"),
+            expires: value["expires"]
+                .as_u64()
+                .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+        }
+    }
+}
+
+/// A tile read back from the cache along with what the server said about it.
+#[derive(Debug, Clone)]
+pub struct CachedTile {
+    pub bytes: Vec<u8>,
+    pub policy: CachePolicy,
+}
+
+#[derive(Debug, Clone)]
+pub struct TileCache {
+    dir: PathBuf,
+}
+
+impl TileCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// The cache in the per user settings directory, None if there isn't one.
+    pub fn open_default() -> Option<Self> {
+        config_dir().map(|dir| Self::new(dir.join("tile_cache")))
+    }
+
+    fn source_dir(&self, source: &TileSource) -> PathBuf {
+        let key = if source.is_scalable() {
+            format!("{} {}", source.url, source.tile_size)
+        } else {
+            source.url.clone()
+        };
+        self.dir.join(format!("{:016x}", fnv1a(key.as_bytes())))
+    }
+
+    fn tile_path(&self, source: &TileSource, coords: &(u32, u32, u32)) -> PathBuf {
+        let (x, y, z) = coords;
+        self.source_dir(source)
+            .join(z.to_string())
+            .join(x.to_string())
+            .join(y.to_string())
+    }
+
+    pub fn contains(&self, source: &TileSource, coords: &(u32, u32, u32)) -> bool {
+        self.tile_path(source, coords).is_file()
+    }
+
+    pub fn read(&self, source: &TileSource, coords: &(u32, u32, u32)) -> Option<CachedTile> {
+        let path = self.tile_path(source, coords);
+        let bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                log::warn!("couldn't read cached tile {:?}: {}", coords, e);
+                return None;
+            }
+        };
+        //tiles cached before policies were kept, or from servers that said nothing, have
+        //no meta file
+        let policy = std::fs::read(path.with_extension(META_EXTENSION))
+            .ok()
+            .and_then(|meta| serde_json::from_slice::<Value>(&meta).ok())
+            .map(|meta| CachePolicy::from_json(&meta))
+            .unwrap_or_default();
+        Some(CachedTile { bytes, policy })
+    }
+
+    /// Stores a tile along with its policy. It's written next to where it goes and moved
+    /// into place so a tile cut off half way is never read back.
+    pub fn write(
+        &self,
+        source: &TileSource,
+        coords: &(u32, u32, u32),
+        bytes: &[u8],
+        policy: &CachePolicy,
+    ) -> io::Result<()> {
+        let path = self.tile_path(source, coords);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let partial = path.with_extension("part");
+        std::fs::write(&partial, bytes)?;
+        std::fs::rename(&partial, &path)?;
+        self.write_policy(source, coords, policy)
+    }
+
+    /// Replaces the policy of a cached tile, when the server says the tile hasn't changed.
+    pub fn write_policy(
+        &self,
+        source: &TileSource,
+        coords: &(u32, u32, u32),
+        policy: &CachePolicy,
+    ) -> io::Result<()> {
+        let meta = self
+            .tile_path(source, coords)
+            .with_extension(META_EXTENSION);
+        if policy.is_empty() {
+            return match std::fs::remove_file(&meta) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        std::fs::write(meta, policy.to_json().to_string())
+    }
+
+    /// The average size in bytes of the tiles cached for `source`, from a sample of them.
+    pub fn average_tile_size(&self, source: &TileSource) -> Option<u64> {
+        let mut sizes = Vec::new();
+        let mut dirs = vec![self.source_dir(source)];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(&dir).into_iter().flatten().flatten() {
+                let is_tile = entry.path().extension().is_none();
+                match entry.metadata() {
+                    Ok(meta) if meta.is_dir() => dirs.push(entry.path()),
+                    Ok(meta) if is_tile => sizes.push(meta.len()),
+                    _ => {}
+                }
+                if sizes.len() >= SIZE_SAMPLE {
+                    break;
+                }
+            }
+            if sizes.len() >= SIZE_SAMPLE {
+                break;
+            }
+        }
+        if sizes.is_empty() {
+            return None;
+        }
+        Some(sizes.iter().sum::<u64>() / sizes.len() as u64)
+    }
+}
+
+//the std hasher may change between releases, the directory names can't
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderName, HeaderValue};
+
+    fn headers(pairs: &[(HeaderName, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(name.clone(), HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn cache(name: &str) -> TileCache {
+        let dir = std::env::temp_dir().join(format!("map_maker_{}_{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        TileCache::new(dir)
+    }
+
+    #[test]
+    fn max_age_counts_from_the_age() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let policy = CachePolicy::from_headers(
+            &headers(&[
+                (CACHE_CONTROL, "public, max-age=3600"),
+                (AGE, "600"),
+                (ETAG, "\"abc\""),
+                //max-age wins over expires
+                (EXPIRES, "Thu, 01 Jan 1970 00:00:00 GMT"),
+            ]),
+            now,
+        )
+        .unwrap();
+        assert_eq!(policy.expires, Some(now + Duration::from_secs(3000)));
+        assert_eq!(policy.etag.as_deref(), Some("\"abc\""));
+        assert!(policy.is_fresh(now + Duration::from_secs(2999)));
+        assert!(!policy.is_fresh(now + Duration::from_secs(3000)));
+    }
+
+    #[test]
+    fn expires_is_read_when_there_is_no_max_age() {
+        let now = SystemTime::UNIX_EPOCH;
+        let dated =
+            CachePolicy::from_headers(&headers(&[(EXPIRES, "Sun, 06 Nov 1994 08:49:37 GMT")]), now)
+                .unwrap();
+        assert_eq!(
+            dated.expires,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_777))
+        );
+        let stale = CachePolicy::from_headers(&headers(&[(EXPIRES, "0")]), now).unwrap();
+        assert!(!stale.is_fresh(now));
+        let no_cache = CachePolicy::from_headers(&headers(&[(CACHE_CONTROL, "no-cache")]), now);
+        assert!(!no_cache.unwrap().is_fresh(now));
+        //servers that say nothing are never checked again
+        let silent = CachePolicy::from_headers(&HeaderMap::new(), now).unwrap();
+        assert_eq!(silent, CachePolicy::default());
+        assert!(silent.is_fresh(now + Duration::from_secs(1 << 40)));
+    }
+
+    #[test]
+    fn no_store_tiles_are_not_kept() {
+        let headers = headers(&[(CACHE_CONTROL, "max-age=60, No-Store")]);
+        assert_eq!(CachePolicy::from_headers(&headers, SystemTime::now()), None);
+    }
+
+    #[test]
+    fn tiles_are_read_back_with_their_policy() {
+        let cache = cache("tile_cache_round_trip");
+        let source = TileSource::default();
+        let policy = CachePolicy {
+            etag: Some(String::from("\"v1\"")),
+            last_modified: None,
+            expires: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1234)),
+        };
+        cache.write(&source, &(1, 2, 3), b"tile", &policy).unwrap();
+        assert!(cache.contains(&source, &(1, 2, 3)));
+        assert!(!cache.contains(&source, &(2, 1, 3)));
+        let cached = cache.read(&source, &(1, 2, 3)).unwrap();
+        assert_eq!(
+            (cached.bytes.as_slice(), &cached.policy),
+            (&b"tile"[..], &policy)
+        );
+        //an empty policy removes the meta file
+        cache
+            .write_policy(&source, &(1, 2, 3), &CachePolicy::default())
+            .unwrap();
+        let cached = cache.read(&source, &(1, 2, 3)).unwrap();
+        assert_eq!(cached.policy, CachePolicy::default());
+        assert_eq!(cache.average_tile_size(&source), Some(4));
+        std::fs::remove_dir_all(&cache.dir).unwrap();
+    }
+}
@@ -1,144 +1,1627 @@
 pub mod tile_manager {
+    use crate::archive::{is_archive_path, TileArchive};
+    use crate::geo::{tile_meters, Bounds, TILE_SIZE};
+    use crate::http::{HttpClient, HttpError, HttpSettings};
+    use crate::offline::BatchResult;
+    use crate::tile_cache::{CachePolicy, CachedTile, TileCache};
+    use crate::vector_tile::mvt;
+    use crate::vector_tile::render_tile;
+    use crate::vector_tile::style::VectorStyle;
+    use ab_glyph::FontVec;
     use futures::future::join_all;
-    use std::collections::HashMap;
+    use futures::stream::{self, StreamExt};
+    use reqwest::header::{IF_MODIFIED_SINCE, IF_NONE_MATCH};
+    use reqwest::StatusCode;
+    use iced::image;
+    use ::image::imageops::FilterType;
+    use ::image::RgbaImage;
+    use std::collections::{HashMap, HashSet};
+    use std::fmt;
+    use std::path::{Path, PathBuf};
     use std::sync::Arc;
+    use std::time::{Duration, Instant, SystemTime};
 
     #[derive(Clone, Debug)]
     pub enum TileState {
         NotLoaded,
         Loading,
         Loaded,
+        //not cached while offline, it's fetched once the network is allowed again
+        Unavailable,
+        //the server couldn't be reached or answered with an error, the tile is fetched
+        //again when it's asked for after this
+        Failed(Instant),
     }
     #[derive(Clone, Debug)]
     pub struct Tile {
+        //id of the raster layer the tile belongs to
+        pub layer: usize,
         pub target_url: (u32, u32, u32),
         pub image: Vec<u8>,
         pub state: TileState,
+        //vector tiles are drawn as they're loaded, away from the ui thread
+        drawn: Option<DrawnTile>,
+    }
+
+    //a vector tile drawn as an image, along with what it was drawn with so one that was
+    //restyled meanwhile isn't shown
+    #[derive(Clone)]
+    struct DrawnTile {
+        //None if it didn't decode
+        image: Option<RgbaImage>,
+        rendering: Arc<VectorRendering>,
+        size: u32,
+    }
+
+    impl fmt::Debug for DrawnTile {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match &self.image {
+                Some(image) => write!(f, "DrawnTile({}x{})", image.width(), image.height()),
+                None => write!(f, "DrawnTile(none)"),
+            }
+        }
     }
 
     impl Tile {
-        pub fn new(target_url: &(u32, u32, u32)) -> Self {
+        pub fn new(layer: usize, target_url: &(u32, u32, u32)) -> Self {
             Self {
+                layer,
                 target_url: *target_url,
                 image: Vec::new(),
                 state: TileState::NotLoaded,
+                drawn: None,
+            }
+        }
+
+        //a failed tile is to be fetched again once it's been long enough
+        fn retry_when_due(&mut self) {
+            if let TileState::Failed(retry_at) = self.state {
+                if Instant::now() >= retry_at {
+                    self.state = TileState::NotLoaded;
+                }
             }
         }
     }
     impl Default for Tile {
         fn default() -> Self {
             Self {
+                layer: 0,
                 target_url: (0, 0, 0),
                 image: Vec::new(),
                 state: TileState::NotLoaded,
+                drawn: None,
             }
         }
     }
 
-    pub struct TileManager {
+    /// What a source's tiles are.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum TileKind {
+        //images, png or jpeg
+        Raster,
+        //mapbox vector tiles, drawn with the vector style
+        Vector,
+    }
+
+    impl TileKind {
+        /// Guesses from the file extension in a tile url, sources are raster unless they
+        /// end in `.pbf` or `.mvt`.
+        pub fn from_url(url: &str) -> Self {
+            let path = url.split('?').next().unwrap_or(url).to_lowercase();
+            if path.ends_with(".pbf") || path.ends_with(".mvt") {
+                TileKind::Vector
+            } else {
+                TileKind::Raster
+            }
+        }
+    }
+
+    /// How a source's urls say which tile they're for.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub enum TileScheme {
+        //`{z}/{x}/{y}`, rows counted from the top like slippy maps
+        Xyz,
+        //the same placeholders, but rows count up from the bottom
+        Tms,
+        //bing's `{quadkey}`, or `{q}`, naming the tile by its path down the quadtree
+        Quadkey,
+        //`{TileMatrix}`, `{TileRow}` and `{TileCol}`, with the tile matrix identifiers by
+        //zoom. matrix sets that skip a zoom have an empty identifier for it, with none at
+        //all the zoom is the identifier
+        Wmts(Vec<String>),
+        //a wms GetMap request with `{bbox}` in it, filled in with the tile's EPSG:3857
+        //extent. the server draws each tile as it's asked for
+        Wms,
+    }
+
+    impl TileScheme {
+        /// Guesses from the placeholders in a url, `{z}/{x}/{y}` unless it has others.
+        pub fn from_url(url: &str) -> Self {
+            if url.contains("{quadkey}") || url.contains("{q}") {
+                TileScheme::Quadkey
+            } else if url.contains("{TileMatrix}") {
+                TileScheme::Wmts(Vec::new())
+            } else if url.contains("{bbox}") {
+                TileScheme::Wms
+            } else {
+                TileScheme::Xyz
+            }
+        }
+
+        /// Whether the source has tiles at `zoom` at all.
+        pub fn has_zoom(&self, zoom: u32) -> bool {
+            match self {
+                TileScheme::Wmts(matrices) if !matrices.is_empty() => matrices
+                    .get(zoom as usize)
+                    .map_or(false, |matrix| !matrix.is_empty()),
+                _ => true,
+            }
+        }
+    }
+
+    //the digits of a quadkey are the quadrant picked at each zoom, most significant first
+    fn quadkey(x: u32, y: u32, z: u32) -> String {
+        (1..=z)
+            .rev()
+            .map(|level| {
+                let mask = 1 << (level - 1);
+                let digit = (x & mask != 0) as u8 + 2 * (y & mask != 0) as u8;
+                (b'0' + digit) as char
+            })
+            .collect()
+    }
+
+    /// How many tiles a second offline downloads ask a source for unless it says otherwise.
+    pub const DEFAULT_RATE_LIMIT: u32 = 2;
+
+    //how many tiles load_tiles has in flight at once
+    const MAX_CONCURRENT_LOADS: usize = 8;
+    //how long a tile that failed to load is left before it's fetched again
+    const RETRY_FAILED: Duration = Duration::from_secs(15);
+
+    /// Guesses how many pixels across a source's tiles are from its url, 512 for `@2x`
+    /// variants and 256 otherwise.
+    pub fn tile_size_from_url(url: &str) -> u32 {
+        if url.contains("@2x") {
+            TILE_SIZE as u32 * 2
+        } else {
+            TILE_SIZE as u32
+        }
+    }
+
+    /// Where tiles are fetched from. `url` has the placeholders of its scheme in it, `{-y}`
+    /// always counts rows from the bottom. `{r}` is `@2x` when the tiles are 512 pixels and
+    /// `{width}` and `{height}` are the tile size. It can also be the path of an mbtiles or
+    /// pmtiles file.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct TileSource {
+        pub name: String,
+        pub url: String,
+        pub attribution: String,
+        pub kind: TileKind,
+        pub scheme: TileScheme,
+        //pixels across a tile, 512 for tiles made for hidpi screens. tiles cover the same
+        //area whatever their size, bigger ones just have more detail
+        pub tile_size: u32,
+        //most tiles a second an offline download fetches from the source
+        pub rate_limit: u32,
+    }
+
+    impl TileSource {
+        pub fn stamen_terrain() -> Self {
+            Self {
+                name: String::from("Stamen Terrain"),
+                url: String::from("https://stamen-tiles.a.ssl.fastly.net/terrain/{z}/{x}/{y}.png"),
+                attribution: String::from(
+                    "Map tiles by Stamen Design, under CC BY 3.0. Data by OpenStreetMap, under ODbL.",
+                ),
+                kind: TileKind::Raster,
+                scheme: TileScheme::Xyz,
+                tile_size: TILE_SIZE as u32,
+                rate_limit: DEFAULT_RATE_LIMIT,
+            }
+        }
+
+        /// A source for a url typed in by hand, named after the server it's on. Archives
+        /// are named after the file.
+        pub fn from_url(url: &str) -> Self {
+            let url = url.trim();
+            if is_archive_path(url) {
+                let name = Path::new(url)
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .unwrap_or(url);
+                return Self {
+                    name: name.to_string(),
+                    url: url.to_string(),
+                    attribution: String::new(),
+                    kind: TileKind::Raster,
+                    scheme: TileScheme::Xyz,
+                    tile_size: TILE_SIZE as u32,
+                    rate_limit: DEFAULT_RATE_LIMIT,
+                };
+            }
+            //a folder of tiles without a template gets the one its layout suggests
+            if url.starts_with("file://") {
+                let template = if url.contains("{z}") {
+                    None
+                } else {
+                    folder_template(&file_url_path(url))
+                };
+                let (url, scheme) =
+                    template.unwrap_or_else(|| (url.to_string(), TileScheme::from_url(url)));
+                let folder = file_url_path(&url);
+                let name = folder
+                    .ancestors()
+                    .find(|dir| !dir.to_string_lossy().contains('{'))
+                    .and_then(|dir| dir.file_name())
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| url.clone());
+                return Self {
+                    name,
+                    kind: TileKind::from_url(&url),
+                    tile_size: tile_size_from_url(&url),
+                    url,
+                    attribution: String::new(),
+                    scheme,
+                    rate_limit: DEFAULT_RATE_LIMIT,
+                };
+            }
+            let host = url
+                .split("://")
+                .nth(1)
+                .and_then(|rest| rest.split('/').next())
+                .filter(|host| !host.is_empty())
+                .unwrap_or(url);
+            Self {
+                name: host.to_string(),
+                url: url.to_string(),
+                attribution: String::new(),
+                kind: TileKind::from_url(url),
+                scheme: TileScheme::from_url(url),
+                tile_size: tile_size_from_url(url),
+                rate_limit: DEFAULT_RATE_LIMIT,
+            }
+        }
+
+        /// Sources that can be added to the map without typing in a url.
+        pub fn presets() -> Vec<TileSource> {
+            vec![
+                TileSource::stamen_terrain(),
+                TileSource {
+                    name: String::from("OpenStreetMap"),
+                    url: String::from("https://tile.openstreetmap.org/{z}/{x}/{y}.png"),
+                    attribution: String::from("© OpenStreetMap contributors"),
+                    kind: TileKind::Raster,
+                    scheme: TileScheme::Xyz,
+                    tile_size: TILE_SIZE as u32,
+                    rate_limit: DEFAULT_RATE_LIMIT,
+                },
+                TileSource {
+                    name: String::from("Hillshading"),
+                    url: String::from("https://tiles.wmflabs.org/hillshading/{z}/{x}/{y}.png"),
+                    attribution: String::from("Hillshading from NASA SRTM data."),
+                    kind: TileKind::Raster,
+                    scheme: TileScheme::Xyz,
+                    tile_size: TILE_SIZE as u32,
+                    rate_limit: DEFAULT_RATE_LIMIT,
+                },
+                TileSource {
+                    name: String::from("Hiking trails"),
+                    url: String::from("https://tile.waymarkedtrails.org/hiking/{z}/{x}/{y}.png"),
+                    attribution: String::from(
+                        "Trails by waymarkedtrails.org, under CC BY-SA 3.0.",
+                    ),
+                    kind: TileKind::Raster,
+                    scheme: TileScheme::Xyz,
+                    tile_size: TILE_SIZE as u32,
+                    rate_limit: DEFAULT_RATE_LIMIT,
+                },
+            ]
+        }
+
+        /// Whether the tiles are on this machine, so there's nothing to fetch or cache.
+        pub fn is_local(&self) -> bool {
+            is_archive_path(&self.url) || self.url.starts_with("file://")
+        }
+
+        /// Whether the url says where each tile is, rather than being a page or a folder.
+        pub fn has_placeholders(&self) -> bool {
+            ["{z}", "{quadkey}", "{q}", "{TileMatrix}", "{bbox}"]
+                .iter()
+                .any(|placeholder| self.url.contains(placeholder))
+        }
+
+        /// Whether the source can serve tiles at more than one size, with `{r}` picking the
+        /// `@2x` variant or `{width}` asking for any size.
+        pub fn is_scalable(&self) -> bool {
+            self.url.contains("{r}") || self.url.contains("{width}")
+        }
+
+        /// The source as it's fetched for a screen with `scale` physical pixels to a logical
+        /// one. Scalable sources are asked for tiles with that much more detail.
+        pub fn at_scale(&self, scale: u32) -> TileSource {
+            let mut source = self.clone();
+            let scale = scale.max(1);
+            if self.url.contains("{width}") {
+                source.tile_size = self.tile_size * scale;
+            } else if self.url.contains("{r}") && scale > 1 {
+                source.tile_size = self.tile_size.max(TILE_SIZE as u32 * 2);
+            }
+            source
+        }
+
+        pub fn tile_url(&self, coords: &(u32, u32, u32)) -> String {
+            let (x, y, z) = *coords;
+            //tms rows count up from the bottom
+            let flipped_y = (1u64 << z).saturating_sub(1 + y as u64);
+            let row = match self.scheme {
+                TileScheme::Tms => flipped_y,
+                _ => y as u64,
+            };
+            let mut url = self
+                .url
+                .replace("{x}", &x.to_string())
+                .replace("{-y}", &flipped_y.to_string())
+                .replace("{y}", &row.to_string())
+                .replace("{z}", &z.to_string())
+                .replace("{r}", if self.tile_size > TILE_SIZE as u32 { "@2x" } else { "" })
+                .replace("{width}", &self.tile_size.to_string())
+                .replace("{height}", &self.tile_size.to_string());
+            match &self.scheme {
+                TileScheme::Quadkey => {
+                    let key = quadkey(x, y, z);
+                    url = url.replace("{quadkey}", &key).replace("{q}", &key);
+                }
+                TileScheme::Wmts(matrices) => {
+                    let matrix = matrices
+                        .get(z as usize)
+                        .cloned()
+                        .unwrap_or_else(|| z.to_string());
+                    url = url
+                        .replace("{TileMatrix}", &matrix)
+                        .replace("{TileRow}", &y.to_string())
+                        .replace("{TileCol}", &x.to_string());
+                }
+                TileScheme::Wms => {
+                    let (min_x, min_y, max_x, max_y) = tile_meters(coords);
+                    let bbox = format!("{:.2},{:.2},{:.2},{:.2}", min_x, min_y, max_x, max_y);
+                    url = url.replace("{bbox}", &bbox);
+                }
+                _ => {}
+            }
+            url
+        }
+
+        /// Where a tile is on disk, for `file://` sources.
+        pub fn tile_path(&self, coords: &(u32, u32, u32)) -> Option<PathBuf> {
+            if self.url.starts_with("file://") {
+                Some(file_url_path(&self.tile_url(coords)))
+            } else {
+                None
+            }
+        }
+    }
+
+    impl Default for TileSource {
+        fn default() -> Self {
+            TileSource::stamen_terrain()
+        }
+    }
+
+    //the path in a file url, file:///C:/tiles is C:/tiles on windows
+    fn file_url_path(url: &str) -> PathBuf {
+        let path = url.trim_start_matches("file://");
+        let bytes = path.as_bytes();
+        if bytes.len() > 2 && bytes[0] == b'/' && bytes[2] == b':' {
+            return PathBuf::from(&path[1..]);
+        }
+        PathBuf::from(path)
+    }
+
+    //a template for a folder laid out z/x/y.ext the way gdal2tiles and qgis write them.
+    //gdal2tiles leaves a tilemapresource.xml next to tms layouts, rows count from the bottom
+    fn folder_template(dir: &Path) -> Option<(String, TileScheme)> {
+        //the first entry of `dir` whose file stem is a number
+        let numbered = |dir: &Path| {
+            std::fs::read_dir(dir).ok()?.flatten().map(|entry| entry.path()).find(|path| {
+                let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+                !stem.is_empty() && stem.chars().all(|c| c.is_ascii_digit())
+            })
+        };
+        let zoom = numbered(dir).filter(|path| path.is_dir())?;
+        let column = numbered(&zoom).filter(|path| path.is_dir())?;
+        let tile = numbered(&column).filter(|path| path.is_file())?;
+        let extension = tile.extension()?.to_str()?;
+        let scheme = if dir.join("tilemapresource.xml").is_file() {
+            TileScheme::Tms
+        } else {
+            TileScheme::Xyz
+        };
+        let dir = dir.to_string_lossy();
+        let url = format!("file://{}/{{z}}/{{x}}/{{y}}.{}", dir.trim_end_matches('/'), extension);
+        Some((url, scheme))
+    }
+
+    impl fmt::Display for TileSource {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}", self.name)
+        }
+    }
+
+    /// One layer of the map's raster stack.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct RasterLayer {
+        pub source: TileSource,
+        //0 is invisible, 1 hides whatever is underneath
+        pub opacity: f32,
+        pub visible: bool,
+        //locked layers can't be renamed or deleted
+        pub locked: bool,
+    }
+
+    impl RasterLayer {
+        pub fn new(source: TileSource) -> Self {
+            Self {
+                source,
+                opacity: 1.0,
+                visible: true,
+                locked: false,
+            }
+        }
+    }
+
+    //a raster layer along with the tiles fetched for it. layers keep an id for as long as
+    //they're in the stack so loads that finish after a reorder still find theirs
+    struct LayerTiles {
+        id: usize,
+        layer: RasterLayer,
+        //the layer's source at the display scale
+        source: Arc<TileSource>,
         tile_dict: HashMap<(u32, u32, u32), Tile>,
-        pub client: std::sync::Arc<reqwest::Client>,
+        //loaded tiles as pixels, kept so fading them again doesn't decode them again. None
+        //for tiles that didn't decode, they aren't tried again
+        decoded: HashMap<(u32, u32, u32), Option<RgbaImage>>,
+        //handles for loaded tiles along with the opacity they were faded to
+        handles: HashMap<(u32, u32, u32), (f32, image::Handle)>,
+        //vector tiles waiting to be drawn again or being drawn, and the ones of those
+        //that haven't been sent off yet
+        drawing: HashSet<(u32, u32, u32)>,
+        draw_queue: Vec<(u32, u32, u32)>,
         load_queue: Vec<(u32, u32, u32)>,
+        //tiles are read from here instead of fetched when the source is an archive
+        archive: Option<Arc<TileArchive>>,
     }
 
-    impl Default for TileManager {
-        fn default() -> Self {
+    impl LayerTiles {
+        //drops every tile as drawn, they're decoded again the next time they're asked for
+        fn forget_drawn(&mut self) {
+            self.redraw();
+            self.handles.clear();
+        }
+
+        //has vector tiles drawn again, what's on the map stays until they are
+        fn redraw(&mut self) {
+            self.decoded.clear();
+            self.drawing.clear();
+            self.draw_queue.clear();
+        }
+
+        fn new(id: usize, mut layer: RasterLayer, scale: u32) -> Self {
+            let archive = if is_archive_path(&layer.source.url) {
+                match TileArchive::open(Path::new(layer.source.url.trim())) {
+                    Ok(archive) => Some(Arc::new(archive)),
+                    Err(e) => {
+                        log::error!("couldn't open tile archive {}: {}", layer.source.url, e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            //the archive knows better than the extension guess what its tiles are
+            if let Some(archive) = &archive {
+                let metadata = archive.metadata();
+                layer.source.kind = metadata.format.kind();
+                if layer.source.attribution.is_empty() {
+                    layer.source.attribution = metadata.attribution.clone().unwrap_or_default();
+                }
+            }
             Self {
+                id,
+                source: Arc::new(layer.source.at_scale(scale)),
+                layer,
                 tile_dict: Default::default(),
-                client: std::sync::Arc::new(reqwest::Client::new()),
+                decoded: Default::default(),
+                handles: Default::default(),
+                drawing: Default::default(),
+                draw_queue: Default::default(),
                 load_queue: Default::default(),
+                archive,
             }
         }
     }
 
+    //how vector tiles are drawn, kept together so it can be handed around as one
+    struct VectorRendering {
+        style: VectorStyle,
+        //for labels, they're left out without it
+        font: Option<Arc<FontVec>>,
+    }
+
+    //the bytes of a loaded tile as an image, vector tiles are drawn `size` pixels across
+    //with `rendering`
+    fn decode_tile(
+        bytes: &[u8],
+        kind: TileKind,
+        zoom: u32,
+        size: u32,
+        rendering: &VectorRendering,
+    ) -> Option<RgbaImage> {
+        match kind {
+            TileKind::Raster => match ::image::load_from_memory(bytes) {
+                Ok(tile) => Some(tile.to_rgba8()),
+                Err(e) => {
+                    log::warn!("tile didn't decode: {}", e);
+                    None
+                }
+            },
+            TileKind::Vector => match mvt::decode(bytes) {
+                Ok(tile) => Some(render_tile(
+                    &tile,
+                    &rendering.style,
+                    zoom as u8,
+                    size,
+                    rendering.font.as_deref(),
+                )),
+                Err(e) => {
+                    log::warn!("vector tile didn't decode: {}", e);
+                    None
+                }
+            },
+        }
+    }
+
+    //a loaded tile as a TILE_SIZE image the way exports draw it, None when it's empty or
+    //doesn't decode
+    fn export_tile(
+        bytes: &[u8],
+        kind: TileKind,
+        zoom: u32,
+        rendering: &VectorRendering,
+    ) -> Option<RgbaImage> {
+        if bytes.is_empty() {
+            return None;
+        }
+        let size = TILE_SIZE as u32;
+        let tile = decode_tile(bytes, kind, zoom, size, rendering)?;
+        if tile.dimensions() == (size, size) {
+            return Some(tile);
+        }
+        Some(::image::imageops::resize(&tile, size, size, FilterType::Triangle))
+    }
+
+    /// The loaded tiles of the visible layers over an area, taken from the manager so an
+    /// export can be drawn on another thread.
+    #[derive(Clone)]
+    pub struct TileSnapshot {
+        //bottom to top
+        layers: Vec<SnapshotLayer>,
+        vector_rendering: Arc<VectorRendering>,
+        attribution: String,
+    }
+
+    #[derive(Clone)]
+    struct SnapshotLayer {
+        id: usize,
+        opacity: f32,
+        kind: TileKind,
+        tiles: HashMap<(u32, u32, u32), Vec<u8>>,
+    }
+
+    impl TileSnapshot {
+        /// Ids of the layers that were drawn, bottom to top.
+        pub fn visible_layers(&self) -> Vec<usize> {
+            self.layers.iter().map(|layer| layer.id).collect()
+        }
+
+        pub fn opacity(&self, layer: usize) -> f32 {
+            self.layer(layer).map_or(1.0, |layer| layer.opacity)
+        }
+
+        /// Credits for the layers, as TileManager::attribution gave them.
+        pub fn attribution(&self) -> &str {
+            &self.attribution
+        }
+
+        /// A loaded tile as a 256 pixel image, unfaded, None if it wasn't loaded or is empty.
+        /// Vector tiles are drawn with the style of when the snapshot was taken, bigger tiles
+        /// are scaled down.
+        pub fn tile_image(&self, layer: usize, coords: &(u32, u32, u32)) -> Option<RgbaImage> {
+            let layer = self.layer(layer)?;
+            let bytes = layer.tiles.get(coords)?;
+            export_tile(bytes, layer.kind, coords.2, &self.vector_rendering)
+        }
+
+        fn layer(&self, id: usize) -> Option<&SnapshotLayer> {
+            self.layers.iter().find(|layer| layer.id == id)
+        }
+    }
+
+    //a decoded tile as a handle the renderer can draw at `opacity`. the renderer has no
+    //opacity of its own, so see-through tiles are faded here
+    fn faded_handle(tile: &RgbaImage, opacity: f32) -> image::Handle {
+        let (width, height) = tile.dimensions();
+        //the renderer wants bgra
+        let mut pixels = tile.as_raw().clone();
+        for pixel in pixels.chunks_mut(4) {
+            pixel.swap(0, 2);
+            pixel[3] = (pixel[3] as f32 * opacity.max(0.0).min(1.0)).round() as u8;
+        }
+        image::Handle::from_pixels(width, height, pixels)
+    }
+
+    //what a server answered a tile request with, along with how long the answer stays good.
+    //no policy means the server asked for the tile not to be kept
+    enum Fetched {
+        Tile(Vec<u8>, Option<CachePolicy>),
+        //the cached copy hasn't changed
+        NotModified(Option<CachePolicy>),
+    }
+
+    pub struct TileManager {
+        //every request goes through it, with the user agent, keys and rate limits of
+        //the host it's for
+        pub http: Arc<HttpClient>,
+        //bottom to top
+        layers: Vec<LayerTiles>,
+        next_layer_id: usize,
+        //how layers of vector tiles are drawn
+        vector_rendering: Arc<VectorRendering>,
+        //tiles from earlier sessions and offline downloads, None if there's nowhere to
+        //keep them
+        cache: Option<Arc<TileCache>>,
+        //only the cache is used, nothing is fetched
+        offline: bool,
+        //physical pixels to a logical one on the screen, tiles are fetched and drawn with
+        //that much more detail where sources have it
+        scale: u32,
+    }
+
+    impl Default for TileManager {
+        fn default() -> Self {
+            TileManager::new()
+        }
+    }
+
     impl TileManager {
         pub fn new() -> Self {
+            TileManager::with_settings(HttpSettings::load_default(), TileCache::open_default())
+        }
+
+        /// A manager that makes requests the way `http` says and caches tiles in `cache`,
+        /// rather than with what's in the settings directory.
+        pub fn with_settings(http: HttpSettings, cache: Option<TileCache>) -> Self {
             Self {
-                tile_dict: Default::default(),
-                client: std::sync::Arc::new(reqwest::Client::new()),
-                load_queue: Default::default(),
+                http: Arc::new(HttpClient::new(http)),
+                layers: vec![LayerTiles::new(0, RasterLayer::new(TileSource::default()), 1)],
+                next_layer_id: 1,
+                vector_rendering: Arc::new(VectorRendering {
+                    style: VectorStyle::default(),
+                    font: None,
+                }),
+                cache: cache.map(Arc::new),
+                offline: false,
+                scale: 1,
+            }
+        }
+
+        pub fn is_offline(&self) -> bool {
+            self.offline
+        }
+
+        /// Stops or starts going to the network for tiles. Tiles that weren't cached while
+        /// offline are fetched the next time they're asked for once back online.
+        pub fn set_offline(&mut self, offline: bool) {
+            self.offline = offline;
+            if offline {
+                return;
+            }
+            for tiles in self.layers.iter_mut() {
+                for tile in tiles.tile_dict.values_mut() {
+                    if let TileState::Unavailable = tile.state {
+                        tile.state = TileState::NotLoaded;
+                    }
+                }
+            }
+        }
+
+        /// Sets how many physical pixels the screen has to a logical one. Layers that can
+        /// serve sharper tiles at the new scale fetch them again, vector layers are redrawn.
+        pub fn set_display_scale(&mut self, scale: u32) {
+            let scale = scale.max(1);
+            if self.scale == scale {
+                return;
+            }
+            self.scale = scale;
+            for tiles in self.layers.iter_mut() {
+                let source = tiles.layer.source.at_scale(scale);
+                if *tiles.source != source {
+                    tiles.source = Arc::new(source);
+                    tiles.tile_dict.clear();
+                    tiles.load_queue.clear();
+                }
+                tiles.forget_drawn();
+            }
+        }
+
+        pub fn cache(&self) -> Option<&TileCache> {
+            self.cache.as_deref()
+        }
+
+        /// Restyles the vector layers, they're drawn again the next time they're asked for.
+        pub fn set_vector_style(&mut self, style: VectorStyle) {
+            let font = self.vector_rendering.font.clone();
+            self.vector_rendering = Arc::new(VectorRendering { style, font });
+            self.clear_vector_handles();
+        }
+
+        /// The font vector tile labels are drawn with.
+        pub fn set_label_font(&mut self, font: Option<Arc<FontVec>>) {
+            let style = self.vector_rendering.style.clone();
+            self.vector_rendering = Arc::new(VectorRendering { style, font });
+            self.clear_vector_handles();
+        }
+
+        fn clear_vector_handles(&mut self) {
+            for tiles in self.layers.iter_mut() {
+                if tiles.source.kind == TileKind::Vector {
+                    tiles.redraw();
+                }
             }
         }
 
-        pub fn get_tile(&mut self, coords: &(u32, u32, u32)) -> Tile {
+        /// The raster layers with their ids, bottom to top.
+        pub fn layers(&self) -> impl Iterator<Item = (usize, &RasterLayer)> {
+            self.layers.iter().map(|tiles| (tiles.id, &tiles.layer))
+        }
+
+        /// Ids of the layers that are drawn, bottom to top.
+        pub fn visible_layers(&self) -> Vec<usize> {
+            self.layers
+                .iter()
+                .filter(|tiles| tiles.layer.visible && tiles.layer.opacity > 0.0)
+                .map(|tiles| tiles.id)
+                .collect()
+        }
+
+        /// Credits for the visible layers, bottom to top, for printing under the map.
+        pub fn attribution(&self) -> String {
+            let mut credits: Vec<&str> = Vec::new();
+            for id in self.visible_layers() {
+                let credit = match self.layer(id) {
+                    Some(layer) => layer.source.attribution.trim(),
+                    None => continue,
+                };
+                if !credit.is_empty() && !credits.contains(&credit) {
+                    credits.push(credit);
+                }
+            }
+            credits.join(" ")
+        }
+
+        pub fn layer(&self, id: usize) -> Option<&RasterLayer> {
+            self.layer_tiles(id).map(|tiles| &tiles.layer)
+        }
+
+        /// Where a layer's tiles come from, shared with the loads that fetch them.
+        pub fn layer_source(&self, id: usize) -> Option<Arc<TileSource>> {
+            self.layer_tiles(id).map(|tiles| tiles.source.clone())
+        }
+
+        /// The area a layer has tiles for, known only for archives that say.
+        pub fn layer_bounds(&self, id: usize) -> Option<Bounds> {
+            self.layer_tiles(id)?.archive.as_ref()?.metadata().bounds
+        }
+
+        fn layer_tiles(&self, id: usize) -> Option<&LayerTiles> {
+            self.layers.iter().find(|tiles| tiles.id == id)
+        }
+
+        fn layer_tiles_mut(&mut self, id: usize) -> Option<&mut LayerTiles> {
+            self.layers.iter_mut().find(|tiles| tiles.id == id)
+        }
+
+        /// Puts `layer` on top of the stack, giving back its id.
+        pub fn add_layer(&mut self, layer: RasterLayer) -> usize {
+            let id = self.next_layer_id;
+            self.next_layer_id += 1;
+            self.layers.push(LayerTiles::new(id, layer, self.scale));
+            id
+        }
+
+        /// Replaces the whole stack. Layers whose source is already in the stack keep the
+        /// tiles they have.
+        pub fn set_layers(&mut self, layers: Vec<RasterLayer>) {
+            let mut old = std::mem::take(&mut self.layers);
+            for layer in layers {
+                match old.iter().position(|tiles| tiles.layer.source == layer.source) {
+                    Some(idx) => {
+                        let mut tiles = old.remove(idx);
+                        tiles.layer = layer;
+                        self.layers.push(tiles);
+                    }
+                    None => {
+                        self.add_layer(layer);
+                    }
+                }
+            }
+        }
+
+        /// Tiles are faded again from what's already decoded the next time they're drawn.
+        pub fn set_opacity(&mut self, id: usize, opacity: f32) {
+            if let Some(tiles) = self.layer_tiles_mut(id) {
+                tiles.layer.opacity = opacity.clamp(0.0, 1.0);
+            }
+        }
+
+        pub fn set_visible(&mut self, id: usize, visible: bool) {
+            if let Some(tiles) = self.layer_tiles_mut(id) {
+                tiles.layer.visible = visible;
+            }
+        }
+
+        pub fn set_locked(&mut self, id: usize, locked: bool) {
+            if let Some(tiles) = self.layer_tiles_mut(id) {
+                tiles.layer.locked = locked;
+            }
+        }
+
+        /// Renames an unlocked layer. Only the name changes, tiles already fetched stay.
+        pub fn rename_layer(&mut self, id: usize, name: String) {
+            if let Some(tiles) = self.layer_tiles_mut(id) {
+                if !tiles.layer.locked {
+                    tiles.layer.source.name = name;
+                }
+            }
+        }
+
+        /// Drops an unlocked layer along with its tiles.
+        pub fn remove_layer(&mut self, id: usize) {
+            self.layers
+                .retain(|tiles| tiles.id != id || tiles.layer.locked);
+        }
+
+        /// Moves a layer to `position` in the stack, counted from the bottom.
+        pub fn move_layer(&mut self, id: usize, position: usize) {
+            if let Some(idx) = self.layers.iter().position(|tiles| tiles.id == id) {
+                let tiles = self.layers.remove(idx);
+                let position = position.min(self.layers.len());
+                self.layers.insert(position, tiles);
+            }
+        }
+
+        pub fn get_tile(&mut self, layer: usize, coords: &(u32, u32, u32)) -> Tile {
             //x, y, z format
-            match self.tile_dict.get(coords) {
+            let tile_dict = match self.layer_tiles_mut(layer) {
+                Some(tiles) => &mut tiles.tile_dict,
+                None => return Tile::new(layer, coords),
+            };
+            match tile_dict.get_mut(coords) {
                 Some(tile) => {
-                    match tile.state {
-                        TileState::Loaded => return tile.clone(),
-                        TileState::Loading => return tile.clone(),
-                        TileState::NotLoaded => return tile.clone(),
-                    }
+                    tile.retry_when_due();
+                    return tile.clone();
                 }
                 None => {
-                    let new_tile = Tile::new(coords);
-                    self.tile_dict.insert(*coords, new_tile.clone());
+                    let new_tile = Tile::new(layer, coords);
+                    tile_dict.insert(*coords, new_tile.clone());
                     return new_tile;
                 }
             }
         }
 
-        pub fn queue_tile_load(&mut self, coords: (u32, u32, u32)) {
-            self.load_queue.push(coords);
+        //how many pixels across vector tiles are drawn for the screen
+        fn drawn_size(&self) -> u32 {
+            TILE_SIZE as u32 * self.scale
+        }
+
+        /// A handle to draw a loaded tile with, faded to its layer's opacity. Changing the
+        /// opacity fades the decoded tile again rather than decoding it again. Vector
+        /// tiles that haven't been drawn yet are queued for generate_async_load.
+        pub fn tile_handle(&mut self, layer: usize, coords: &(u32, u32, u32)) -> Option<image::Handle> {
+            let rendering = self.vector_rendering.clone();
+            let size = self.drawn_size();
+            let tiles = self.layer_tiles_mut(layer)?;
+            let opacity = tiles.layer.opacity;
+            let kind = tiles.source.kind;
+            let bytes = match tiles.tile_dict.get(coords) {
+                Some(Tile {
+                    image,
+                    state: TileState::Loaded,
+                    ..
+                }) if !image.is_empty() => image,
+                _ => return None,
+            };
+            //vector tiles are drawn by generate_async_load, until then whatever was drawn
+            //last stays up
+            if kind == TileKind::Vector && !tiles.decoded.contains_key(coords) {
+                if tiles.drawing.insert(*coords) {
+                    tiles.draw_queue.push(*coords);
+                }
+                return tiles.handles.get(coords).map(|(_, handle)| handle.clone());
+            }
+            match tiles.handles.get(coords) {
+                Some((faded_to, handle)) if *faded_to == opacity => return Some(handle.clone()),
+                _ => {}
+            }
+            let handle = match tiles.decoded.get(coords) {
+                Some(Some(tile)) => faded_handle(tile, opacity),
+                Some(None) => return None,
+                //the renderer decodes raster tiles drawn as they are itself
+                None if opacity >= 1.0 => image::Handle::from_memory(bytes.clone()),
+                None => {
+                    let tile = decode_tile(bytes, kind, coords.2, size, &rendering);
+                    let handle = tile.as_ref().map(|tile| faded_handle(tile, opacity));
+                    tiles.decoded.insert(*coords, tile);
+                    handle?
+                }
+            };
+            tiles.handles.insert(*coords, (opacity, handle.clone()));
+            Some(handle)
+        }
+
+        /// The loaded tiles at `coords` of the visible layers, to draw an export from once
+        /// they're all in.
+        pub fn snapshot(&self, coords: &[(u32, u32, u32)]) -> TileSnapshot {
+            let layers = self
+                .visible_layers()
+                .into_iter()
+                .filter_map(|id| {
+                    let tiles = self.layer_tiles(id)?;
+                    let loaded = coords.iter().filter_map(|coord| {
+                        let bytes = self.loaded_image(id, coord)?;
+                        Some((*coord, bytes.to_vec()))
+                    });
+                    Some(SnapshotLayer {
+                        id,
+                        opacity: tiles.layer.opacity,
+                        kind: tiles.source.kind,
+                        tiles: loaded.collect(),
+                    })
+                })
+                .collect();
+            TileSnapshot {
+                layers,
+                vector_rendering: self.vector_rendering.clone(),
+                attribution: self.attribution(),
+            }
+        }
+
+        /// Whether a tile couldn't be loaded because it isn't cached and the manager is
+        /// offline.
+        pub fn is_unavailable(&self, layer: usize, coords: &(u32, u32, u32)) -> bool {
+            match self.layer_tiles(layer).and_then(|tiles| tiles.tile_dict.get(coords)) {
+                Some(tile) => matches!(tile.state, TileState::Unavailable),
+                None => false,
+            }
+        }
+
+        /// The image bytes of a tile that has finished loading.
+        pub fn loaded_image(&self, layer: usize, coords: &(u32, u32, u32)) -> Option<&[u8]> {
+            match self.layer_tiles(layer)?.tile_dict.get(coords) {
+                Some(Tile {
+                    image,
+                    state: TileState::Loaded,
+                    ..
+                }) => Some(image),
+                _ => None,
+            }
+        }
+
+        /// Which of `coords` still have to be fetched for the visible layers, as layer ids
+        /// and coordinates. They're marked as loading, the caller is expected to load them
+        /// and hand them to ingest_loaded_tiles. Tiles something else is already loading
+        /// aren't, wait for them with tiles_loading.
+        pub fn missing_tiles(&mut self, coords: &[(u32, u32, u32)]) -> Vec<(usize, (u32, u32, u32))> {
+            let mut missing = Vec::new();
+            for layer in self.visible_layers() {
+                let tile_dict = match self.layer_tiles_mut(layer) {
+                    Some(tiles) => &mut tiles.tile_dict,
+                    None => continue,
+                };
+                for coord in coords {
+                    let tile = tile_dict
+                        .entry(*coord)
+                        .or_insert_with(|| Tile::new(layer, coord));
+                    tile.retry_when_due();
+                    if let TileState::NotLoaded = tile.state {
+                        tile.state = TileState::Loading;
+                        missing.push((layer, *coord));
+                    }
+                }
+            }
+            missing
+        }
+
+        /// Whether any of `coords` are still on their way for the visible layers, fetched
+        /// by missing_tiles or the map's own loads.
+        pub fn tiles_loading(&self, coords: &[(u32, u32, u32)]) -> bool {
+            self.visible_layers()
+                .into_iter()
+                .filter_map(|layer| self.layer_tiles(layer))
+                .any(|tiles| {
+                    coords.iter().any(|coord| match tiles.tile_dict.get(coord) {
+                        Some(tile) => matches!(tile.state, TileState::Loading),
+                        None => false,
+                    })
+                })
+        }
+
+        pub fn queue_tile_load(&mut self, layer: usize, coords: (u32, u32, u32)) {
+            if let Some(tiles) = self.layer_tiles_mut(layer) {
+                tiles.load_queue.push(coords);
+            }
+        }
 
+        //one tile from the server, error pages count as errors. with the policy of a
+        //cached copy the server is asked only for a tile that's changed since. downloads
+        //keep to the source's rate limit
+        async fn fetch_tile(
+            http: &HttpClient,
+            source: &TileSource,
+            coords: &(u32, u32, u32),
+            cached: Option<&CachePolicy>,
+            download: bool,
+        ) -> Result<Fetched, HttpError> {
+            let url = source.tile_url(coords);
+            let mut request = http.get_tile(source, &url, download).await?;
+            if let Some(cached) = cached {
+                if let Some(etag) = &cached.etag {
+                    request = request.header(IF_NONE_MATCH, etag.as_str());
+                }
+                if let Some(last_modified) = &cached.last_modified {
+                    request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+                }
+            }
+            let resp = request.send().await?;
+            let policy = CachePolicy::from_headers(resp.headers(), SystemTime::now());
+            if resp.status() == StatusCode::NOT_MODIFIED {
+                //304s needn't repeat the validators
+                let policy = policy.map(|mut policy| {
+                    let cached = cached.cloned().unwrap_or_default();
+                    policy.etag = policy.etag.or(cached.etag);
+                    policy.last_modified = policy.last_modified.or(cached.last_modified);
+                    policy
+                });
+                return Ok(Fetched::NotModified(policy));
+            }
+            let bytes = resp.error_for_status()?.bytes().await?.to_vec();
+            Ok(Fetched::Tile(bytes, policy))
         }
 
         async fn load_tile(
             mut request_tile: Tile,
-            client: Arc<reqwest::Client>,
-        ) -> Result<Tile, reqwest::Error> {
-            let (x, y, z) = request_tile.target_url;
-            let request = client
-                .get(format!(
-                    "https://stamen-tiles.a.ssl.fastly.net/terrain/{}/{}/{}.png",
-                    z, x, y
-                ))
-                .send()
-                .await;
-
-            if let Ok(resp) = request {
-                let bytes_req = resp.bytes().await;
-                if let Ok(bytes) = bytes_req {
-                    request_tile.image = bytes.to_vec();
+            http: Arc<HttpClient>,
+            source: Arc<TileSource>,
+            archive: Option<Arc<TileArchive>>,
+            cache: Option<Arc<TileCache>>,
+            offline: bool,
+        ) -> Tile {
+            let coords = request_tile.target_url;
+            //wmts matrix sets may not go down to every zoom
+            if !source.scheme.has_zoom(coords.2) {
+                request_tile.state = TileState::Loaded;
+                return request_tile;
+            }
+            //archives and folders are local, they need neither the cache nor the network.
+            //tiles they don't have are left empty
+            if source.is_local() {
+                let bytes = match (archive, source.tile_path(&coords)) {
+                    (Some(archive), _) => archive.tile(&coords).map_err(|e| e.to_string()),
+                    (None, Some(path)) => match std::fs::read(&path) {
+                        Ok(bytes) => Ok(Some(bytes)),
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                        Err(e) => Err(e.to_string()),
+                    },
+                    //the archive didn't open
+                    (None, None) => Ok(None),
+                };
+                match bytes {
+                    Ok(bytes) => request_tile.image = bytes.unwrap_or_default(),
+                    Err(e) => {
+                        log::warn!("couldn't read tile {:?} from {}: {}", coords, source.url, e)
+                    }
+                }
+                request_tile.state = TileState::Loaded;
+                return request_tile;
+            }
+            let cached = cache.as_ref().and_then(|cache| cache.read(&source, &coords));
+            //stale tiles are better than none while offline
+            match cached {
+                Some(cached) if offline || cached.policy.is_fresh(SystemTime::now()) => {
+                    request_tile.image = cached.bytes;
                     request_tile.state = TileState::Loaded;
+                    return request_tile;
                 }
+                None if offline => {
+                    request_tile.state = TileState::Unavailable;
+                    return request_tile;
+                }
+                _ => {}
+            }
+
+            let policy = cached.as_ref().map(|cached| &cached.policy);
+            let fetched = TileManager::fetch_tile(&http, &source, &coords, policy, false).await;
+            let cache_result = match (fetched, cached) {
+                (Ok(Fetched::Tile(bytes, policy)), _) => {
+                    let written = match (&cache, &policy) {
+                        (Some(cache), Some(policy)) => {
+                            cache.write(&source, &coords, &bytes, policy)
+                        }
+                        _ => Ok(()),
+                    };
+                    request_tile.image = bytes;
+                    request_tile.state = TileState::Loaded;
+                    written
+                }
+                //unchanged, the cached copy is good for a while longer
+                (Ok(Fetched::NotModified(policy)), Some(CachedTile { bytes, .. })) => {
+                    let written = match (&cache, &policy) {
+                        (Some(cache), Some(policy)) => {
+                            cache.write_policy(&source, &coords, policy)
+                        }
+                        _ => Ok(()),
+                    };
+                    request_tile.image = bytes;
+                    request_tile.state = TileState::Loaded;
+                    written
+                }
+                (Ok(Fetched::NotModified(_)), None) => {
+                    log::warn!("{} says uncached tile {:?} is unchanged", source.name, coords);
+                    request_tile.state = TileState::Failed(Instant::now() + RETRY_FAILED);
+                    Ok(())
+                }
+                (Err(e), Some(CachedTile { bytes, .. })) => {
+                    log::warn!("couldn't revalidate tile {:?}, showing it stale: {}", coords, e);
+                    request_tile.image = bytes;
+                    request_tile.state = TileState::Loaded;
+                    Ok(())
+                }
+                //the server hasn't got the tile, there's nothing there to show
+                (Err(HttpError::Http(e)), None) if e.status() == Some(StatusCode::NOT_FOUND) => {
+                    request_tile.state = TileState::Loaded;
+                    Ok(())
+                }
+                (Err(e), None) => {
+                    log::warn!("couldn't fetch tile {:?} from {}: {}", coords, source.name, e);
+                    request_tile.state = TileState::Failed(Instant::now() + RETRY_FAILED);
+                    Ok(())
+                }
+            };
+            if let Err(e) = cache_result {
+                log::warn!("couldn't cache tile {:?}: {}", coords, e);
             }
-            Ok(request_tile)
+            request_tile
         }
         pub fn ingest_loaded_tiles(&mut self, mut new_tiles: Vec<Tile>) {
+            let rendering = self.vector_rendering.clone();
+            let size = self.drawn_size();
             for mut tile in new_tiles {
-                tile.state = TileState::Loaded;
-                self.tile_dict.insert(tile.target_url, tile);
+                //the layer may have been removed while its tiles were loading
+                if let Some(tiles) = self.layer_tiles_mut(tile.layer) {
+                    //unavailable and failed tiles have nothing to show yet
+                    if !matches!(tile.state, TileState::Loaded) {
+                        tiles.tile_dict.insert(tile.target_url, tile);
+                        continue;
+                    }
+                    let coords = tile.target_url;
+                    tiles.drawing.remove(&coords);
+                    let current = |drawn: &DrawnTile| {
+                        Arc::ptr_eq(&drawn.rendering, &rendering) && drawn.size == size
+                    };
+                    match tile.drawn.take() {
+                        Some(drawn) if current(&drawn) => {
+                            tiles.decoded.insert(coords, drawn.image);
+                            tiles.handles.remove(&coords);
+                        }
+                        //drawn in a style that's since been replaced, it's drawn again the
+                        //next time it's asked for
+                        Some(_) => {}
+                        None => {
+                            tiles.decoded.remove(&coords);
+                            tiles.handles.remove(&coords);
+                        }
+                    }
+                    tiles.tile_dict.insert(coords, tile);
+                }
             }
         }
 
         pub fn generate_async_load(&mut self) ->impl futures::Future<Output=Option<Vec<Tile>>> {
 
-            let load_queue =  self.load_queue.clone();
-            self.load_queue.clear();
-            TileManager::load_tiles(self.client.clone(),load_queue)
+            let mut requests = Vec::new();
+            let mut redraws = Vec::new();
+            let mut vector_layers = Vec::new();
+            for tiles in self.layers.iter_mut() {
+                for coords in tiles.load_queue.drain(..) {
+                    requests.push((
+                        Tile::new(tiles.id, &coords),
+                        tiles.source.clone(),
+                        tiles.archive.clone(),
+                    ));
+                }
+                for coords in tiles.draw_queue.drain(..) {
+                    redraws.extend(tiles.tile_dict.get(&coords).cloned());
+                }
+                if tiles.source.kind == TileKind::Vector {
+                    vector_layers.push(tiles.id);
+                }
+            }
+            let http = self.http.clone();
+            let load = TileManager::load_tiles(http, self.cache.clone(), self.offline, requests);
+            let (rendering, size) = (self.vector_rendering.clone(), self.drawn_size());
+            async move {
+                let mut tiles = load.await.unwrap_or_default();
+                tiles.extend(redraws);
+                Some(TileManager::draw_vector_tiles(tiles, vector_layers, rendering, size).await)
+            }
+        }
+
+        //draws the vector tiles among `tiles` on blocking threads, there's too much to
+        //them to do on the ui thread
+        async fn draw_vector_tiles(
+            tiles: Vec<Tile>,
+            vector_layers: Vec<usize>,
+            rendering: Arc<VectorRendering>,
+            size: u32,
+        ) -> Vec<Tile> {
+            let drawn = tiles.into_iter().map(|mut tile| {
+                let rendering = rendering.clone();
+                let is_vector = vector_layers.contains(&tile.layer);
+                async move {
+                    if !is_vector || tile.image.is_empty() {
+                        return tile;
+                    }
+                    let (bytes, zoom) = (tile.image.clone(), tile.target_url.2);
+                    let drawing = rendering.clone();
+                    let draw = tokio::task::spawn_blocking(move || {
+                        decode_tile(&bytes, TileKind::Vector, zoom, size, &drawing)
+                    });
+                    //one that fails is left blank rather than drawn again and again
+                    let image = match draw.await {
+                        Ok(image) => image,
+                        Err(e) => {
+                            log::error!("drawing tile {:?} failed: {}", tile.target_url, e);
+                            None
+                        }
+                    };
+                    tile.drawn = Some(DrawnTile { image, rendering, size });
+                    tile
+                }
+            });
+            join_all(drawn).await
+        }
+
+        /// Fetches tiles of the given layers, e.g. the ones `missing_tiles` gave back.
+        pub fn fetch_tiles(
+            &self,
+            coords: Vec<(usize, (u32, u32, u32))>,
+        ) -> impl futures::Future<Output = Option<Vec<Tile>>> {
+            let requests = coords
+                .into_iter()
+                .filter_map(|(layer, coords)| {
+                    let tiles = self.layer_tiles(layer)?;
+                    Some((Tile::new(layer, &coords), tiles.source.clone(), tiles.archive.clone()))
+                })
+                .collect();
+            let http = self.http.clone();
+            TileManager::load_tiles(http, self.cache.clone(), self.offline, requests)
         }
 
         pub async fn load_tiles(
-            client: Arc<reqwest::Client>,
-            load_coords: Vec<(u32,u32,u32)>,
+            http: Arc<HttpClient>,
+            cache: Option<Arc<TileCache>>,
+            offline: bool,
+            requests: Vec<(Tile, Arc<TileSource>, Option<Arc<TileArchive>>)>,
         ) -> Option<Vec<Tile>> {
             
-            log::info!("me loading {} tiles", load_coords.len());
-            let mut return_tiles: Vec<Tile> = load_coords.iter().map(|coord| Tile::new(coord)).collect::<Vec<Tile>>();
+            log::info!("me loading {} tiles", requests.len());
 
-            let tile_futures = return_tiles
-                .iter_mut()
-                .map(|tile| TileManager::load_tile(tile.clone(), client.clone()));
+            let tile_futures = requests
+                .into_iter()
+                .map(|(tile, source, archive)| {
+                    let (http, cache) = (http.clone(), cache.clone());
+                    TileManager::load_tile(tile, http, source, archive, cache, offline)
+                });
 
-            let tile_results = join_all(tile_futures).await;
+            //a big export needs hundreds of tiles, don't ask for them all at once. they come
+            //back in the order they were asked for, failed ones too
+            let return_tiles: Vec<Tile> =
+                stream::iter(tile_futures).buffered(MAX_CONCURRENT_LOADS).collect().await;
+            Some(return_tiles)
+        }
 
-            for tile_result in tile_results {
-                if let Ok(tile) = tile_result {
-                    return_tiles.push(tile);
+        /// Fetches tiles straight into the disk cache, without keeping them around in
+        /// memory. It's how areas are downloaded for offline use.
+        pub fn download_tiles(
+            &self,
+            requests: Vec<(Arc<TileSource>, (u32, u32, u32))>,
+        ) -> impl futures::Future<Output = BatchResult> {
+            let http = self.http.clone();
+            let cache = self.cache.clone();
+            let offline = self.offline;
+            async move {
+                let mut result = BatchResult::default();
+                let cache = match cache {
+                    Some(cache) if !offline => cache,
+                    Some(_) => {
+                        log::warn!("not downloading tiles while offline");
+                        result.failed = requests.len() as u64;
+                        return result;
+                    }
+                    None => {
+                        log::error!("there's no tile cache to download to");
+                        result.failed = requests.len() as u64;
+                        return result;
+                    }
+                };
+                //the client spaces them out by the rate limit of each source
+                let fetches = requests.iter().map(|(source, coords)| {
+                    TileManager::fetch_tile(&http, source, coords, None, true)
+                });
+                let fetched = join_all(fetches).await;
+                for ((source, coords), fetched) in requests.iter().zip(fetched) {
+                    let written = match fetched {
+                        //kept even if the server would rather it wasn't, that's what an
+                        //offline download is for
+                        Ok(Fetched::Tile(bytes, policy)) => cache
+                            .write(source, coords, &bytes, &policy.unwrap_or_default())
+                            .map(|_| bytes.len() as u64)
+                            .map_err(|e| e.to_string()),
+                        Ok(Fetched::NotModified(_)) => Err(String::from("unexpected 304")),
+                        Err(e) => Err(e.to_string()),
+                    };
+                    match written {
+                        Ok(bytes) => {
+                            result.fetched += 1;
+                            result.bytes += bytes;
+                        }
+                        Err(e) => {
+                            log::warn!(
+                                "download of tile {:?} from {} failed: {}",
+                                coords,
+                                source.name,
+                                e
+                            );
+                            result.failed += 1;
+                        }
+                    }
                 }
+                result
             }
-            Some(return_tiles)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::http::RequestSettings;
+        use crate::mock_tile_server::{tile_color, MockTileServer};
+
+        //a manager that only knows the server, caching in `dir` and with no rate limit.
+        //nothing in the settings directory is read
+        fn test_manager(dir: &Path) -> TileManager {
+            let settings = HttpSettings {
+                default: RequestSettings {
+                    max_requests_per_second: 0,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let mut manager =
+                TileManager::with_settings(settings, Some(TileCache::new(dir.to_path_buf())));
+            manager.remove_layer(0);
+            manager
+        }
+
+        fn add_source(manager: &mut TileManager, url: String) -> usize {
+            manager.add_layer(RasterLayer::new(TileSource::from_url(&url)))
+        }
+
+        //queues tiles of a layer and loads them the way the map does
+        async fn load(manager: &mut TileManager, layer: usize, coords: &[(u32, u32, u32)]) {
+            for coords in coords {
+                manager.queue_tile_load(layer, *coords);
+            }
+            let tiles = manager.generate_async_load().await.unwrap();
+            manager.ingest_loaded_tiles(tiles);
+        }
+
+        //a fresh directory for a cache, emptied of anything an earlier run left and removed
+        //once the test is done with it, passed or not
+        struct CacheDir(PathBuf);
+
+        impl Drop for CacheDir {
+            fn drop(&mut self) {
+                let _ = std::fs::remove_dir_all(&self.0);
+            }
+        }
+
+        impl std::ops::Deref for CacheDir {
+            type Target = Path;
+
+            fn deref(&self) -> &Path {
+                &self.0
+            }
+        }
+
+        fn cache_dir(name: &str) -> CacheDir {
+            let dir = std::env::temp_dir()
+                .join(format!("map_maker_test_{}_{}", std::process::id(), name));
+            let _ = std::fs::remove_dir_all(&dir);
+            CacheDir(dir)
+        }
+
+        #[tokio::test]
+        async fn queued_tiles_are_fetched_and_decoded() {
+            let server = MockTileServer::start().await;
+            let dir = cache_dir("fetched");
+            let mut manager = test_manager(&dir);
+            let layer = add_source(&mut manager, server.url("tiles"));
+            let coords = [(1, 0, 1), (1, 1, 1), (5, 3, 4)];
+            load(&mut manager, layer, &coords).await;
+
+            let snapshot = manager.snapshot(&coords);
+            for coords in coords.iter() {
+                let image = snapshot.tile_image(layer, coords).expect("tile should be loaded");
+                assert_eq!(image.dimensions(), (256, 256));
+                assert_eq!(*image.get_pixel(128, 128), tile_color(coords));
+                assert!(manager.tile_handle(layer, coords).is_some());
+            }
+            let requests = server.requests();
+            assert_eq!(requests.len(), 3);
+            assert!(requests.iter().any(|request| request.path == "/tiles/4/5/3.png"));
+            assert!(requests
+                .iter()
+                .all(|request| request.header("user-agent") == Some(crate::http::USER_AGENT)));
+        }
+
+        #[tokio::test]
+        async fn missing_tiles_are_left_empty() {
+            let server = MockTileServer::start().await;
+            let dir = cache_dir("missing");
+            let mut manager = test_manager(&dir);
+            let layer = add_source(&mut manager, server.url("missing"));
+            let coords = (2, 1, 3);
+            load(&mut manager, layer, &[coords]).await;
+
+            assert_eq!(manager.loaded_image(layer, &coords), Some(&[][..]));
+            assert!(manager.snapshot(&[coords]).tile_image(layer, &coords).is_none());
+            assert!(manager.tile_handle(layer, &coords).is_none());
+            //the server said there's no such tile, it isn't asked again
+            assert!(matches!(manager.get_tile(layer, &coords).state, TileState::Loaded));
+            assert!(manager.missing_tiles(&[coords]).is_empty());
+            assert_eq!(server.requests().len(), 1);
+        }
+
+        #[tokio::test]
+        async fn failed_tiles_are_fetched_again_later() {
+            let server = MockTileServer::start().await;
+            let dir = cache_dir("failed");
+            let mut manager = test_manager(&dir);
+            let coords = (2, 1, 3);
+            for route in ["error", "truncated"].iter() {
+                let layer = add_source(&mut manager, server.url(route));
+                load(&mut manager, layer, &[coords]).await;
+                assert!(manager.loaded_image(layer, &coords).is_none(), "{}", route);
+                assert!(manager.tile_handle(layer, &coords).is_none(), "{}", route);
+                let state = manager.get_tile(layer, &coords).state;
+                assert!(matches!(state, TileState::Failed(_)), "{}: {:?}", route, state);
+                assert!(manager.missing_tiles(&[coords]).is_empty(), "{}", route);
+
+                //once it's been long enough the map and exports ask for it again
+                let tile = manager.layer_tiles_mut(layer).unwrap().tile_dict.get_mut(&coords);
+                tile.unwrap().state = TileState::Failed(Instant::now());
+                let state = manager.get_tile(layer, &coords).state;
+                assert!(matches!(state, TileState::NotLoaded), "{}: {:?}", route, state);
+                load(&mut manager, layer, &[coords]).await;
+            }
+            assert_eq!(server.requests().len(), 4);
+        }
+
+        #[tokio::test]
+        async fn tiles_load_together_up_to_the_limit() {
+            let server = MockTileServer::start().await;
+            let dir = cache_dir("together");
+            let mut manager = test_manager(&dir);
+            let layer = add_source(&mut manager, server.url("slow"));
+            let coords: Vec<_> = (0..MAX_CONCURRENT_LOADS as u32 + 4).map(|x| (x, 0, 4)).collect();
+            load(&mut manager, layer, &coords).await;
+
+            //how many overlap depends on how busy the machine is, never more than the limit
+            let most = server.most_at_once();
+            assert!(most <= MAX_CONCURRENT_LOADS && most > 1, "{} at once", most);
+            let snapshot = manager.snapshot(&coords);
+            for coords in coords.iter() {
+                assert!(snapshot.tile_image(layer, coords).is_some());
+            }
+        }
+
+        #[tokio::test]
+        async fn load_tiles_returns_a_tile_for_every_request() {
+            let server = MockTileServer::start().await;
+            let dir = cache_dir("every");
+            let source = Arc::new(TileSource::from_url(&server.url("tiles")));
+            let missing = Arc::new(TileSource::from_url(&server.url("missing")));
+            let error = Arc::new(TileSource::from_url(&server.url("error")));
+            let requests = vec![
+                (Tile::new(1, &(0, 0, 0)), source, None),
+                (Tile::new(2, &(0, 0, 0)), missing, None),
+                (Tile::new(3, &(0, 0, 0)), error, None),
+            ];
+            let http = test_manager(&dir).http;
+            let tiles = TileManager::load_tiles(http, None, false, requests)
+                .await
+                .unwrap();
+
+            //in the order they were asked for
+            let layers: Vec<_> = tiles.iter().map(|tile| tile.layer).collect();
+            assert_eq!(layers, [1, 2, 3]);
+            assert!(matches!(tiles[0].state, TileState::Loaded));
+            assert!(!tiles[0].image.is_empty());
+            assert!(matches!(tiles[1].state, TileState::Loaded));
+            assert!(tiles[1].image.is_empty());
+            assert!(matches!(tiles[2].state, TileState::Failed(_)));
+            assert!(tiles[2].image.is_empty());
+        }
+
+        #[tokio::test]
+        async fn cached_tiles_are_not_fetched_again() {
+            let server = MockTileServer::start().await;
+            let dir = cache_dir("cached");
+            let mut manager = test_manager(&dir);
+            let layer = add_source(&mut manager, server.url("tiles"));
+            load(&mut manager, layer, &[(3, 2, 2)]).await;
+
+            //a new manager with the same cache, as after a restart
+            let mut restarted = test_manager(&dir);
+            let layer = add_source(&mut restarted, server.url("tiles"));
+            load(&mut restarted, layer, &[(3, 2, 2)]).await;
+
+            let image = restarted.snapshot(&[(3, 2, 2)]).tile_image(layer, &(3, 2, 2)).unwrap();
+            assert_eq!(*image.get_pixel(0, 0), tile_color(&(3, 2, 2)));
+            assert_eq!(server.requests().len(), 1);
+        }
+
+        #[tokio::test]
+        async fn offline_tiles_are_unavailable_without_a_request() {
+            let server = MockTileServer::start().await;
+            let dir = cache_dir("offline");
+            let mut manager = test_manager(&dir);
+            manager.set_offline(true);
+            let layer = add_source(&mut manager, server.url("tiles"));
+            load(&mut manager, layer, &[(0, 0, 1)]).await;
+
+            assert!(manager.is_unavailable(layer, &(0, 0, 1)));
+            assert!(server.requests().is_empty());
+            //they're asked for again once back online
+            manager.set_offline(false);
+            assert!(!manager.is_unavailable(layer, &(0, 0, 1)));
+        }
+
+        #[tokio::test]
+        async fn tiles_of_removed_layers_are_dropped() {
+            let server = MockTileServer::start().await;
+            let dir = cache_dir("removed");
+            let mut manager = test_manager(&dir);
+            let layer = add_source(&mut manager, server.url("slow"));
+            manager.queue_tile_load(layer, (0, 0, 0));
+            let loading = manager.generate_async_load();
+            manager.remove_layer(layer);
+            manager.ingest_loaded_tiles(loading.await.unwrap());
+
+            assert!(manager.loaded_image(layer, &(0, 0, 0)).is_none());
+            assert_eq!(server.requests().len(), 1);
         }
     }
 }
@@ -0,0 +1,769 @@
+//the part of the mapbox gl expression language styles use for filters and paint values,
+//https://docs.mapbox.com/mapbox-gl-js/style-spec/expressions/. the older filter syntax and
+//`stops` functions are read into the same expressions
+use super::mvt::{self, Feature, Geometry};
+use super::style::StyleError;
+use crate::features::color_from_hex;
+use iced::Color;
+use serde_json::Value as Json;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Color(Color),
+    Array(Vec<ExprValue>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Literal(ExprValue),
+    Get(String),
+    Has(String),
+    Zoom,
+    GeometryType,
+    Id,
+    Not(Box<Expression>),
+    All(Vec<Expression>),
+    Any(Vec<Expression>),
+    Compare(Comparison, Box<Expression>, Box<Expression>),
+    //needle, then an array or string to look in
+    In(Box<Expression>, Box<Expression>),
+    Match {
+        input: Box<Expression>,
+        arms: Vec<(Vec<ExprValue>, Expression)>,
+        fallback: Box<Expression>,
+    },
+    Case {
+        arms: Vec<(Expression, Expression)>,
+        fallback: Box<Expression>,
+    },
+    Coalesce(Vec<Expression>),
+    Concat(Vec<Expression>),
+    //base 1 is linear
+    Interpolate {
+        base: f64,
+        input: Box<Expression>,
+        stops: Vec<(f64, Expression)>,
+    },
+    Step {
+        input: Box<Expression>,
+        first: Box<Expression>,
+        stops: Vec<(f64, Expression)>,
+    },
+}
+
+/// What an expression is evaluated against.
+#[derive(Debug, Clone, Copy)]
+pub struct Context<'a> {
+    pub zoom: f32,
+    pub feature: Option<&'a Feature>,
+}
+
+impl From<&mvt::Value> for ExprValue {
+    fn from(value: &mvt::Value) -> Self {
+        match value {
+            mvt::Value::String(text) => ExprValue::String(text.clone()),
+            mvt::Value::Number(number) => ExprValue::Number(*number),
+            mvt::Value::Bool(value) => ExprValue::Bool(*value),
+        }
+    }
+}
+
+impl ExprValue {
+    fn from_json(json: &Json) -> Self {
+        match json {
+            Json::Null | Json::Object(_) => ExprValue::Null,
+            Json::Bool(value) => ExprValue::Bool(*value),
+            Json::Number(number) => ExprValue::Number(number.as_f64().unwrap_or_default()),
+            Json::String(text) => ExprValue::String(text.clone()),
+            Json::Array(values) => ExprValue::Array(values.iter().map(Self::from_json).collect()),
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            ExprValue::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    //only true is true, filters that come out as anything else leave the feature out
+    pub fn is_true(&self) -> bool {
+        *self == ExprValue::Bool(true)
+    }
+
+    pub fn to_color(&self) -> Option<Color> {
+        match self {
+            ExprValue::Color(color) => Some(*color),
+            ExprValue::String(text) => parse_color(text),
+            _ => None,
+        }
+    }
+
+    /// The value as text, e.g. for a label. Whole numbers don't get a decimal point.
+    pub fn to_text(&self) -> String {
+        match self {
+            ExprValue::Null => String::new(),
+            ExprValue::Bool(value) => value.to_string(),
+            ExprValue::Number(number) if number.fract() == 0.0 => format!("{:.0}", number),
+            ExprValue::Number(number) => number.to_string(),
+            ExprValue::String(text) => text.clone(),
+            ExprValue::Color(color) => format!(
+                "rgba({},{},{},{})",
+                (color.r * 255.0).round(),
+                (color.g * 255.0).round(),
+                (color.b * 255.0).round(),
+                color.a
+            ),
+            ExprValue::Array(values) => values
+                .iter()
+                .map(ExprValue::to_text)
+                .collect::<Vec<String>>()
+                .join(","),
+        }
+    }
+}
+
+fn invalid(message: String) -> StyleError {
+    StyleError::Invalid(message)
+}
+
+fn boxed(json: Option<&Json>, op: &str) -> Result<Box<Expression>, StyleError> {
+    let json = json.ok_or_else(|| invalid(format!("\"{}\" is missing an argument", op)))?;
+    Ok(Box::new(Expression::parse(json)?))
+}
+
+//the number/output pairs of interpolate and step
+fn stops(args: &[Json], op: &str) -> Result<Vec<(f64, Expression)>, StyleError> {
+    let mut stops = Vec::new();
+    for pair in args.chunks(2) {
+        match pair {
+            [input, output] => {
+                let input = input
+                    .as_f64()
+                    .ok_or_else(|| invalid(format!("\"{}\" stops need numbers", op)))?;
+                stops.push((input, Expression::parse(output)?));
+            }
+            _ => return Err(invalid(format!("\"{}\" has a stop without an output", op))),
+        }
+    }
+    Ok(stops)
+}
+
+fn comparison(op: &str) -> Option<Comparison> {
+    Some(match op {
+        "==" => Comparison::Equal,
+        "!=" => Comparison::NotEqual,
+        "<" => Comparison::Less,
+        "<=" => Comparison::LessOrEqual,
+        ">" => Comparison::Greater,
+        ">=" => Comparison::GreaterOrEqual,
+        _ => return None,
+    })
+}
+
+//the property a legacy filter names, with the two special ones
+fn legacy_key(key: &str) -> Expression {
+    match key {
+        "$type" => Expression::GeometryType,
+        "$id" => Expression::Id,
+        _ => Expression::Get(key.to_string()),
+    }
+}
+
+impl Expression {
+    /// Reads an expression, or a plain value.
+    pub fn parse(json: &Json) -> Result<Self, StyleError> {
+        let array = match json {
+            Json::Array(array) => array,
+            Json::Object(function) if function.contains_key("stops") => {
+                return Self::parse_function(json);
+            }
+            _ => return Ok(Expression::Literal(ExprValue::from_json(json))),
+        };
+        let op = match array.first().and_then(Json::as_str) {
+            Some(op) => op,
+            //plain arrays only come up as arguments, e.g. to "in"
+            None => return Ok(Expression::Literal(ExprValue::from_json(json))),
+        };
+        let args = &array[1..];
+        let text_arg = || {
+            args.first()
+                .and_then(Json::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| invalid(format!("\"{}\" needs a property name", op)))
+        };
+        let all_args = || args.iter().map(Self::parse).collect::<Result<Vec<_>, _>>();
+        let expression = match op {
+            "literal" => {
+                Expression::Literal(ExprValue::from_json(args.first().unwrap_or(&Json::Null)))
+            }
+            "get" => Expression::Get(text_arg()?),
+            "has" => Expression::Has(text_arg()?),
+            "zoom" => Expression::Zoom,
+            "geometry-type" => Expression::GeometryType,
+            "id" => Expression::Id,
+            "!" => Expression::Not(boxed(args.first(), op)?),
+            "all" => Expression::All(all_args()?),
+            "any" => Expression::Any(all_args()?),
+            "coalesce" => Expression::Coalesce(all_args()?),
+            "concat" => Expression::Concat(all_args()?),
+            "in" => Expression::In(boxed(args.first(), op)?, boxed(args.get(1), op)?),
+            "match" => {
+                let input = boxed(args.first(), op)?;
+                if args.len() < 2 || args.len() % 2 != 0 {
+                    return Err(invalid(String::from(
+                        "\"match\" needs label/output pairs and a fallback",
+                    )));
+                }
+                let mut arms = Vec::new();
+                for pair in args[1..args.len() - 1].chunks(2) {
+                    let labels = match ExprValue::from_json(&pair[0]) {
+                        ExprValue::Array(labels) => labels,
+                        label => vec![label],
+                    };
+                    arms.push((labels, Self::parse(&pair[1])?));
+                }
+                Expression::Match {
+                    input,
+                    arms,
+                    fallback: boxed(args.last(), op)?,
+                }
+            }
+            "case" => {
+                if args.len() % 2 != 1 {
+                    return Err(invalid(String::from(
+                        "\"case\" needs condition/output pairs and a fallback",
+                    )));
+                }
+                let mut arms = Vec::new();
+                for pair in args[..args.len() - 1].chunks(2) {
+                    arms.push((Self::parse(&pair[0])?, Self::parse(&pair[1])?));
+                }
+                Expression::Case {
+                    arms,
+                    fallback: boxed(args.last(), op)?,
+                }
+            }
+            "interpolate" => {
+                //cubic bezier easing is drawn as linear
+                let base = match args.first().and_then(Json::as_array).map(Vec::as_slice) {
+                    Some([kind, base]) if kind == "exponential" => base.as_f64().unwrap_or(1.0),
+                    Some(_) => 1.0,
+                    None => return Err(invalid(String::from("\"interpolate\" needs a type"))),
+                };
+                Expression::Interpolate {
+                    base,
+                    input: boxed(args.get(1), op)?,
+                    stops: stops(args.get(2..).unwrap_or_default(), op)?,
+                }
+            }
+            "step" => Expression::Step {
+                input: boxed(args.first(), op)?,
+                first: boxed(args.get(1), op)?,
+                stops: stops(args.get(2..).unwrap_or_default(), op)?,
+            },
+            _ => match comparison(op) {
+                Some(comparison) => Expression::Compare(
+                    comparison,
+                    boxed(args.first(), op)?,
+                    boxed(args.get(1), op)?,
+                ),
+                None => return Err(invalid(format!("unsupported expression \"{}\"", op))),
+            },
+        };
+        Ok(expression)
+    }
+
+    /// Reads a layer's filter, which can also be in the older syntax where properties are
+    /// named by plain strings, e.g. `["==", "class", "river"]`.
+    pub fn parse_filter(json: &Json) -> Result<Self, StyleError> {
+        let array = match json.as_array() {
+            Some(array) => array,
+            None => return Self::parse(json),
+        };
+        //an empty filter doesn't leave anything out
+        let (op, args) = match array.split_first() {
+            Some((op, args)) => (op.as_str().unwrap_or_default(), args),
+            None => return Ok(Expression::Literal(ExprValue::Bool(true))),
+        };
+        let filters = || {
+            args.iter()
+                .map(Self::parse_filter)
+                .collect::<Result<Vec<_>, _>>()
+        };
+        match op {
+            "all" => return Ok(Expression::All(filters()?)),
+            "any" => return Ok(Expression::Any(filters()?)),
+            "none" => return Ok(Expression::Not(Box::new(Expression::Any(filters()?)))),
+            _ => {}
+        }
+        let key = match args.first().and_then(Json::as_str) {
+            Some(key) => legacy_key(key),
+            None => return Self::parse(json),
+        };
+        let values = || {
+            Expression::Literal(ExprValue::Array(
+                args[1..].iter().map(ExprValue::from_json).collect(),
+            ))
+        };
+        let filter = match op {
+            "has" => Expression::Has(args[0].as_str().unwrap_or_default().to_string()),
+            "!has" => Expression::Not(Box::new(Expression::Has(
+                args[0].as_str().unwrap_or_default().to_string(),
+            ))),
+            "in" => Expression::In(Box::new(key), Box::new(values())),
+            "!in" => Expression::Not(Box::new(Expression::In(Box::new(key), Box::new(values())))),
+            _ => match comparison(op) {
+                Some(comparison) => {
+                    let value = ExprValue::from_json(args.get(1).unwrap_or(&Json::Null));
+                    Expression::Compare(
+                        comparison,
+                        Box::new(key),
+                        Box::new(Expression::Literal(value)),
+                    )
+                }
+                None => return Self::parse(json),
+            },
+        };
+        Ok(filter)
+    }
+
+    //a `{"stops": ...}` function from before expressions, on zoom unless it names a property
+    fn parse_function(json: &Json) -> Result<Self, StyleError> {
+        let input = Box::new(match json["property"].as_str() {
+            Some(property) => Expression::Get(property.to_string()),
+            None => Expression::Zoom,
+        });
+        let pairs = json["stops"]
+            .as_array()
+            .ok_or_else(|| invalid(String::from("function stops aren't a list")))?;
+        let mut inputs = Vec::new();
+        let mut outputs = Vec::new();
+        for pair in pairs {
+            match pair.as_array().map(Vec::as_slice) {
+                Some([input, output]) => {
+                    inputs.push(input);
+                    outputs.push(Self::parse(output)?);
+                }
+                _ => {
+                    return Err(invalid(String::from(
+                        "function stops need an input and an output",
+                    )))
+                }
+            }
+        }
+        if outputs.is_empty() {
+            return Err(invalid(String::from("function has no stops")));
+        }
+        let numeric = || {
+            inputs
+                .iter()
+                .map(|input| input.as_f64())
+                .collect::<Option<Vec<f64>>>()
+                .ok_or_else(|| invalid(String::from("function stops need numbers")))
+        };
+        let function = match json["type"].as_str() {
+            Some("categorical") => Expression::Match {
+                input,
+                arms: inputs
+                    .iter()
+                    .map(|input| vec![ExprValue::from_json(input)])
+                    .zip(outputs)
+                    .collect(),
+                fallback: Box::new(Expression::Literal(ExprValue::from_json(&json["default"]))),
+            },
+            Some("interval") => {
+                let first = Box::new(outputs[0].clone());
+                Expression::Step {
+                    input,
+                    first,
+                    stops: numeric()?.into_iter().zip(outputs).skip(1).collect(),
+                }
+            }
+            _ => Expression::Interpolate {
+                base: json["base"].as_f64().unwrap_or(1.0),
+                input,
+                stops: numeric()?.into_iter().zip(outputs).collect(),
+            },
+        };
+        Ok(function)
+    }
+
+    pub fn eval(&self, context: &Context<'_>) -> ExprValue {
+        let property = |key: &str| {
+            context
+                .feature
+                .and_then(|feature| feature.properties.get(key))
+                .map(ExprValue::from)
+        };
+        match self {
+            Expression::Literal(value) => value.clone(),
+            Expression::Get(key) => property(key).unwrap_or(ExprValue::Null),
+            Expression::Has(key) => ExprValue::Bool(property(key).is_some()),
+            Expression::Zoom => ExprValue::Number(context.zoom as f64),
+            Expression::GeometryType => match context.feature.map(|feature| &feature.geometry) {
+                Some(Geometry::Points(_)) => ExprValue::String(String::from("Point")),
+                Some(Geometry::Lines(_)) => ExprValue::String(String::from("LineString")),
+                Some(Geometry::Polygons(_)) => ExprValue::String(String::from("Polygon")),
+                None => ExprValue::Null,
+            },
+            Expression::Id => match context.feature.and_then(|feature| feature.id) {
+                Some(id) => ExprValue::Number(id as f64),
+                None => ExprValue::Null,
+            },
+            Expression::Not(inner) => ExprValue::Bool(!inner.eval(context).is_true()),
+            Expression::All(all) => ExprValue::Bool(all.iter().all(|e| e.eval(context).is_true())),
+            Expression::Any(any) => ExprValue::Bool(any.iter().any(|e| e.eval(context).is_true())),
+            Expression::Compare(comparison, a, b) => {
+                ExprValue::Bool(compare(*comparison, &a.eval(context), &b.eval(context)))
+            }
+            Expression::In(needle, haystack) => {
+                let needle = needle.eval(context);
+                ExprValue::Bool(match (haystack.eval(context), &needle) {
+                    (ExprValue::Array(values), _) => values.contains(&needle),
+                    (ExprValue::String(text), ExprValue::String(part)) => {
+                        text.contains(part.as_str())
+                    }
+                    _ => false,
+                })
+            }
+            Expression::Match {
+                input,
+                arms,
+                fallback,
+            } => {
+                let input = input.eval(context);
+                arms.iter()
+                    .find(|(labels, _)| labels.contains(&input))
+                    .map_or_else(
+                        || fallback.eval(context),
+                        |(_, output)| output.eval(context),
+                    )
+            }
+            Expression::Case { arms, fallback } => arms
+                .iter()
+                .find(|(condition, _)| condition.eval(context).is_true())
+                .map_or_else(
+                    || fallback.eval(context),
+                    |(_, output)| output.eval(context),
+                ),
+            Expression::Coalesce(options) => options
+                .iter()
+                .map(|option| option.eval(context))
+                .find(|value| *value != ExprValue::Null)
+                .unwrap_or(ExprValue::Null),
+            Expression::Concat(parts) => ExprValue::String(
+                parts
+                    .iter()
+                    .map(|part| part.eval(context).to_text())
+                    .collect(),
+            ),
+            Expression::Interpolate { base, input, stops } => {
+                let x = match input.eval(context).as_f64() {
+                    Some(x) => x,
+                    None => return ExprValue::Null,
+                };
+                interpolate(*base, x, stops, context)
+            }
+            Expression::Step {
+                input,
+                first,
+                stops,
+            } => {
+                let x = input.eval(context).as_f64().unwrap_or(f64::NEG_INFINITY);
+                stops
+                    .iter()
+                    .rev()
+                    .find(|(stop, _)| *stop <= x)
+                    .map_or_else(|| first.eval(context), |(_, output)| output.eval(context))
+            }
+        }
+    }
+
+    pub fn eval_f32(&self, context: &Context<'_>, default: f32) -> f32 {
+        self.eval(context)
+            .as_f64()
+            .map_or(default, |value| value as f32)
+    }
+
+    pub fn eval_color(&self, context: &Context<'_>) -> Option<Color> {
+        self.eval(context).to_color()
+    }
+}
+
+fn compare(comparison: Comparison, a: &ExprValue, b: &ExprValue) -> bool {
+    let ordering = match (a, b) {
+        (ExprValue::Number(a), ExprValue::Number(b)) => a.partial_cmp(b),
+        (ExprValue::String(a), ExprValue::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    };
+    match comparison {
+        Comparison::Equal => a == b,
+        Comparison::NotEqual => a != b,
+        Comparison::Less => ordering.map_or(false, |o| o.is_lt()),
+        Comparison::LessOrEqual => ordering.map_or(false, |o| o.is_le()),
+        Comparison::Greater => ordering.map_or(false, |o| o.is_gt()),
+        Comparison::GreaterOrEqual => ordering.map_or(false, |o| o.is_ge()),
+    }
+}
+
+fn interpolate(base: f64, x: f64, stops: &[(f64, Expression)], context: &Context<'_>) -> ExprValue {
+    let upper = match stops.iter().position(|(stop, _)| *stop > x) {
+        Some(0) => return stops[0].1.eval(context),
+        Some(upper) => upper,
+        None => {
+            return stops
+                .last()
+                .map_or(ExprValue::Null, |(_, last)| last.eval(context))
+        }
+    };
+    let (x0, low) = &stops[upper - 1];
+    let (x1, high) = &stops[upper];
+    let range = x1 - x0;
+    let t = if (base - 1.0).abs() < 1e-9 {
+        (x - x0) / range
+    } else {
+        (base.powf(x - x0) - 1.0) / (base.powf(range) - 1.0)
+    } as f32;
+    let (low, high) = (low.eval(context), high.eval(context));
+    match (&low, &high) {
+        (ExprValue::Number(a), ExprValue::Number(b)) => ExprValue::Number(a + (b - a) * t as f64),
+        _ => match (low.to_color(), high.to_color()) {
+            (Some(a), Some(b)) => ExprValue::Color(Color {
+                r: a.r + (b.r - a.r) * t,
+                g: a.g + (b.g - a.g) * t,
+                b: a.b + (b.b - a.b) * t,
+                a: a.a + (b.a - a.a) * t,
+            }),
+            //things that can't be blended switch over at the higher stop
+            _ => low,
+        },
+    }
+}
+
+/// Parses the css colors styles use: hex, `rgb()`, `rgba()`, `hsl()`, `hsla()` and a few
+/// names.
+pub fn parse_color(text: &str) -> Option<Color> {
+    let text = text.trim().to_lowercase();
+    match text.as_str() {
+        "black" => return Some(Color::BLACK),
+        "white" => return Some(Color::WHITE),
+        "transparent" => return Some(Color::TRANSPARENT),
+        _ => {}
+    }
+    if text.starts_with('#') {
+        return color_from_hex(&text);
+    }
+    let open = text.find('(')?;
+    let function = &text[..open];
+    let args: Vec<f32> = text[open + 1..]
+        .trim_end_matches(')')
+        .split(',')
+        .map(|arg| arg.trim().trim_end_matches('%').parse::<f32>().ok())
+        .collect::<Option<Vec<f32>>>()?;
+    let alpha = |idx: usize| args.get(idx).copied().unwrap_or(1.0).max(0.0).min(1.0);
+    match (function, args.len()) {
+        ("rgb", 3) | ("rgba", 4) => Some(Color::from_rgba(
+            args[0] / 255.0,
+            args[1] / 255.0,
+            args[2] / 255.0,
+            alpha(3),
+        )),
+        ("hsl", 3) | ("hsla", 4) => {
+            let (r, g, b) = hsl_to_rgb(args[0], args[1] / 100.0, args[2] / 100.0);
+            Some(Color::from_rgba(r, g, b, alpha(3)))
+        }
+        _ => None,
+    }
+}
+
+fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> (f32, f32, f32) {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let sector = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+    (r + m, g + m, b + m)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn river() -> Feature {
+        let mut properties = std::collections::HashMap::new();
+        properties.insert(
+            String::from("class"),
+            mvt::Value::String(String::from("river")),
+        );
+        properties.insert(String::from("width"), mvt::Value::Number(12.0));
+        Feature {
+            id: Some(7),
+            geometry: Geometry::Lines(vec![vec![(0.0, 0.0), (1.0, 1.0)]]),
+            properties,
+        }
+    }
+
+    fn eval(json: Json, zoom: f32) -> ExprValue {
+        let feature = river();
+        let context = Context {
+            zoom,
+            feature: Some(&feature),
+        };
+        Expression::parse(&json).unwrap().eval(&context)
+    }
+
+    fn filter(json: Json) -> bool {
+        let feature = river();
+        let context = Context {
+            zoom: 10.0,
+            feature: Some(&feature),
+        };
+        Expression::parse_filter(&json)
+            .unwrap()
+            .eval(&context)
+            .is_true()
+    }
+
+    #[test]
+    fn empty_filters_leave_nothing_out() {
+        assert_eq!(
+            Expression::parse_filter(&json!([])).unwrap(),
+            Expression::Literal(ExprValue::Bool(true))
+        );
+        assert_eq!(
+            Expression::parse_filter(&json!(["all", []])).unwrap(),
+            Expression::All(vec![Expression::Literal(ExprValue::Bool(true))])
+        );
+        assert!(filter(json!([])));
+        assert!(filter(json!(["all", []])));
+    }
+
+    #[test]
+    fn legacy_filters_name_properties_by_string() {
+        assert!(filter(json!(["==", "class", "river"])));
+        assert!(!filter(json!(["!=", "class", "river"])));
+        assert!(filter(json!(["in", "class", "lake", "river"])));
+        assert!(filter(json!(["!in", "class", "lake"])));
+        assert!(filter(json!(["==", "$type", "LineString"])));
+        assert!(filter(json!(["==", "$id", 7])));
+        assert!(filter(json!(["has", "width"])));
+        assert!(filter(json!(["!has", "name"])));
+        assert!(filter(json!([
+            "none",
+            [">", "width", 20],
+            ["==", "class", "lake"]
+        ])));
+    }
+
+    #[test]
+    fn expression_filters_are_read_too() {
+        assert!(filter(json!(["==", ["get", "class"], "river"])));
+        assert!(filter(json!([
+            "all",
+            [">=", ["get", "width"], 12],
+            ["<", ["zoom"], 11]
+        ])));
+        assert!(!filter(json!([
+            "any",
+            ["has", "name"],
+            ["<", ["get", "width"], 5]
+        ])));
+        //comparisons between different types are false
+        assert!(!filter(json!([">", ["get", "class"], 1])));
+    }
+
+    #[test]
+    fn values_are_picked_and_blended() {
+        let matched = json!(["match", ["get", "class"], ["lake", "river"], 1, 0]);
+        assert_eq!(eval(matched, 0.0), ExprValue::Number(1.0));
+        let case = json!(["case", ["has", "name"], "named", "unnamed"]);
+        assert_eq!(eval(case, 0.0), ExprValue::String(String::from("unnamed")));
+        let coalesce = json!(["coalesce", ["get", "name"], ["get", "class"]]);
+        assert_eq!(
+            eval(coalesce, 0.0),
+            ExprValue::String(String::from("river"))
+        );
+        let concat = json!(["concat", ["get", "class"], " ", ["get", "width"], "m"]);
+        assert_eq!(
+            eval(concat, 0.0),
+            ExprValue::String(String::from("river 12m"))
+        );
+        let step = json!(["step", ["zoom"], 1, 10, 2, 14, 3]);
+        assert_eq!(eval(step.clone(), 9.0), ExprValue::Number(1.0));
+        assert_eq!(eval(step, 12.0), ExprValue::Number(2.0));
+        let linear = json!(["interpolate", ["linear"], ["zoom"], 10, 1, 20, 3]);
+        assert_eq!(eval(linear.clone(), 5.0), ExprValue::Number(1.0));
+        assert_eq!(eval(linear.clone(), 15.0), ExprValue::Number(2.0));
+        assert_eq!(eval(linear, 25.0), ExprValue::Number(3.0));
+        let colors = json!([
+            "interpolate",
+            ["linear"],
+            ["zoom"],
+            0,
+            "#000000",
+            10,
+            "#ffffff"
+        ]);
+        assert_eq!(eval(colors, 5.0).to_color().map(|c| c.r), Some(0.5));
+    }
+
+    #[test]
+    fn stop_functions_become_expressions() {
+        let function = json!({"base": 2, "stops": [[10, 1], [12, 5]]});
+        //with base 2 the first half of the range covers a third of the change
+        let value = eval(function, 11.0).as_f64().unwrap();
+        assert!((value - (1.0 + 4.0 / 3.0)).abs() < 1e-6);
+        let categorical = json!({
+            "property": "class",
+            "type": "categorical",
+            "stops": [["lake", "blue"], ["river", "teal"]],
+            "default": "gray"
+        });
+        assert_eq!(
+            eval(categorical, 0.0),
+            ExprValue::String(String::from("teal"))
+        );
+        assert!(Expression::parse(&json!({"stops": []})).is_err());
+        assert!(Expression::parse(&json!(["frobnicate", 1])).is_err());
+        assert!(Expression::parse(&json!(["match", ["get", "class"], "river", 1])).is_err());
+    }
+
+    #[test]
+    fn css_colors_are_parsed() {
+        assert_eq!(parse_color("White"), Some(Color::WHITE));
+        assert_eq!(
+            parse_color("rgba(255, 0, 0, 0.5)"),
+            Some(Color::from_rgba(1.0, 0.0, 0.0, 0.5))
+        );
+        assert_eq!(
+            parse_color("hsl(120, 100%, 50%)"),
+            Some(Color::from_rgba(0.0, 1.0, 0.0, 1.0))
+        );
+        assert_eq!(parse_color("#0000ff"), Some(Color::from_rgb(0.0, 0.0, 1.0)));
+        assert_eq!(parse_color("rgb(1, 2)"), None);
+        assert_eq!(parse_color("cornflowerblue"), None);
+    }
+}
@@ -0,0 +1,317 @@
+//mapbox vector tiles, decoded and drawn into images so the map can treat them like any other
+//tile
+pub mod expression;
+pub mod mvt;
+pub mod style;
+
+use crate::export::raster::{blend_pixel, fill_shape};
+use crate::export::text;
+use crate::features::tessellate::{fill, stroke};
+use ab_glyph::FontVec;
+use expression::Context;
+use iced::Color;
+use iced_graphics::triangle::Mesh2D;
+use image::{Rgba, RgbaImage};
+use mvt::{Feature, Geometry, VectorTile};
+use style::{Paint, StyleLayer, VectorStyle};
+
+/// Draws `tile`, fetched at `zoom`, as a `size` pixel square image the way `style` says.
+/// Labels need `font`, they're left out without one.
+pub fn render_tile(
+    tile: &VectorTile,
+    style: &VectorStyle,
+    zoom: u8,
+    size: u32,
+    font: Option<&FontVec>,
+) -> RgbaImage {
+    //styles are written for 512 pixel tiles, a 256 pixel tile is drawn the way they'd draw
+    //it a zoom level out
+    let zoom = (zoom as f32 - 1.0).max(0.0);
+    //and in pixels of a 256 pixel tile
+    let pixel_scale = size as f32 / 256.0;
+    let mut image = RgbaImage::from_pixel(size, size, Rgba([0, 0, 0, 0]));
+    let mut symbols = Vec::new();
+    for layer in style.layers.iter().filter(|layer| layer.applies_at(zoom)) {
+        let context = Context {
+            zoom,
+            feature: None,
+        };
+        let source_layer = match (&layer.paint, &layer.source_layer) {
+            (Paint::Background { color, opacity }, _) => {
+                if let Some(color) = color.eval_color(&context) {
+                    let color = faded(color, opacity.eval_f32(&context, 1.0));
+                    for y in 0..size as i64 {
+                        for x in 0..size as i64 {
+                            blend_pixel(&mut image, x, y, color, 1.0);
+                        }
+                    }
+                }
+                continue;
+            }
+            //labels go over everything else
+            (Paint::Symbol { .. }, _) => {
+                symbols.push(layer);
+                continue;
+            }
+            (_, Some(source_layer)) => source_layer,
+            (_, None) => continue,
+        };
+        for tile_layer in tile.layers.iter().filter(|l| l.name == *source_layer) {
+            let scale = size as f32 / tile_layer.extent as f32;
+            for feature in tile_layer.features.iter() {
+                if layer.matches(feature, zoom) {
+                    let context = Context {
+                        zoom,
+                        feature: Some(feature),
+                    };
+                    let geometry = scaled(&feature.geometry, scale);
+                    draw_feature(&mut image, &layer.paint, &geometry, &context, pixel_scale);
+                }
+            }
+        }
+    }
+
+    if let Some(font) = font {
+        let mut placed = Vec::new();
+        for layer in symbols {
+            draw_symbols(&mut image, &mut placed, tile, layer, zoom, font);
+        }
+    }
+    image
+}
+
+fn faded(color: Color, opacity: f32) -> Color {
+    Color {
+        a: color.a * opacity.max(0.0).min(1.0),
+        ..color
+    }
+}
+
+fn scaled(geometry: &Geometry, scale: f32) -> Geometry {
+    let path = |path: &Vec<(f32, f32)>| -> Vec<(f32, f32)> {
+        path.iter().map(|(x, y)| (x * scale, y * scale)).collect()
+    };
+    match geometry {
+        Geometry::Points(points) => Geometry::Points(path(points)),
+        Geometry::Lines(lines) => Geometry::Lines(lines.iter().map(path).collect()),
+        Geometry::Polygons(polygons) => Geometry::Polygons(
+            polygons
+                .iter()
+                .map(|rings| rings.iter().map(path).collect())
+                .collect(),
+        ),
+    }
+}
+
+fn new_mesh() -> Mesh2D {
+    Mesh2D {
+        vertices: Vec::new(),
+        indices: Vec::new(),
+    }
+}
+
+//each feature is rasterized as a shape of its own so see-through paint blends evenly
+fn draw_feature(
+    image: &mut RgbaImage,
+    paint: &Paint,
+    geometry: &Geometry,
+    context: &Context<'_>,
+    pixel_scale: f32,
+) {
+    match (paint, geometry) {
+        (
+            Paint::Fill {
+                color,
+                opacity,
+                outline_color,
+            },
+            Geometry::Polygons(polygons),
+        ) => {
+            let opacity = opacity.eval_f32(context, 1.0);
+            let color = color.eval_color(context).map(|color| faded(color, opacity));
+            let outline = outline_color
+                .as_ref()
+                .and_then(|outline| outline.eval_color(context))
+                .map(|outline| faded(outline, opacity));
+            if let Some(color) = color {
+                let mut mesh = new_mesh();
+                for rings in polygons {
+                    fill(&mut mesh, rings, color);
+                }
+                fill_shape(image, &mesh, color);
+            }
+            if let Some(outline) = outline {
+                let mut mesh = new_mesh();
+                for ring in polygons.iter().flatten() {
+                    stroke(&mut mesh, &closed(ring), pixel_scale, outline);
+                }
+                fill_shape(image, &mesh, outline);
+            }
+        }
+        (
+            Paint::Line {
+                color,
+                width,
+                opacity,
+            },
+            Geometry::Lines(_) | Geometry::Polygons(_),
+        ) => {
+            let color = match color.eval_color(context) {
+                Some(color) => faded(color, opacity.eval_f32(context, 1.0)),
+                None => return,
+            };
+            let width = width.eval_f32(context, 1.0) * pixel_scale;
+            let mut mesh = new_mesh();
+            match geometry {
+                Geometry::Lines(lines) => {
+                    for line in lines {
+                        stroke(&mut mesh, line, width, color);
+                    }
+                }
+                Geometry::Polygons(polygons) => {
+                    for ring in polygons.iter().flatten() {
+                        stroke(&mut mesh, &closed(ring), width, color);
+                    }
+                }
+                Geometry::Points(_) => {}
+            }
+            fill_shape(image, &mesh, color);
+        }
+        _ => {}
+    }
+}
+
+fn closed(ring: &[(f32, f32)]) -> Vec<(f32, f32)> {
+    let mut closed = ring.to_vec();
+    if let Some(first) = ring.first() {
+        closed.push(*first);
+    }
+    closed
+}
+
+//where a feature's label goes: on points, halfway along lines and in the middle of the
+//bounding box of polygons
+fn anchors(geometry: &Geometry) -> Vec<(f32, f32)> {
+    match geometry {
+        Geometry::Points(points) => points.clone(),
+        Geometry::Lines(lines) => lines
+            .iter()
+            .filter_map(|line| line.get(line.len() / 2).copied())
+            .collect(),
+        Geometry::Polygons(polygons) => polygons
+            .iter()
+            .filter_map(|rings| {
+                let outer = rings.first()?;
+                let (mut min, mut max) = (outer[0], outer[0]);
+                for point in outer {
+                    min = (min.0.min(point.0), min.1.min(point.1));
+                    max = (max.0.max(point.0), max.1.max(point.1));
+                }
+                Some(((min.0 + max.0) / 2.0, (min.1 + max.1) / 2.0))
+            })
+            .collect(),
+    }
+}
+
+//fills in legacy `{property}` tokens
+fn expand_tokens(text: &str, feature: &Feature) -> String {
+    let mut expanded = String::new();
+    let mut rest = text;
+    while let Some(open) = rest.find('{') {
+        let close = match rest[open..].find('}') {
+            Some(close) => open + close,
+            None => break,
+        };
+        expanded.push_str(&rest[..open]);
+        if let Some(value) = feature.properties.get(&rest[open + 1..close]) {
+            let value = expression::ExprValue::from(value);
+            expanded.push_str(&value.to_text());
+        }
+        rest = &rest[close + 1..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+//labels are only drawn where they fit inside the tile without overlapping one already
+//placed, the neighbouring tiles can't draw the part that would be cut off
+fn draw_symbols(
+    image: &mut RgbaImage,
+    placed: &mut Vec<(f32, f32, f32, f32)>,
+    tile: &VectorTile,
+    layer: &StyleLayer,
+    zoom: f32,
+    font: &FontVec,
+) {
+    let (text_field, text_size, text_color, halo_color, halo_width) = match &layer.paint {
+        Paint::Symbol {
+            text_field,
+            text_size,
+            text_color,
+            halo_color,
+            halo_width,
+        } => (text_field, text_size, text_color, halo_color, halo_width),
+        _ => return,
+    };
+    let size = image.width() as f32;
+    let pixel_scale = size / 256.0;
+    let source_layer = layer.source_layer.as_deref().unwrap_or_default();
+    for tile_layer in tile.layers.iter().filter(|l| l.name == source_layer) {
+        let scale = size / tile_layer.extent as f32;
+        for feature in tile_layer.features.iter() {
+            if !layer.matches(feature, zoom) {
+                continue;
+            }
+            let context = Context {
+                zoom,
+                feature: Some(feature),
+            };
+            let content = expand_tokens(&text_field.eval(&context).to_text(), feature);
+            let content = content.trim();
+            let color = match text_color.eval_color(&context) {
+                Some(color) if !content.is_empty() => color,
+                _ => continue,
+            };
+            let font_size = (text_size.eval_f32(&context, 16.0) * pixel_scale)
+                .round()
+                .max(1.0);
+            let halo = halo_width.eval_f32(&context, 0.0) * pixel_scale;
+            let halo_color = halo_color
+                .eval_color(&context)
+                .unwrap_or(Color::TRANSPARENT);
+            let (width, height) = text::measure(font, content, font_size as u16);
+            for anchor in anchors(&scaled(&feature.geometry, scale)) {
+                let left = anchor.0 - width / 2.0 - halo;
+                let top = anchor.1 - height / 2.0 - halo;
+                let (right, bottom) = (left + width + halo * 2.0, top + height + halo * 2.0);
+                let fits = left >= 0.0 && top >= 0.0 && right <= size && bottom <= size;
+                let overlaps = placed
+                    .iter()
+                    .any(|b| left < b.2 && right > b.0 && top < b.3 && bottom > b.1);
+                if !fits || overlaps {
+                    continue;
+                }
+                placed.push((left, top, right, bottom));
+                let mut x = anchor.0 - width / 2.0;
+                for glyph in content.chars() {
+                    let advance = text::measure(font, &glyph.to_string(), font_size as u16).0;
+                    let center = (x + advance / 2.0, anchor.1);
+                    if halo > 0.0 && halo_color.a > 0.0 {
+                        for dx in [-halo, 0.0, halo].iter() {
+                            for dy in [-halo, 0.0, halo].iter() {
+                                if *dx != 0.0 || *dy != 0.0 {
+                                    let offset = (center.0 + dx, center.1 + dy);
+                                    text::draw_glyph(
+                                        image, font, glyph, offset, font_size, halo_color,
+                                    );
+                                }
+                            }
+                        }
+                    }
+                    text::draw_glyph(image, font, glyph, center, font_size, color);
+                    x += advance;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,520 @@
+//decodes mapbox vector tiles, version 2 of https://github.com/mapbox/vector-tile-spec
+//
+//a tile is a protobuf message. there's no protobuf crate here, the handful of messages in
+//the spec are read field by field instead
+use flate2::read::GzDecoder;
+use std::collections::HashMap;
+use std::io::Read;
+use thiserror::Error;
+
+//the spec's default when a layer doesn't say
+const DEFAULT_EXTENT: u32 = 4096;
+
+#[derive(Debug, Error)]
+pub enum MvtError {
+    #[error("tile ends in the middle of a field")]
+    Truncated,
+    #[error("unsupported protobuf wire type {0}")]
+    WireType(u64),
+    #[error("couldn't unzip tile: {0}")]
+    Gzip(#[from] std::io::Error),
+    #[error("text in tile isn't utf-8: {0}")]
+    Utf8(#[from] std::string::FromUtf8Error),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VectorTile {
+    pub layers: Vec<Layer>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Layer {
+    pub name: String,
+    //size of the tile in the layer's coordinates
+    pub extent: u32,
+    pub features: Vec<Feature>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Feature {
+    pub id: Option<u64>,
+    pub geometry: Geometry,
+    pub properties: HashMap<String, Value>,
+}
+
+/// Geometry in tile coordinates, 0 to the layer's extent with y down. Features near the
+/// edges reach a little past the tile.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Geometry {
+    Points(Vec<(f32, f32)>),
+    Lines(Vec<Vec<(f32, f32)>>),
+    //each polygon is an outer ring followed by its holes
+    Polygons(Vec<Vec<Vec<(f32, f32)>>>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Number(f64),
+    Bool(bool),
+}
+
+/// Decodes a tile, gzipped or not. Servers often send them zipped without saying so.
+pub fn decode(bytes: &[u8]) -> Result<VectorTile, MvtError> {
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut unzipped = Vec::new();
+        GzDecoder::new(bytes).read_to_end(&mut unzipped)?;
+        return decode_tile(&unzipped);
+    }
+    decode_tile(bytes)
+}
+
+fn decode_tile(bytes: &[u8]) -> Result<VectorTile, MvtError> {
+    let mut tile = VectorTile::default();
+    let mut reader = Reader::new(bytes);
+    while let Some((field, wire)) = reader.key()? {
+        match (field, wire) {
+            (3, WIRE_LEN) => tile.layers.push(decode_layer(reader.bytes()?)?),
+            _ => reader.skip(wire)?,
+        }
+    }
+    Ok(tile)
+}
+
+fn decode_layer(bytes: &[u8]) -> Result<Layer, MvtError> {
+    let mut name = String::new();
+    let mut extent = DEFAULT_EXTENT;
+    let mut keys = Vec::new();
+    let mut values = Vec::new();
+    //features refer to keys and values that may come after them, so they wait
+    let mut raw_features = Vec::new();
+    let mut reader = Reader::new(bytes);
+    while let Some((field, wire)) = reader.key()? {
+        match (field, wire) {
+            (1, WIRE_LEN) => name = String::from_utf8(reader.bytes()?.to_vec())?,
+            (2, WIRE_LEN) => raw_features.push(reader.bytes()?),
+            (3, WIRE_LEN) => keys.push(String::from_utf8(reader.bytes()?.to_vec())?),
+            (4, WIRE_LEN) => values.push(decode_value(reader.bytes()?)?),
+            (5, WIRE_VARINT) => extent = (reader.varint()? as u32).max(1),
+            _ => reader.skip(wire)?,
+        }
+    }
+    let mut features = Vec::new();
+    for raw in raw_features {
+        if let Some(feature) = decode_feature(raw, &keys, &values)? {
+            features.push(feature);
+        }
+    }
+    Ok(Layer {
+        name,
+        extent,
+        features,
+    })
+}
+
+fn decode_value(bytes: &[u8]) -> Result<Value, MvtError> {
+    let mut value = Value::Bool(false);
+    let mut reader = Reader::new(bytes);
+    while let Some((field, wire)) = reader.key()? {
+        value = match (field, wire) {
+            (1, WIRE_LEN) => Value::String(String::from_utf8(reader.bytes()?.to_vec())?),
+            (2, WIRE_32) => Value::Number(f32::from_bits(reader.fixed32()?) as f64),
+            (3, WIRE_64) => Value::Number(f64::from_bits(reader.fixed64()?)),
+            (4, WIRE_VARINT) => Value::Number(reader.varint()? as i64 as f64),
+            (5, WIRE_VARINT) => Value::Number(reader.varint()? as f64),
+            (6, WIRE_VARINT) => Value::Number(zigzag(reader.varint()?) as f64),
+            (7, WIRE_VARINT) => Value::Bool(reader.varint()? != 0),
+            _ => {
+                reader.skip(wire)?;
+                continue;
+            }
+        };
+    }
+    Ok(value)
+}
+
+//None for features without a geometry type, the spec says to ignore those
+fn decode_feature(
+    bytes: &[u8],
+    keys: &[String],
+    values: &[Value],
+) -> Result<Option<Feature>, MvtError> {
+    let mut id = None;
+    let mut tags = Vec::new();
+    let mut kind = 0;
+    let mut commands = Vec::new();
+    let mut reader = Reader::new(bytes);
+    while let Some((field, wire)) = reader.key()? {
+        match (field, wire) {
+            (1, WIRE_VARINT) => id = Some(reader.varint()?),
+            (2, WIRE_LEN) => tags = packed(reader.bytes()?)?,
+            (3, WIRE_VARINT) => kind = reader.varint()?,
+            (4, WIRE_LEN) => commands = packed(reader.bytes()?)?,
+            _ => reader.skip(wire)?,
+        }
+    }
+
+    let mut properties = HashMap::new();
+    for pair in tags.chunks(2) {
+        if let [key, value] = pair {
+            match (keys.get(*key as usize), values.get(*value as usize)) {
+                (Some(key), Some(value)) => {
+                    properties.insert(key.clone(), value.clone());
+                }
+                _ => log::warn!("vector tile feature has a tag out of range"),
+            }
+        }
+    }
+
+    let paths = decode_commands(&commands);
+    let geometry = match kind {
+        1 => Geometry::Points(paths.into_iter().flatten().collect()),
+        2 => Geometry::Lines(paths.into_iter().filter(|path| path.len() > 1).collect()),
+        3 => Geometry::Polygons(group_rings(paths)),
+        _ => return Ok(None),
+    };
+    Ok(Some(Feature {
+        id,
+        geometry,
+        properties,
+    }))
+}
+
+//turns the move to, line to and close path commands into paths. a move to starts a new
+//one, closing a path doesn't repeat its first point
+fn decode_commands(commands: &[u64]) -> Vec<Vec<(f32, f32)>> {
+    let mut paths: Vec<Vec<(f32, f32)>> = Vec::new();
+    let mut cursor = (0i64, 0i64);
+    let mut idx = 0;
+    while idx < commands.len() {
+        let command = commands[idx];
+        idx += 1;
+        let (id, count) = (command & 0x7, (command >> 3) as usize);
+        match id {
+            //move to, line to
+            1 | 2 => {
+                for _ in 0..count {
+                    if idx + 1 >= commands.len() {
+                        log::warn!("vector tile geometry ends early");
+                        return paths;
+                    }
+                    cursor.0 += zigzag(commands[idx]);
+                    cursor.1 += zigzag(commands[idx + 1]);
+                    idx += 2;
+                    let point = (cursor.0 as f32, cursor.1 as f32);
+                    match (id, paths.last_mut()) {
+                        (2, Some(path)) => path.push(point),
+                        _ => paths.push(vec![point]),
+                    }
+                }
+            }
+            //close path
+            7 => {}
+            _ => {
+                log::warn!("unknown vector tile command {}", id);
+                return paths;
+            }
+        }
+    }
+    paths
+}
+
+//outer rings wind clockwise on screen and holes the other way, a new outer ring starts a
+//new polygon
+fn group_rings(rings: Vec<Vec<(f32, f32)>>) -> Vec<Vec<Vec<(f32, f32)>>> {
+    let mut polygons: Vec<Vec<Vec<(f32, f32)>>> = Vec::new();
+    for ring in rings {
+        let area = signed_area(&ring);
+        if ring.len() < 3 || area == 0.0 {
+            continue;
+        }
+        match polygons.last_mut() {
+            Some(polygon) if area < 0.0 => polygon.push(ring),
+            //a hole with nothing to be a hole in, the spec has polygons start with their
+            //outer ring
+            None if area < 0.0 => {}
+            _ => polygons.push(vec![ring]),
+        }
+    }
+    polygons
+}
+
+//positive for clockwise rings with y pointing down
+fn signed_area(ring: &[(f32, f32)]) -> f32 {
+    let mut sum = 0.0;
+    for (idx, a) in ring.iter().enumerate() {
+        let b = ring[(idx + 1) % ring.len()];
+        sum += a.0 * b.1 - b.0 * a.1;
+    }
+    sum / 2.0
+}
+
+fn zigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+fn packed(bytes: &[u8]) -> Result<Vec<u64>, MvtError> {
+    let mut reader = Reader::new(bytes);
+    let mut values = Vec::new();
+    while !reader.is_done() {
+        values.push(reader.varint()?);
+    }
+    Ok(values)
+}
+
+const WIRE_VARINT: u64 = 0;
+const WIRE_64: u64 = 1;
+const WIRE_LEN: u64 = 2;
+const WIRE_32: u64 = 5;
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    fn is_done(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    //field number and wire type of the next field, None at the end of the message
+    fn key(&mut self) -> Result<Option<(u64, u64)>, MvtError> {
+        if self.is_done() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        Ok(Some((key >> 3, key & 0x7)))
+    }
+
+    fn varint(&mut self) -> Result<u64, MvtError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = *self.bytes.get(self.pos).ok_or(MvtError::Truncated)?;
+            self.pos += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(MvtError::Truncated)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], MvtError> {
+        let end = self.pos.checked_add(len).ok_or(MvtError::Truncated)?;
+        let bytes = self.bytes.get(self.pos..end).ok_or(MvtError::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], MvtError> {
+        let len = self.varint()? as usize;
+        self.take(len)
+    }
+
+    fn fixed32(&mut self) -> Result<u32, MvtError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn fixed64(&mut self) -> Result<u64, MvtError> {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn skip(&mut self, wire: u64) -> Result<(), MvtError> {
+        match wire {
+            WIRE_VARINT => {
+                self.varint()?;
+            }
+            WIRE_64 => {
+                self.take(8)?;
+            }
+            WIRE_LEN => {
+                self.bytes()?;
+            }
+            WIRE_32 => {
+                self.take(4)?;
+            }
+            _ => return Err(MvtError::WireType(wire)),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn varint(out: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            out.push((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    fn field(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+        varint(out, field << 3 | WIRE_LEN);
+        varint(out, bytes.len() as u64);
+        out.extend_from_slice(bytes);
+    }
+
+    fn packed_field(out: &mut Vec<u8>, number: u64, values: &[u64]) {
+        let mut bytes = Vec::new();
+        for value in values {
+            varint(&mut bytes, *value);
+        }
+        field(out, number, &bytes);
+    }
+
+    fn encode_zigzag(value: i64) -> u64 {
+        ((value << 1) ^ (value >> 63)) as u64
+    }
+
+    fn command(id: u64, count: u64) -> u64 {
+        id | count << 3
+    }
+
+    //a ring of move to, line to and close path commands starting at the cursor
+    fn ring(commands: &mut Vec<u64>, cursor: &mut (i64, i64), points: &[(i64, i64)]) {
+        for (idx, point) in points.iter().enumerate() {
+            if idx == 0 {
+                commands.push(command(1, 1));
+            } else if idx == 1 {
+                commands.push(command(2, points.len() as u64 - 1));
+            }
+            commands.push(encode_zigzag(point.0 - cursor.0));
+            commands.push(encode_zigzag(point.1 - cursor.1));
+            *cursor = *point;
+        }
+        commands.push(command(7, 1));
+    }
+
+    fn feature(kind: u64, tags: &[u64], commands: &[u64]) -> Vec<u8> {
+        let mut out = Vec::new();
+        varint(&mut out, 1 << 3 | WIRE_VARINT);
+        varint(&mut out, 42);
+        packed_field(&mut out, 2, tags);
+        varint(&mut out, 3 << 3 | WIRE_VARINT);
+        varint(&mut out, kind);
+        packed_field(&mut out, 4, commands);
+        out
+    }
+
+    fn tile(features: &[Vec<u8>]) -> Vec<u8> {
+        let mut layer = Vec::new();
+        field(&mut layer, 1, b"roads");
+        for feature in features {
+            field(&mut layer, 2, feature);
+        }
+        field(&mut layer, 3, b"name");
+        field(&mut layer, 3, b"lanes");
+        let mut name = Vec::new();
+        field(&mut name, 1, "Rue de l'Église".as_bytes());
+        field(&mut layer, 4, &name);
+        let mut lanes = Vec::new();
+        varint(&mut lanes, 6 << 3 | WIRE_VARINT);
+        varint(&mut lanes, encode_zigzag(-2));
+        field(&mut layer, 4, &lanes);
+        varint(&mut layer, 5 << 3 | WIRE_VARINT);
+        varint(&mut layer, 512);
+        let mut tile = Vec::new();
+        field(&mut tile, 3, &layer);
+        tile
+    }
+
+    #[test]
+    fn lines_and_properties_are_decoded() {
+        let commands = [
+            command(1, 1),
+            encode_zigzag(10),
+            encode_zigzag(20),
+            command(2, 2),
+            encode_zigzag(5),
+            encode_zigzag(0),
+            encode_zigzag(-15),
+            encode_zigzag(-20),
+        ];
+        let decoded = decode(&tile(&[feature(2, &[0, 0, 1, 1], &commands)])).unwrap();
+        assert_eq!(decoded.layers.len(), 1);
+        let layer = &decoded.layers[0];
+        assert_eq!((layer.name.as_str(), layer.extent), ("roads", 512));
+        let feature = &layer.features[0];
+        assert_eq!(feature.id, Some(42));
+        assert_eq!(
+            feature.geometry,
+            Geometry::Lines(vec![vec![(10.0, 20.0), (15.0, 20.0), (0.0, 0.0)]])
+        );
+        assert_eq!(
+            feature.properties["name"],
+            Value::String(String::from("Rue de l'Église"))
+        );
+        assert_eq!(feature.properties["lanes"], Value::Number(-2.0));
+    }
+
+    #[test]
+    fn gzipped_tiles_are_unzipped() {
+        let commands = [command(1, 2), 2, 4, 6, 8];
+        let bytes = tile(&[feature(1, &[], &commands)]);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&bytes).unwrap();
+        let decoded = decode(&encoder.finish().unwrap()).unwrap();
+        assert_eq!(
+            decoded.layers[0].features[0].geometry,
+            Geometry::Points(vec![(1.0, 2.0), (4.0, 6.0)])
+        );
+    }
+
+    #[test]
+    fn holes_are_grouped_with_the_ring_before_them() {
+        let mut commands = Vec::new();
+        let mut cursor = (0, 0);
+        let outer = [(0, 0), (10, 0), (10, 10), (0, 10)];
+        let hole = [(2, 2), (2, 8), (8, 8), (8, 2)];
+        ring(&mut commands, &mut cursor, &hole);
+        ring(&mut commands, &mut cursor, &outer);
+        ring(&mut commands, &mut cursor, &hole);
+        ring(&mut commands, &mut cursor, &outer);
+        let decoded = decode(&tile(&[feature(3, &[], &commands)])).unwrap();
+        let to_f32 = |points: &[(i64, i64)]| -> Vec<(f32, f32)> {
+            points.iter().map(|p| (p.0 as f32, p.1 as f32)).collect()
+        };
+        //the leading hole has no outer ring and is dropped
+        assert_eq!(
+            decoded.layers[0].features[0].geometry,
+            Geometry::Polygons(vec![
+                vec![to_f32(&outer), to_f32(&hole)],
+                vec![to_f32(&outer)]
+            ])
+        );
+    }
+
+    #[test]
+    fn features_without_a_type_are_skipped() {
+        let decoded = decode(&tile(&[feature(0, &[], &[command(1, 1), 2, 2])])).unwrap();
+        assert!(decoded.layers[0].features.is_empty());
+    }
+
+    #[test]
+    fn broken_tiles_are_errors() {
+        let bytes = tile(&[feature(2, &[], &[command(1, 1), 2, 2])]);
+        assert!(matches!(
+            decode(&bytes[..bytes.len() - 3]),
+            Err(MvtError::Truncated)
+        ));
+        assert!(matches!(decode(&[3 << 3 | 3]), Err(MvtError::WireType(3))));
+        assert!(matches!(decode(&[0x1f, 0x8b, 0]), Err(MvtError::Gzip(_))));
+        assert!(matches!(
+            decode(&[3 << 3 | 2, 3, 1 << 3 | 2, 1, 0xff]),
+            Err(MvtError::Utf8(_))
+        ));
+    }
+}
@@ -0,0 +1,218 @@
+//how vector tiles are drawn, read from a mapbox gl style file,
+//https://docs.mapbox.com/mapbox-gl-js/style-spec/. only the layers matter here, sources
+//come from the tile layer the style is used for, and only background, fill, line and
+//symbol layers are drawn
+use super::expression::{Context, Expression};
+use super::mvt::Feature;
+use serde_json::Value as Json;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use thiserror::Error;
+
+//shown until a style file is loaded, and a starting point for writing one
+const DEFAULT_STYLE: &str = include_str!("default_style.json");
+
+#[derive(Debug, Error)]
+pub enum StyleError {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("style isn't json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("{0}")]
+    Invalid(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VectorStyle {
+    //bottom to top
+    pub layers: Vec<StyleLayer>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyleLayer {
+    pub id: String,
+    //name of the layer in the tile the features come from, background layers have none
+    pub source_layer: Option<String>,
+    pub filter: Option<Expression>,
+    //shown from min_zoom up to but not including max_zoom
+    pub min_zoom: f32,
+    pub max_zoom: f32,
+    pub paint: Paint,
+}
+
+/// The properties of each kind of layer, named after their style spec counterparts.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Paint {
+    Background {
+        color: Expression,
+        opacity: Expression,
+    },
+    Fill {
+        color: Expression,
+        opacity: Expression,
+        outline_color: Option<Expression>,
+    },
+    Line {
+        color: Expression,
+        width: Expression,
+        opacity: Expression,
+    },
+    Symbol {
+        //`{name}` style tokens are filled in from the feature's properties
+        text_field: Expression,
+        text_size: Expression,
+        text_color: Expression,
+        halo_color: Expression,
+        halo_width: Expression,
+    },
+}
+
+impl StyleLayer {
+    pub fn applies_at(&self, zoom: f32) -> bool {
+        zoom >= self.min_zoom && zoom < self.max_zoom
+    }
+
+    pub fn matches(&self, feature: &Feature, zoom: f32) -> bool {
+        let context = Context {
+            zoom,
+            feature: Some(feature),
+        };
+        self.filter
+            .as_ref()
+            .map_or(true, |filter| filter.eval(&context).is_true())
+    }
+}
+
+impl VectorStyle {
+    pub fn from_json(root: &Json) -> Result<Self, StyleError> {
+        let layers = root["layers"]
+            .as_array()
+            .ok_or_else(|| StyleError::Invalid(String::from("style has no layers")))?;
+        let mut parsed = Vec::new();
+        for layer in layers {
+            let id = layer["id"].as_str().unwrap_or_default().to_string();
+            if layer["layout"]["visibility"] == "none" {
+                continue;
+            }
+            //a bad layer is reported by id, it's the first thing to look for in the file
+            match parse_layer(layer, &id) {
+                Ok(Some(layer)) => parsed.push(layer),
+                Ok(None) => log::info!("style layer {} is of a type that isn't drawn", id),
+                Err(StyleError::Invalid(message)) => {
+                    return Err(StyleError::Invalid(format!("layer {}: {}", id, message)))
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Self { layers: parsed })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, StyleError> {
+        let text = std::fs::read_to_string(path)?;
+        Self::from_json(&serde_json::from_str(&text)?)
+    }
+}
+
+impl Default for VectorStyle {
+    /// An outdoor map for tiles in the OpenMapTiles schema, with contours from a `contour`
+    /// layer if the tiles have one.
+    fn default() -> Self {
+        let root = serde_json::from_str(DEFAULT_STYLE).expect("built in style is json");
+        VectorStyle::from_json(&root).expect("built in style is valid")
+    }
+}
+
+//a paint or layout property, falling back on the spec's default
+fn property(section: &Json, key: &str, default: Json) -> Result<Expression, StyleError> {
+    Expression::parse(section.get(key).unwrap_or(&default))
+}
+
+fn parse_layer(layer: &Json, id: &str) -> Result<Option<StyleLayer>, StyleError> {
+    let paint = &layer["paint"];
+    let layout = &layer["layout"];
+    let paint = match layer["type"].as_str() {
+        Some("background") => Paint::Background {
+            color: property(paint, "background-color", "#000000".into())?,
+            opacity: property(paint, "background-opacity", 1.0.into())?,
+        },
+        Some("fill") => Paint::Fill {
+            color: property(paint, "fill-color", "#000000".into())?,
+            opacity: property(paint, "fill-opacity", 1.0.into())?,
+            outline_color: match paint.get("fill-outline-color") {
+                Some(color) => Some(Expression::parse(color)?),
+                None => None,
+            },
+        },
+        Some("line") => Paint::Line {
+            color: property(paint, "line-color", "#000000".into())?,
+            width: property(paint, "line-width", 1.0.into())?,
+            opacity: property(paint, "line-opacity", 1.0.into())?,
+        },
+        Some("symbol") => Paint::Symbol {
+            text_field: property(layout, "text-field", "".into())?,
+            text_size: property(layout, "text-size", 16.0.into())?,
+            text_color: property(paint, "text-color", "#000000".into())?,
+            halo_color: property(paint, "text-halo-color", "rgba(0,0,0,0)".into())?,
+            halo_width: property(paint, "text-halo-width", 0.0.into())?,
+        },
+        Some(_) => return Ok(None),
+        None => return Err(StyleError::Invalid(String::from("layer has no type"))),
+    };
+    let source_layer = layer["source-layer"].as_str().map(str::to_string);
+    if source_layer.is_none() && !matches!(paint, Paint::Background { .. }) {
+        return Err(StyleError::Invalid(String::from(
+            "layer has no source-layer",
+        )));
+    }
+    Ok(Some(StyleLayer {
+        id: id.to_string(),
+        source_layer,
+        filter: match layer.get("filter") {
+            Some(filter) => Some(Expression::parse_filter(filter)?),
+            None => None,
+        },
+        min_zoom: layer["minzoom"].as_f64().unwrap_or(0.0) as f32,
+        max_zoom: layer["maxzoom"].as_f64().unwrap_or(24.0) as f32,
+        paint,
+    }))
+}
+
+/// A style file that's read again whenever it changes on disk.
+#[derive(Debug, Clone)]
+pub struct StyleWatcher {
+    path: PathBuf,
+    //when the file was last read, None until it has been
+    modified: Option<SystemTime>,
+}
+
+impl StyleWatcher {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            modified: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The style, if the file changed since the last call. Errors are reported once per
+    /// change too, so a half written file doesn't keep complaining.
+    pub fn poll(&mut self) -> Option<Result<VectorStyle, StyleError>> {
+        let modified = match std::fs::metadata(&self.path).and_then(|meta| meta.modified()) {
+            Ok(modified) => modified,
+            Err(e) if self.modified.is_none() => {
+                //an error the first time so a mistyped path gets noticed
+                self.modified = Some(SystemTime::UNIX_EPOCH);
+                return Some(Err(e.into()));
+            }
+            Err(_) => return None,
+        };
+        if self.modified == Some(modified) {
+            return None;
+        }
+        self.modified = Some(modified);
+        Some(VectorStyle::load(&self.path))
+    }
+}
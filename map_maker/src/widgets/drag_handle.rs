@@ -0,0 +1,173 @@
+//a grip to drag a row of a list up or down by. it doesn't move anything itself, when it's
+//let go it reports how far the cursor went and the list reorders to match
+use iced_graphics::backend::Backend;
+use iced_graphics::Primitive;
+use iced_native::event;
+use iced_native::{
+    layout, mouse, Background, Clipboard, Color, Element, Event, Hasher, Layout, Length, Point,
+    Rectangle, Size, Widget,
+};
+
+const GRIP_LINES: usize = 3;
+
+pub struct DragHandle<'a, Message> {
+    state: &'a mut State,
+    on_drop: Box<dyn Fn(f32) -> Message + 'a>,
+    width: u16,
+    height: u16,
+}
+
+/// The state of a [`DragHandle`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct State {
+    //where the cursor was when the handle was grabbed
+    grabbed_at: Option<Point>,
+}
+
+impl State {
+    pub fn is_grabbed(&self) -> bool {
+        self.grabbed_at.is_some()
+    }
+}
+
+impl<'a, Message> DragHandle<'a, Message> {
+    /// `on_drop` gets how far down the cursor moved between grabbing and letting go, in
+    /// pixels, negative for up.
+    pub fn new<F>(state: &'a mut State, on_drop: F) -> Self
+    where
+        F: 'a + Fn(f32) -> Message,
+    {
+        Self {
+            state,
+            on_drop: Box::new(on_drop),
+            width: 16,
+            height: 24,
+        }
+    }
+
+    pub fn height(mut self, height: u16) -> Self {
+        self.height = height;
+        self
+    }
+}
+
+impl<'a, Message, Renderer> Widget<Message, Renderer> for DragHandle<'a, Message>
+where
+    Renderer: self::Renderer,
+{
+    fn width(&self) -> Length {
+        Length::Units(self.width)
+    }
+
+    fn height(&self) -> Length {
+        Length::Units(self.height)
+    }
+
+    fn layout(&self, _renderer: &Renderer, limits: &layout::Limits) -> layout::Node {
+        let size = Size::new(self.width as f32, self.height as f32);
+        layout::Node::new(limits.resolve(size))
+    }
+
+    fn on_event(
+        &mut self,
+        event: Event,
+        layout: Layout<'_>,
+        cursor_position: Point,
+        _renderer: &Renderer,
+        _clipboard: &mut dyn Clipboard,
+        messages: &mut Vec<Message>,
+    ) -> event::Status {
+        match event {
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
+                if layout.bounds().contains(cursor_position) {
+                    self.state.grabbed_at = Some(cursor_position);
+                    return event::Status::Captured;
+                }
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) => {
+                if let Some(grabbed_at) = self.state.grabbed_at.take() {
+                    //a scrollable hands its children a cursor above everything once it's
+                    //outside, letting go out there puts the row back
+                    if cursor_position.y >= 0.0 {
+                        messages.push((self.on_drop)(cursor_position.y - grabbed_at.y));
+                    }
+                    return event::Status::Captured;
+                }
+            }
+            _ => {}
+        }
+        event::Status::Ignored
+    }
+
+    fn draw(
+        &self,
+        renderer: &mut Renderer,
+        _defaults: &Renderer::Defaults,
+        layout: Layout<'_>,
+        cursor_position: Point,
+        _viewport: &Rectangle,
+    ) -> Renderer::Output {
+        let bounds = layout.bounds();
+        self::Renderer::draw(
+            renderer,
+            bounds,
+            self.state.is_grabbed(),
+            bounds.contains(cursor_position),
+        )
+    }
+
+    fn hash_layout(&self, state: &mut Hasher) {
+        use std::hash::Hash;
+        self.width.hash(state);
+        self.height.hash(state);
+    }
+}
+
+/// The renderer of a [`DragHandle`].
+pub trait Renderer: iced_native::Renderer + Sized {
+    /// Draws the grip, darker while it's held.
+    fn draw(&mut self, bounds: Rectangle, is_grabbed: bool, is_hovered: bool) -> Self::Output;
+}
+
+impl<B> Renderer for iced_graphics::Renderer<B>
+where
+    B: Backend,
+{
+    fn draw(&mut self, bounds: Rectangle, is_grabbed: bool, is_hovered: bool) -> Self::Output {
+        let shade = if is_grabbed { 0.2 } else { 0.55 };
+        let line_height = 2.0;
+        let gap = (bounds.height - GRIP_LINES as f32 * line_height) / (GRIP_LINES + 1) as f32;
+        let primitives = (0..GRIP_LINES)
+            .map(|line| Primitive::Quad {
+                bounds: Rectangle {
+                    x: bounds.x + 2.0,
+                    y: bounds.y + gap + line as f32 * (gap + line_height),
+                    width: (bounds.width - 4.0).max(1.0),
+                    height: line_height,
+                },
+                background: Background::Color(Color::from_rgb(shade, shade, shade)),
+                border_radius: 1.0,
+                border_width: 0.0,
+                border_color: Color::TRANSPARENT,
+            })
+            .collect();
+        let interaction = if is_grabbed {
+            mouse::Interaction::Grabbing
+        } else if is_hovered {
+            mouse::Interaction::Grab
+        } else {
+            mouse::Interaction::default()
+        };
+        (Primitive::Group { primitives }, interaction)
+    }
+}
+
+impl<'a, Message, Renderer> Into<Element<'a, Message, Renderer>> for DragHandle<'a, Message>
+where
+    Message: 'a,
+    Renderer: 'a + self::Renderer,
+{
+    fn into(self) -> Element<'a, Message, Renderer> {
+        Element::new(self)
+    }
+}
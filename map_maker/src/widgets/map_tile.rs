@@ -8,20 +8,83 @@
 // Of course, you can choose to make the implementation renderer-agnostic,
 // if you wish to, by creating your own `Renderer` trait, which could be
 // implemented by `iced_wgpu` and other renderers.
-use crate::widgets::map_tile_overlay::TileOverlay;
+use crate::features::label::{layout_labels, Label};
+use crate::features::tessellate::tessellate_layers;
+use crate::features::{shown_labels, DrawingLayer};
+use crate::geo::{LatLon, MapView, TILE_SIZE};
+use crate::widgets::map_tile_overlay::{TileOverlay, STATUS_PADDING, STATUS_TEXT_SIZE};
 use iced::image;
 use iced_graphics::backend::{self, Backend};
 use iced_graphics::Primitive;
 use iced_native::event;
 use iced_native::mouse::click;
 use iced_native::{
-    button, layout, mouse, overlay, touch, Button, Clipboard, Element, Event, Hasher, Layout,
-    Length, Point, Rectangle, Size, Vector, Widget,
+    button, layout, mouse, overlay, touch, Background, Button, Clipboard, Color, Element, Event,
+    Font, Hasher, HorizontalAlignment, Layout, Length, Point, Rectangle, Size, Vector,
+    VerticalAlignment, Widget,
 };
 
 use log;
+use std::fmt;
 
 pub const TILE_DIMENSION: usize = 5;
+//logical pixels across a tile on screen, whatever size its image is
+const TILE: f32 = TILE_SIZE as f32;
+
+/// How many physical pixels the screen has to a logical one. Tiles are fetched and drawn
+/// with that much more detail, so the map stays sharp on hidpi screens without looking any
+/// more zoomed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayScale(pub u32);
+
+impl DisplayScale {
+    pub const ALL: [DisplayScale; 4] = [
+        DisplayScale(1),
+        DisplayScale(2),
+        DisplayScale(3),
+        DisplayScale(4),
+    ];
+    //tiles are drawn this many times over, past it they'd take more memory than they're
+    //worth
+    const MAX: u32 = 4;
+
+    /// The scale in `MAP_MAKER_SCALE`, otherwise the one `WINIT_X11_SCALE_FACTOR` and
+    /// `GDK_SCALE` give the window. iced doesn't pass the monitor's scale factor on, so
+    /// anything else is picked in the settings.
+    pub fn detect() -> Self {
+        let from_env = |names: &[&str]| {
+            names
+                .iter()
+                .filter_map(|name| std::env::var(name).ok())
+                .find_map(|value| value.trim().parse::<f64>().ok())
+        };
+        let scale = from_env(&["MAP_MAKER_SCALE"])
+            .or_else(|| from_env(&["WINIT_X11_SCALE_FACTOR", "GDK_SCALE"]))
+            .filter(|scale| scale.is_finite() && *scale >= 1.0)
+            .unwrap_or(1.0)
+            .round() as u32;
+        if scale > Self::MAX {
+            log::warn!("drawing tiles at {}x rather than {}x", Self::MAX, scale);
+        }
+        DisplayScale(scale.min(Self::MAX))
+    }
+}
+
+impl Default for DisplayScale {
+    fn default() -> Self {
+        DisplayScale(1)
+    }
+}
+
+impl fmt::Display for DisplayScale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x", self.0)
+    }
+}
+
+/// Handles of one raster layer's tiles around the load point, `None` where a tile isn't
+/// loaded yet.
+pub type TileGrid = [[Option<image::Handle>; TILE_DIMENSION]; TILE_DIMENSION];
 
 pub struct MapTile<'a, B, Message> {
     state: &'a mut State,
@@ -29,11 +92,18 @@ pub struct MapTile<'a, B, Message> {
     zoom_out_state: &'a mut button::State,
     zoom_in: B,
     zoom_out: B,
-    tile_handles: [[Option<image::Handle>; TILE_DIMENSION]; TILE_DIMENSION],
+    //one grid per raster layer, bottom to top
+    tile_layers: Vec<TileGrid>,
+    layers: &'a [DrawingLayer],
+    view: MapView,
     width: Length,
     height: Length,
     center_requester: Message,
     velocity_event: Message,
+    place_label: fn(LatLon) -> Message,
+    //how many tiles on screen aren't cached, when the tile manager is offline
+    offline: Option<usize>,
+    scale: DisplayScale,
 }
 
 impl<'a, B, Message, Renderer> Widget<Message, Renderer> for MapTile<'a, B, Message>
@@ -58,7 +128,7 @@ where
 
     fn layout(&self, renderer: &Renderer, limits: &layout::Limits) -> layout::Node {
         //let (width, height) = renderer.dimensions(&self.handle);
-        let (width, height) = (256.0 * 3.0, 256.0 * 3.0);
+        let (width, height) = (TILE * 3.0, TILE * 3.0);
         layout::Node::new(limits.resolve(Size::new(width, height)))
     }
 
@@ -89,6 +159,16 @@ where
 
                 self.state.last_click = Some(click);
             }
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Right)) => {
+                if bounds.contains(cursor_position) {
+                    let lat_lon = self
+                        .view
+                        .offset(self.state.load_pixel)
+                        .to_lat_lon((cursor_position.x - bounds.x, cursor_position.y - bounds.y));
+                    log::info!("placing label at {}, {}", lat_lon.lat, lat_lon.lon);
+                    messages.push((self.place_label)(lat_lon));
+                }
+            }
             Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left))
             | Event::Touch(touch::Event::FingerLifted { .. })
             | Event::Touch(touch::Event::FingerLost { .. }) => {
@@ -117,8 +197,8 @@ where
                 }
                 self.state.last_position = (position.x, position.y);
                 if self.state.center_requested == false
-                    && (self.state.load_pixel.0.abs() > 256.0
-                        || self.state.load_pixel.1.abs() > 256.0)
+                    && (self.state.load_pixel.0.abs() > TILE
+                        || self.state.load_pixel.1.abs() > TILE)
                 {
                     log::trace!("requesting centering");
                     self.state.center_requested = true;
@@ -157,16 +237,20 @@ where
         //    image_top_left
         //};
 
+        //the map is laid out from 0,0 and moved to wherever the widget ended up
         let translation = {
-            let image_top_left = Vector::new(0.0, 0.0);
+            let image_top_left = Vector::new(bounds.x, bounds.y);
             image_top_left
         };
         self::Renderer::draw(
             renderer,
             bounds,
             translation,
-            &self.tile_handles,
+            &self.tile_layers,
             self.state.load_pixel,
+            self.layers,
+            self.view.offset(self.state.load_pixel),
+            self.scale,
         )
         //renderer.draw(self.handle.clone(), layout)
     }
@@ -175,7 +259,11 @@ where
         use std::hash::Hash;
         //TODO: proper hashing of layout
         //self.state.load_pixel.hash(state);
-        self.tile_handles.hash(state);
+        self.tile_layers.hash(state);
+        self.layers.len().hash(state);
+        for layer in self.layers.iter() {
+            layer.hash_drawn(state);
+        }
         self.width.hash(state);
         self.height.hash(state);
         let interpreted_x = (self.state.velocity.0 * 1000.0) as i32;
@@ -190,10 +278,11 @@ where
         let zoom_out = (self.zoom_out)(&mut self.zoom_out_state);
         let edge_x = layout.bounds().x + layout.bounds().width - 125.0;
         let edge_y = layout.bounds().y + layout.bounds().height - 125.0;
+        let status = self.offline.map(offline_status);
         Some(
-            TileOverlay::new(zoom_in, zoom_out).overlay(Point::new(
-                f32::min(edge_x, 256.0 * 3.0 - 125.0),
-                f32::min(edge_y, 256.0 * 3.0 - 125.0),
+            TileOverlay::new(zoom_in, zoom_out, status).overlay(Point::new(
+                f32::min(edge_x, TILE * 3.0 - 125.0),
+                f32::min(edge_y, TILE * 3.0 - 125.0),
             )),
             //overlay::Element::new(position, Box::new(TileOverlay::new().overlay()))
             //    .overlay(Point::new(0.0, 0.0)),
@@ -201,6 +290,15 @@ where
     }
 }
 
+//says the map is offline, so a blank map isn't mistaken for a slow server
+fn offline_status(unavailable: usize) -> String {
+    match unavailable {
+        0 => String::from("offline"),
+        1 => String::from("offline, 1 tile not cached"),
+        _ => format!("offline, {} tiles not cached", unavailable),
+    }
+}
+
 /// The state of a [`MapTile`].
 #[derive(Debug, Default, Clone, Copy)]
 pub struct State {
@@ -258,39 +356,48 @@ where
 {
     pub fn new(
         state: &'a mut State,
-        tiles: [[Vec<u8>; TILE_DIMENSION]; TILE_DIMENSION],
+        tile_layers: Vec<TileGrid>,
         zoom_in_state: &'a mut button::State,
         zoom_out_state: &'a mut button::State,
         zoom_in: B,
         zoom_out: B,
         center_requester: Message,
         velocity_event: Message,
+        layers: &'a [DrawingLayer],
+        view: MapView,
+        place_label: fn(LatLon) -> Message,
     ) -> Self {
-        let mut tile_handles: [[Option<image::Handle>; TILE_DIMENSION]; TILE_DIMENSION] =
-            Default::default();
-
-        for (idx_x, x) in tiles.iter().enumerate() {
-            for (idx_y, y) in x.iter().enumerate() {
-                tile_handles[idx_x][idx_y] =
-                    Some(image::Handle::from_memory(tiles[idx_x][idx_y].clone()));
-            }
-        }
-
-        //let tile_handles = image::Handle::from_memory(bytes.to_vec());
         Self {
             state,
             zoom_in_state,
             zoom_out_state,
             zoom_in,
             zoom_out,
-            tile_handles,
+            tile_layers,
+            layers,
+            view,
             width: Length::Fill,
             height: Length::Fill,
             center_requester,
             velocity_event,
+            place_label,
+            offline: None,
+            scale: DisplayScale::default(),
         }
     }
 
+    /// Marks the map as offline, with how many of the tiles it shows aren't cached.
+    pub fn offline(mut self, unavailable: Option<usize>) -> Self {
+        self.offline = unavailable;
+        self
+    }
+
+    /// The scale of the screen the map is shown on, tiles are lined up with its pixels.
+    pub fn scale(mut self, scale: DisplayScale) -> Self {
+        self.scale = scale;
+        self
+    }
+
     // Returns the bounds of the underlying image, given the bounds of
     // the [`Viewer`]. Scaling will be applied and original aspect ratio
     // will be respected.
@@ -342,8 +449,10 @@ pub trait Renderer:
     /// - the bounds of the [`Viewer`] widget
     /// - the [`Size`] of the scaled [`Viewer`] image
     /// - the translation of the clipped image
-    /// - the [`Handle`] to the underlying image
+    /// - the [`Handle`]s of every raster layer's tiles, bottom to top
     /// - whether the mouse is over the [`Viewer`] or not
+    /// - the [`DrawingLayer`]s to draw over the tiles and the [`MapView`] to place them with
+    /// - the [`DisplayScale`] of the screen, tiles are snapped to its pixels
     ///
     /// [`Handle`]: image::Handle
     fn draw(
@@ -351,8 +460,11 @@ pub trait Renderer:
         bounds: Rectangle,
         translation: Vector,
         //handle: image::Handle,
-        tile_handles: &[[Option<image::Handle>; TILE_DIMENSION]; TILE_DIMENSION],
+        tile_layers: &[TileGrid],
         load_point: (f32, f32),
+        layers: &[DrawingLayer],
+        view: MapView,
+        scale: DisplayScale,
     ) -> Self::Output;
 
     fn overlay_draw<Message: Clone>(
@@ -362,6 +474,7 @@ pub trait Renderer:
         cursor_position: Point,
         zoom_in: &iced_native::Button<'_, Message, Self>,
         zoom_out: &iced_native::Button<'_, Message, Self>,
+        status: Option<&str>,
     ) -> Self::Output;
 
     //fn draw<Message>(
@@ -386,113 +499,171 @@ where
         translation: Vector,
         //handle: image::Handle,
         //
-        tile_handles: &[[Option<image::Handle>; TILE_DIMENSION]; TILE_DIMENSION],
+        tile_layers: &[TileGrid],
         load_point: (f32, f32),
+        layers: &[DrawingLayer],
+        view: MapView,
+        scale: DisplayScale,
     ) -> Self::Output {
         let mut primitives_vec: Vec<Primitive> = Vec::new();
         log::trace!("load point {}, {}", load_point.0, load_point.1);
-        let load_point = (load_point.0 / 256.0, load_point.1 / 256.0);
-        let load_top_left = ((load_point.0 - 1.5) * 256.0, (load_point.1 - 1.5) * 256.0);
-        let load_bottom_right = ((load_point.0 + 1.5) * 256.0, (load_point.1 + 1.5) * 256.0);
-        for (idx_x, x) in tile_handles.iter().enumerate() {
-            for (idx_y, y) in x.iter().enumerate() {
-                let handle_top_left = ((idx_x as f32 - 2.5) * 256.0, (idx_y as f32 - 2.5) * 256.0);
-                let handle_bottom_right =
-                    ((idx_x as f32 - 1.5) * 256.0, (idx_y as f32 - 1.5) * 256.0);
-                if let Some(tile) = &tile_handles[idx_x][idx_y] {
-                    //let top_left = Vector::new(idx_x as f32 * 0.0, idx_y as f32 * 0.0);
-                    let mut x = load_top_left.0 - handle_top_left.0;
-                    let mut y = load_top_left.1 - handle_top_left.1;
-
-                    let mut width = handle_top_left.0 - load_bottom_right.0;
-                    let mut height = handle_top_left.1 - load_bottom_right.1;
-                    log::debug!(
-                        "comparing {},{} - {},{} to {},{} - {},{}",
-                        load_top_left.0,
-                        load_top_left.1,
-                        load_bottom_right.0,
-                        load_bottom_right.1,
-                        handle_top_left.0,
-                        handle_top_left.1,
-                        handle_bottom_right.0,
-                        handle_bottom_right.1
-                    );
-
-                    log::debug!("raw vals {}, {}, {}, {}", x, y, width, height);
-                    if handle_bottom_right.0 <= load_top_left.0
-                        || handle_bottom_right.1 <= load_top_left.1
-                    {
-                        log::debug!("skipping tile {}, {}", idx_x, idx_y);
-                        continue;
-                    }
-                    if handle_top_left.0 >= load_bottom_right.0
-                        || handle_top_left.1 >= load_bottom_right.1
-                    {
-                        log::debug!("skipping tile {}, {}", idx_x, idx_y);
-                        continue;
+        //tiles that don't start on a physical pixel are blurred and leave seams between them
+        let snap = |value: f32| (value * scale.0 as f32).round() / scale.0 as f32;
+        let load_point = (load_point.0 / TILE, load_point.1 / TILE);
+        let load_top_left = ((load_point.0 - 1.5) * TILE, (load_point.1 - 1.5) * TILE);
+        let load_bottom_right = ((load_point.0 + 1.5) * TILE, (load_point.1 + 1.5) * TILE);
+        //each layer's grid goes over the ones before it
+        for tile_handles in tile_layers.iter() {
+            for (idx_x, x) in tile_handles.iter().enumerate() {
+                for (idx_y, y) in x.iter().enumerate() {
+                    let handle_top_left =
+                        ((idx_x as f32 - 2.5) * TILE, (idx_y as f32 - 2.5) * TILE);
+                    let handle_bottom_right =
+                        ((idx_x as f32 - 1.5) * TILE, (idx_y as f32 - 1.5) * TILE);
+                    if let Some(tile) = &tile_handles[idx_x][idx_y] {
+                        //let top_left = Vector::new(idx_x as f32 * 0.0, idx_y as f32 * 0.0);
+                        let mut x = load_top_left.0 - handle_top_left.0;
+                        let mut y = load_top_left.1 - handle_top_left.1;
+
+                        let mut width = handle_top_left.0 - load_bottom_right.0;
+                        let mut height = handle_top_left.1 - load_bottom_right.1;
+                        log::debug!(
+                            "comparing {},{} - {},{} to {},{} - {},{}",
+                            load_top_left.0,
+                            load_top_left.1,
+                            load_bottom_right.0,
+                            load_bottom_right.1,
+                            handle_top_left.0,
+                            handle_top_left.1,
+                            handle_bottom_right.0,
+                            handle_bottom_right.1
+                        );
+
+                        log::debug!("raw vals {}, {}, {}, {}", x, y, width, height);
+                        if handle_bottom_right.0 <= load_top_left.0
+                            || handle_bottom_right.1 <= load_top_left.1
+                        {
+                            log::debug!("skipping tile {}, {}", idx_x, idx_y);
+                            continue;
+                        }
+                        if handle_top_left.0 >= load_bottom_right.0
+                            || handle_top_left.1 >= load_bottom_right.1
+                        {
+                            log::debug!("skipping tile {}, {}", idx_x, idx_y);
+                            continue;
+                        }
+
+                        //if the top left handle is before the top left load, for a dimension, then
+                        //that dimension is less than a tile and x/y compensate
+                        //if the bottom right handle is greater than the bottom right load, then
+                        //that dimension is less than a tile and x/y remain 0
+                        //otherwise, the entire tile is swallowed
+
+                        if handle_top_left.0 < load_top_left.0 {
+                            width = handle_bottom_right.0 - load_top_left.0;
+                            x = TILE - width;
+                        } else if handle_bottom_right.0 > load_bottom_right.0 {
+                            width = load_bottom_right.0 - handle_top_left.0;
+                            x = 0.0;
+                        } else {
+                            width = TILE;
+                            x = 0.0;
+                        }
+
+                        if handle_top_left.1 < load_top_left.1 {
+                            height = handle_bottom_right.1 - load_top_left.1;
+                            y = TILE - height;
+                        } else if handle_bottom_right.1 > load_bottom_right.1 {
+                            height = load_bottom_right.1 - handle_top_left.1;
+                            y = 0.0;
+                        } else {
+                            height = TILE;
+                            y = 0.0;
+                        }
+
+                        let pixel_x = handle_top_left.0 - load_top_left.0 as f32 + x;
+                        let pixel_y = handle_top_left.1 - load_top_left.1 as f32 + y;
+
+                        log::trace!(
+                            "putting tile {}, {} at {}, {}: {}x{}",
+                            idx_x,
+                            idx_y,
+                            pixel_x,
+                            pixel_y,
+                            width,
+                            height
+                        );
+                        //x = 0.0;
+                        //y =0.0;
+                        //width = 256.0;
+                        //height=256.0;
+                        let new_clip = Primitive::Image {
+                            handle: tile.clone(),
+                            //bounds: Rectangle {
+                            //    x: 0.0,
+                            //    y: 0.0,
+                            //    ..Rectangle::with_size(image_size)
+                            //},
+                            bounds: Rectangle {
+                                x: snap(pixel_x),
+                                y: snap(pixel_y),
+                                width: snap(pixel_x + width) - snap(pixel_x),
+                                height: snap(pixel_y + height) - snap(pixel_y),
+                            },
+                        };
+                        primitives_vec.push(new_clip);
                     }
+                    //self.tile_handles[idx_x][idx_y] = Some(image::Handle::from_memory(tiles[idx_x][idx_y]));
+                }
+            }
+        }
 
-                    //if the top left handle is before the top left load, for a dimension, then
-                    //that dimension is less than 256 and x/y compensate
-                    //if the bottom right handle is greater than the bottom right load, then
-                    //that dimension is less than 256 and x/y remain 0
-                    //otherwise, the entire tile is swallowed
-
-                    if handle_top_left.0 < load_top_left.0 {
-                        width = handle_bottom_right.0 - load_top_left.0;
-                        x = 256. - width;
-                    } else if handle_bottom_right.0 > load_bottom_right.0 {
-                        width = load_bottom_right.0 - handle_top_left.0;
-                        x = 0.0;
-                    } else {
-                        width = 256.0;
-                        x = 0.0;
-                    }
+        //drawn features go on top of every tile, then labels on top of those
+        let mesh = tessellate_layers(layers.iter(), &view);
+        if !mesh.indices.is_empty() {
+            primitives_vec.push(Primitive::Mesh2D {
+                buffers: mesh,
+                size: bounds.size(),
+            });
+        }
 
-                    if handle_top_left.1 < load_top_left.1 {
-                        height = handle_bottom_right.1 - load_top_left.1;
-                        y = 256. - height;
-                    } else if handle_bottom_right.1 > load_bottom_right.1 {
-                        height = load_bottom_right.1 - handle_top_left.1;
-                        y = 0.0;
-                    } else {
-                        height = 256.0;
-                        y = 0.0;
+        let labels = shown_labels(layers);
+        let labels: Vec<&Label> = labels.iter().collect();
+        let viewport = (bounds.width, bounds.height);
+        let measure = |content: &str, size: u16| {
+            iced_native::text::Renderer::measure(self, content, size, Font::Default, Size::INFINITY)
+        };
+        for placed in layout_labels(&labels, &view, viewport, &measure) {
+            let style = placed.style;
+            let halo = style.halo_width;
+            for (glyph, center) in placed.glyphs.iter() {
+                let mut passes: Vec<((f32, f32), iced::Color)> = Vec::new();
+                if halo > 0.0 {
+                    for dx in [-halo, 0.0, halo].iter() {
+                        for dy in [-halo, 0.0, halo].iter() {
+                            if *dx != 0.0 || *dy != 0.0 {
+                                passes.push(((center.0 + dx, center.1 + dy), style.halo_color));
+                            }
+                        }
                     }
-
-                    let pixel_x = handle_top_left.0 - load_top_left.0 as f32 + x;
-                    let pixel_y = handle_top_left.1 - load_top_left.1 as f32 + y;
-
-                    log::trace!(
-                        "putting tile {}, {} at {}, {}: {}x{}",
-                        idx_x,
-                        idx_y,
-                        pixel_x,
-                        pixel_y,
-                        width,
-                        height
-                    );
-                    //x = 0.0;
-                    //y =0.0;
-                    //width = 256.0;
-                    //height=256.0;
-                    let new_clip = Primitive::Image {
-                        handle: tile.clone(),
-                        //bounds: Rectangle {
-                        //    x: 0.0,
-                        //    y: 0.0,
-                        //    ..Rectangle::with_size(image_size)
-                        //},
+                }
+                passes.push((*center, style.color));
+                for (position, color) in passes {
+                    primitives_vec.push(Primitive::Text {
+                        content: glyph.to_string(),
                         bounds: Rectangle {
-                            x: pixel_x,
-                            y: pixel_y,
-                            width,
-                            height,
+                            x: position.0,
+                            y: position.1,
+                            width: f32::INFINITY,
+                            height: f32::INFINITY,
                         },
-                    };
-                    primitives_vec.push(new_clip);
+                        color,
+                        size: style.font_size as f32,
+                        font: Font::Default,
+                        horizontal_alignment: HorizontalAlignment::Center,
+                        vertical_alignment: VerticalAlignment::Center,
+                    });
                 }
-                //self.tile_handles[idx_x][idx_y] = Some(image::Handle::from_memory(tiles[idx_x][idx_y]));
             }
         }
 
@@ -512,8 +683,15 @@ where
         (
             {
                 //
-                Primitive::Group {
-                    primitives: primitives_vec,
+                Primitive::Clip {
+                    bounds,
+                    offset: Vector::new(0, 0),
+                    content: Box::new(Primitive::Translate {
+                        translation,
+                        content: Box::new(Primitive::Group {
+                            primitives: primitives_vec,
+                        }),
+                    }),
                 }
             },
             { mouse::Interaction::Grab },
@@ -528,6 +706,7 @@ where
         cursor_position: Point,
         zoom_in: &iced_native::Button<'_, Message, Self>,
         zoom_out: &iced_native::Button<'_, Message, Self>,
+        status: Option<&str>,
     ) -> Self::Output {
         let bounds = layout.bounds();
         let mouse_interaction = mouse::Interaction::default();
@@ -544,10 +723,32 @@ where
 
         let (zoom_out_button, zoom_out_interaction) =
             zoom_out.draw(self, defaults, zoom_out_layout, cursor_position, &bounds);
+        let mut primitives = vec![zoom_in_button, zoom_out_button];
+        if let (Some(status), Some(status_layout)) = (status, children.next()) {
+            let status_bounds = status_layout.bounds();
+            primitives.push(Primitive::Quad {
+                bounds: status_bounds,
+                background: Background::Color(Color::from_rgb8(0xb0, 0x30, 0x30)),
+                border_radius: 4.0,
+                border_width: 0.0,
+                border_color: Color::TRANSPARENT,
+            });
+            primitives.push(Primitive::Text {
+                content: status.to_string(),
+                bounds: Rectangle {
+                    x: status_bounds.x + STATUS_PADDING,
+                    y: status_bounds.y + STATUS_PADDING,
+                    ..status_bounds
+                },
+                color: Color::WHITE,
+                size: STATUS_TEXT_SIZE as f32,
+                font: Font::Default,
+                horizontal_alignment: HorizontalAlignment::Left,
+                vertical_alignment: VerticalAlignment::Top,
+            });
+        }
         (
-            Primitive::Group {
-                primitives: vec![zoom_in_button, zoom_out_button],
-            },
+            Primitive::Group { primitives },
             mouse_interaction
                 .max(zoom_in_interaction)
                 .max(zoom_out_interaction),
@@ -17,6 +17,9 @@ use iced_native::{
     Event, Hasher, Layout, Length, Overlay, Point, Rectangle, Size, Text, Vector, Widget,
 };
 
+pub const STATUS_TEXT_SIZE: u16 = 16;
+pub const STATUS_PADDING: f32 = 4.0;
+
 pub struct TileOverlay<'a, Message, Renderer>
 where
     Message: 'a + Clone,
@@ -26,6 +29,8 @@ where
     /// #     iced_native::Button<'a, Message, iced_native::renderer::Null>;
     zoom_in: Button<'a, Message, Renderer>,
     zoom_out: Button<'a, Message, Renderer>,
+    //shown under the buttons, e.g. that the map is offline
+    status: Option<String>,
 }
 impl<'a, Message, Renderer> TileOverlay<'a, Message, Renderer>
 where
@@ -38,8 +43,13 @@ where
     pub fn new(
         zoom_in: Button<'a, Message, Renderer>,
         zoom_out: Button<'a, Message, Renderer>,
+        status: Option<String>,
     ) -> Self {
-        Self { zoom_in, zoom_out }
+        Self {
+            zoom_in,
+            zoom_out,
+            status,
+        }
     }
     pub fn overlay(self, position: Point) -> overlay::Element<'a, Message, Renderer> {
         overlay::Element::new(position, Box::new(self))
@@ -58,14 +68,28 @@ where
         let zoom_out_bounds = zoom_out_layout.bounds();
 
         zoom_out_layout.move_to(Point::new(0.0, zoom_in_bounds.height + 5.0));
-        let stacked_height = zoom_out_bounds.height + zoom_in_bounds.height;
-        let mut node = layout::Node::with_children(
-            Size::new(
-                f32::max(zoom_in_bounds.width, zoom_out_bounds.width),
-                stacked_height,
-            ),
-            vec![zoom_in_layout, zoom_out_layout],
-        );
+        let mut stacked_height = zoom_out_bounds.height + zoom_in_bounds.height;
+        let width = f32::max(zoom_in_bounds.width, zoom_out_bounds.width);
+        let mut children = vec![zoom_in_layout, zoom_out_layout];
+        if let Some(status) = &self.status {
+            let (text_width, text_height) = iced_native::text::Renderer::measure(
+                renderer,
+                status,
+                STATUS_TEXT_SIZE,
+                Default::default(),
+                Size::INFINITY,
+            );
+            let mut status_layout = layout::Node::new(Size::new(
+                text_width + STATUS_PADDING * 2.0,
+                text_height + STATUS_PADDING * 2.0,
+            ));
+            //lined up with the right edge of the buttons, the map's edge is close by
+            let status_width = status_layout.bounds().width;
+            status_layout.move_to(Point::new(width - status_width, stacked_height + 10.0));
+            stacked_height += status_layout.bounds().height + 10.0;
+            children.push(status_layout);
+        }
+        let mut node = layout::Node::with_children(Size::new(width, stacked_height), children);
         node.move_to(position);
         node
     }
@@ -77,6 +101,7 @@ where
         //(self.height).hash(state);
         self.zoom_in.hash_layout(state);
         self.zoom_out.hash_layout(state);
+        self.status.hash(state);
     }
 
     fn on_event(
@@ -128,6 +153,7 @@ where
             cursor_position,
             &self.zoom_in,
             &self.zoom_out,
+            self.status.as_deref(),
         )
     }
 }
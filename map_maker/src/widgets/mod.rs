@@ -1,3 +1,5 @@
 //import all the things needed for implementing a new egui widget
+pub mod drag_handle;
 pub mod map_tile;
 pub mod map_tile_overlay;
+pub mod page_preview;
@@ -0,0 +1,490 @@
+//shows a composed print page scaled down to fit, so the layout can be checked before it's
+//exported. it draws the same page the pdf and svg writers get
+//
+//the map and the things around it can be dragged about, or resized by their bottom right
+//corner. like the drag handle it doesn't move anything itself, it reports the change
+//once the item is let go and the layout is redone to match
+use crate::export::page::{Decoration, LayoutItem, Page, PageItem, Rect, TextAnchor};
+use crate::features::tessellate;
+use iced_graphics::backend::{self, Backend};
+use iced_graphics::triangle::Mesh2D;
+use iced_graphics::Primitive;
+use iced_native::event;
+use iced_native::image::Handle;
+use iced_native::{
+    layout, mouse, Background, Clipboard, Color, Element, Event, Font, Hasher, HorizontalAlignment,
+    Layout, Length, Point, Rectangle, Size, Vector, VerticalAlignment, Widget,
+};
+use image::{imageops, RgbaImage};
+
+//longest side of the images handed to the renderer, the page is never shown bigger
+const PREVIEW_IMAGE_SIZE: u32 = 1024;
+//iced sizes text by line height, the page by em size
+const LINE_HEIGHT_PER_EM: f32 = 1.16;
+//how close to an item's bottom right corner, in pixels, a drag resizes it
+const GRIP_SIZE: f64 = 8.0;
+//drags shorter than this, in pixels, are taken as clicks
+const MIN_DRAG: f32 = 2.0;
+
+/// A composed page along with image handles for its pictures, built once per layout
+/// change rather than every frame.
+pub struct LayoutPreview {
+    pub page: Page,
+    images: Vec<Handle>,
+}
+
+impl LayoutPreview {
+    pub fn new(page: Page) -> Self {
+        let images = page
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                PageItem::Image { image, .. } => Some(preview_handle(image)),
+                _ => None,
+            })
+            .collect();
+        Self { page, images }
+    }
+
+    /// Swaps what's around the map, see Page::decorate.
+    pub fn decorate(&mut self, decorations: Vec<Decoration>) {
+        self.page.decorate(decorations);
+    }
+}
+
+fn preview_handle(image: &RgbaImage) -> Handle {
+    let longest = image.width().max(image.height()).max(1);
+    let image = if longest > PREVIEW_IMAGE_SIZE {
+        let ratio = PREVIEW_IMAGE_SIZE as f32 / longest as f32;
+        let width = ((image.width() as f32 * ratio).round() as u32).max(1);
+        let height = ((image.height() as f32 * ratio).round() as u32).max(1);
+        imageops::resize(image, width, height, imageops::FilterType::Triangle)
+    } else {
+        image.clone()
+    };
+    let (width, height) = image.dimensions();
+    //the renderer wants bgra
+    let mut pixels = image.into_raw();
+    for pixel in pixels.chunks_mut(4) {
+        pixel.swap(0, 2);
+    }
+    Handle::from_pixels(width, height, pixels)
+}
+
+pub struct PagePreview<'a, Message> {
+    state: &'a mut State,
+    preview: &'a LayoutPreview,
+    on_move: Box<dyn Fn(LayoutItem, (f64, f64)) -> Message + 'a>,
+    on_resize: Box<dyn Fn(LayoutItem, f64) -> Message + 'a>,
+    width: Length,
+    height: Length,
+}
+
+/// The state of a [`PagePreview`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct State {
+    grab: Option<Grab>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Grab {
+    item: LayoutItem,
+    //where the item was on the page when it was grabbed
+    rect: Rect,
+    resize: bool,
+    //where the cursor was
+    at: Point,
+}
+
+impl Grab {
+    //where the item ends up if it's let go with the cursor at `cursor`
+    fn target(&self, cursor: Point, scale: f64) -> Rect {
+        let dx = (cursor.x - self.at.x) as f64 / scale;
+        let dy = (cursor.y - self.at.y) as f64 / scale;
+        if self.resize {
+            let factor = self.factor(dx, dy);
+            Rect {
+                width: self.rect.width * factor,
+                height: self.rect.height * factor,
+                ..self.rect
+            }
+        } else {
+            Rect {
+                x: self.rect.x + dx,
+                y: self.rect.y + dy,
+                ..self.rect
+            }
+        }
+    }
+
+    //items keep their shape, the corner is followed as well as it can be
+    fn factor(&self, dx: f64, dy: f64) -> f64 {
+        let across = (self.rect.width + dx) / self.rect.width.max(0.1);
+        let down = (self.rect.height + dy) / self.rect.height.max(0.1);
+        ((across + down) / 2.0).max(0.05)
+    }
+}
+
+impl<'a, Message> PagePreview<'a, Message> {
+    /// `on_move` gets how far an item was dragged in millimeters on the page, `on_resize`
+    /// how much bigger it was made.
+    pub fn new<M, R>(
+        state: &'a mut State,
+        preview: &'a LayoutPreview,
+        on_move: M,
+        on_resize: R,
+    ) -> Self
+    where
+        M: 'a + Fn(LayoutItem, (f64, f64)) -> Message,
+        R: 'a + Fn(LayoutItem, f64) -> Message,
+    {
+        Self {
+            state,
+            preview,
+            on_move: Box::new(on_move),
+            on_resize: Box::new(on_resize),
+            width: Length::Fill,
+            height: Length::Fill,
+        }
+    }
+}
+
+//millimeters to pixels and where the top left corner of the page is when it's centered
+//in `bounds`
+fn fit(bounds: Rectangle, page: &Page) -> (f64, Vector) {
+    let scale = (bounds.width as f64 / page.size.0).min(bounds.height as f64 / page.size.1);
+    let origin = Vector::new(
+        bounds.x + (bounds.width - (page.size.0 * scale) as f32) / 2.0,
+        bounds.y + (bounds.height - (page.size.1 * scale) as f32) / 2.0,
+    );
+    (scale, origin)
+}
+
+//the item under `cursor`, topmost first, and whether it's on the item's resize grip
+fn item_at(bounds: Rectangle, page: &Page, cursor: Point) -> Option<(LayoutItem, Rect, bool)> {
+    if !bounds.contains(cursor) {
+        return None;
+    }
+    let (scale, origin) = fit(bounds, page);
+    let at = (
+        (cursor.x - origin.x) as f64 / scale,
+        (cursor.y - origin.y) as f64 / scale,
+    );
+    let grip = GRIP_SIZE / scale;
+    page.regions
+        .iter()
+        .rev()
+        .find(|(_, rect)| rect.contains(at))
+        .map(|(item, rect)| {
+            let on_grip = at.0 >= rect.x + rect.width - grip && at.1 >= rect.y + rect.height - grip;
+            (*item, *rect, on_grip)
+        })
+}
+
+impl<'a, Message, Renderer> Widget<Message, Renderer> for PagePreview<'a, Message>
+where
+    Renderer: self::Renderer,
+{
+    fn width(&self) -> Length {
+        Length::Shrink
+    }
+
+    fn height(&self) -> Length {
+        Length::Shrink
+    }
+
+    fn layout(&self, _renderer: &Renderer, limits: &layout::Limits) -> layout::Node {
+        //the same room the map gets, so switching modes doesn't shuffle the window
+        let (width, height) = (256.0 * 3.0, 256.0 * 3.0);
+        layout::Node::new(limits.resolve(Size::new(width, height)))
+    }
+
+    fn on_event(
+        &mut self,
+        event: Event,
+        layout: Layout<'_>,
+        cursor_position: Point,
+        _renderer: &Renderer,
+        _clipboard: &mut dyn Clipboard,
+        messages: &mut Vec<Message>,
+    ) -> event::Status {
+        let bounds = layout.bounds();
+        let page = &self.preview.page;
+        match event {
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
+                if let Some((item, rect, resize)) = item_at(bounds, page, cursor_position) {
+                    self.state.grab = Some(Grab {
+                        item,
+                        rect,
+                        resize,
+                        at: cursor_position,
+                    });
+                    return event::Status::Captured;
+                }
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) => {
+                if let Some(grab) = self.state.grab.take() {
+                    let moved = (cursor_position.x - grab.at.x, cursor_position.y - grab.at.y);
+                    if moved.0.abs().max(moved.1.abs()) >= MIN_DRAG {
+                        let (scale, _) = fit(bounds, page);
+                        let (dx, dy) = (moved.0 as f64 / scale, moved.1 as f64 / scale);
+                        messages.push(if grab.resize {
+                            (self.on_resize)(grab.item, grab.factor(dx, dy))
+                        } else {
+                            (self.on_move)(grab.item, (dx, dy))
+                        });
+                    }
+                    return event::Status::Captured;
+                }
+            }
+            _ => {}
+        }
+        event::Status::Ignored
+    }
+
+    fn draw(
+        &self,
+        renderer: &mut Renderer,
+        _defaults: &Renderer::Defaults,
+        layout: Layout<'_>,
+        cursor_position: Point,
+        _viewport: &Rectangle,
+    ) -> Renderer::Output {
+        let bounds = layout.bounds();
+        let page = &self.preview.page;
+        //the grabbed item where it would go, or the one under the cursor
+        let (outline, interaction) = match &self.state.grab {
+            Some(grab) => {
+                let (scale, _) = fit(bounds, page);
+                let interaction = if grab.resize {
+                    mouse::Interaction::Crosshair
+                } else {
+                    mouse::Interaction::Grabbing
+                };
+                (Some(grab.target(cursor_position, scale)), interaction)
+            }
+            None => match item_at(bounds, page, cursor_position) {
+                Some((_, rect, true)) => (Some(rect), mouse::Interaction::Crosshair),
+                Some((_, rect, false)) => (Some(rect), mouse::Interaction::Grab),
+                None => (None, mouse::Interaction::default()),
+            },
+        };
+        self::Renderer::draw(renderer, bounds, self.preview, outline, interaction)
+    }
+
+    fn hash_layout(&self, state: &mut Hasher) {
+        use std::hash::Hash;
+        self.preview.page.items.len().hash(state);
+        self.width.hash(state);
+        self.height.hash(state);
+    }
+}
+
+/// The renderer of a [`PagePreview`].
+pub trait Renderer: iced_native::Renderer + Sized {
+    /// Draws the page of `preview` centered in `bounds`, with `outline` around an item
+    /// on it.
+    fn draw(
+        &mut self,
+        bounds: Rectangle,
+        preview: &LayoutPreview,
+        outline: Option<Rect>,
+        interaction: mouse::Interaction,
+    ) -> Self::Output;
+}
+
+impl<B> Renderer for iced_graphics::Renderer<B>
+where
+    B: Backend + backend::Image + backend::Text,
+{
+    fn draw(
+        &mut self,
+        bounds: Rectangle,
+        preview: &LayoutPreview,
+        outline: Option<Rect>,
+        interaction: mouse::Interaction,
+    ) -> Self::Output {
+        let page = &preview.page;
+        let (scale, origin) = fit(bounds, page);
+        let page_size = Size::new((page.size.0 * scale) as f32, (page.size.1 * scale) as f32);
+        let to_pixels = |point: &(f64, f64)| ((point.0 * scale) as f32, (point.1 * scale) as f32);
+
+        let mut primitives = vec![Primitive::Quad {
+            bounds: Rectangle::with_size(page_size),
+            background: Background::Color(Color::WHITE),
+            border_radius: 0.0,
+            border_width: 1.0,
+            border_color: Color::from_rgb(0.6, 0.6, 0.6),
+        }];
+        //consecutive paths share a mesh, it's flushed whenever something else is drawn so
+        //the page keeps its stacking order
+        let mut mesh = Mesh2D {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+        };
+        let flush = |mesh: &mut Mesh2D, primitives: &mut Vec<Primitive>| {
+            if !mesh.indices.is_empty() {
+                let buffers = std::mem::replace(
+                    mesh,
+                    Mesh2D {
+                        vertices: Vec::new(),
+                        indices: Vec::new(),
+                    },
+                );
+                primitives.push(Primitive::Mesh2D {
+                    buffers,
+                    size: page_size,
+                });
+            }
+        };
+        let mut images = preview.images.iter();
+        for item in &page.items {
+            match item {
+                PageItem::Image { rect, .. } => {
+                    flush(&mut mesh, &mut primitives);
+                    if let Some(handle) = images.next() {
+                        let (x, y) = to_pixels(&(rect.x, rect.y));
+                        let (width, height) = to_pixels(&(rect.width, rect.height));
+                        primitives.push(Primitive::Image {
+                            handle: handle.clone(),
+                            bounds: Rectangle {
+                                x,
+                                y,
+                                width,
+                                height,
+                            },
+                        });
+                    }
+                }
+                PageItem::Path {
+                    rings,
+                    closed,
+                    fill,
+                    stroke,
+                } => {
+                    let rings: Vec<Vec<(f32, f32)>> = rings
+                        .iter()
+                        .map(|ring| ring.iter().map(to_pixels).collect())
+                        .collect();
+                    if let Some(color) = fill {
+                        tessellate::fill(&mut mesh, &rings, *color);
+                    }
+                    if let Some((color, width)) = stroke {
+                        //hairlines would vanish at this size
+                        let width = ((width * scale) as f32).max(0.5);
+                        for ring in &rings {
+                            let mut line = ring.clone();
+                            if *closed && ring.len() > 2 {
+                                line.push(ring[0]);
+                            }
+                            tessellate::stroke(&mut mesh, &line, width, *color);
+                        }
+                    }
+                }
+                PageItem::Text {
+                    text,
+                    position,
+                    size,
+                    color,
+                    anchor,
+                    halo,
+                } => {
+                    flush(&mut mesh, &mut primitives);
+                    let size = *size as f32 * scale as f32 * LINE_HEIGHT_PER_EM;
+                    let (x, y) = to_pixels(position);
+                    //iced aligns the bottom of the line box, the page aligns the baseline
+                    let y = y + size * 0.2;
+                    let horizontal_alignment = match anchor {
+                        TextAnchor::Start => HorizontalAlignment::Left,
+                        TextAnchor::Middle => HorizontalAlignment::Center,
+                        TextAnchor::End => HorizontalAlignment::Right,
+                    };
+                    let mut passes: Vec<((f32, f32), Color)> = Vec::new();
+                    if let Some((halo_color, halo_width)) = halo {
+                        let halo = (*halo_width * scale) as f32;
+                        if halo > 0.0 {
+                            for dx in [-halo, 0.0, halo].iter() {
+                                for dy in [-halo, 0.0, halo].iter() {
+                                    if *dx != 0.0 || *dy != 0.0 {
+                                        passes.push(((x + dx, y + dy), *halo_color));
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    passes.push(((x, y), *color));
+                    for (position, color) in passes {
+                        primitives.push(Primitive::Text {
+                            content: text.clone(),
+                            bounds: Rectangle {
+                                x: position.0,
+                                y: position.1,
+                                width: f32::INFINITY,
+                                height: f32::INFINITY,
+                            },
+                            color,
+                            size,
+                            font: Font::Default,
+                            horizontal_alignment,
+                            vertical_alignment: VerticalAlignment::Bottom,
+                        });
+                    }
+                }
+            }
+        }
+        flush(&mut mesh, &mut primitives);
+
+        if let Some(rect) = outline {
+            let (x, y) = to_pixels(&(rect.x, rect.y));
+            let (width, height) = to_pixels(&(rect.width, rect.height));
+            let outline_color = Color::from_rgb(0.2, 0.45, 0.9);
+            primitives.push(Primitive::Quad {
+                bounds: Rectangle {
+                    x,
+                    y,
+                    width,
+                    height,
+                },
+                background: Background::Color(Color::TRANSPARENT),
+                border_radius: 0.0,
+                border_width: 1.0,
+                border_color: outline_color,
+            });
+            let grip = GRIP_SIZE as f32;
+            primitives.push(Primitive::Quad {
+                bounds: Rectangle {
+                    x: x + width - grip,
+                    y: y + height - grip,
+                    width: grip,
+                    height: grip,
+                },
+                background: Background::Color(outline_color),
+                border_radius: 0.0,
+                border_width: 0.0,
+                border_color: Color::TRANSPARENT,
+            });
+        }
+
+        (
+            Primitive::Clip {
+                bounds,
+                offset: Vector::new(0, 0),
+                content: Box::new(Primitive::Translate {
+                    translation: origin,
+                    content: Box::new(Primitive::Group { primitives }),
+                }),
+            },
+            interaction,
+        )
+    }
+}
+
+impl<'a, Message, Renderer> Into<Element<'a, Message, Renderer>> for PagePreview<'a, Message>
+where
+    Message: 'a,
+    Renderer: 'a + self::Renderer,
+{
+    fn into(self) -> Element<'a, Message, Renderer> {
+        Element::new(self)
+    }
+}